@@ -8,16 +8,46 @@ pub const PHYS_FRAME_END: usize = 0x83f00000;
 // 用户栈映射的虚拟地址, 用户栈大小为一个页, 暂时不支持修改
 pub const USER_STACK_SIZE: usize = PAGE_SIZE;
 pub const USER_STACK_PAGE: usize = 0x80000000 - USER_STACK_SIZE;
-// 每个hart使用的栈大小
-pub const BOOT_STACK_SIZE: usize = 2 * PAGE_SIZE;
+// 信号处理函数返回时跳转的sigreturn跳板，所有进程共享，映射在用户栈下方的一页
+//     mmap区域从这一页下方开始分配
+pub const SIGRETURN_TRAMPOLINE: usize = USER_STACK_PAGE - PAGE_SIZE;
+// 每个进程的内核栈大小
+pub const KERNEL_STACK_SIZE: usize = 2 * PAGE_SIZE;
+// 内核栈映射的虚拟地址，所有进程的内核栈都映射在这里，运行时由hart映射到自己的页表
+//     高于物理内存和用户地址空间，下方不映射作为保护页
+pub const KERNEL_STACK_TOP: usize = 0x1_0000_0000;
 // 定时器频率
 #[cfg(feature = "board_unleashed")]
 pub const RTCLK_FREQ: usize = 1000_000; // 1M Hz
+#[cfg(not(feature = "board_unleashed"))]
+pub const RTCLK_FREQ: usize = 10_000_000; // qemu virt为10M Hz
+// 空闲的hart每次休眠的最长时间(10ms)，醒来后检查控制台输入
+pub const IDLE_TIMEOUT: usize = RTCLK_FREQ / 100;
+// hartid的范围，unleashed的hart 0是不运行内核的监控核
+pub const MAX_HARTS: usize = 5;
+// 默认的CPU亲和性，可以在所有hart上运行
+pub const ALL_HARTS: usize = (1 << MAX_HARTS) - 1;
+
+// qemu virt的virtio MMIO设备
+#[cfg(feature = "virtio")]
+pub const VIRTIO_MMIO_BASE: usize = 0x1000_1000;
+#[cfg(feature = "virtio")]
+pub const VIRTIO_MMIO_SIZE: usize = 0x1000;
+#[cfg(feature = "virtio")]
+pub const VIRTIO_MMIO_NUM: usize = 8;
+// 块缓存的块数
+pub const BLOCK_CACHE_SIZE: usize = 64;
 
 pub const PTE_FLAG_SIZE: usize = 8;
 pub const PTE_PPN_OFFSET: usize = 10;
 // 每个进程最多能打开的文件
 pub const MAX_FDS: usize = 1024;
 
+// init进程的路径，编译时可以通过环境变量INIT_PATH修改，比如INIT_PATH=/shell直接运行shell
+pub const INIT_PATH: &str = match option_env!("INIT_PATH") {
+    Some(path) => path,
+    None => "/init",
+};
+
 // 文件目录最大长度
 pub const PATH_LIMITS: usize = 512;
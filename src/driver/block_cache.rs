@@ -0,0 +1,159 @@
+/**
+ * 块缓存
+ * 缓存最近使用的块，写入时只标记为脏，替换或sync时才写回设备
+ * 缓存的数据放在kalloc分配的物理页中，不占用内核堆
+ */
+use super::*;
+use crate::config::PAGE_SIZE;
+use crate::mm::*;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Mutex;
+
+const BLOCKS_PER_FRAME: usize = PAGE_SIZE / BLOCK_SIZE;
+
+lazy_static! {
+    // 所有的块缓存，用于sync
+    static ref BLOCK_CACHES: Mutex<Vec<Weak<BlockCache>>> = Mutex::new(Vec::new());
+}
+
+struct CacheSlot {
+    block_id: Option<usize>,
+    dirty: bool,
+    // 最近一次访问的时间，用于LRU替换
+    last_use: usize,
+}
+
+struct BlockCacheInner {
+    frames: Vec<FrameTracker>,
+    slots: Vec<CacheSlot>,
+    clock: usize,
+}
+
+pub struct BlockCache {
+    dev: Arc<dyn BlockDevice>,
+    inner: Mutex<BlockCacheInner>,
+}
+
+impl BlockCacheInner {
+    fn slot_addr(&self, slot: usize) -> PhysAddr {
+        self.frames[slot / BLOCKS_PER_FRAME]
+            .page()
+            .offset_phys(slot % BLOCKS_PER_FRAME * BLOCK_SIZE)
+    }
+}
+
+impl BlockCache {
+    // 创建缓存capacity个块的缓存，capacity向上对齐到整数个页
+    pub fn new(dev: Arc<dyn BlockDevice>, capacity: usize) -> Result<Arc<Self>, KallocErr> {
+        let nframes = (capacity + BLOCKS_PER_FRAME - 1) / BLOCKS_PER_FRAME;
+        let mut frames = Vec::with_capacity(nframes);
+        for _ in 0..nframes {
+            frames.push(try_kalloc()?);
+        }
+        let slots = (0..nframes * BLOCKS_PER_FRAME)
+            .map(|_| CacheSlot {
+                block_id: None,
+                dirty: false,
+                last_use: 0,
+            })
+            .collect();
+        let cache = Arc::new(Self {
+            dev,
+            inner: Mutex::new(BlockCacheInner {
+                frames,
+                slots,
+                clock: 0,
+            }),
+        });
+        let mut caches = BLOCK_CACHES.lock();
+        caches.retain(|cache| cache.strong_count() > 0);
+        caches.push(Arc::downgrade(&cache));
+        Ok(cache)
+    }
+
+    // 获取块所在的缓存位置，不在缓存中时替换最久未使用的块
+    //     read为false时调用者会覆盖整个块，不需要从设备读出
+    fn get_slot(&self, inner: &mut BlockCacheInner, block_id: usize, read: bool) -> usize {
+        inner.clock += 1;
+        let clock = inner.clock;
+        if let Some(slot) = inner
+            .slots
+            .iter()
+            .position(|slot| slot.block_id == Some(block_id))
+        {
+            inner.slots[slot].last_use = clock;
+            return slot;
+        }
+        let victim = inner
+            .slots
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, slot)| slot.last_use)
+            .map(|(i, _)| i)
+            .unwrap();
+        self.write_back(inner, victim);
+        if read {
+            let mut addr = inner.slot_addr(victim);
+            self.dev
+                .read_block(block_id, addr.as_slice_mut(BLOCK_SIZE));
+        }
+        let slot = &mut inner.slots[victim];
+        slot.block_id = Some(block_id);
+        slot.dirty = false;
+        slot.last_use = clock;
+        log!("driver":"block_cache">"load block {} to slot {}", block_id, victim);
+        victim
+    }
+
+    fn write_back(&self, inner: &mut BlockCacheInner, slot: usize) {
+        if let Some(block_id) = inner.slots[slot].block_id {
+            if inner.slots[slot].dirty {
+                let addr = inner.slot_addr(slot);
+                self.dev.write_block(block_id, addr.as_slice(BLOCK_SIZE));
+                inner.slots[slot].dirty = false;
+            }
+        }
+    }
+}
+
+impl BlockDevice for BlockCache {
+    fn num_blocks(&self) -> usize {
+        self.dev.num_blocks()
+    }
+
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        let mut inner = self.inner.lock();
+        let slot = self.get_slot(&mut inner, block_id, true);
+        inner.slot_addr(slot).read(&mut buf[..BLOCK_SIZE]);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let mut inner = self.inner.lock();
+        let slot = self.get_slot(&mut inner, block_id, false);
+        inner.slot_addr(slot).write(&buf[..BLOCK_SIZE]);
+        inner.slots[slot].dirty = true;
+    }
+
+    // 将所有脏块写回设备
+    fn sync(&self) {
+        let mut inner = self.inner.lock();
+        for slot in 0..inner.slots.len() {
+            self.write_back(&mut inner, slot);
+        }
+        drop(inner);
+        self.dev.sync();
+    }
+}
+
+// 将所有块缓存写回设备
+pub fn sync_all() {
+    let caches: Vec<Arc<BlockCache>> = BLOCK_CACHES
+        .lock()
+        .iter()
+        .filter_map(|cache| cache.upgrade())
+        .collect();
+    for cache in caches {
+        cache.sync();
+    }
+}
@@ -0,0 +1,55 @@
+/**
+ * 设备驱动
+ */
+mod block_cache;
+mod ramdisk;
+#[cfg(feature = "virtio")]
+mod virtio_blk;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use spin::RwLock;
+
+pub use block_cache::*;
+pub use ramdisk::*;
+#[cfg(feature = "virtio")]
+pub use virtio_blk::*;
+
+// 块设备的块大小，与磁盘扇区大小一致
+pub const BLOCK_SIZE: usize = 512;
+
+// 块设备接口，文件系统通过该接口读写磁盘
+// buf的长度必须为BLOCK_SIZE
+pub trait BlockDevice: Send + Sync {
+    // 设备的块数
+    fn num_blocks(&self) -> usize;
+    fn read_block(&self, block_id: usize, buf: &mut [u8]);
+    fn write_block(&self, block_id: usize, buf: &[u8]);
+
+    // 将缓存的数据写回设备，没有缓存的设备不需要实现
+    fn sync(&self) {}
+}
+
+lazy_static! {
+    // 已注册的块设备，mount通过设备名查找
+    static ref BLOCK_DEVICES: RwLock<BTreeMap<String, Arc<dyn BlockDevice>>> =
+        RwLock::new(BTreeMap::new());
+}
+
+// 注册块设备，设备名不包含"/dev/"前缀
+pub fn register_block_device(name: &str, dev: Arc<dyn BlockDevice>) {
+    log!("driver":"register">"block device {}", name);
+    BLOCK_DEVICES.write().insert(String::from(name), dev);
+}
+
+pub fn get_block_device(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES.read().get(name).cloned()
+}
+
+// 查找第一个virtio-blk设备
+#[cfg(feature = "virtio")]
+pub fn probe_virtio_blk() -> Option<VirtioBlk> {
+    use crate::config::{VIRTIO_MMIO_BASE, VIRTIO_MMIO_NUM, VIRTIO_MMIO_SIZE};
+    (0..VIRTIO_MMIO_NUM).find_map(|i| VirtioBlk::probe(VIRTIO_MMIO_BASE + i * VIRTIO_MMIO_SIZE))
+}
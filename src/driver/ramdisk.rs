@@ -0,0 +1,64 @@
+/**
+ * 内存中的块设备
+ * 磁盘镜像被复制到物理页中，可以读写，但是不会写回镜像
+ */
+use super::*;
+use crate::config::PAGE_SIZE;
+use crate::mm::*;
+use alloc::vec::Vec;
+use core::cmp::min;
+use spin::Mutex;
+
+// 编译时打包进内核的磁盘镜像(make fat32.img)
+#[cfg(feature = "ramdisk")]
+pub static DISK_IMAGE: &'static [u8] = include_bytes!("../../fat32.img");
+
+pub struct RamDisk {
+    frames: Mutex<Vec<FrameTracker>>,
+    blocks: usize,
+}
+
+impl RamDisk {
+    pub fn new(image: &[u8]) -> Result<Self, KallocErr> {
+        let npages = (image.len() + PAGE_SIZE - 1) / PAGE_SIZE;
+        let mut frames = Vec::with_capacity(npages);
+        for i in 0..npages {
+            let frame = try_kalloc()?;
+            let start = i * PAGE_SIZE;
+            let end = min(start + PAGE_SIZE, image.len());
+            frame.page().offset_phys(0).write(&image[start..end]);
+            frames.push(frame);
+        }
+        log!("driver":"ramdisk">"{} blocks", image.len() / BLOCK_SIZE);
+        Ok(Self {
+            frames: Mutex::new(frames),
+            blocks: image.len() / BLOCK_SIZE,
+        })
+    }
+
+    // 块所在的物理地址，一个页可以放下整数个块
+    fn block_addr(frames: &[FrameTracker], block_id: usize) -> PhysAddr {
+        let offset = block_id * BLOCK_SIZE;
+        frames[offset / PAGE_SIZE]
+            .page()
+            .offset_phys(offset % PAGE_SIZE)
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn num_blocks(&self) -> usize {
+        self.blocks
+    }
+
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        assert!(block_id < self.blocks, "ramdisk: block {} out of range", block_id);
+        let frames = self.frames.lock();
+        Self::block_addr(&frames, block_id).read(&mut buf[..BLOCK_SIZE]);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        assert!(block_id < self.blocks, "ramdisk: block {} out of range", block_id);
+        let frames = self.frames.lock();
+        Self::block_addr(&frames, block_id).write(&buf[..BLOCK_SIZE]);
+    }
+}
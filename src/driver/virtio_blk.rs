@@ -0,0 +1,323 @@
+/**
+ * virtio-blk驱动(MMIO)
+ * 用于qemu的virt机器，同时支持legacy(version 1)和version 2的MMIO接口
+ * 内核态不开中断，提交请求后轮询used ring等待完成
+ */
+use super::*;
+use crate::config::PAGE_SIZE;
+use crate::mm::*;
+use core::arch::asm;
+use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
+use spin::Mutex;
+
+// MMIO寄存器偏移
+const MMIO_MAGIC: usize = 0x000;
+const MMIO_VERSION: usize = 0x004;
+const MMIO_DEVICE_ID: usize = 0x008;
+const MMIO_DEVICE_FEATURES: usize = 0x010;
+const MMIO_DEVICE_FEATURES_SEL: usize = 0x014;
+const MMIO_DRIVER_FEATURES: usize = 0x020;
+const MMIO_DRIVER_FEATURES_SEL: usize = 0x024;
+const MMIO_GUEST_PAGE_SIZE: usize = 0x028; // legacy
+const MMIO_QUEUE_SEL: usize = 0x030;
+const MMIO_QUEUE_NUM_MAX: usize = 0x034;
+const MMIO_QUEUE_NUM: usize = 0x038;
+const MMIO_QUEUE_ALIGN: usize = 0x03c; // legacy
+const MMIO_QUEUE_PFN: usize = 0x040; // legacy
+const MMIO_QUEUE_READY: usize = 0x044;
+const MMIO_QUEUE_NOTIFY: usize = 0x050;
+const MMIO_INTERRUPT_STATUS: usize = 0x060;
+const MMIO_INTERRUPT_ACK: usize = 0x064;
+const MMIO_STATUS: usize = 0x070;
+const MMIO_QUEUE_DESC_LOW: usize = 0x080;
+const MMIO_QUEUE_DESC_HIGH: usize = 0x084;
+const MMIO_QUEUE_DRIVER_LOW: usize = 0x090;
+const MMIO_QUEUE_DRIVER_HIGH: usize = 0x094;
+const MMIO_QUEUE_DEVICE_LOW: usize = 0x0a0;
+const MMIO_QUEUE_DEVICE_HIGH: usize = 0x0a4;
+// 设备配置空间，virtio-blk的前8字节为扇区数
+const MMIO_CONFIG: usize = 0x100;
+
+const VIRTIO_MAGIC: u32 = 0x7472_6976;
+const VIRTIO_DEVICE_BLK: u32 = 2;
+
+// 设备状态
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+
+// version 2的设备必须协商VIRTIO_F_VERSION_1，即第二组特性(第32-63位)中的第0位
+const VIRTIO_F_VERSION_1: u32 = 1 << 0;
+
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_S_OK: u8 = 0;
+
+// 每次只提交一个请求，只需要3个描述符
+const QUEUE_SIZE: usize = 8;
+
+// 队列和请求都放在一个物理页中:
+//     描述符表 | avail ring | used ring | 请求头 | 状态 | 数据
+const DESC_OFFSET: usize = 0;
+const AVAIL_OFFSET: usize = DESC_OFFSET + QUEUE_SIZE * size_of::<VirtqDesc>();
+// legacy设备按照QUEUE_ALIGN对齐used ring，设置为4可以让整个队列放在一个页中
+const USED_ALIGN: usize = 4;
+const USED_OFFSET: usize =
+    (AVAIL_OFFSET + 4 + 2 * QUEUE_SIZE + USED_ALIGN - 1) / USED_ALIGN * USED_ALIGN;
+const HEADER_OFFSET: usize = 256;
+const STATUS_OFFSET: usize = HEADER_OFFSET + size_of::<BlkReqHeader>();
+const DATA_OFFSET: usize = 512;
+
+#[repr(C)]
+struct VirtqDesc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[allow(unused)]
+#[repr(C)]
+struct VirtqAvail {
+    flags: u16,
+    idx: u16,
+    ring: [u16; QUEUE_SIZE],
+}
+
+#[allow(unused)]
+#[repr(C)]
+struct VirtqUsedElem {
+    id: u32,
+    len: u32,
+}
+
+#[allow(unused)]
+#[repr(C)]
+struct VirtqUsed {
+    flags: u16,
+    idx: u16,
+    ring: [VirtqUsedElem; QUEUE_SIZE],
+}
+
+#[allow(unused)]
+#[repr(C)]
+struct BlkReqHeader {
+    req_type: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+struct VirtQueue {
+    frame: FrameTracker,
+    // 下一个写入avail ring的位置
+    avail_idx: u16,
+    // 已经处理完的used ring位置
+    used_idx: u16,
+}
+
+pub struct VirtioBlk {
+    base: usize,
+    blocks: usize,
+    queue: Mutex<VirtQueue>,
+}
+
+fn mmio_read(base: usize, reg: usize) -> u32 {
+    unsafe { read_volatile((base + reg) as *const u32) }
+}
+
+fn mmio_write(base: usize, reg: usize, val: u32) {
+    unsafe { write_volatile((base + reg) as *mut u32, val) }
+}
+
+// 保证内存访问和设备IO的顺序
+fn io_fence() {
+    unsafe { asm!("fence iorw, iorw") }
+}
+
+impl VirtioBlk {
+    // 检查base处是否为virtio-blk设备，并初始化
+    pub fn probe(base: usize) -> Option<Self> {
+        if mmio_read(base, MMIO_MAGIC) != VIRTIO_MAGIC
+            || mmio_read(base, MMIO_DEVICE_ID) != VIRTIO_DEVICE_BLK
+        {
+            return None;
+        }
+        let version = mmio_read(base, MMIO_VERSION);
+        if version != 1 && version != 2 {
+            log!("driver":"virtio">"unsupported version {} at 0x{:x}", version, base);
+            return None;
+        }
+
+        // 重置设备
+        mmio_write(base, MMIO_STATUS, 0);
+        let mut status = STATUS_ACKNOWLEDGE | STATUS_DRIVER;
+        mmio_write(base, MMIO_STATUS, status);
+
+        // 不使用任何可选特性
+        mmio_write(base, MMIO_DEVICE_FEATURES_SEL, 0);
+        mmio_write(base, MMIO_DRIVER_FEATURES_SEL, 0);
+        mmio_write(base, MMIO_DRIVER_FEATURES, 0);
+        if version == 2 {
+            mmio_write(base, MMIO_DEVICE_FEATURES_SEL, 1);
+            if mmio_read(base, MMIO_DEVICE_FEATURES) & VIRTIO_F_VERSION_1 == 0 {
+                return None;
+            }
+            mmio_write(base, MMIO_DRIVER_FEATURES_SEL, 1);
+            mmio_write(base, MMIO_DRIVER_FEATURES, VIRTIO_F_VERSION_1);
+            status |= STATUS_FEATURES_OK;
+            mmio_write(base, MMIO_STATUS, status);
+            if mmio_read(base, MMIO_STATUS) & STATUS_FEATURES_OK == 0 {
+                log!("driver":"virtio">"features not accepted at 0x{:x}", base);
+                return None;
+            }
+        } else {
+            mmio_write(base, MMIO_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        }
+
+        // 初始化队列0
+        mmio_write(base, MMIO_QUEUE_SEL, 0);
+        let max = mmio_read(base, MMIO_QUEUE_NUM_MAX) as usize;
+        if max < QUEUE_SIZE {
+            log!("driver":"virtio">"queue too small ({}) at 0x{:x}", max, base);
+            return None;
+        }
+        let frame = try_kalloc().ok()?;
+        let queue_pa = frame.page().offset_phys(0).0;
+        mmio_write(base, MMIO_QUEUE_NUM, QUEUE_SIZE as u32);
+        if version == 2 {
+            let desc = (queue_pa + DESC_OFFSET) as u64;
+            let avail = (queue_pa + AVAIL_OFFSET) as u64;
+            let used = (queue_pa + USED_OFFSET) as u64;
+            mmio_write(base, MMIO_QUEUE_DESC_LOW, desc as u32);
+            mmio_write(base, MMIO_QUEUE_DESC_HIGH, (desc >> 32) as u32);
+            mmio_write(base, MMIO_QUEUE_DRIVER_LOW, avail as u32);
+            mmio_write(base, MMIO_QUEUE_DRIVER_HIGH, (avail >> 32) as u32);
+            mmio_write(base, MMIO_QUEUE_DEVICE_LOW, used as u32);
+            mmio_write(base, MMIO_QUEUE_DEVICE_HIGH, (used >> 32) as u32);
+            mmio_write(base, MMIO_QUEUE_READY, 1);
+        } else {
+            mmio_write(base, MMIO_QUEUE_ALIGN, USED_ALIGN as u32);
+            mmio_write(base, MMIO_QUEUE_PFN, (queue_pa / PAGE_SIZE) as u32);
+        }
+
+        status |= STATUS_DRIVER_OK;
+        mmio_write(base, MMIO_STATUS, status);
+
+        let blocks = mmio_read(base, MMIO_CONFIG) as usize
+            | (mmio_read(base, MMIO_CONFIG + 4) as usize) << 32;
+        log!("driver":"virtio">"blk at 0x{:x}, version {}, {} blocks", base, version, blocks);
+        Some(Self {
+            base,
+            blocks,
+            queue: Mutex::new(VirtQueue {
+                frame,
+                avail_idx: 0,
+                used_idx: 0,
+            }),
+        })
+    }
+
+    // 提交一个请求并等待完成，数据通过队列页中的缓存区传递
+    fn request(&self, req_type: u32, block_id: usize, buf: &mut [u8]) {
+        assert!(block_id < self.blocks, "virtio-blk: block {} out of range", block_id);
+        let mut queue = self.queue.lock();
+        let page = queue.frame.page();
+        let pa = |offset: usize| page.offset_phys(offset);
+        let ptr = |offset: usize| pa(offset).0 as *mut u8;
+
+        if req_type == VIRTIO_BLK_T_OUT {
+            pa(DATA_OFFSET).write(&buf[..BLOCK_SIZE]);
+        }
+        unsafe {
+            write_volatile(
+                ptr(HEADER_OFFSET) as *mut BlkReqHeader,
+                BlkReqHeader {
+                    req_type,
+                    reserved: 0,
+                    sector: block_id as u64,
+                },
+            );
+            write_volatile(ptr(STATUS_OFFSET), 0xFF);
+        }
+
+        // 请求头 -> 数据 -> 状态
+        let data_flags = if req_type == VIRTIO_BLK_T_IN {
+            VIRTQ_DESC_F_WRITE
+        } else {
+            0
+        };
+        let descs = [
+            (HEADER_OFFSET, size_of::<BlkReqHeader>(), VIRTQ_DESC_F_NEXT),
+            (DATA_OFFSET, BLOCK_SIZE, data_flags | VIRTQ_DESC_F_NEXT),
+            (STATUS_OFFSET, 1, VIRTQ_DESC_F_WRITE),
+        ];
+        let desc_table = ptr(DESC_OFFSET) as *mut VirtqDesc;
+        for (i, &(offset, len, flags)) in descs.iter().enumerate() {
+            unsafe {
+                write_volatile(
+                    desc_table.add(i),
+                    VirtqDesc {
+                        addr: pa(offset).0 as u64,
+                        len: len as u32,
+                        flags,
+                        next: (i + 1) as u16,
+                    },
+                );
+            }
+        }
+
+        let avail = ptr(AVAIL_OFFSET) as *mut VirtqAvail;
+        let used = ptr(USED_OFFSET) as *const VirtqUsed;
+        unsafe {
+            let ring = &mut (*avail).ring[queue.avail_idx as usize % QUEUE_SIZE];
+            write_volatile(ring, 0);
+            io_fence();
+            queue.avail_idx = queue.avail_idx.wrapping_add(1);
+            write_volatile(&mut (*avail).idx, queue.avail_idx);
+            io_fence();
+            mmio_write(self.base, MMIO_QUEUE_NOTIFY, 0);
+
+            while read_volatile(&(*used).idx) == queue.used_idx {
+                core::hint::spin_loop();
+            }
+            io_fence();
+        }
+        queue.used_idx = queue.used_idx.wrapping_add(1);
+        // 没有开启设备中断，但仍然需要应答
+        let irq = mmio_read(self.base, MMIO_INTERRUPT_STATUS);
+        mmio_write(self.base, MMIO_INTERRUPT_ACK, irq);
+
+        let status = unsafe { read_volatile(ptr(STATUS_OFFSET)) };
+        assert!(
+            status == VIRTIO_BLK_S_OK,
+            "virtio-blk: request {} on block {} failed ({})",
+            req_type,
+            block_id,
+            status
+        );
+        if req_type == VIRTIO_BLK_T_IN {
+            pa(DATA_OFFSET).read(&mut buf[..BLOCK_SIZE]);
+        }
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn num_blocks(&self) -> usize {
+        self.blocks
+    }
+
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        self.request(VIRTIO_BLK_T_IN, block_id, buf);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let mut data = [0u8; BLOCK_SIZE];
+        data.copy_from_slice(&buf[..BLOCK_SIZE]);
+        self.request(VIRTIO_BLK_T_OUT, block_id, &mut data);
+    }
+}
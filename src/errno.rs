@@ -0,0 +1,125 @@
+/**
+ * Linux错误码
+ * 系统调用出错时返回-errno，与Linux riscv64的ABI一致，用户程序(libc)可以通过errno区分错误
+ */
+use crate::mm::{KallocErr, UserAccessErr};
+use crate::process::signal::SendErr;
+use crate::task::BlockErr;
+use crate::vfs::FileErr;
+
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(isize)]
+pub enum Errno {
+    EPERM = 1,
+    ENOENT = 2,
+    ESRCH = 3,
+    EINTR = 4,
+    EIO = 5,
+    ENXIO = 6,
+    E2BIG = 7,
+    ENOEXEC = 8,
+    EBADF = 9,
+    ECHILD = 10,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EACCES = 13,
+    EFAULT = 14,
+    EBUSY = 16,
+    EEXIST = 17,
+    EXDEV = 18,
+    ENODEV = 19,
+    ENOTDIR = 20,
+    EISDIR = 21,
+    EINVAL = 22,
+    ENFILE = 23,
+    EMFILE = 24,
+    ENOTTY = 25,
+    EFBIG = 27,
+    ENOSPC = 28,
+    ESPIPE = 29,
+    EROFS = 30,
+    EMLINK = 31,
+    EPIPE = 32,
+    ERANGE = 34,
+    ENAMETOOLONG = 36,
+    ENOSYS = 38,
+    ENOTEMPTY = 39,
+    ETIMEDOUT = 110,
+    // 只在内核中使用，被信号中断的系统调用在返回用户态前重新执行或者改为EINTR
+    ERESTARTSYS = 512,
+}
+
+// 系统调用的返回值，Err在写回a0时转换为-errno
+pub type SysResult = Result<usize, Errno>;
+
+impl Errno {
+    // 写入a0的返回值
+    pub fn as_ret(self) -> usize {
+        -(self as isize) as usize
+    }
+}
+
+// 将系统调用的结果转换为写入a0的值
+pub fn syscall_ret(ret: SysResult) -> usize {
+    match ret {
+        Ok(val) => val,
+        Err(errno) => errno.as_ret(),
+    }
+}
+
+impl From<FileErr> for Errno {
+    fn from(e: FileErr) -> Self {
+        match e {
+            FileErr::FileNotRead | FileErr::FileNotWrite => Errno::EBADF,
+            FileErr::FdInvalid => Errno::EBADF,
+            FileErr::InodeNotChild | FileErr::InodeDelete => Errno::ENOENT,
+            FileErr::InodeNotDir => Errno::ENOTDIR,
+            FileErr::InodeIsDir => Errno::EISDIR,
+            FileErr::DirNotEmpty => Errno::ENOTEMPTY,
+            FileErr::Busy => Errno::EBUSY,
+            FileErr::NoDevice => Errno::ENODEV,
+            FileErr::InodeChildExist => Errno::EEXIST,
+            FileErr::ReadWait | FileErr::WriteWait => Errno::EAGAIN,
+            FileErr::InvalidArgs => Errno::EINVAL,
+            FileErr::NameTooLong => Errno::ENAMETOOLONG,
+            FileErr::NoSpace => Errno::ENOSPC,
+            // 以下两个正常情况下由系统调用处理，不应该返回给用户
+            FileErr::FileEOF | FileErr::InodeEndOfDir => Errno::EINVAL,
+        }
+    }
+}
+
+impl From<UserAccessErr> for Errno {
+    fn from(e: UserAccessErr) -> Self {
+        match e {
+            UserAccessErr::BadAddr(_) => Errno::EFAULT,
+            UserAccessErr::TooLong => Errno::ENAMETOOLONG,
+        }
+    }
+}
+
+impl From<KallocErr> for Errno {
+    fn from(_: KallocErr) -> Self {
+        Errno::ENOMEM
+    }
+}
+
+impl From<BlockErr> for Errno {
+    fn from(e: BlockErr) -> Self {
+        match e {
+            BlockErr::Timeout => Errno::ETIMEDOUT,
+            BlockErr::Interrupted => Errno::EINTR,
+            BlockErr::Signal => Errno::ERESTARTSYS,
+        }
+    }
+}
+
+impl From<SendErr> for Errno {
+    fn from(e: SendErr) -> Self {
+        match e {
+            SendErr::NoProcess => Errno::ESRCH,
+            SendErr::QueueFull => Errno::EAGAIN,
+        }
+    }
+}
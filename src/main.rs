@@ -8,10 +8,11 @@
 #![feature(alloc_error_handler)]
 #![feature(ptr_to_from_bits)]
 #![feature(const_trait_impl)]
+#![feature(allocator_api)]
 
 use crate::{
     clock::clock_init,
-    process::cpu::{hart_enable_timer_interrupt, init_hart},
+    process::cpu::{hart_enable_ipi, hart_enable_timer_interrupt, init_hart},
 };
 use core::arch::asm;
 
@@ -23,6 +24,7 @@ mod sbi;
 mod console;
 
 mod entry;
+mod errno;
 mod heap;
 mod link_syms;
 mod mm;
@@ -33,6 +35,7 @@ mod user;
 
 mod clock;
 mod config;
+mod driver;
 mod vfs;
 
 #[macro_use]
@@ -77,15 +80,29 @@ extern "C" fn kernel_start() {
 
         mm::init();
 
-        init_hart();
+        vfs::init();
+
+        // 注册打包进内核的磁盘镜像，由init的启动脚本挂载
+        #[cfg(feature = "ramdisk")]
+        match driver::RamDisk::new(driver::DISK_IMAGE) {
+            Ok(disk) => driver::register_block_device("ram0", alloc::sync::Arc::new(disk)),
+            Err(e) => println!("load ramdisk failed: {:?}", e),
+        }
 
-        // Load shell
-        #[cfg(not(feature = "batch"))]
-        scheduler_load_pcb(MemorySpace::from_elf_memory(user::SHELL).unwrap());
+        // 注册virtio磁盘，通过块缓存读写
+        #[cfg(feature = "virtio")]
+        if let Some(blk) = driver::probe_virtio_blk() {
+            match driver::BlockCache::new(alloc::sync::Arc::new(blk), config::BLOCK_CACHE_SIZE) {
+                Ok(cache) => driver::register_block_device("vda", cache),
+                Err(e) => println!("alloc block cache failed: {:?}", e),
+            }
+        }
+
+        init_hart();
 
-        #[cfg(feature = "batch")]
-        for i in user::BATCH.iter() {
-            scheduler_load_pcb(MemorySpace::from_elf_memory(i).unwrap());
+        // 其余的进程都由init创建
+        if let Err(e) = scheduler_load_init(config::INIT_PATH) {
+            panic!("failed to load init {}: {:?}", config::INIT_PATH, e);
         }
 
         #[cfg(feature = "multicore")]
@@ -99,5 +116,6 @@ extern "C" fn kernel_start() {
     }
     trap::init();
     hart_enable_timer_interrupt();
+    hart_enable_ipi();
     schedule();
 }
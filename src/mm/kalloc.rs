@@ -1,8 +1,12 @@
 /**
- * Feature: 为分配的页面使用引用计数指针，避免内存泄漏
+ * 物理页面分配器
+ * 分配的页面由FrameTracker持有，FrameTracker被drop时自动释放页面，避免内存泄漏和重复释放。
+ * 每个物理页面有一个引用计数，clone FrameTracker会共享同一个页面(用于写时复制)
  */
 use super::address::*;
 use crate::config::PAGE_SIZE;
+use core::mem::size_of;
+use core::slice;
 use lazy_static::lazy_static;
 use spin::Mutex;
 
@@ -10,11 +14,40 @@ lazy_static! {
     pub static ref KALLOCATOR: Mutex<Kallocator> = Mutex::new(Kallocator::default());
 }
 
-pub struct Kallocator(usize);
+#[derive(Debug)]
+pub enum KallocErr {
+    // 没有空闲的物理页面
+    OutOfMemory,
+}
+
+// 物理页面使用情况
+#[derive(Clone, Copy, Debug)]
+pub struct KallocStats {
+    pub free: usize,
+    pub used: usize,
+}
+
+pub struct Kallocator {
+    // 空闲链表的第一个页
+    freelist: usize,
+    // 引用计数表对应的第一个物理页
+    base: usize,
+    // 每个物理页的引用计数，用于写时复制时共享物理页
+    refs: &'static mut [u16],
+    // 可分配的页面总数和空闲页面数
+    total: usize,
+    free: usize,
+}
 
 impl Default for Kallocator {
     fn default() -> Self {
-        Self(0)
+        Self {
+            freelist: 0,
+            base: 0,
+            refs: &mut [],
+            total: 0,
+            free: 0,
+        }
     }
 }
 
@@ -22,8 +55,17 @@ use core::ops::Range;
 impl Kallocator {
     pub fn init(&mut self, pages: Range<PageNum>) {
         log!("kalloc":"init">"0x{:x} - 0x{:x}", pages.start.page(), pages.end.page());
-        self.0 = pages.start.page();
-        for i in pages.start.page()..pages.end.page() {
+        // 使用最前面的几个页存放引用计数表
+        let nframes = pages.end.page() - pages.start.page();
+        let table_pages = (nframes * size_of::<u16>() + PAGE_SIZE - 1) / PAGE_SIZE;
+        let mut table = pages.start.offset_phys(0);
+        table.write_bytes(0, table_pages * PAGE_SIZE);
+        self.refs = unsafe { slice::from_raw_parts_mut(table.0 as *mut u16, nframes) };
+        self.base = pages.start.page();
+
+        let start = pages.start + table_pages;
+        self.freelist = start.page();
+        for i in start.page()..pages.end.page() {
             let mut pa: PhysAddr = Into::<PageNum>::into(i).into();
             let pa: &mut usize = pa.as_mut();
             *pa = i + 1;
@@ -31,28 +73,102 @@ impl Kallocator {
         let mut pa: PhysAddr = (pages.end - 1).into();
         let pa: &mut usize = pa.as_mut();
         *pa = 0;
+        self.total = pages.end.page() - start.page();
+        self.free = self.total;
     }
 
-    pub fn kalloc(&mut self) -> PageNum {
-        if self.0 == 0 {
-            panic!("run out of memory");
+    fn kalloc(&mut self) -> Result<PageNum, KallocErr> {
+        if self.freelist == 0 {
+            log!("kalloc":"kalloc""warn">"run out of memory");
+            return Err(KallocErr::OutOfMemory);
         }
-        let pa: PhysAddr = Into::<PageNum>::into(self.0).into();
+        let pa: PhysAddr = Into::<PageNum>::into(self.freelist).into();
         let pa: &usize = pa.as_ref();
-        let ret: PageNum = self.0.into();
-        self.0 = *pa;
-        // REMOVE
-        if self.0 == 0 {
-            log!("kalloc":"kalloc""warn">"the last page 0x{:x}", ret.page());
-        }
+        let ret: PageNum = self.freelist.into();
+        self.freelist = *pa;
+        self.free -= 1;
+        *self.refs_mut(ret) = 1;
         // clear page
         Into::<PhysAddr>::into(ret).write_bytes(0, PAGE_SIZE);
-        ret
+        Ok(ret)
+    }
+
+    // 增加页面的引用计数，页面由多个所有者共享
+    fn kdup(&mut self, page: PageNum) -> PageNum {
+        log!("kalloc":"kdup">"0x{:x}", page.page());
+        let refs = self.refs_mut(page);
+        assert!(*refs > 0, "dup free page 0x{:x}", page.page());
+        *refs += 1;
+        page
     }
 
-    pub fn kfree(&mut self, page: PageNum) {
+    // 减少页面的引用计数，引用计数为0时才放回空闲链表
+    fn kfree(&mut self, page: PageNum) {
         log!("kalloc":"kfree">"0x{:x}", page.page());
-        *(page.offset_phys(0).as_mut()) = self.0;
-        self.0 = page.page();
+        let refs = self.refs_mut(page);
+        assert!(*refs > 0, "double free page 0x{:x}", page.page());
+        *refs -= 1;
+        if *refs > 0 {
+            return;
+        }
+        *(page.offset_phys(0).as_mut()) = self.freelist;
+        self.freelist = page.page();
+        self.free += 1;
+    }
+
+    fn refs(&self, page: PageNum) -> usize {
+        self.refs[page.page() - self.base] as usize
+    }
+
+    fn refs_mut(&mut self, page: PageNum) -> &mut u16 {
+        &mut self.refs[page.page() - self.base]
+    }
+
+    pub fn stats(&self) -> KallocStats {
+        KallocStats {
+            free: self.free,
+            used: self.total - self.free,
+        }
     }
 }
+
+// 物理页面的句柄，clone时共享页面，drop时减少引用计数
+#[derive(Debug)]
+pub struct FrameTracker(PageNum);
+
+impl FrameTracker {
+    pub fn page(&self) -> PageNum {
+        self.0
+    }
+
+    // 共享该页面的FrameTracker个数
+    pub fn refs(&self) -> usize {
+        KALLOCATOR.lock().refs(self.0)
+    }
+}
+
+impl Clone for FrameTracker {
+    fn clone(&self) -> Self {
+        Self(KALLOCATOR.lock().kdup(self.0))
+    }
+}
+
+impl Drop for FrameTracker {
+    fn drop(&mut self) {
+        KALLOCATOR.lock().kfree(self.0);
+    }
+}
+
+// 分配一个清零的物理页面
+pub fn try_kalloc() -> Result<FrameTracker, KallocErr> {
+    KALLOCATOR.lock().kalloc().map(FrameTracker)
+}
+
+// 用于内核无法处理分配失败的地方，比如hart的页表
+pub fn kalloc() -> FrameTracker {
+    try_kalloc().unwrap_or_else(|_| panic!("run out of memory"))
+}
+
+pub fn kalloc_stats() -> KallocStats {
+    KALLOCATOR.lock().stats()
+}
@@ -1,8 +1,10 @@
 use super::address::*;
+use super::kalloc::*;
 use super::PTEFlag;
-use super::KALLOCATOR;
 use crate::config::*;
-use crate::process::TrapFrame;
+use crate::errno::Errno;
+use crate::process::cpu::{hartid, wait_user_harts};
+use crate::process::futex::futex_migrate;
 use crate::vfs::*;
 use alloc::collections::BTreeMap;
 use alloc::vec;
@@ -15,24 +17,28 @@ use core::ops::IndexMut;
 use core::ops::Range;
 use core::slice;
 
-pub type Segments = BTreeMap<PageNum, (PageNum, PTEFlag)>;
+pub type Segments = BTreeMap<PageNum, (FrameTracker, PTEFlag)>;
 
-// 表示进程的内存空间, 包括代码和数据段、一个用于上下文切换的trapframe页、用户栈、堆指针和堆内存
+// 表示进程的内存空间, 包括代码和数据段、用户栈、堆指针和堆内存，同一个进程的线程共享
 pub struct MemorySpace {
     // 进程的入口
     entry: usize,
     // 保存数据段和代码段、堆内存等映射信息
     pub segments: Segments,
-    // 用于上下文切换的trapframe
-    pub trapframe: PageNum,
     // 用户栈的物理页面，目前用户态的栈大小为一个页面
-    pub user_stack: PageNum,
+    pub user_stack: FrameTracker,
+    // 用户栈的映射权限，fork后可能为写时复制
+    pub user_stack_flags: PTEFlag,
     // 进程的programe_break指针，用于分配堆内存
     pub prog_break: VirtualAddr,
     // 堆内存映射的最高的一个页面
     prog_high_page: PageNum,
     // mmap 区域
     pub mmap_areas: MmapAreas,
+    // 映射了这个内存空间的hart，每个hart有自己的页表，修改映射时需要通知其他hart
+    harts: usize,
+    // 其他hart的映射需要更新时加一，hart返回用户态前检查映射是否过期
+    generation: usize,
 }
 
 pub struct MmapAreas {
@@ -59,7 +65,7 @@ bitflags! {
 }
 pub struct MmapPage {
     pub vpage: PageNum,
-    pub ppage: Option<PageNum>,
+    pub ppage: Option<FrameTracker>,
     pub inode: Option<Inode>,
     pub offset: usize,
     pub length: usize,
@@ -68,18 +74,19 @@ pub struct MmapPage {
 }
 
 impl MemorySpace {
-    pub fn new() -> Self {
-        let tf = KALLOCATOR.lock().kalloc();
-        let stack = KALLOCATOR.lock().kalloc();
-        Self {
+    pub fn new() -> Result<Self, KallocErr> {
+        let stack = try_kalloc()?;
+        Ok(Self {
             entry: 0,
             segments: BTreeMap::new(),
-            trapframe: tf,
             user_stack: stack,
+            user_stack_flags: PTEFlag::R | PTEFlag::W | PTEFlag::U,
             prog_break: VirtualAddr(0),
             prog_high_page: PageNum(0),
             mmap_areas: MmapAreas::new(),
-        }
+            harts: 0,
+            generation: 0,
+        })
     }
 
     fn init_prog_break(&mut self) {
@@ -112,12 +119,17 @@ impl MemorySpace {
                     self.prog_high_page.offset(0).0
                 );
             }
+            let frame = match try_kalloc() {
+                Ok(frame) => frame,
+                Err(e) => {
+                    // 分配失败时不移动program break
+                    log!("mmap":"brk">"{:?}", e);
+                    return retva;
+                }
+            };
             self.segments.insert(
                 self.prog_high_page + 1,
-                (
-                    KALLOCATOR.lock().kalloc(),
-                    PTEFlag::R | PTEFlag::W | PTEFlag::U,
-                ),
+                (frame, PTEFlag::R | PTEFlag::W | PTEFlag::U),
             );
             self.prog_high_page = self.prog_high_page + 1;
         }
@@ -125,10 +137,8 @@ impl MemorySpace {
         retva
     }
 
-
-    pub fn trapframe(&mut self) -> &mut TrapFrame {
-        let phys = self.trapframe.offset_phys(0).0;
-        unsafe { <*mut TrapFrame>::from_bits(phys).as_mut().unwrap() }
+    pub fn entry(&self) -> usize {
+        self.entry
     }
 
     pub fn get_stack_sp() -> VirtualAddr {
@@ -139,49 +149,121 @@ impl MemorySpace {
         VirtualAddr(USER_STACK_PAGE)
     }
 
-    /*
-    pub fn copy_from_user(&mut self, src: VirtualAddr,  dst: &mut [u8]) {
-        let pte = current_hart_pgtbl().walk(src, false);
-        if !pte.is_valid() {
-            panic!("")
+    // 复制一个内存空间，用于fork
+    // 父子进程共享物理页面，可写的页面标记为写时复制，在第一次写入时才复制。
+    pub fn copy(&mut self) -> Result<Self, KallocErr> {
+        let mut segments = Segments::new();
+        for (vpage, (frame, flags)) in self.segments.iter_mut() {
+            Self::mark_cow(flags);
+            segments.insert(*vpage, (frame.clone(), *flags));
         }
-        log!(debug "Copy 0x{:x} from user 0x{:x} {:?}", src.0, pte.ppn().offset(src.page_offset()).0, pte.flags());
-        let pa = PhysAddr(src.0);
-        pa.read(unsafe { core::slice::from_raw_parts_mut(dst.as_mut_ptr(), dst.len()) });
+        Self::mark_cow(&mut self.user_stack_flags);
+        // 同一进程的其他线程可能还在用可写的映射，写入会出现在子进程的页面中
+        self.shootdown();
+        Ok(Self {
+            entry: self.entry,
+            segments,
+            user_stack: self.user_stack.clone(),
+            user_stack_flags: self.user_stack_flags,
+            prog_break: self.prog_break,
+            prog_high_page: self.prog_high_page,
+            mmap_areas: MmapAreas::new(),
+            harts: 0,
+            generation: 0,
+        })
     }
 
-    pub fn copy_to_user(&mut self, dst: VirtualAddr, src: &[u8]) {
-        let pte = current_hart_pgtbl().walk(dst, false);
-        if !pte.is_valid() {
-            panic!("")
+    // 将可写页面的权限改为只读的写时复制
+    fn mark_cow(flags: &mut PTEFlag) {
+        if flags.contains(PTEFlag::W) {
+            flags.remove(PTEFlag::W);
+            flags.insert(PTEFlag::COW);
         }
-        log!(debug "Copy 0x{:x} to user 0x{:x} {:?}", dst.0, pte.ppn().offset(dst.page_offset()).0, pte.flags());
-        let mut dst = PhysAddr(dst.0);
-        let dst = dst.as_slice_mut(src.len());
-        dst.copy_from_slice(src);
     }
-    */
 
-    // 完全复制一个内存空间，分配新的物理页面，将原页面的内容复制到新页面。用于fork
-    pub fn copy(&self) -> Self {
-        let mut mem = MemorySpace::new();
-        mem.entry = self.entry;
-        for (vpage, (page, flags)) in self.segments().iter() {
-            let newpage = KALLOCATOR.lock().kalloc();
-            let mut phys = newpage.offset_phys(0);
-            phys.write(page.offset_phys(0).as_slice(PAGE_SIZE));
-            mem.segments.insert(*vpage, (newpage, *flags));
-        }
-        let newpage = KALLOCATOR.lock().kalloc();
-        let mut phys = newpage.offset_phys(0);
-        phys.write(self.user_stack.offset_phys(0).as_slice(PAGE_SIZE));
-        mem.user_stack = newpage;
+    /**
+     * 多个hart上的映射
+     */
+    // hart映射或取消映射这个内存空间时调用
+    pub fn hart_map(&mut self, hart: usize) {
+        self.harts |= 1 << hart;
+    }
 
-        let newpage = KALLOCATOR.lock().kalloc();
-        let mut phys = newpage.offset_phys(0);
-        phys.write(self.trapframe.offset_phys(0).as_slice(PAGE_SIZE));
-        mem.trapframe = newpage;
-        mem
+    pub fn hart_unmap(&mut self, hart: usize) {
+        self.harts &= !(1 << hart);
+    }
+
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    // 减少权限或更换物理页面之后，让其他hart上的映射失效
+    //     正在运行用户态的hart收到IPI后陷入，经过current_hart_run重新映射；其他hart在返回用户态前
+    //     (见trap::user_return)发现generation改变，同样重新映射
+    //     持有MemorySpace的锁时等待，hart只能在持有这个锁时进入用户态，等待期间不会有新的hart使用旧的映射
+    fn shootdown(&mut self) {
+        self.generation += 1;
+        let others = self.harts & !(1 << hartid());
+        wait_user_harts(others);
+    }
+
+    // 内存空间的标识，用作私有futex的键
+    //     MemorySpace在Arc中，地址不会改变
+    pub fn id(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    // va所在的页面在MemorySpace中并且有perm权限
+    //     同一个进程的其他线程修改MemorySpace(比如brk、写时复制)后，当前hart的页表可能还是旧的映射
+    pub fn is_mapped(&self, va: VirtualAddr, perm: PTEFlag) -> bool {
+        let vpage = va.floor();
+        let flags = if vpage == Self::get_stack_start().floor() {
+            self.user_stack_flags
+        } else if let Some((_, flags)) = self.segments.get(&vpage) {
+            *flags
+        } else {
+            return false;
+        };
+        flags.contains(perm | PTEFlag::U)
+    }
+
+    // 处理对写时复制页面的写入，返回va所在页面新的映射
+    // 若物理页面仍被其他进程共享则复制一个新页面，否则直接恢复写权限
+    pub fn copy_on_write(&mut self, va: VirtualAddr) -> Result<(PageNum, PTEFlag), ()> {
+        let id = self.id();
+        let mut replaced = false;
+        let vpage = va.floor();
+        let (frame, flags) = if vpage == Self::get_stack_start().floor() {
+            (&mut self.user_stack, &mut self.user_stack_flags)
+        } else if let Some((frame, flags)) = self.segments.get_mut(&vpage) {
+            (frame, flags)
+        } else {
+            return Err(());
+        };
+        if !flags.contains(PTEFlag::COW) {
+            return Err(());
+        }
+        if frame.refs() > 1 {
+            let newframe = try_kalloc().map_err(|_| ())?;
+            newframe
+                .page()
+                .offset_phys(0)
+                .write(frame.page().offset_phys(0).as_slice(PAGE_SIZE));
+            log!("cow":"copy">"vpage 0x{:x}: 0x{:x} -> 0x{:x}", vpage.page(), frame.page().page(), newframe.page().page());
+            // 这个内存空间中等待共享futex的线程改为在新页面上等待
+            futex_migrate(id, frame.page(), newframe.page());
+            // 旧的页面引用计数减一
+            *frame = newframe;
+            replaced = true;
+        }
+        flags.remove(PTEFlag::COW);
+        flags.insert(PTEFlag::W);
+        let mapping = (frame.page(), *flags);
+        // 其他线程还映射着旧的页面，之后会读到旧的值
+        if replaced {
+            self.shootdown();
+        }
+        Ok(mapping)
     }
 
     // 从elf中加载MemorySpace, ELF存储于data中
@@ -191,7 +273,7 @@ impl MemorySpace {
             return Err(());
         }
         let elf = elf.unwrap();
-        let mut ms = Self::new();
+        let mut ms = Self::new().map_err(|_| ())?;
         for phdr in elf.phdr_iter() {
             let start_va = VirtualAddr(phdr.p_vaddr as usize);
             let end_va = VirtualAddr((phdr.p_vaddr + phdr.p_memsz) as usize);
@@ -200,27 +282,26 @@ impl MemorySpace {
                 start_va..end_va,
                 map_perm | PTEFlag::V,
                 &data[phdr.p_offset as usize..(phdr.p_offset + phdr.p_filesz) as usize],
-            );
+            )
+            .map_err(|_| ())?;
         }
         ms.set_entry_point(elf.entry_point() as usize);
-        let sp = Self::get_stack_sp().0;
-        ms.trapframe().init(sp, elf.entry_point() as usize);
         return Ok(ms);
     }
 
     // 从elf中加载MemorySpace, ELF为Inode对于的文件
     // 按需读取文件，不需要将文件全部读入内存
-    pub fn from_elf_inode(inode: Inode) -> Result<Self, FileErr> {
+    pub fn from_elf_inode(inode: Inode) -> Result<Self, Errno> {
         let ehdr_size = size_of::<elf_parser::Elf64Ehdr>();
         let mut elf = vec![0; ehdr_size];
         if let Ok(_) = inode.read_offset(0, elf.as_mut_slice()) {
             let elf = elf_parser::Elf64::from_bytes(elf.as_slice());
             if let Err(e) = elf {
                 println!("{:?}", e);
-                return Err(FileErr::NotDefine);
+                return Err(Errno::ENOEXEC);
             }
             let elf = elf.unwrap();
-            let mut ms = Self::new();
+            let mut ms = Self::new()?;
             // map programe
             for i in 0..elf.phdr_num() {
                 let inode_offset = elf.ehdr().e_phoff + i as u64 * elf.ehdr().e_phentsize as u64;
@@ -241,14 +322,12 @@ impl MemorySpace {
                     start_va..end_va,
                     map_perm | PTEFlag::V,
                     data.as_slice(),
-                );
+                )?;
             }
             ms.set_entry_point(elf.entry_point() as usize);
-            let sp = Self::get_stack_sp().0;
-            ms.trapframe().init(sp, elf.entry_point() as usize);
             return Ok(ms);
         }
-        Err(FileErr::NotDefine)
+        Err(Errno::ENOEXEC)
     }
 
     pub fn segments(&self) -> &Segments {
@@ -278,7 +357,12 @@ impl MemorySpace {
     */
 
     // 将data中的数据映射到area
-    fn add_area_data_each_byte(&mut self, area: Range<VirtualAddr>, flags: PTEFlag, data: &[u8]) {
+    fn add_area_data_each_byte(
+        &mut self,
+        area: Range<VirtualAddr>,
+        flags: PTEFlag,
+        data: &[u8],
+    ) -> Result<(), KallocErr> {
         let mut start = area.start;
         let end = area.end;
         let start_page = start.floor();
@@ -290,11 +374,12 @@ impl MemorySpace {
             let page;
             if self.segments.contains_key(&vpage) {
                 // 多个段可能在同一页，将所有段的flags 或
-                page = self.segments[&vpage].0;
+                page = self.segments[&vpage].0.page();
                 self.segments.get_mut(&vpage).unwrap().1 |= flags;
             } else {
-                page = KALLOCATOR.lock().kalloc();
-                self.segments.insert(vpage, (page, flags));
+                let frame = try_kalloc()?;
+                page = frame.page();
+                self.segments.insert(vpage, (frame, flags));
             }
             let size = min(PAGE_SIZE - start.page_offset(), total - wroten);
             if size == 0 {
@@ -305,6 +390,7 @@ impl MemorySpace {
             wroten += size;
             start = start + size;
         }
+        Ok(())
     }
 
     // Helper functions
@@ -372,21 +458,11 @@ impl MemorySpace {
     }
 }
 
-impl Drop for MemorySpace {
-    fn drop(&mut self) {
-        KALLOCATOR.lock().kfree(self.user_stack);
-        KALLOCATOR.lock().kfree(self.trapframe);
-        for (_, (page, _)) in self.segments.iter() {
-            KALLOCATOR.lock().kfree(*page);
-        }
-    }
-}
-
 impl MmapAreas {
     pub fn new() -> Self {
         Self {
             mmap_pages: Vec::new(),
-            lowest_page: VirtualAddr(USER_STACK_PAGE - PAGE_SIZE).floor(),
+            lowest_page: VirtualAddr(SIGRETURN_TRAMPOLINE).floor(),
         }
     }
 
@@ -458,20 +534,20 @@ impl MmapPage {
         if !self.prot.contains(prot) {
             return Err(());
         }
-        if let Some(ppage) = self.ppage {
-            return Ok(ppage);
+        if let Some(ref ppage) = self.ppage {
+            return Ok(ppage.page());
         } else {
             // 保证存在Inode，因为ANONYMOUS映射时会直接分配内存
             let inode = self.inode.clone().unwrap();
-            let ppage = KALLOCATOR.lock().kalloc();
-            let mut phys = ppage.offset_phys(0);
+            let ppage = try_kalloc().map_err(|_| ())?;
+            let mut phys = ppage.page().offset_phys(0);
             let mut buf: &mut [u8] = phys.as_slice_mut(self.length);
             if inode.read_offset(self.offset, &mut buf).is_err() {
-                KALLOCATOR.lock().kfree(ppage);
                 return Err(());
             }
+            let page = ppage.page();
             self.ppage = Some(ppage);
-            return Ok(ppage);
+            return Ok(page);
         }
     }
 
@@ -492,12 +568,12 @@ impl MmapPage {
 
 impl Drop for MmapPage {
     fn drop(&mut self) {
-        if let Some(ppage) = self.ppage {
+        if let Some(ref ppage) = self.ppage {
             // 写回文件
             if self.flags.contains(MapFlags::SHARED) && self.prot.contains(MapProt::WRITE) {
                 // todo: 只在脏时写回
                 if let Some(ref inode) = self.inode {
-                    let buf = ppage.offset_phys(0);
+                    let buf = ppage.page().offset_phys(0);
                     let buf = buf.as_slice(self.length);
                     match inode.write_offset(self.offset, buf) {
                         Ok(_) => {
@@ -509,7 +585,6 @@ impl Drop for MmapPage {
                     }
                 }
             }
-            KALLOCATOR.lock().kfree(ppage);
         }
     }
 }
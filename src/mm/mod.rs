@@ -3,6 +3,7 @@ pub mod kalloc;
 pub mod memory_space;
 pub mod pgtbl;
 pub mod pte_sv39;
+pub mod user_access;
 
 use crate::config::*;
 use crate::link_syms;
@@ -15,6 +16,7 @@ pub use kalloc::*;
 pub use memory_space::*;
 pub use pgtbl::*;
 pub use pte_sv39::*;
+pub use user_access::*;
 
 pub fn init() {
     // phys_frame::init();
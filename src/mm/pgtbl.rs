@@ -2,20 +2,27 @@ use super::address::*;
 use super::pte_sv39::{PTEFlag, PTE};
 use crate::config::*;
 use crate::mm::memory_space::Segments;
+use alloc::vec;
+use alloc::vec::Vec;
 use core::mem::size_of;
 use core::ops::Range;
 
-use super::kalloc::KALLOCATOR;
+use super::kalloc::{kalloc, FrameTracker};
 
 pub struct Pgtbl {
     pub root: PageNum,
+    // 页表本身使用的页面(包括根页表)，随页表一起释放
+    frames: Vec<FrameTracker>,
 }
 
 impl Pgtbl {
     pub fn new() -> Self {
-        let page = KALLOCATOR.lock().kalloc();
-        log!("pgtbl":"new">"page(0x{:x})", page.page());
-        Self { root: page }
+        let frame = kalloc();
+        log!("pgtbl":"new">"page(0x{:x})", frame.page().page());
+        Self {
+            root: frame.page(),
+            frames: vec![frame],
+        }
     }
 
     pub fn walk(&mut self, va: VirtualAddr, do_alloc: bool) -> &mut PTE {
@@ -40,8 +47,9 @@ impl Pgtbl {
                 ppn = pte.ppn();
             } else {
                 if do_alloc {
-                    let page = KALLOCATOR.lock().kalloc();
-                    page.offset_phys(0).write_bytes(0, PAGE_SIZE);
+                    let frame = kalloc();
+                    let page = frame.page();
+                    self.frames.push(frame);
                     pte.set_ppn(page);
                     pte.set_flags(PTEFlag::V);
                     ppn = page;
@@ -80,79 +88,21 @@ impl Pgtbl {
         pte.set_flags(flags | PTEFlag::V);
     }
 
-    fn _unmap_page_table(ppn: PageNum, addr: usize) {
-        for idx in 0..(PAGE_SIZE / size_of::<usize>()) {
-            let mut physpte = ppn.offset_phys(idx * size_of::<usize>());
-            let pte: &mut PTE = physpte.as_mut();
-            if pte.is_valid() {
-                if pte.is_leaf() {
-                    log!("pgtbl":"_unmap_page_table""warn"> "unmaping valid leaf page 0x{:x}", ((addr << SV39_VPN_BIT) + idx) << PAGE_OFFSET_BIT);
-                } else {
-                    Self::_unmap_page_table(pte.ppn(), (addr << SV39_VPN_BIT) + idx);
-                }
-            }
-        }
-        KALLOCATOR.lock().kfree(ppn);
-    }
-
     #[allow(unused)]
-    pub fn unmap_page_table(&mut self) {
-        Self::_unmap_page_table(self.root, 0);
-    }
-
-    #[allow(unused)]
-    pub fn unmap_pages(&mut self, vpages: Range<PageNum>, do_free: bool) {
+    pub fn unmap_pages(&mut self, vpages: Range<PageNum>) {
         for page in vpages.start.page()..vpages.end.page() {
-            self.unmap(page.into(), do_free);
+            self.unmap(page.into());
         }
     }
 
     // 不会报错当尝试两次unmap同一个页，因为memory_space的unmap_segments需要
-    pub fn unmap(&mut self, vpage: PageNum, do_free: bool) {
+    // 页表不持有映射的页面，页面由其所有者(FrameTracker)释放
+    pub fn unmap(&mut self, vpage: PageNum) {
         // Fixme: when unmap an invalid page
         let pte = self.walk(vpage.offset(0), false);
-        if do_free && pte.is_valid() {
-            KALLOCATOR.lock().kfree(pte.ppn());
-        }
         pte.set_flags(PTEFlag::empty());
     }
 
-    #[allow(unused)]
-    fn copy_page(from: PageNum, to: PageNum, alloc_mem: bool) {
-        // Fixme: 不用搜索整个空间，只复制用户空间和trapframe,trampoline
-        for idx in 0..(PAGE_SIZE / size_of::<usize>()) {
-            let mut physpte = from.offset_phys(idx * size_of::<usize>());
-            let pte: &mut PTE = physpte.as_mut();
-            if pte.is_valid() {
-                let mut child_phys = to.offset_phys(idx * size_of::<usize>());
-                let child_pte: &mut PTE = child_phys.as_mut();
-                child_pte.set_flags(pte.flags());
-
-                if pte.is_leaf() {
-                    if alloc_mem {
-                        let child_page = KALLOCATOR.lock().kalloc();
-                        child_pte.set_ppn(child_page);
-                        child_page
-                            .offset_phys(0)
-                            .write(pte.ppn().offset_phys(0).as_slice(PAGE_SIZE));
-                    } else {
-                        child_pte.set_ppn(pte.ppn());
-                    }
-                } else {
-                    child_pte.set_ppn(KALLOCATOR.lock().kalloc());
-                    Self::copy_page(pte.ppn(), child_pte.ppn(), alloc_mem)
-                }
-            }
-        }
-    }
-
-    #[allow(unused)]
-    pub fn copy(&self, alloc_mem: bool) -> Self {
-        let child = Pgtbl::new();
-        Self::copy_page(self.root, child.root, alloc_mem);
-        child
-    }
-
     pub fn get_satp(&self) -> usize {
         self.root.page() | 0x8000000000000000
     }
@@ -181,24 +131,18 @@ impl Pgtbl {
         self._print(self.root, 0, 1);
     }
 
-    pub fn unmap_segments(&mut self, segments: &Segments, do_free: bool) {
-        for (virt, (_, _)) in segments.iter() {
+    pub fn unmap_segments(&mut self, segments: &Segments) {
+        for (virt, (frame, _)) in segments.iter() {
             log!(debug "unmap seg 0x{:x}", virt.page());
-            log!("pgtbl":"unmap_segments"> "vpage 0x{:x} -> 0x{:x}", virt.page(), phys.page());
-            self.unmap(*virt, do_free);
+            log!("pgtbl":"unmap_segments"> "vpage 0x{:x} -> 0x{:x}", virt.page(), frame.page().page());
+            self.unmap(*virt);
         }
     }
 
     pub fn map_segments(&mut self, segments: &Segments) {
-        for (virt, (phys, flags)) in segments.iter() {
-            log!("pgtbl":"map_segments">"vpage 0x{:x} -> 0x{:x} ({:?})", virt.page(), phys.page(), flags);
-            self.map(*virt, *phys, *flags);
+        for (virt, (frame, flags)) in segments.iter() {
+            log!("pgtbl":"map_segments">"vpage 0x{:x} -> 0x{:x} ({:?})", virt.page(), frame.page().page(), flags);
+            self.map(*virt, frame.page(), *flags);
         }
     }
 }
-
-impl Drop for Pgtbl {
-    fn drop(&mut self) {
-        panic!("freeing page table");
-    }
-}
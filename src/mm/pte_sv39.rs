@@ -39,12 +39,13 @@ impl PTE {
         self.0 = (page_num.page() << PTE_PPN_OFFSET) | (self.0 % (1 << PTE_PPN_OFFSET));
     }
 
+    // flags包括RSW位，RSW用于记录软件定义的标志(如写时复制)
     pub fn flags(&self) -> PTEFlag {
-        PTEFlag::from_bits(self.0 & ((1 << PTE_FLAG_SIZE) - 1)).unwrap()
+        PTEFlag::from_bits(self.0 & ((1 << PTE_PPN_OFFSET) - 1)).unwrap()
     }
 
     pub fn set_flags(&mut self, flags: PTEFlag) {
-        self.0 = (self.0 >> PTE_FLAG_SIZE << PTE_FLAG_SIZE) | flags.bits() as usize
+        self.0 = (self.0 >> PTE_PPN_OFFSET << PTE_PPN_OFFSET) | flags.bits() as usize
     }
 }
 
@@ -62,6 +63,8 @@ const PTE_FLAG_U: usize = 4;
 const PTE_FLAG_G: usize = 5;
 const PTE_FLAG_A: usize = 6;
 const PTE_FLAG_D: usize = 7;
+// RSW: 硬件忽略，由内核使用
+const PTE_FLAG_COW: usize = PTE_FLAG_SIZE;
 
 bitflags! {
     pub struct PTEFlag: usize {
@@ -73,5 +76,7 @@ bitflags! {
         const G = 1 << PTE_FLAG_G ;
         const A = 1 << PTE_FLAG_A ;
         const D = 1 << PTE_FLAG_D ;
+        // 写时复制页面，原本可写
+        const COW = 1 << PTE_FLAG_COW;
     }
 }
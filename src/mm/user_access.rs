@@ -0,0 +1,200 @@
+/**
+ * 内核访问用户内存
+ * 系统调用中所有用户传入的指针都需要经过这里检查，地址无效时返回错误而不是让内核出错
+ */
+use super::address::*;
+use super::memory_space::*;
+use super::PTEFlag;
+use crate::config::*;
+use crate::process::cpu::current_hart_pgtbl;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::cmp::min;
+use core::mem::{size_of, MaybeUninit};
+use core::slice;
+
+#[derive(Debug)]
+pub enum UserAccessErr {
+    // 地址没有映射或者没有相应的权限
+    BadAddr(usize),
+    // 字符串或数组超过长度限制
+    TooLong,
+}
+
+impl MemorySpace {
+    // 查找用户虚拟地址所在的物理页面，并检查U和读写权限
+    // 写时复制页面会先复制，lazy映射的mmap页面会先分配，两者都会更新当前hart的页表
+    fn translate_user(&mut self, va: VirtualAddr, write: bool) -> Result<PageNum, UserAccessErr> {
+        let vpage = va.floor();
+        if write {
+            if let Ok((page, flags)) = self.copy_on_write(va) {
+                Self::map_current(vpage, page, flags);
+            }
+        }
+        let (page, flags) = if vpage == Self::get_stack_start().floor() {
+            (self.user_stack.page(), self.user_stack_flags)
+        } else if let Some((frame, flags)) = self.segments.get(&vpage) {
+            (frame.page(), *flags)
+        } else {
+            let prot = if write { MapProt::WRITE } else { MapProt::READ };
+            let page = self
+                .mmap_areas
+                .check_lazy(va, prot)
+                .map_err(|_| UserAccessErr::BadAddr(va.0))?;
+            let flags = self
+                .mmap_areas
+                .pages()
+                .find(|mappage| mappage.vpage == vpage)
+                .map(|mappage| mappage.get_pte_flags() | PTEFlag::U)
+                .unwrap();
+            Self::map_current(vpage, page, flags);
+            (page, flags)
+        };
+        let perm = if write { PTEFlag::W } else { PTEFlag::R };
+        if !flags.contains(PTEFlag::U | perm) {
+            log!("uaccess":"translate">"bad addr 0x{:x} ({:?})", va.0, flags);
+            return Err(UserAccessErr::BadAddr(va.0));
+        }
+        Ok(page)
+    }
+
+    // 系统调用期间MemorySpace已经映射在当前hart的页表中，更新映射使用户态和内核态看到同一个页面
+    fn map_current(vpage: PageNum, page: PageNum, flags: PTEFlag) {
+        current_hart_pgtbl().map(vpage, page, flags);
+        unsafe {
+            asm!("sfence.vma");
+        }
+    }
+
+    // 检查[start, start + len)每个页面都可以访问
+    fn check_user_range(
+        &mut self,
+        start: VirtualAddr,
+        len: usize,
+        write: bool,
+    ) -> Result<(), UserAccessErr> {
+        let end = start
+            .0
+            .checked_add(len)
+            .ok_or(UserAccessErr::BadAddr(start.0))?;
+        for vpage in start.floor().page()..VirtualAddr(end).ceil().page() {
+            let va = if vpage == start.floor().page() {
+                start
+            } else {
+                PageNum(vpage).offset(0)
+            };
+            self.translate_user(va, write)?;
+        }
+        Ok(())
+    }
+
+    pub fn copy_from_user(&mut self, src: VirtualAddr, dst: &mut [u8]) -> Result<(), UserAccessErr> {
+        src.0
+            .checked_add(dst.len())
+            .ok_or(UserAccessErr::BadAddr(src.0))?;
+        let mut copied = 0;
+        while copied < dst.len() {
+            let va = src + copied;
+            let page = self.translate_user(va, false)?;
+            let size = min(PAGE_SIZE - va.page_offset(), dst.len() - copied);
+            page.offset_phys(va.page_offset())
+                .read(&mut dst[copied..copied + size]);
+            copied += size;
+        }
+        Ok(())
+    }
+
+    pub fn copy_to_user(&mut self, dst: VirtualAddr, src: &[u8]) -> Result<(), UserAccessErr> {
+        dst.0
+            .checked_add(src.len())
+            .ok_or(UserAccessErr::BadAddr(dst.0))?;
+        let mut copied = 0;
+        while copied < src.len() {
+            let va = dst + copied;
+            let page = self.translate_user(va, true)?;
+            let size = min(PAGE_SIZE - va.page_offset(), src.len() - copied);
+            page.offset_phys(va.page_offset())
+                .write(&src[copied..copied + size]);
+            copied += size;
+        }
+        Ok(())
+    }
+
+    // 用户地址对应的物理地址，用于futex等需要跨进程识别同一内存的场合
+    //     写时复制页面会先复制，之后物理地址不会因为写入而改变
+    pub fn user_paddr(&mut self, va: VirtualAddr) -> Result<PhysAddr, UserAccessErr> {
+        let page = self.translate_user(va, true)?;
+        Ok(page.offset_phys(va.page_offset()))
+    }
+
+    // 从用户内存读出一个repr(C)的值
+    pub fn read_user<T: Copy>(&mut self, src: VirtualAddr) -> Result<T, UserAccessErr> {
+        let mut val = MaybeUninit::<T>::uninit();
+        let buf = unsafe { slice::from_raw_parts_mut(val.as_mut_ptr() as *mut u8, size_of::<T>()) };
+        self.copy_from_user(src, buf)?;
+        Ok(unsafe { val.assume_init() })
+    }
+
+    // 将一个repr(C)的值写入用户内存
+    pub fn write_user<T>(&mut self, dst: VirtualAddr, val: &T) -> Result<(), UserAccessErr> {
+        let buf = unsafe { slice::from_raw_parts(val as *const T as *const u8, size_of::<T>()) };
+        self.copy_to_user(dst, buf)
+    }
+
+    // 读取以'\0'结尾的字符串，返回的字符串不包括'\0'
+    pub fn read_user_str(&mut self, src: VirtualAddr) -> Result<String, UserAccessErr> {
+        let mut bytes = Vec::new();
+        // Note: 将从用户空间传入的字符串大小作一个限制
+        while bytes.len() < PATH_LIMITS {
+            let va = src
+                .0
+                .checked_add(bytes.len())
+                .ok_or(UserAccessErr::BadAddr(src.0))?;
+            let va = VirtualAddr(va);
+            let page = self.translate_user(va, false)?;
+            let len = PAGE_SIZE - va.page_offset();
+            for &ch in page.offset_phys(va.page_offset()).as_slice(len) {
+                if ch == 0 {
+                    return Ok(String::from_utf8_lossy(&bytes).into_owned());
+                }
+                bytes.push(ch);
+            }
+        }
+        Err(UserAccessErr::TooLong)
+    }
+
+    // 读取以0结尾的usize数组(比如execve的argv)，返回的数组不包括结尾的0
+    pub fn read_user_usize_array(&mut self, src: VirtualAddr) -> Result<Vec<usize>, UserAccessErr> {
+        let mut array = Vec::new();
+        // 数组需要能放入用户栈
+        while array.len() < USER_STACK_SIZE / size_of::<usize>() {
+            let va = src
+                .0
+                .checked_add(array.len() * size_of::<usize>())
+                .ok_or(UserAccessErr::BadAddr(src.0))?;
+            let item: usize = self.read_user(VirtualAddr(va))?;
+            if item == 0 {
+                return Ok(array);
+            }
+            array.push(item);
+        }
+        Err(UserAccessErr::TooLong)
+    }
+
+    // 检查后返回用户缓冲区，用于read/write等需要直接读写大块用户内存的系统调用
+    // Note: 返回的切片直接指向用户地址，只能在进程的MemorySpace映射在当前hart时使用
+    pub fn user_buf<'a>(&mut self, start: VirtualAddr, len: usize) -> Result<&'a [u8], UserAccessErr> {
+        self.check_user_range(start, len, false)?;
+        Ok(unsafe { slice::from_raw_parts(start.0 as *const u8, len) })
+    }
+
+    pub fn user_buf_mut<'a>(
+        &mut self,
+        start: VirtualAddr,
+        len: usize,
+    ) -> Result<&'a mut [u8], UserAccessErr> {
+        self.check_user_range(start, len, true)?;
+        Ok(unsafe { slice::from_raw_parts_mut(start.0 as *mut u8, len) })
+    }
+}
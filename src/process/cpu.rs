@@ -1,17 +1,17 @@
 use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use spin::Mutex;
 
-use super::Pcb;
+use super::{Pcb, Pid};
 use crate::asm;
 use crate::config::*;
-use crate::link_syms;
-use crate::mm::address::PhysAddr;
 use crate::mm::pgtbl::Pgtbl;
 use crate::mm::*;
 use crate::sbi::*;
+use crate::task::{TaskContext, __switch};
 
-// 最多支持4核
-static mut _HARTS: [Hart; 5] = [
+// 最多支持MAX_HARTS个核
+static mut _HARTS: [Hart; MAX_HARTS] = [
     Hart::default(),
     Hart::default(),
     Hart::default(),
@@ -22,10 +22,15 @@ static mut _HARTS: [Hart; 5] = [
 pub struct Hart {
     pub hartid: usize,
     pub pcb: Option<Arc<Mutex<Pcb>>>,
-    pub kernel_sp: usize,
+    // 调度器的上下文，运行在hart的启动栈上
+    pub context: TaskContext,
     pub pgtbl: Option<Pgtbl>,
     // 保存hart在进入内核态时或将要进入用户态前的时钟，用于计算用户态和内核态运行时间
     pub times: usize,
+    // 浮点寄存器中是哪个线程的值，0表示没有，见TrapFrame::restore_fp
+    pub fp_owner: Pid,
+    // 映射MemorySpace时它的generation，返回用户态前不相等时需要重新映射
+    pub ms_generation: usize,
 }
 
 impl const Default for Hart {
@@ -33,9 +38,11 @@ impl const Default for Hart {
         Self {
             hartid: 0,
             pcb: None,
-            kernel_sp: 0,
+            context: TaskContext::zero(),
             pgtbl: None,
             times: 0,
+            fp_owner: 0,
+            ms_generation: 0,
         }
     }
 }
@@ -60,12 +67,68 @@ pub fn hart_enable_timer_interrupt() {
     hart_set_next_trigger();
 }
 
+// 其他hart向这个hart上运行的线程发送信号时使用IPI(见signal::SigQueue::kick)
+pub fn hart_enable_ipi() {
+    use riscv::register::*;
+    unsafe {
+        sie::set_ssoft();
+    }
+}
+
+pub fn hart_clear_ipi() {
+    use riscv::register::*;
+    unsafe {
+        sip::clear_ssoft();
+    }
+}
+
+// 没有可以运行的进程时休眠，直到收到IPI、到达deadline或者经过IDLE_TIMEOUT
+//     内核态不开启中断，wfi在中断pending时返回，不进入trap_handler
+pub fn hart_idle(deadline: Option<usize>) {
+    let timeout = get_time() + IDLE_TIMEOUT;
+    hart_set_timecmp(deadline.map_or(timeout, |deadline| deadline.min(timeout)));
+    unsafe {
+        asm!("wfi");
+    }
+    hart_clear_ipi();
+}
+
+// 已经启动的hart
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn online_harts() -> usize {
+    ONLINE_HARTS.load(Ordering::SeqCst)
+}
+
+// 正在运行用户态的hart，返回用户态前持有MemorySpace的锁时设置，陷入时最先清除
+static USER_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+pub fn hart_enter_user() {
+    USER_HARTS.fetch_or(1 << hartid(), Ordering::SeqCst);
+}
+
+pub fn hart_leave_user() {
+    USER_HARTS.fetch_and(!(1 << hartid()), Ordering::SeqCst);
+}
+
+// 让harts中正在运行用户态的hart陷入，等待它们离开用户态
+//     内核态不开启中断，等待期间不需要处理IPI；陷入的hart在获取任何锁之前离开用户态，不会死锁
+pub fn wait_user_harts(harts: usize) {
+    let targets = harts & USER_HARTS.load(Ordering::SeqCst);
+    if targets == 0 {
+        return;
+    }
+    log!("hart":"shootdown">"harts 0x{:x}", targets);
+    sbi_send_ipi(&targets);
+    while USER_HARTS.load(Ordering::SeqCst) & targets != 0 {
+        core::hint::spin_loop();
+    }
+}
+
 pub fn init_hart() {
     log!("hart":>"init");
-    let sp: usize = link_syms::boot_stack_top as usize - BOOT_STACK_SIZE * hartid();
-    let sp: PhysAddr = PhysAddr(sp).ceil().into();
     current_hart().hartid = hartid();
-    current_hart().kernel_sp = sp.0;
+    ONLINE_HARTS.fetch_or(1 << hartid(), Ordering::SeqCst);
     current_hart().pgtbl = Some(Pgtbl::new());
     current_hart_pgtbl().map_pages(
         kernel_range(),
@@ -79,8 +142,25 @@ pub fn init_hart() {
         PTEFlag::R | PTEFlag::W,
     );
 
+    // sigreturn跳板，用户态可以执行，所有进程共享
+    current_hart_pgtbl().map(
+        VirtualAddr(SIGRETURN_TRAMPOLINE).floor(),
+        PhysAddr(crate::trap::__sigreturn_trampoline as usize).floor(),
+        PTEFlag::R | PTEFlag::X | PTEFlag::U,
+    );
+
+    // virtio设备的MMIO寄存器
+    #[cfg(feature = "virtio")]
+    {
+        let start = PhysAddr(VIRTIO_MMIO_BASE).floor();
+        let end = PhysAddr(VIRTIO_MMIO_BASE + VIRTIO_MMIO_NUM * VIRTIO_MMIO_SIZE).ceil();
+        current_hart_pgtbl().map_pages(start..end, start, PTEFlag::R | PTEFlag::W);
+    }
+
     unsafe {
         riscv::register::sstatus::set_sum();
+        // 允许内核保存和恢复用户的浮点寄存器
+        riscv::register::sstatus::set_fs(riscv::register::sstatus::FS::Initial);
         // 内核态不支持中断
         riscv::register::sstatus::clear_sie();
     }
@@ -106,70 +186,108 @@ pub fn current_hart_set_trap_times(times: usize) -> usize {
 pub fn current_hart_leak() {
     if let Some(current) = current_hart().pcb.take() {
         let pcblock = current.lock();
+        let mut ms = pcblock.memory_space.lock();
+        ms.hart_unmap(hartid());
         log!("hart":"leak">"pid({}) unmap segments", pcblock.pid);
-        current_hart_pgtbl().unmap_segments(pcblock.memory_space.segments(), false);
+        current_hart_pgtbl().unmap_segments(ms.segments());
         unsafe {
             asm!("sfence.vma");
         }
         // 用户栈
         log!("hart":"leak">"pid({}) unmap user stack", pcblock.pid);
-        current_hart_pgtbl().unmap(MemorySpace::get_stack_start().floor(), false);
-        unmap_mmap_areas(&*pcblock);
+        current_hart_pgtbl().unmap(MemorySpace::get_stack_start().floor());
+        unmap_mmap_areas(&*ms);
+        // 内核栈，hart已经切换回调度器，不再使用进程的内核栈
+        for i in 0..pcblock.kernel_stack().len() {
+            current_hart_pgtbl().unmap(kernel_stack_start() + i);
+        }
+        drop(ms);
         drop(pcblock);
     }
 }
 
 // 映射mmap区域
-fn map_mmap_areas(pcb: &Pcb) {
-    for mappage in pcb.memory_space.mmap_areas.pages() {
-        if let Some(ppage) = mappage.ppage {
-            log!("mmap":"map">"vpage 0x{:x} -> ppage 0x{:x} ({:?})", mappage.vpage.page(), ppage.page(), mappage.get_pte_flags());
-            current_hart_pgtbl().map(mappage.vpage, ppage, mappage.get_pte_flags() | PTEFlag::U);
+fn map_mmap_areas(ms: &MemorySpace) {
+    for mappage in ms.mmap_areas.pages() {
+        if let Some(ref ppage) = mappage.ppage {
+            log!("mmap":"map">"vpage 0x{:x} -> ppage 0x{:x} ({:?})", mappage.vpage.page(), ppage.page().page(), mappage.get_pte_flags());
+            current_hart_pgtbl().map(mappage.vpage, ppage.page(), mappage.get_pte_flags() | PTEFlag::U);
         }
     }
 }
 
 // 取消映射mmap区域
-fn unmap_mmap_areas(pcb: &Pcb) {
-    for mappage in pcb.memory_space.mmap_areas.pages() {
-        if let Some(ppage) = mappage.ppage {
-            log!("mmap":"unmap">"vpage 0x{:x} -> ppage 0x{:x} ({:?})", mappage.vpage.page(), ppage.page(), mappage.get_pte_flags());
-            current_hart_pgtbl().unmap(mappage.vpage, false);
+fn unmap_mmap_areas(ms: &MemorySpace) {
+    for mappage in ms.mmap_areas.pages() {
+        if let Some(ref ppage) = mappage.ppage {
+            log!("mmap":"unmap">"vpage 0x{:x} -> ppage 0x{:x} ({:?})", mappage.vpage.page(), ppage.page().page(), mappage.get_pte_flags());
+            current_hart_pgtbl().unmap(mappage.vpage);
         }
     }
 }
 
-pub fn current_hart_run(pcb: Arc<Mutex<Pcb>>) -> ! {
+fn kernel_stack_start() -> PageNum {
+    VirtualAddr(KERNEL_STACK_TOP - KERNEL_STACK_SIZE).floor()
+}
+
+// 映射进程的地址空间和内核栈，然后切换到进程，进程让出hart时返回
+//     切换到进程时持有进程锁，由进程在继续运行后释放(见task::sched)
+//     进程切换回来时同样持有进程锁，在这里释放
+pub fn current_hart_run(pcb: Arc<Mutex<Pcb>>) {
     current_hart_leak();
     let mut pcblock = pcb.lock();
     log!("hart":"run">"pid({})", pcblock.pid);
-    // 需要设置进程的代码数据段、用户栈、trapframe、内核栈
+    // 需要设置进程的代码数据段、用户栈、内核栈
     log!("hart":"run">"map segments");
+    let memory_space = pcblock.memory_space.clone();
+    let mut ms = memory_space.lock();
+    ms.hart_map(hartid());
+    current_hart().ms_generation = ms.generation();
     // map_segments将代码数据段映射到页表
-    current_hart_pgtbl().map_segments(pcblock.memory_space.segments());
-    map_mmap_areas(&*pcblock);
+    current_hart_pgtbl().map_segments(ms.segments());
+    map_mmap_areas(&*ms);
     // 映射用户栈,U flags
-    let stack = pcblock.memory_space.user_stack;
+    let stack = ms.user_stack.page();
     log!("hart":"run">"map user stack page 0x{:x}", stack.page());
     current_hart_pgtbl().map(
         MemorySpace::get_stack_start().floor(),
         stack,
-        PTEFlag::R | PTEFlag::W | PTEFlag::U,
+        ms.user_stack_flags,
     );
+    // 映射内核栈，不设置U flag
+    for (i, frame) in pcblock.kernel_stack().iter().enumerate() {
+        current_hart_pgtbl().map(kernel_stack_start() + i, frame.page(), PTEFlag::R | PTEFlag::W);
+    }
+    unsafe {
+        asm!("sfence.vma");
+    }
+    // CLONE_CHILD_SETTID: 子进程第一次运行时将tid写入自己的内存
+    if pcblock.set_child_tid != 0 {
+        let ctid = VirtualAddr(pcblock.set_child_tid);
+        pcblock.set_child_tid = 0;
+        if let Err(e) = ms.write_user(ctid, &(pcblock.pid as u32)) {
+            log!("hart":"run">"pid({}) set child tid {:?}", pcblock.pid, e);
+        }
+    }
+    drop(ms);
 
-    // 设置内核栈
-    pcblock.trapframe().kernel_sp = current_hart().kernel_sp;
-    log!("hart":"run">"sepc: 0x{:x}", pcblock.trapframe()["sepc"]);
-    let tf = VirtualAddr(pcblock.trapframe() as *const _ as usize);
-    pcblock.stimes_add(get_time() - current_hart_set_trap_times(get_time()));
-    drop(pcblock);
-    current_hart().pcb = Some(pcb);
+    let context = &pcblock.context as *const TaskContext;
+    // 记录进程运行在哪个hart上，发送信号时通知这个hart
+    let sigqueue = pcblock.sigqueue.clone();
+    sigqueue.set_hart(Some(hartid()));
+    // 不释放进程锁，进程继续运行后释放
+    core::mem::forget(pcblock);
+    current_hart().pcb = Some(pcb.clone());
     // 因为是在对当前使用的页表进行映射，所以可能需要刷新快表
     unsafe {
         asm!("sfence.vma");
     }
-    unsafe { crate::trap::__restore(tf.0); }
-    loop {}
+    unsafe {
+        __switch(&mut current_hart().context, context);
+        // 进程阻塞、让出或退出，切换回调度器时仍然持有进程锁
+        sigqueue.set_hart(None);
+        pcb.force_unlock();
+    }
 }
 
 pub fn current_hart_pgtbl() -> &'static mut Pgtbl {
@@ -0,0 +1,151 @@
+/**
+ * futex等待队列
+ * 私有futex(FUTEX_PRIVATE_FLAG)以内存空间和用户地址为键，只有共享同一内存空间的线程可以互相唤醒，
+ *     写时复制改变物理页面后键不变
+ * 共享futex以物理地址为键，共享同一物理页面的线程和进程(比如MAP_SHARED)可以互相唤醒，
+ *     写时复制把页面换成新的物理页面时，这个内存空间中的等待者移到新页面上
+ */
+use crate::config::PAGE_SIZE;
+use crate::mm::{PageNum, PhysAddr, VirtualAddr};
+use crate::task::Waiter;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+pub const FUTEX_WAIT: usize = 0;
+pub const FUTEX_WAKE: usize = 1;
+pub const FUTEX_REQUEUE: usize = 3;
+pub const FUTEX_CMP_REQUEUE: usize = 4;
+pub const FUTEX_PRIVATE_FLAG: usize = 128;
+pub const FUTEX_CLOCK_REALTIME: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FutexKey {
+    // 内存空间(MemorySpace::id)和用户地址
+    Private(usize, usize),
+    // 物理地址
+    Shared(usize),
+}
+
+impl FutexKey {
+    pub fn new(private: bool, ms: usize, uaddr: VirtualAddr, paddr: PhysAddr) -> Self {
+        if private {
+            FutexKey::Private(ms, uaddr.0)
+        } else {
+            FutexKey::Shared(paddr.0)
+        }
+    }
+}
+
+// 等待者和它所在的内存空间，写时复制时由内存空间找到需要移动的共享futex等待者
+type Waiters = Vec<(usize, Arc<Waiter>)>;
+
+// 每个键的等待者按等待的顺序排列，超时的等待者在唤醒或requeue时清理
+//     requeue需要在不同键之间移动等待者，所以不直接使用WaitQueue
+lazy_static! {
+    static ref FUTEXES: Mutex<BTreeMap<FutexKey, Waiters>> = Mutex::new(BTreeMap::new());
+}
+
+fn read_word(word: PhysAddr) -> u32 {
+    let cur: &u32 = word.as_ref();
+    unsafe { (cur as *const u32).read_volatile() }
+}
+
+// 如果word处的值等于val，将ms中的waiter加入key的等待队列，否则返回false
+//     检查和加入队列时持有队列的锁，唤醒者修改值之后再唤醒，不会丢失唤醒
+pub fn futex_enqueue(key: FutexKey, word: PhysAddr, ms: usize, val: u32, waiter: Arc<Waiter>) -> bool {
+    let mut futexes = FUTEXES.lock();
+    if read_word(word) != val {
+        return false;
+    }
+    let waiters = futexes.entry(key).or_insert_with(Vec::new);
+    waiters.retain(|(_, waiter)| !waiter.is_woken());
+    waiters.push((ms, waiter));
+    log!("futex":"wait">"on {:x?}", key);
+    true
+}
+
+// 从队列头部唤醒最多n个等待者，返回唤醒的个数
+fn wake_locked(futexes: &mut BTreeMap<FutexKey, Waiters>, key: FutexKey, n: usize) -> usize {
+    let mut count = 0;
+    if let Some(waiters) = futexes.get_mut(&key) {
+        while count < n && !waiters.is_empty() {
+            if waiters.remove(0).1.wake() {
+                count += 1;
+            }
+        }
+        waiters.retain(|(_, waiter)| !waiter.is_woken());
+        if waiters.is_empty() {
+            futexes.remove(&key);
+        }
+    }
+    log!("futex":"wake">"{:x?}: woken {}", key, count);
+    count
+}
+
+pub fn futex_wake(key: FutexKey, n: usize) -> usize {
+    wake_locked(&mut FUTEXES.lock(), key, n)
+}
+
+// 唤醒key上最多nwake个等待者，剩下的最多nrequeue个移到key2上等待
+//     cmp不为None时key对应的word处的值需要等于cmp，否则返回Err
+pub fn futex_requeue(
+    key: FutexKey,
+    word: PhysAddr,
+    nwake: usize,
+    key2: FutexKey,
+    nrequeue: usize,
+    cmp: Option<u32>,
+) -> Result<usize, ()> {
+    let mut futexes = FUTEXES.lock();
+    if let Some(cmp) = cmp {
+        if read_word(word) != cmp {
+            return Err(());
+        }
+    }
+    let woken = wake_locked(&mut futexes, key, nwake);
+    if key == key2 {
+        return Ok(woken);
+    }
+    let mut moved = Vec::new();
+    if let Some(waiters) = futexes.get_mut(&key) {
+        let n = nrequeue.min(waiters.len());
+        moved = waiters.drain(..n).collect();
+        if waiters.is_empty() {
+            futexes.remove(&key);
+        }
+    }
+    let requeued = moved.len();
+    if requeued > 0 {
+        futexes.entry(key2).or_insert_with(Vec::new).extend(moved);
+    }
+    log!("futex":"requeue">"{:x?} -> {:x?}: woken {}, requeued {}", key, key2, woken, requeued);
+    Ok(woken + requeued)
+}
+
+// 写时复制把ms中的一个页面从old换成new之后，ms中在old上等待共享futex的线程移到new上
+//     其他进程仍然使用old，它们的等待者不变
+pub fn futex_migrate(ms: usize, old: PageNum, new: PageNum) {
+    let mut futexes = FUTEXES.lock();
+    let range = FutexKey::Shared(old.offset_phys(0).0)..FutexKey::Shared(old.offset_phys(PAGE_SIZE).0);
+    let keys: Vec<FutexKey> = futexes.range(range).map(|(key, _)| *key).collect();
+    for key in keys {
+        let offset = match key {
+            FutexKey::Shared(paddr) => paddr - old.offset_phys(0).0,
+            FutexKey::Private(..) => continue,
+        };
+        let waiters = futexes.get_mut(&key).unwrap();
+        let (moved, kept): (Waiters, Waiters) =
+            core::mem::take(waiters).into_iter().partition(|(owner, _)| *owner == ms);
+        *waiters = kept;
+        if waiters.is_empty() {
+            futexes.remove(&key);
+        }
+        if !moved.is_empty() {
+            let key2 = FutexKey::Shared(new.offset_phys(offset).0);
+            log!("futex":"migrate">"{:x?} -> {:x?}: {}", key, key2, moved.len());
+            futexes.entry(key2).or_insert_with(Vec::new).extend(moved);
+        }
+    }
+}
@@ -1,8 +1,9 @@
 pub mod cpu;
+pub mod futex;
 pub mod pcb;
 pub mod signal;
 mod trapframe;
 
-pub use pcb::{Pcb, PcbState, Pid};
+pub use pcb::{CloneFlags, Pcb, PcbState, Pid};
 pub use trapframe::TrapFrame;
 
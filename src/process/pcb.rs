@@ -1,29 +1,54 @@
+use super::futex::{futex_wake, FutexKey};
 use super::signal::*;
 use super::TrapFrame;
 use crate::config::*;
+use crate::errno::Errno;
+use crate::mm::KallocErr;
 use crate::mm::MemorySpace;
-use crate::mm::PageNum;
+use crate::mm::{try_kalloc, FrameTracker, VirtualAddr};
+use crate::task::{task_entry, TaskContext, WaitQueue, Waiter};
 use crate::vfs::*;
+use alloc::collections::BTreeMap;
 use alloc::string::String;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
 use core::convert::TryFrom;
+use core::mem::size_of;
 use core::sync::atomic::AtomicUsize;
 use core::sync::atomic::Ordering;
-use spin::Mutex;
+use spin::{Mutex, RwLock};
 
 pub type Pid = usize;
 
+// init是第一个创建的进程，pid为1，它的父进程0不存在
+pub const INIT_PID: Pid = 1;
+
 lazy_static! {
-    // 1 用作不存在的根Pcb
-    static ref PIDALLOCATOR: AtomicUsize = AtomicUsize::new(2);
+    static ref PIDALLOCATOR: AtomicUsize = AtomicUsize::new(INIT_PID);
 }
 
 pub fn alloc_pid() -> usize {
     PIDALLOCATOR.fetch_add(1, Ordering::Relaxed)
 }
 
+lazy_static! {
+    // init进程的pid和线程组，接管父进程退出后留下的子进程
+    static ref INITPROC: Mutex<Option<(Pid, Weak<ThreadGroup>)>> = Mutex::new(None);
+}
+
+// 设置init进程，只有第一次设置有效
+pub fn set_init_proc(pcb: &Pcb) {
+    INITPROC
+        .lock()
+        .get_or_insert_with(|| (pcb.tgid, Arc::downgrade(&pcb.thread_group)));
+}
+
+// 内核栈的页面不需要物理连续，运行时映射到连续的虚拟地址
+fn alloc_kernel_stack() -> Result<Vec<FrameTracker>, KallocErr> {
+    (0..KERNEL_STACK_SIZE / PAGE_SIZE).map(|_| try_kalloc()).collect()
+}
+
 // Note: 使用Atomic类型会出错
 // 统计所有Pcb是否释放，检测引用计数
 #[cfg(feature = "pcb")]
@@ -33,24 +58,503 @@ use core::ops::Fn;
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum PcbState {
     Running,
+    // 退出的进程，保存wait4的wstatus
     Zombie(isize),
-    // 保持信号处理前的trapframe和signal mask
-    SigHandling(PageNum, Signal),
     // Blocking(fn(Arc<Mutex<Pcb>>) -> bool),
     Blocking,
 }
 
+// 正常退出时的wstatus，退出码在8~15位
+pub fn exit_wstatus(code: isize) -> isize {
+    (code & 0xff) << 8
+}
+
+// 被信号终止时的wstatus，低7位为信号，core dump时设置0x80
+pub fn signal_wstatus(signal: Signal, core_dump: bool) -> isize {
+    signal.signum() as isize | if core_dump { 0x80 } else { 0 }
+}
+
+bitflags! {
+    // clone(2)的flags，低8位为子进程退出时发送给父进程的信号
+    pub struct CloneFlags: usize {
+        const CSIGNAL = 0x000000ff;
+        const SIGCHLD = 17;
+        const CLONE_VM = 0x00000100;
+        const CLONE_FS = 0x00000200;
+        const CLONE_FILES = 0x00000400;
+        const CLONE_SIGHAND = 0x00000800;
+        const CLONE_VFORK = 0x00004000;
+        const CLONE_PARENT = 0x00008000;
+        const CLONE_THREAD = 0x00010000;
+        const CLONE_SYSVSEM = 0x00040000;
+        const CLONE_SETTLS = 0x00080000;
+        const CLONE_PARENT_SETTID = 0x00100000;
+        const CLONE_CHILD_CLEARTID = 0x00200000;
+        const CLONE_CHILD_SETTID = 0x01000000;
+    }
+}
+
+// 进程的文件描述符表，CLONE_FILES时共享
+pub type FdTable = Arc<Mutex<Vec<Option<Fd>>>>;
+
+// 线程组，同一个进程的所有线程共享
+pub struct ThreadGroup {
+    tgid: Pid,
+    // 进程组id和会话id，fork时继承
+    pgid: AtomicUsize,
+    sid: AtomicUsize,
+    // 父进程的pid和线程组，父进程退出后交给init
+    parent: Mutex<(Pid, Weak<ThreadGroup>)>,
+    // 创建该进程的线程，__WNOTHREAD只等待当前线程创建的子进程
+    //     线程退出时交给同一个进程中的其他线程，父进程退出时为init
+    creator: AtomicUsize,
+    // 所有线程退出后进程才退出，通知父进程并可以被wait4回收
+    threads: Mutex<Threads>,
+    // 发送给进程的信号，由任意一个没有屏蔽它的线程处理
+    pub signals: SigPending,
+    // 线程组退出(exit_group、execve或被信号终止)时设置，其他线程在下一次调度时以这个wstatus退出
+    exit_code: Mutex<Option<isize>>,
+    // 阻塞的线程，线程组退出时唤醒
+    pub blocked: WaitQueue,
+    // wait4等待子进程退出、停止或继续
+    pub child_exit: WaitQueue,
+    // 子进程的线程组，进程中的任意线程都可以wait，wait4不需要锁子进程的Pcb
+    //     最后一个线程退出时交给init
+    pub children: Mutex<Vec<Arc<ThreadGroup>>>,
+    // 已经退出的线程的用户态和内核态时间，包括它们回收的子进程，wait4时累加到父进程
+    times: Mutex<(usize, usize)>,
+    // 停止和继续的状态
+    job: Mutex<JobState>,
+    // 线程组停止时等待SIGCONT的线程
+    pub continued: WaitQueue,
+}
+
+struct Threads {
+    // 还没有退出的线程的信号队列，第一个是组长线程
+    live: Vec<Arc<SigQueue>>,
+    // 组长线程退出时的wstatus，其他线程可能还在运行
+    leader_status: Option<isize>,
+    // 最后一个线程退出时设置，wait4报告的wstatus
+    status: Option<isize>,
+}
+
+#[derive(Default)]
+struct JobState {
+    stopped: bool,
+    // 还没有被父进程的wait4取走的状态变化
+    report: Option<JobReport>,
+    // 继续运行之后还没有通知父进程
+    notify_continued: bool,
+}
+
+// wait4(WUNTRACED/WCONTINUED)报告的子进程状态变化
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum JobReport {
+    Stopped(Signal),
+    Continued,
+}
+
+lazy_static! {
+    // 进程表，按tgid索引所有线程组，用于查找进程和进程组
+    static ref THREADGROUPS: Mutex<BTreeMap<Pid, Weak<ThreadGroup>>> = Mutex::new(BTreeMap::new());
+}
+
+// 查找进程，tgid不存在或者是线程的tid时返回None
+pub fn find_thread_group(tgid: Pid) -> Option<Arc<ThreadGroup>> {
+    THREADGROUPS.lock().get(&tgid).and_then(Weak::upgrade)
+}
+
+lazy_static! {
+    // 线程表，按tid索引线程的CPU亲和性，Pcb和线程表各持有一个Arc
+    //     运行和阻塞的线程一直持有自己的Pcb锁，sched_setaffinity修改其他线程时不能锁它的Pcb
+    static ref AFFINITIES: RwLock<BTreeMap<Pid, Arc<AtomicUsize>>> = RwLock::new(BTreeMap::new());
+}
+
+// 查找线程的CPU亲和性，tid可以是任意进程中的线程，线程已经被回收时返回None
+pub fn find_affinity(tid: Pid) -> Option<Arc<AtomicUsize>> {
+    AFFINITIES.read().get(&tid).cloned()
+}
+
+fn affinity_init(tid: Pid, affinity: usize) -> Arc<AtomicUsize> {
+    let affinity = Arc::new(AtomicUsize::new(affinity));
+    AFFINITIES.write().insert(tid, affinity.clone());
+    affinity
+}
+
+// 所有存在的进程
+//     ThreadGroup释放时需要锁进程表，不能在持有进程表的锁时释放Arc
+pub fn thread_groups() -> Vec<Arc<ThreadGroup>> {
+    let groups: Vec<_> = THREADGROUPS.lock().values().cloned().collect();
+    groups.iter().filter_map(Weak::upgrade).collect()
+}
+
+impl ThreadGroup {
+    // sigqueue为线程组中第一个线程的信号队列
+    fn new(
+        tgid: Pid,
+        pgid: Pid,
+        sid: Pid,
+        parent: Pid,
+        parent_group: Weak<ThreadGroup>,
+        sigqueue: Arc<SigQueue>,
+    ) -> Arc<Self> {
+        let group = Arc::new(Self {
+            tgid,
+            pgid: AtomicUsize::new(pgid),
+            sid: AtomicUsize::new(sid),
+            parent: Mutex::new((parent, parent_group)),
+            creator: AtomicUsize::new(parent),
+            threads: Mutex::new(Threads {
+                live: vec![sigqueue],
+                leader_status: None,
+                status: None,
+            }),
+            signals: SigPending::new(),
+            exit_code: Mutex::new(None),
+            blocked: WaitQueue::new(),
+            child_exit: WaitQueue::new(),
+            children: Mutex::new(Vec::new()),
+            times: Mutex::new((0, 0)),
+            job: Mutex::new(JobState::default()),
+            continued: WaitQueue::new(),
+        });
+        THREADGROUPS.lock().insert(tgid, Arc::downgrade(&group));
+        group
+    }
+
+    pub fn exit(&self, wstatus: isize) {
+        self.exit_code.lock().get_or_insert(wstatus);
+        self.blocked.wake_all();
+    }
+
+    pub fn exit_code(&self) -> Option<isize> {
+        *self.exit_code.lock()
+    }
+
+    pub fn tgid(&self) -> Pid {
+        self.tgid
+    }
+
+    pub fn pgid(&self) -> Pid {
+        self.pgid.load(Ordering::SeqCst)
+    }
+
+    pub fn sid(&self) -> Pid {
+        self.sid.load(Ordering::SeqCst)
+    }
+
+    pub fn set_pgid(&self, pgid: Pid) {
+        self.pgid.store(pgid, Ordering::SeqCst);
+    }
+
+    // 创建新的会话和进程组，进程成为会话首进程和进程组组长
+    pub fn set_sid(&self) {
+        self.sid.store(self.tgid, Ordering::SeqCst);
+        self.pgid.store(self.tgid, Ordering::SeqCst);
+    }
+
+    pub fn parent(&self) -> (Pid, Weak<ThreadGroup>) {
+        self.parent.lock().clone()
+    }
+
+    pub fn set_parent(&self, parent: Pid, parent_group: Weak<ThreadGroup>) {
+        *self.parent.lock() = (parent, parent_group);
+    }
+
+    pub fn creator(&self) -> Pid {
+        self.creator.load(Ordering::SeqCst)
+    }
+
+    fn set_creator(&self, tid: Pid) {
+        self.creator.store(tid, Ordering::SeqCst);
+    }
+
+    // 进程退出、停止或继续时唤醒父进程的wait4，并发送SIGCHLD
+    pub fn notify_parent(&self, info: SigInfo) {
+        let (parent, parent_group) = self.parent();
+        if let Some(parent_group) = parent_group.upgrade() {
+            parent_group.child_exit.wake_all();
+            // SIGCHLD是发送给父进程的，父进程的任意一个线程都可以处理
+            let _ = parent_group.send_signal(info, false);
+        }
+    }
+
+    /**
+     * 发送给进程的信号
+     */
+    // 保存在线程组共享的pending中，唤醒一个没有屏蔽这个信号的线程，优先选择组长线程
+    //     所有线程都屏蔽时保持pending，直到某个线程解除屏蔽，返回用户态前处理
+    //     limit为false时不受SIGQUEUE_MAX的限制(内核发送的信号)
+    pub fn send_signal(&self, info: SigInfo, limit: bool) -> Result<(), SendErr> {
+        let signal = Signal::from_signum(info.si_signo as usize).unwrap();
+        self.signals.push(info, limit)?;
+        let threads = self.threads.lock();
+        let target = threads.live.iter().find(|queue| !queue.mask().contains(signal));
+        log!("signal":"send">"tgid({}), signal({:?}), target({:?})", self.tgid, signal, target.map(|queue| queue.pid()));
+        if let Some(queue) = target {
+            queue.kick();
+        }
+        Ok(())
+    }
+
+    // 丢弃进程和所有线程pending的信号
+    pub fn discard_signals(&self, signals: Signal) {
+        self.signals.discard(signals);
+        for queue in self.threads.lock().live.iter() {
+            queue.discard(signals);
+        }
+    }
+
+    /**
+     * 线程的创建和退出
+     */
+    pub fn threads(&self) -> usize {
+        self.threads.lock().live.len()
+    }
+
+    // 新线程在开始运行之前加入
+    pub fn add_thread(&self, sigqueue: Arc<SigQueue>) {
+        self.threads.lock().live.push(sigqueue);
+    }
+
+    // 线程退出，最后一个线程退出时返回进程的wstatus
+    //     exit_group或者被信号终止时为线程组的exit_code，否则为组长线程exit的状态
+    fn thread_exit(&self, pid: Pid, leader_status: Option<isize>) -> Option<isize> {
+        let mut threads = self.threads.lock();
+        threads.live.retain(|queue| queue.pid() != pid);
+        if leader_status.is_some() {
+            threads.leader_status = leader_status;
+        }
+        if !threads.live.is_empty() {
+            return None;
+        }
+        let status = self.exit_code().or(threads.leader_status).unwrap_or(0);
+        threads.status = Some(status);
+        Some(status)
+    }
+
+    // 加入父进程的children
+    //     父进程可能同时退出，持有父进程children的锁时重新检查；父进程已经退出时交给init
+    fn attach_parent(self: &Arc<Self>) {
+        loop {
+            let (_, parent_group) = self.parent();
+            let parent_arc = match parent_group.upgrade() {
+                Some(parent_arc) => parent_arc,
+                None => return,
+            };
+            let mut children = parent_arc.children.lock();
+            if !Weak::ptr_eq(&self.parent().1, &parent_group) {
+                continue;
+            }
+            if parent_arc.exit_status().is_none() {
+                children.push(self.clone());
+                return;
+            }
+            match INITPROC.lock().clone() {
+                Some((pid, init)) if !Weak::ptr_eq(&init, &parent_group) => {
+                    self.set_parent(pid, init);
+                    self.set_creator(pid);
+                }
+                _ => self.set_parent(0, Weak::new()),
+            }
+        }
+    }
+
+    // 线程退出时把它创建的子进程交给进程中还在运行的线程
+    fn hand_over_children(&self, tid: Pid) {
+        let heir = match self.threads.lock().live.first() {
+            Some(queue) => queue.pid(),
+            None => return,
+        };
+        for child in self.children.lock().iter().filter(|child| child.creator() == tid) {
+            child.set_creator(heir);
+        }
+    }
+
+    // 子进程交给init，由init的wait4回收
+    //     持有children的锁时修改子进程的父进程，execve替换子进程的线程组时由此判断子进程是否还在这里
+    fn reparent_children(&self) {
+        let mut children = self.children.lock();
+        if children.is_empty() {
+            return;
+        }
+        let init = INITPROC.lock().clone();
+        let init = init.and_then(|(pid, group)| Some((pid, group.upgrade()?)));
+        // init退出时直接关机
+        let init = init.filter(|(_, group)| !core::ptr::eq(Arc::as_ptr(group), self));
+        let mut zombie = false;
+        for child in children.iter() {
+            match init {
+                Some((pid, ref group)) => {
+                    child.set_parent(pid, Arc::downgrade(group));
+                    child.set_creator(pid);
+                }
+                // 没有init时孤儿进程不会被wait，直接释放
+                None => child.set_parent(0, Weak::new()),
+            }
+            if child.exit_status().is_some() {
+                zombie = true;
+            }
+        }
+        let children = core::mem::take(&mut *children);
+        if let Some((pid, group)) = init {
+            log!("pcb":"reparent">"pid({}) {} children -> init({})", self.tgid, children.len(), pid);
+            group.children.lock().extend(children);
+            if zombie {
+                group.child_exit.wake_all();
+            }
+        }
+    }
+
+    // 所有线程都已经退出时返回wait4报告的wstatus
+    pub fn exit_status(&self) -> Option<isize> {
+        self.threads.lock().status
+    }
+
+    // 所有线程退出后为进程的用户态和内核态时间
+    pub fn times(&self) -> (usize, usize) {
+        *self.times.lock()
+    }
+
+    /**
+     * 作业控制
+     */
+    // 停止线程组，已经停止时返回false
+    pub fn stop(&self, signal: Signal) -> bool {
+        let mut job = self.job.lock();
+        if job.stopped {
+            return false;
+        }
+        job.stopped = true;
+        job.report = Some(JobReport::Stopped(signal));
+        job.notify_continued = false;
+        true
+    }
+
+    // 收到SIGCONT时继续运行，唤醒等待的线程，没有停止时返回false
+    pub fn cont(&self) -> bool {
+        let mut job = self.job.lock();
+        if !job.stopped {
+            return false;
+        }
+        job.stopped = false;
+        job.report = Some(JobReport::Continued);
+        job.notify_continued = true;
+        drop(job);
+        self.continued.wake_all();
+        true
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.job.lock().stopped
+    }
+
+    // 由继续运行的线程通知父进程，只通知一次
+    pub fn take_continued_notify(&self) -> bool {
+        core::mem::take(&mut self.job.lock().notify_continued)
+    }
+
+    pub fn job_report(&self) -> Option<JobReport> {
+        self.job.lock().report
+    }
+
+    // wait4报告之后清除，期间状态又发生变化时保留新的状态
+    pub fn clear_job_report(&self, report: JobReport) {
+        let mut job = self.job.lock();
+        if job.report == Some(report) {
+            job.report = None;
+        }
+    }
+}
+
+impl Drop for ThreadGroup {
+    fn drop(&mut self) {
+        let mut groups = THREADGROUPS.lock();
+        // execve后同一个tgid可能已经对应新的线程组
+        if groups.get(&self.tgid).map_or(false, |group| group.as_ptr() == self as *const _) {
+            groups.remove(&self.tgid);
+        }
+    }
+}
+
+// 向进程发送信号，info为None时只检查进程是否存在
+pub fn kill_process(pid: Pid, info: Option<SigInfo>) -> Result<(), SendErr> {
+    // 不存在，表明进程已经被回收
+    let group = find_thread_group(pid).ok_or(SendErr::NoProcess)?;
+    kill_group(&group, info)
+}
+
+// SIGKILL、SIGCONT和停止信号在发送时就起作用，被屏蔽或者进程已经停止时也一样
+fn kill_group(group: &ThreadGroup, info: Option<SigInfo>) -> Result<(), SendErr> {
+    let info = match info {
+        Some(info) => info,
+        None => return Ok(()),
+    };
+    let signal = Signal::from_signum(info.si_signo as usize).unwrap();
+    if SIG_STOPS.contains(signal) {
+        group.discard_signals(Signal::SIGCONT);
+    } else if signal == Signal::SIGCONT {
+        group.discard_signals(SIG_STOPS);
+    }
+    group.send_signal(info, true)?;
+    match signal {
+        Signal::SIGKILL => group.exit(signal_wstatus(signal, false)),
+        Signal::SIGCONT => {
+            group.cont();
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// 向多个进程发送信号，只要有一个成功就返回Ok，否则返回最后一个错误
+fn kill_groups<'a>(groups: impl Iterator<Item = &'a Arc<ThreadGroup>>, info: Option<SigInfo>) -> Result<(), SendErr> {
+    groups.fold(Err(SendErr::NoProcess), |sent, group| match kill_group(group, info) {
+        Ok(()) => Ok(()),
+        Err(e) => sent.or(Err(e)),
+    })
+}
+
+// 向进程组中的所有进程发送信号，进程组不存在时返回NoProcess
+pub fn kill_pgrp(pgid: Pid, info: Option<SigInfo>) -> Result<(), SendErr> {
+    let groups = thread_groups();
+    kill_groups(groups.iter().filter(|group| group.pgid() == pgid), info)
+}
+
+// kill(-1)，发送给除了init和当前进程之外的所有进程
+pub fn kill_all(tgid: Pid, info: Option<SigInfo>) -> Result<(), SendErr> {
+    let groups = thread_groups();
+    kill_groups(groups.iter().filter(|group| group.tgid() != INIT_PID && group.tgid() != tgid), info)
+}
+
+// Pcb是调度的单位，即一个线程
+// 同一个进程的线程共享MemorySpace、文件描述符表和信号处理函数，trapframe和信号掩码每个线程独有
 pub struct Pcb {
-    pub parent: Pid,
+    // 线程id，即Linux的tid
     pub pid: Pid,
+    // 线程组id，即getpid返回的进程id，等于线程组中第一个线程的pid
+    pub tgid: Pid,
+    pub thread_group: Arc<ThreadGroup>,
     pub cwd: String,
     pub state: PcbState,
-    pub memory_space: MemorySpace,
-    pub fds: Vec<Option<Fd>>,
-    pub children: Vec<Arc<Mutex<Pcb>>>,
-    pub sabinds: SigActionBinds,
+    pub memory_space: Arc<Mutex<MemorySpace>>,
+    // 用于上下文切换的trapframe
+    trapframe: FrameTracker,
+    // 内核栈和内核上下文，进程在内核中阻塞时保存在这里
+    kernel_stack: Vec<FrameTracker>,
+    pub context: TaskContext,
+    pub fds: FdTable,
+    pub sabinds: Arc<Mutex<SigActionBinds>>,
+    // 线程的信号队列和掩码
+    pub sigqueue: Arc<SigQueue>,
+    // CLONE_CHILD_SETTID: 第一次运行时将tid写入这个地址，0表示不需要
+    pub set_child_tid: usize,
+    // CLONE_CHILD_CLEARTID和set_tid_address: 线程退出时将这个地址的tid清零
+    pub clear_child_tid: usize,
     // 进程文件系统根目录
     pub root: Inode,
+    // CPU亲和性，允许运行的hart，子进程和线程继承，由线程表共享(见find_affinity)
+    pub affinity: Arc<AtomicUsize>,
 
     // times()
     utimes: usize,
@@ -58,61 +562,225 @@ pub struct Pcb {
     cutimes: usize,
     cstimes: usize,
 
-    pub block_fn: Option<Arc<dyn Fn(&mut Pcb) -> bool>>,
+    // 阻塞时等待的Waiter，被唤醒后由task::block取出
+    pub waiter: Option<Arc<Waiter>>,
+    // sigaltstack设置的备用信号栈
+    pub sigaltstack: SignalStack,
+    // 被信号中断、需要重新执行的系统调用的a0
+    pub syscall_restart: Option<usize>,
+    // sigsuspend之前的信号掩码，信号处理完成后恢复
+    pub saved_sigmask: Option<Signal>,
 }
 
 unsafe impl Send for Pcb {}
 
 impl Pcb {
-    pub fn new(memory_space: MemorySpace, parent: Pid, cwd: String) -> Self {
-        let pcb = Self {
-            parent,
-            pid: alloc_pid(),
+    // 创建一个新的进程，从memory_space的入口开始运行
+    pub fn new(memory_space: MemorySpace, parent: Pid, cwd: String) -> Result<Self, KallocErr> {
+        let entry = memory_space.entry();
+        let trapframe = try_kalloc()?;
+        let kernel_stack = alloc_kernel_stack()?;
+        let pid = alloc_pid();
+        // 信号队列注册在SIGQUEUES中，由Pcb的drop删除，在可能失败的分配之后创建
+        let sigqueue = sigqueue_init(pid, Signal::empty());
+        let affinity = affinity_init(pid, ALL_HARTS);
+        let mut pcb = Self {
+            pid,
+            tgid: pid,
+            thread_group: ThreadGroup::new(pid, pid, pid, parent, Weak::new(), sigqueue.clone()),
             state: PcbState::Running,
             cwd,
-            memory_space,
-            fds: vec![Some(STDIN.clone()), Some(STDOUT.clone())],
-            children: Vec::new(),
-            sabinds: SigActionBinds::new(),
+            memory_space: Arc::new(Mutex::new(memory_space)),
+            trapframe,
+            kernel_stack,
+            context: TaskContext::new(task_entry as usize, KERNEL_STACK_TOP),
+            fds: Arc::new(Mutex::new(vec![Some(STDIN.clone()), Some(STDOUT.clone())])),
+            sabinds: Arc::new(Mutex::new(SigActionBinds::new())),
+            sigqueue,
+            set_child_tid: 0,
+            clear_child_tid: 0,
             // 默认根目录
             root: ROOT.clone(),
+            affinity,
 
             utimes: 0,
             stimes: 0,
             cutimes: 0,
             cstimes: 0,
 
-            block_fn: None,
+            waiter: None,
+            sigaltstack: SignalStack::disabled(),
+            syscall_restart: None,
+            saved_sigmask: None,
         };
         #[cfg(feature = "pcb")]
         unsafe {
             *DROPPCBS.lock() += 1;
         }
-        sigqueue_init(pcb.pid);
-        pcb
+        pcb.trapframe().init(MemorySpace::get_stack_sp().0, entry);
+        Ok(pcb)
     }
 
     /**
      * 进程上下文
      */
     pub fn trapframe(&mut self) -> &mut TrapFrame {
-        self.memory_space.trapframe()
+        let phys = self.trapframe.page().offset_phys(0).0;
+        unsafe { <*mut TrapFrame>::from_bits(phys).as_mut().unwrap() }
+    }
+
+    // 内核栈的物理页面，从低地址到高地址
+    pub fn kernel_stack(&self) -> &[FrameTracker] {
+        &self.kernel_stack
+    }
+
+    // 根据clone的flags创建子进程或者线程，子进程从clone的下一条指令开始运行，返回值为0
+    pub fn clone_child(&mut self, flags: CloneFlags) -> Result<Arc<Mutex<Pcb>>, KallocErr> {
+        let memory_space = if flags.contains(CloneFlags::CLONE_VM) {
+            self.memory_space.clone()
+        } else {
+            Arc::new(Mutex::new(self.memory_space.lock().copy()?))
+        };
+        let trapframe = try_kalloc()?;
+        trapframe
+            .page()
+            .offset_phys(0)
+            .write(self.trapframe.page().offset_phys(0).as_slice(size_of::<TrapFrame>()));
+        let kernel_stack = alloc_kernel_stack()?;
+        let pid = alloc_pid();
+        let sigqueue = sigqueue_init(pid, self.sigqueue.mask());
+        let affinity = affinity_init(pid, self.affinity());
+        let is_thread = flags.contains(CloneFlags::CLONE_THREAD);
+        // CLONE_PARENT的子进程与当前进程有同一个父进程
+        let (parent, parent_group) = if flags.contains(CloneFlags::CLONE_PARENT) {
+            self.thread_group.parent()
+        } else {
+            (self.tgid, Arc::downgrade(&self.thread_group))
+        };
+        let mut child = Self {
+            pid,
+            tgid: if is_thread { self.tgid } else { pid },
+            // 线程由sys_clone在开始运行之前加入线程组
+            thread_group: if is_thread {
+                self.thread_group.clone()
+            } else {
+                ThreadGroup::new(
+                    pid,
+                    self.thread_group.pgid(),
+                    self.thread_group.sid(),
+                    parent,
+                    parent_group,
+                    sigqueue.clone(),
+                )
+            },
+            state: PcbState::Running,
+            cwd: self.cwd.clone(),
+            memory_space,
+            trapframe,
+            kernel_stack,
+            context: TaskContext::new(task_entry as usize, KERNEL_STACK_TOP),
+            // todo: 考虑O_CLOSEXEC，不拷贝所有fd
+            fds: if flags.contains(CloneFlags::CLONE_FILES) {
+                self.fds.clone()
+            } else {
+                Arc::new(Mutex::new(self.fds.lock().clone()))
+            },
+            // 不共享时子进程复制一份信号处理函数
+            sabinds: if flags.contains(CloneFlags::CLONE_SIGHAND) {
+                self.sabinds.clone()
+            } else {
+                Arc::new(Mutex::new(self.sabinds.lock().clone()))
+            },
+            sigqueue,
+            set_child_tid: 0,
+            clear_child_tid: 0,
+            root: self.root.clone(),
+            affinity,
+
+            utimes: 0,
+            stimes: 0,
+            cutimes: 0,
+            cstimes: 0,
+
+            waiter: None,
+            // 共享地址空间的线程不能使用同一个备用信号栈
+            sigaltstack: if flags.contains(CloneFlags::CLONE_VM) {
+                SignalStack::disabled()
+            } else {
+                self.sigaltstack
+            },
+            syscall_restart: None,
+            saved_sigmask: None,
+        };
+        #[cfg(feature = "pcb")]
+        unsafe {
+            *DROPPCBS.lock() += 1;
+        }
+        child.trapframe()["a0"] = 0;
+        // 线程不是子进程，不能被wait；CLONE_PARENT的子进程是父进程的子进程
+        if !is_thread {
+            if !flags.contains(CloneFlags::CLONE_PARENT) {
+                child.thread_group.set_creator(self.pid);
+            }
+            child.thread_group.attach_parent();
+        }
+        let child = Arc::new(Mutex::new(child));
+        Ok(child)
+    }
+
+    // 是否为线程组中的第一个线程，它的退出状态由父进程wait
+    pub fn is_group_leader(&self) -> bool {
+        self.pid == self.tgid
     }
 
-    pub fn clone_child(&mut self) -> Arc<Mutex<Pcb>> {
-        let child_ms = self.memory_space.copy();
-        let child = Arc::new(Mutex::new(Pcb::new(child_ms, self.pid, self.cwd.clone())));
-        let mut childlock = child.lock();
-        childlock.trapframe()["a0"] = 0;
-        // 先删掉STDIN、STDOUT
-        childlock.fds.clear();
-        // todo: 考虑O_CLOSEXEC，不拷贝所有fd
-        for fd in self.fds.iter() {
-            childlock.fds.push(fd.clone())
+    // execve时线程组的其他线程退出，当前线程使用新的线程组
+    //     Note: 与Linux不同，非leader线程execve时不会接管leader的pid
+    pub fn leave_thread_group(&mut self) {
+        if self.thread_group.threads() > 1 {
+            let old = self.thread_group.clone();
+            old.exit(0);
+            let (parent, parent_group) = old.parent();
+            let group =
+                ThreadGroup::new(self.tgid, old.pgid(), old.sid(), parent, parent_group.clone(), self.sigqueue.clone());
+            group.set_creator(old.creator());
+            // 子进程属于进程，交给新的线程组，其他线程都会退出，子进程交给当前线程
+            let mut children = old.children.lock();
+            for child in children.iter() {
+                child.set_parent(self.tgid, Arc::downgrade(&group));
+                child.set_creator(self.pid);
+            }
+            group.children.lock().extend(core::mem::take(&mut *children));
+            drop(children);
+            // 组长继续作为原来的进程运行，父进程的children中替换为新的线程组
+            //     父进程可能同时退出把子进程交给init，持有父进程children的锁时重新检查父进程
+            if self.is_group_leader() {
+                loop {
+                    let (parent, parent_group) = old.parent();
+                    let parent_arc = match parent_group.upgrade() {
+                        Some(parent_arc) => parent_arc,
+                        None => {
+                            group.set_parent(0, Weak::new());
+                            break;
+                        }
+                    };
+                    let mut children = parent_arc.children.lock();
+                    if !Weak::ptr_eq(&old.parent().1, &parent_group) {
+                        continue;
+                    }
+                    group.set_parent(parent, parent_group);
+                    if let Some(child) = children.iter_mut().find(|child| Arc::ptr_eq(child, &old)) {
+                        *child = group.clone();
+                    }
+                    break;
+                }
+                old.set_parent(0, Weak::new());
+            }
+            // 其他线程可能已经全部退出
+            if let Some(status) = old.thread_exit(self.pid, None) {
+                old.notify_parent(SigInfo::child_exit(self.tgid, status));
+            }
+            self.thread_group = group;
         }
-        drop(childlock);
-        self.children.push(child.clone());
-        child
     }
 
     /**
@@ -120,7 +788,7 @@ impl Pcb {
      */
     pub fn get_fd(&self, idx: isize) -> Option<Fd> {
         if let Ok(idx) = usize::try_from(idx) {
-            if let Some(fd) = self.fds.get(idx) {
+            if let Some(fd) = self.fds.lock().get(idx) {
                 return fd.clone();
             }
         }
@@ -129,16 +797,17 @@ impl Pcb {
 
     // 在指定的fd处插入
     pub fn fds_add(&mut self, idx: isize, fd: Fd) -> bool {
+        let mut fds = self.fds.lock();
         if let Ok(fd_ind) = usize::try_from(idx) {
-            if self.fds.len() <= fd_ind && fd_ind < MAX_FDS {
-                for _ in 0..fd_ind - self.fds.len() {
-                    self.fds.push(None)
+            if fds.len() <= fd_ind && fd_ind < MAX_FDS {
+                for _ in 0..fd_ind - fds.len() {
+                    fds.push(None)
                 }
-                self.fds.push(Some(fd));
+                fds.push(Some(fd));
                 return true;
-            } else if self.fds.len() > fd_ind {
-                if let None = self.get_fd(idx) {
-                    self.fds[fd_ind] = Some(fd);
+            } else if fds.len() > fd_ind {
+                if let None = fds[fd_ind] {
+                    fds[fd_ind] = Some(fd);
                     return true;
                 }
             }
@@ -148,23 +817,23 @@ impl Pcb {
 
     // 找到空闲的位置插入File，或者push
     pub fn fds_insert(&mut self, fd: Fd) -> Option<usize> {
-        match self.fds.iter_mut().enumerate().find(|(_, fd)| fd.is_none()) {
+        let mut fds = self.fds.lock();
+        match fds.iter_mut().enumerate().find(|(_, fd)| fd.is_none()) {
             Some((idx, pos)) => {
                 *pos = Some(fd);
                 return Some(idx);
             }
             None => {
-                self.fds.push(Some(fd));
-                return Some(self.fds.len() - 1);
+                fds.push(Some(fd));
+                return Some(fds.len() - 1);
             }
         }
     }
 
     pub fn fds_close(&mut self, idx: isize) -> bool {
         if let Ok(fd_ind) = usize::try_from(idx) {
-            if let Some(_) = self.get_fd(idx) {
-                self.fds[fd_ind] = None;
-                return true;
+            if let Some(fd) = self.fds.lock().get_mut(fd_ind) {
+                return fd.take().is_some();
             }
         }
         false
@@ -173,11 +842,6 @@ impl Pcb {
     /**
      * 进程状态相关
      */
-    // 表明进程可以从阻塞态变为就绪态
-    pub fn non_block(&mut self) -> bool {
-        self.block_fn.clone().unwrap()(self)
-    }
-
     pub fn state(&self) -> PcbState {
         self.state
     }
@@ -188,10 +852,58 @@ impl Pcb {
         old_state
     }
 
-    pub fn exit(&mut self, xcode: isize) {
-        self.state = PcbState::Zombie(xcode);
-        // 进程退出就把打开的文件关闭
-        self.fds.clear();
+    // 线程退出，wstatus由exit_wstatus或signal_wstatus得到
+    pub fn exit(&mut self, wstatus: isize) {
+        self.state = PcbState::Zombie(wstatus);
+        // CLONE_CHILD_CLEARTID: 将tid清零，pthread_join等待这个值变为0
+        if self.clear_child_tid != 0 {
+            let ctid = VirtualAddr(self.clear_child_tid);
+            self.clear_child_tid = 0;
+            // 与Linux相同按共享futex唤醒，没有共享的等待者时唤醒同一内存空间中的私有等待者
+            let mut ms = self.memory_space.lock();
+            match ms.write_user(ctid, &0u32).and_then(|_| ms.user_paddr(ctid)) {
+                Ok(word) => {
+                    if futex_wake(FutexKey::new(false, ms.id(), ctid, word), 1) == 0 {
+                        futex_wake(FutexKey::new(true, ms.id(), ctid, word), 1);
+                    }
+                }
+                Err(e) => {
+                    log!("pcb":"exit">"pid({}) clear child tid {:?}", self.pid, e);
+                }
+            }
+        }
+        self.waiter = None;
+        // 线程退出就不再引用文件描述符表，最后一个线程退出时关闭打开的文件
+        self.fds = Arc::new(Mutex::new(Vec::new()));
+        // 在thread_exit之前累加，wait4看到退出状态时时间已经统计完成
+        let mut times = self.thread_group.times.lock();
+        times.0 += self.utimes + self.cutimes;
+        times.1 += self.stimes + self.cstimes;
+        drop(times);
+        // 组长退出时其他线程可能还在运行，最后一个线程退出时才通知父进程
+        let leader_status = if self.is_group_leader() { Some(wstatus) } else { None };
+        let status = match self.thread_group.thread_exit(self.pid, leader_status) {
+            Some(status) => status,
+            None => {
+                self.thread_group.hand_over_children(self.pid);
+                return;
+            }
+        };
+        // 子进程属于整个进程，最后一个线程退出时才交给init
+        self.thread_group.reparent_children();
+        self.thread_group.notify_parent(SigInfo::child_exit(self.tgid, status));
+        // init退出后孤儿进程无法回收，直接关机
+        if self.tgid == INIT_PID {
+            println!("init exited with status 0x{:x}, shutting down", status);
+            log!("pcb":"remain">"{}", unsafe { DROPPCBS.lock() });
+            log!("kalloc":"remain">"{:?}", crate::mm::kalloc_stats());
+            crate::sbi::shutdown();
+        }
+    }
+
+    // 进程停止或继续时通知父进程，唤醒父进程的wait4
+    pub fn notify_parent(&self, code: i32, signal: Signal) {
+        self.thread_group.notify_parent(SigInfo::child_job(self.tgid, code, signal));
     }
 
     /**
@@ -232,85 +944,186 @@ impl Pcb {
     /**
      * 信号处理
      */
-    pub fn get_sigaction(&mut self, signal: Signal) -> SigAction {
-        let act = self
-            .sabinds
-            .iter_mut()
-            .find(|(sig, _)| if *sig == signal { true } else { false });
-        if let None = act {
-            sigactionbinds_default(signal)
-        } else {
-            act.unwrap().clone().1
+    pub fn get_sigaction(&self, signal: Signal) -> SigAction {
+        match self.sabinds.lock().get(&signal) {
+            Some(act) if act.sa_handler == SIG_IGN => SigAction::Ign,
+            Some(act) if act.sa_handler != SIG_DFL => SigAction::Custom(*act),
+            _ => sigactionbinds_default(signal),
         }
     }
 
-    pub fn sigaction_bind(&mut self, signal: Signal, act: SigAction) {
+    // 设置信号的处理函数，返回原来的设置
+    pub fn sigaction_bind(&mut self, signal: Signal, act: CustomSigAction) -> CustomSigAction {
         log!("signal":"bind">"signal({:?}) -> handler({:?})", signal, act);
-        self.sabinds.push((signal, act));
+        let mut sabinds = self.sabinds.lock();
+        let old = if act.sa_handler == SIG_DFL {
+            sabinds.remove(&signal)
+        } else {
+            sabinds.insert(signal, act)
+        };
+        old.unwrap_or_default()
+    }
+
+    pub fn sigaction_get(&self, signal: Signal) -> CustomSigAction {
+        self.sabinds.lock().get(&signal).copied().unwrap_or_default()
+    }
+
+    // execve之后原来的处理函数和备用信号栈不再存在，恢复默认处理，被忽略的信号保持忽略
+    pub fn reset_sigactions(&mut self) {
+        let mut sabinds = self.sabinds.lock().clone();
+        sabinds.retain(|_, act| act.sa_handler == SIG_IGN);
+        self.sabinds = Arc::new(Mutex::new(sabinds));
+        self.sigaltstack = SignalStack::disabled();
+    }
+
+    // 用户程序的错误(非法指令、访问无效地址等)产生的信号
+    //     被屏蔽或忽略时恢复默认处理并解除屏蔽，不能让进程回到出错的指令继续执行
+    pub fn force_signal(&mut self, info: SigInfo) {
+        let signal = Signal::from_signum(info.si_signo as usize).unwrap();
+        let mask = self.sigqueue.mask();
+        if mask.contains(signal) || matches!(self.get_sigaction(signal), SigAction::Ign) {
+            self.sigaction_bind(signal, CustomSigAction::default());
+            self.sigqueue.set_mask(mask - signal);
+        }
+        log!("signal":"force">"pid({}) signal({:?}) code({}) addr(0x{:x})", self.pid, signal, info.si_code, info.si_addr());
+        sigqueue_send_info(self.pid, info);
+    }
+
+    // 允许运行的hart
+    pub fn affinity(&self) -> usize {
+        self.affinity.load(Ordering::SeqCst)
     }
 
-    pub fn try_handle_signal(&mut self) -> PcbState {
-        // 信号处理
-        // 应该加上一层循环，等待所有信号处理完毕后再调度
-        while let Some(signal) = sigqueue_fetch(self.pid) {
+    // 线程组中的所有线程以wstatus退出
+    fn exit_group(&mut self, wstatus: isize) {
+        self.thread_group.exit(wstatus);
+        self.exit(wstatus);
+    }
+
+    // 返回用户态之前处理未屏蔽的信号，需要调用处理函数时修改trapframe，返回用户态后进入处理函数
+    pub fn try_handle_signal(&mut self) {
+        let restart = self.syscall_restart.take();
+        while let Some((signal, info)) = self.sigqueue.fetch(&self.thread_group.signals) {
             log!("signal":"handle">"pid({}) try handle signal({:?})", self.pid, signal);
-            let act = self.get_sigaction(signal);
-            match act {
-                SigAction::Cont => {
-                    // 不做处理
-                    continue;
-                }
-                SigAction::Term => {
-                    self.exit(-1);
-                    return PcbState::Zombie(-1);
-                }
-                SigAction::Core => {
-                    self.exit(-1);
-                    return PcbState::Zombie(-1);
+            match self.get_sigaction(signal) {
+                // 发送SIGCONT时已经继续运行(见kill_group)
+                SigAction::Cont | SigAction::Ign => continue,
+                action @ (SigAction::Term | SigAction::Core) => {
+                    let core_dump = matches!(action, SigAction::Core);
+                    self.exit_group(signal_wstatus(signal, core_dump));
+                    return;
                 }
+                // 停止线程组，由trap::user_return等待SIGCONT，之后再处理剩下的信号
                 SigAction::Stop => {
-                    self.exit(-1);
-                    return PcbState::Zombie(-1);
+                    if self.thread_group.stop(signal) {
+                        log!("signal":"stop">"pid({}) stopped by {:?}", self.pid, signal);
+                        self.notify_parent(CLD_STOPPED, signal);
+                    }
+                    return;
                 }
-                SigAction::Ign => {
-                    // 不做处理
-                    continue;
+                // SA_NOCLDSTOP: 子进程停止或继续时不调用处理函数
+                SigAction::Custom(act)
+                    if signal == Signal::SIGCHLD
+                        && matches!(info.si_code, CLD_STOPPED | CLD_CONTINUED)
+                        && act.sa_flags.contains(SaFlags::SA_NOCLDSTOP) =>
+                {
+                    continue
                 }
                 SigAction::Custom(act) => {
-                    // 使用原有的用户栈
-                    // 暂时不支持处理信号时处理其他信号
-                    // 不支持使用新的信号处理栈
-                    log!("signal":"handle">"pid({}) custom action", self.pid);
-                    let mask = sigqueue_mask(self.pid, act.borrow().sa_mask);
-                    let oldsp = self.trapframe()["sp"];
-                    let oldtf = self.swap_trapframe(act.borrow().trapframe);
-                    self.trapframe().init(oldsp, act.borrow().sa_handler);
-                    self.trapframe()["a0"] = signal.bits();
-                    self.set_state(PcbState::SigHandling(oldtf, mask));
-                    return PcbState::SigHandling(oldtf, mask);
+                    // 被中断的系统调用在处理函数返回后重新执行，或者返回EINTR
+                    if let Some(a0) = restart {
+                        if act.sa_flags.contains(SaFlags::SA_RESTART) {
+                            self.restart_syscall(a0);
+                        } else {
+                            self.trapframe()["a0"] = Errno::EINTR.as_ret();
+                        }
+                    }
+                    // 无法在栈上压入信号帧，与Linux相同以SIGSEGV终止
+                    if let Err(e) = self.enter_sighandler(signal, info, act) {
+                        log!("signal":"handle">"pid({}) failed to enter handler {:?}", self.pid, e);
+                        self.exit_group(signal_wstatus(Signal::SIGSEGV, true));
+                    }
+                    return;
                 }
             }
         }
-        self.set_state(PcbState::Running);
-        PcbState::Running
+        // 没有调用处理函数，被中断的系统调用直接重新执行
+        if let Some(a0) = restart {
+            self.restart_syscall(a0);
+        }
+        if let Some(mask) = self.saved_sigmask.take() {
+            self.sigqueue.set_mask(mask);
+        }
     }
 
-    // 从信号处理的上下文中恢复
-    pub fn signal_return(&mut self) {
-        if let PcbState::SigHandling(tf, mask) = self.state() {
-            log!("signal":"return">"pid({})", self.pid);
-            self.swap_trapframe(tf);
-            sigqueue_mask(self.pid, mask);
+    // 回到ecall重新执行系统调用
+    fn restart_syscall(&mut self, a0: usize) {
+        let trapframe = self.trapframe();
+        trapframe["a0"] = a0;
+        trapframe["sepc"] -= 4;
+    }
+
+    // 在用户栈或备用信号栈上压入siginfo和被中断的上下文，从处理函数开始执行
+    //     处理函数返回到sigreturn跳板，由sigreturn恢复上下文，处理函数可以嵌套
+    fn enter_sighandler(
+        &mut self,
+        signal: Signal,
+        info: SigInfo,
+        act: CustomSigAction,
+    ) -> Result<(), Errno> {
+        // sigsuspend设置的临时掩码在处理函数返回后恢复为原来的掩码
+        let mask = match self.saved_sigmask.take() {
+            Some(mask) => mask,
+            None => self.sigqueue.mask(),
+        };
+        let sp = self.trapframe()["sp"];
+        let stack = self.sigaltstack.status(sp);
+        // 已经在备用信号栈上时(嵌套的处理函数)继续使用当前栈
+        let sp = if act.sa_flags.contains(SaFlags::SA_ONSTACK) && stack.ss_flags == 0 {
+            stack.ss_sp + stack.ss_size
         } else {
-            panic!("Not handling signal");
+            sp
+        };
+        let frame = SigFrame::new(info, mask, stack, self.trapframe());
+        let frame_va = sp.checked_sub(size_of::<SigFrame>()).ok_or(Errno::EFAULT)?;
+        let frame_va = VirtualAddr(frame_va & !0xf);
+        self.memory_space.lock().write_user(frame_va, &frame)?;
+
+        let mut handler_mask = mask | act.sa_mask;
+        if !act.sa_flags.contains(SaFlags::SA_NODEFER) {
+            handler_mask |= signal;
+        }
+        self.sigqueue.set_mask(handler_mask);
+        if act.sa_flags.contains(SaFlags::SA_RESETHAND) {
+            self.sigaction_bind(signal, CustomSigAction::default());
         }
+
+        log!("signal":"handle">"pid({}) enter handler 0x{:x}, frame 0x{:x}", self.pid, act.sa_handler, frame_va.0);
+        let trapframe = self.trapframe();
+        trapframe["sepc"] = act.sa_handler;
+        trapframe["ra"] = SIGRETURN_TRAMPOLINE;
+        trapframe["sp"] = frame_va.0;
+        trapframe["a0"] = signal.signum();
+        trapframe["a1"] = frame_va.0;
+        trapframe["a2"] = frame_va.0 + size_of::<SigInfo>();
+        Ok(())
     }
 
-    // 交换trapframe
-    fn swap_trapframe(&mut self, tf: PageNum) -> PageNum {
-        let old = self.memory_space.trapframe;
-        self.memory_space.trapframe = tf;
-        old
+    // 从信号处理函数返回，sp指向enter_sighandler压入的SigFrame，恢复其中的上下文和信号掩码
+    //     用户可能修改了ucontext，只恢复通用寄存器、pc和浮点寄存器
+    pub fn signal_return(&mut self) -> Result<(), Errno> {
+        let sp = self.trapframe()["sp"];
+        let uc_va = sp.checked_add(size_of::<SigInfo>()).ok_or(Errno::EFAULT)?;
+        let uc: UContext = self.memory_space.lock().read_user(VirtualAddr(uc_va))?;
+        log!("signal":"return">"pid({}) sepc 0x{:x}", self.pid, uc.uc_mcontext.gregs[0]);
+        let trapframe = self.trapframe();
+        trapframe.general_reg[1..].copy_from_slice(&uc.uc_mcontext.gregs[1..]);
+        trapframe["sepc"] = uc.uc_mcontext.gregs[0];
+        trapframe.fregs = uc.uc_mcontext.fregs;
+        trapframe.fcsr = uc.uc_mcontext.fcsr as usize;
+        trapframe.invalidate_fp();
+        self.sigqueue.set_mask(Signal::from_bits_truncate(uc.uc_sigmask.bits()));
+        Ok(())
     }
 }
 
@@ -322,5 +1135,6 @@ impl Drop for Pcb {
         }
         log!("pcb":"drop">"pid({})", self.pid);
         sigqueue_clear(self.pid);
+        AFFINITIES.write().remove(&self.pid);
     }
 }
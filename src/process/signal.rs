@@ -1,18 +1,57 @@
-use core::cell::RefCell;
-
+use super::cpu::hartid;
 use super::Pid;
-use crate::mm::kalloc::KALLOCATOR;
-use crate::mm::PageNum;
-use alloc::collections::BTreeMap;
+use super::TrapFrame;
+use crate::sbi::sbi_send_ipi;
+use crate::task::Waiter;
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::sync::Arc;
-use alloc::vec::Vec;
-use spin::RwLock;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::{Mutex, RwLock};
+
+/**
+ * 每个线程的信号队列，Pcb和SIGQUEUES各持有一个Arc，发送信号时不需要持有Pcb的锁
+ *     pending和mask是原子的位图，检查是否有信号、修改掩码时不需要加锁
+ *     siginfo由pending自己的锁保护，只有这个线程和向它发送信号的线程竞争
+ *     发送信号时先保存siginfo再设置pending，取出信号时先清除pending再释放锁，看到pending时siginfo一定已经保存
+ * 发送给进程的信号保存在线程组共享的SigPending中(见ThreadGroup::send_signal)，由任意一个没有屏蔽它的线程处理
+ *     线程检查和取出信号时同时检查自己的和进程的pending
+ */
+pub struct SigQueue {
+    pid: Pid,
+    // 只发送给这个线程的信号，比如用户程序的错误产生的信号
+    pending: SigPending,
+    mask: AtomicUsize,
+    // 线程阻塞时的Waiter，收到未屏蔽的信号时唤醒，被中断的系统调用返回EINTR或重新执行
+    waiter: Mutex<Option<Arc<Waiter>>>,
+    // 正在运行这个线程的hart，收到未屏蔽的信号时通过IPI让它陷入内核，不用等到下一次时钟中断
+    hart: AtomicUsize,
+}
+
+// 等待处理的信号和它们的siginfo
+pub struct SigPending {
+    pending: AtomicUsize,
+    infos: Mutex<SigInfos>,
+}
+
+struct SigInfos {
+    // 标准信号不排队，每个信号只保存第一次发送时的siginfo
+    info: [SigInfo; SIGRTMIN],
+    // 实时信号排队，每次发送都保存siginfo，同一个信号按发送的顺序处理
+    rt: VecDeque<SigInfo>,
+}
+
+// 线程没有在任何hart上运行
+const NO_HART: usize = usize::MAX;
 
-// Feature: 使用AtomicUsize原子类型
 lazy_static! {
-    static ref SIGQUEUE: RwLock<BTreeMap<Pid, (Signal, Signal)>> = RwLock::new(BTreeMap::new());
+    // 按线程id查找信号队列，只在发送信号和创建、释放线程时使用
+    static ref SIGQUEUES: RwLock<BTreeMap<Pid, Arc<SigQueue>>> = RwLock::new(BTreeMap::new());
 }
-pub const SIGTMIN: usize = 32;
+// 实时信号的编号范围，编号小于SIGRTMIN的是标准信号
+pub const SIGRTMIN: usize = 32;
+pub const SIGRTMAX: usize = 64;
+// 每个线程最多排队的实时信号，超过时用户发送的实时信号返回EAGAIN
+pub const SIGQUEUE_MAX: usize = 64;
 bitflags! {
     pub struct Signal: usize{
         const	SIGHUP		= 1 << ( 1-1);
@@ -45,6 +84,10 @@ bitflags! {
         const	SIGPROF		= 1 << (27-1);
         const	SIGWINCH	= 1 << (28-1);
         const	SIGIO		= 1 << (29-1);
+        const	SIGPWR		= 1 << (30-1);
+        const	SIGSYS		= 1 << (31-1);
+        // 所有实时信号，不是一个单独的信号，让bitflags保留SIGRTMIN..=SIGRTMAX对应的位
+        const	SIGRT		= !0 << (SIGRTMIN-1);
     }
     pub struct SaFlags: usize{
         const SA_NOCLDSTOP = 1		   ;     /* Don't send SIGCHLD when children stop.  */
@@ -58,97 +101,433 @@ bitflags! {
     }
 }
 
-pub fn sigqueue_send(pid: Pid, signal: Signal) {
-    // 目前signal只能为单个信号
-    if let Some((pending, mask)) = SIGQUEUE.write().get_mut(&pid) {
-        if signal & *mask == Signal::empty() {
-            *pending |= signal;
-            log!("signal":"send">"successed (pid({}), signal({:?}))", pid, signal);
-        } else {
-            log!("signal":"send">"masked (pid({}), signal({:?}))", pid, signal);
+impl Signal {
+    // 信号编号从1开始，第n个信号对应第n-1位
+    pub fn from_signum(signum: usize) -> Option<Signal> {
+        if signum == 0 || signum > SIGRTMAX {
+            return None;
         }
-    } else {
-        // 不存在，表明进程已经退出
+        Signal::from_bits(1 << (signum - 1))
+    }
+
+    // 编号最小的信号
+    pub fn signum(&self) -> usize {
+        self.bits().trailing_zeros() as usize + 1
+    }
+
+    pub fn is_realtime(&self) -> bool {
+        self.signum() >= SIGRTMIN
     }
 }
-pub fn sigqueue_clear(pid: Pid) {
-    // 清除进程的sigqueue
-    log!("signal":"clear">"pid({})", pid);
-    SIGQUEUE.write().remove(&pid);
+
+// 不能被捕获、忽略和屏蔽的信号
+pub const SIG_UNBLOCKABLE: Signal = Signal::from_bits_truncate(Signal::SIGKILL.bits() | Signal::SIGSTOP.bits());
+// 默认处理方式为停止进程的信号，发送SIGCONT时丢弃
+pub const SIG_STOPS: Signal = Signal::from_bits_truncate(
+    Signal::SIGSTOP.bits() | Signal::SIGTSTP.bits() | Signal::SIGTTIN.bits() | Signal::SIGTTOU.bits(),
+);
+
+// siginfo_t的si_code
+pub const SI_USER: i32 = 0;
+pub const SI_KERNEL: i32 = 0x80;
+pub const SI_QUEUE: i32 = -1;
+pub const SI_TKILL: i32 = -6;
+pub const CLD_EXITED: i32 = 1;
+pub const CLD_KILLED: i32 = 2;
+pub const CLD_DUMPED: i32 = 3;
+pub const CLD_STOPPED: i32 = 5;
+pub const CLD_CONTINUED: i32 = 6;
+// 用户程序的错误产生的信号
+//     Note: RISC-V的整数除零和浮点异常不会陷入，硬件不会产生SIGFPE
+pub const ILL_ILLOPC: i32 = 1;
+pub const SEGV_MAPERR: i32 = 1;
+pub const SEGV_ACCERR: i32 = 2;
+pub const BUS_ADRALN: i32 = 1;
+pub const TRAP_BRKPT: i32 = 1;
+
+/**
+ * siginfo_t，共128字节
+ * 前三个字段之后是联合体，这里只使用kill、SIGCHLD和错误信号的部分:
+ *     kill:    si_pid, si_uid
+ *     sigqueue: si_pid, si_uid, si_value，si_value与si_status位于相同的位置，内核不需要解释
+ *     SIGCHLD: si_pid, si_uid, si_status
+ *     SIGSEGV、SIGBUS、SIGILL、SIGTRAP: si_addr，与si_pid和si_uid位于相同的8个字节
+ */
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SigInfo {
+    pub si_signo: i32,
+    pub si_errno: i32,
+    pub si_code: i32,
+    _pad: i32,
+    pub si_pid: i32,
+    pub si_uid: u32,
+    pub si_status: i32,
+    _reserved: [i32; 25],
 }
 
-pub fn sigqueue_init(pid: Pid) {
-    log!("signal":"init">"pid({})", pid);
-    if let Some(_) = SIGQUEUE
-        .write()
-        .insert(pid, (Signal::empty(), Signal::empty()))
-    {
-        panic!("dumplicated sigqueue for pid {}", pid)
+impl SigInfo {
+    pub fn new(signal: Signal, code: i32, pid: Pid) -> Self {
+        Self {
+            si_signo: signal.signum() as i32,
+            si_code: code,
+            si_pid: pid as i32,
+            ..Default::default()
+        }
+    }
+
+    // 子进程退出时发送给父进程的SIGCHLD，被信号终止时si_status为信号，否则为退出码
+    pub fn child_exit(pid: Pid, wstatus: isize) -> Self {
+        let (code, status) = match wstatus & 0x7f {
+            0 => (CLD_EXITED, (wstatus >> 8) & 0xff),
+            signum if wstatus & 0x80 != 0 => (CLD_DUMPED, signum),
+            signum => (CLD_KILLED, signum),
+        };
+        Self {
+            si_status: status as i32,
+            ..Self::new(Signal::SIGCHLD, code, pid)
+        }
+    }
+
+    // 用户程序的错误产生的信号，addr为出错的地址
+    pub fn fault(signal: Signal, code: i32, addr: usize) -> Self {
+        Self {
+            si_pid: addr as u32 as i32,
+            si_uid: (addr >> 32) as u32,
+            ..Self::new(signal, code, 0)
+        }
+    }
+
+    pub fn si_addr(&self) -> usize {
+        self.si_pid as u32 as usize | (self.si_uid as usize) << 32
+    }
+
+    // 子进程停止(CLD_STOPPED)或继续(CLD_CONTINUED)时发送给父进程的SIGCHLD，si_status为信号
+    pub fn child_job(pid: Pid, code: i32, signal: Signal) -> Self {
+        Self {
+            si_status: signal.signum() as i32,
+            ..Self::new(Signal::SIGCHLD, code, pid)
+        }
     }
 }
 
-pub fn sigqueue_pending(pid: Pid) -> bool {
-    if let Some((pending, _)) = SIGQUEUE.read().get(&pid) {
-        return !pending.is_empty();
+// stack_t，描述信号处理使用的栈
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SignalStack {
+    pub ss_sp: usize,
+    pub ss_flags: i32,
+    pub ss_size: usize,
+}
+
+// sigaltstack的ss_flags
+pub const SS_ONSTACK: i32 = 1;
+pub const SS_DISABLE: i32 = 2;
+// 备用信号栈的最小大小
+pub const MINSIGSTKSZ: usize = 2048;
+
+impl SignalStack {
+    pub fn disabled() -> Self {
+        Self {
+            ss_flags: SS_DISABLE,
+            ..Default::default()
+        }
+    }
+
+    // sp是否在备用信号栈上，栈从高地址向低地址增长
+    pub fn contains(&self, sp: usize) -> bool {
+        self.ss_flags != SS_DISABLE && sp > self.ss_sp && sp - self.ss_sp <= self.ss_size
+    }
+
+    // 返回给用户的状态，ss_flags为SS_DISABLE、SS_ONSTACK或0
+    pub fn status(&self, sp: usize) -> Self {
+        Self {
+            ss_flags: if self.contains(sp) { SS_ONSTACK } else { self.ss_flags },
+            ..*self
+        }
     }
-    false
 }
 
-pub fn sigqueue_mask(pid: Pid, mask: Signal) -> Signal {
-    let mut sigqueue = SIGQUEUE.write();
-    let (_, oldmask) = sigqueue.get_mut(&pid).unwrap();
-    let old = oldmask.clone();
-    *oldmask = mask;
-    old
+/**
+ * struct sigcontext，被中断时的寄存器
+ *     gregs与TrapFrame的general_reg顺序相同，只是第一个位置保存pc而不是zero
+ *     浮点部分使用__riscv_d_ext_state的格式，大小按照__riscv_q_ext_state为528字节
+ */
+#[repr(C, align(16))]
+#[derive(Clone, Copy)]
+pub struct MContext {
+    pub gregs: [usize; 32],
+    pub fregs: [usize; 32],
+    pub fcsr: u32,
+    _reserved: [u32; 67],
 }
 
-// 返回一个信号并且将sigqueue里相应的pending清空
-pub fn sigqueue_fetch(pid: Pid) -> Option<Signal> {
-    let mut sigqueue = SIGQUEUE.write();
-    if let Some((pending, _)) = sigqueue.get_mut(&pid) {
-        for i in 0..SIGTMIN {
-            let testsig = Signal::from_bits(1 << i).unwrap_or(Signal::empty());
-            if *pending & testsig != Signal::empty() {
-                *pending &= !testsig;
-                return Some(testsig);
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct UContext {
+    pub uc_flags: usize,
+    pub uc_link: usize,
+    pub uc_stack: SignalStack,
+    pub uc_sigmask: Signal,
+    // sigset_t按1024位预留
+    _unused: [u8; 120],
+    pub uc_mcontext: MContext,
+}
+
+// 信号处理函数被调用时压入用户栈的内容，SA_SIGINFO的处理函数通过第二、三个参数访问
+//     处理函数返回到sigreturn跳板时sp指向SigFrame，sigreturn从uc恢复被中断的上下文
+#[repr(C)]
+pub struct SigFrame {
+    pub info: SigInfo,
+    pub uc: UContext,
+}
+
+impl SigFrame {
+    pub fn new(info: SigInfo, mask: Signal, stack: SignalStack, tf: &TrapFrame) -> Self {
+        let mut gregs = tf.general_reg;
+        gregs[0] = tf.sepc;
+        Self {
+            info,
+            uc: UContext {
+                uc_flags: 0,
+                uc_link: 0,
+                uc_stack: stack,
+                uc_sigmask: mask,
+                _unused: [0; 120],
+                uc_mcontext: MContext {
+                    gregs,
+                    fregs: tf.fregs,
+                    fcsr: tf.fcsr as u32,
+                    _reserved: [0; 67],
+                },
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendErr {
+    // 进程不存在或者已经退出
+    NoProcess,
+    // 排队的实时信号达到SIGQUEUE_MAX
+    QueueFull,
+}
+
+impl Default for SigPending {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SigPending {
+    pub fn new() -> Self {
+        Self {
+            pending: AtomicUsize::new(0),
+            infos: Mutex::new(SigInfos {
+                info: [SigInfo::default(); SIGRTMIN],
+                rt: VecDeque::new(),
+            }),
+        }
+    }
+
+    // 所有pending的信号，包括被屏蔽的
+    pub fn pending(&self) -> Signal {
+        Signal::from_bits_truncate(self.pending.load(Ordering::SeqCst))
+    }
+
+    // 标准信号已经pending时再次发送不起作用，实时信号每次发送都排队
+    pub fn push(&self, info: SigInfo, limit: bool) -> Result<(), SendErr> {
+        let signal = Signal::from_signum(info.si_signo as usize).unwrap();
+        let mut infos = self.infos.lock();
+        if signal.is_realtime() {
+            if limit && infos.rt.len() >= SIGQUEUE_MAX {
+                return Err(SendErr::QueueFull);
             }
+            infos.rt.push_back(info);
+        } else if !self.pending().contains(signal) {
+            infos.info[signal.signum()] = info;
+        }
+        self.pending.fetch_or(signal.bits(), Ordering::SeqCst);
+        Ok(())
+    }
+
+    // 丢弃pending的信号，SIGCONT和停止信号互相抵消
+    pub fn discard(&self, signals: Signal) {
+        let mut infos = self.infos.lock();
+        self.pending.fetch_and(!signals.bits(), Ordering::SeqCst);
+        infos.rt.retain(|info| !signals.contains(Signal::from_signum(info.si_signo as usize).unwrap()));
+    }
+
+    // 返回编号最小的未屏蔽信号和它的siginfo，并清空相应的pending
+    //     标准信号的编号都小于实时信号，先于实时信号处理
+    //     实时信号每次取出最早发送的一个，队列中没有这个信号时才清空pending
+    fn fetch(&self, mask: Signal) -> Option<(Signal, SigInfo)> {
+        let mut infos = self.infos.lock();
+        let deliverable = self.pending() - mask;
+        if deliverable.is_empty() {
+            return None;
+        }
+        let signum = deliverable.signum();
+        let signal = Signal::from_bits_truncate(1 << (signum - 1));
+        if !signal.is_realtime() {
+            self.pending.fetch_and(!signal.bits(), Ordering::SeqCst);
+            return Some((signal, infos.info[signum]));
+        }
+        let index = infos.rt.iter().position(|info| info.si_signo as usize == signum).unwrap();
+        let info = infos.rt.remove(index).unwrap();
+        if !infos.rt.iter().any(|info| info.si_signo as usize == signum) {
+            self.pending.fetch_and(!signal.bits(), Ordering::SeqCst);
         }
-        None
-    } else {
-        None
+        Some((signal, info))
     }
 }
 
-#[derive(Clone, Debug)]
+impl SigQueue {
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    // 只发送给这个线程的pending的信号，包括被屏蔽的
+    pub fn pending(&self) -> Signal {
+        self.pending.pending()
+    }
+
+    pub fn mask(&self) -> Signal {
+        Signal::from_bits_truncate(self.mask.load(Ordering::SeqCst))
+    }
+
+    // 线程自己或者进程是否有未屏蔽的信号等待处理，shared为线程组共享的pending
+    pub fn has_deliverable(&self, shared: &SigPending) -> bool {
+        !((self.pending() | shared.pending()) - self.mask()).is_empty()
+    }
+
+    // 设置信号掩码，返回原来的掩码，SIGKILL和SIGSTOP不能被屏蔽
+    //     只有线程自己修改掩码，解除屏蔽的信号在返回用户态前处理，不需要唤醒
+    pub fn set_mask(&self, mask: Signal) -> Signal {
+        Signal::from_bits_truncate(self.mask.swap((mask - SIG_UNBLOCKABLE).bits(), Ordering::SeqCst))
+    }
+
+    // 阻塞时设置Waiter，收到未屏蔽的信号时唤醒，返回false表示已经有未屏蔽的信号
+    //     与kick在同一个锁中检查，发送者设置pending之后要么看到Waiter，要么这里看到pending
+    pub fn set_waiter(&self, waiter: Option<Arc<Waiter>>, shared: &SigPending) -> bool {
+        let mut current = self.waiter.lock();
+        if waiter.is_some() && self.has_deliverable(shared) {
+            return false;
+        }
+        *current = waiter;
+        true
+    }
+
+    // 线程开始在hart上运行或者让出hart，None表示没有运行
+    pub fn set_hart(&self, hart: Option<usize>) {
+        self.hart.store(hart.unwrap_or(NO_HART), Ordering::SeqCst);
+    }
+
+    // 只发送给这个线程的信号，被屏蔽的信号保持pending，解除屏蔽后处理
+    fn push(&self, info: SigInfo, limit: bool) -> Result<(), SendErr> {
+        let signal = Signal::from_signum(info.si_signo as usize).unwrap();
+        self.pending.push(info, limit)?;
+        let masked = self.mask().contains(signal);
+        log!("signal":"send">"pid({}), signal({:?}), masked({})", self.pid, signal, masked);
+        if !masked {
+            self.kick();
+        }
+        Ok(())
+    }
+
+    // 让线程尽快处理信号: 唤醒阻塞的线程，正在其他hart上运行时发送IPI
+    //     IPI在返回用户态后才会触发陷入，由trap_return让出hart，再在user_return中处理信号
+    pub fn kick(&self) {
+        if let Some(waiter) = self.waiter.lock().take() {
+            waiter.wake();
+            return;
+        }
+        let hart = self.hart.load(Ordering::SeqCst);
+        if hart != NO_HART && hart != hartid() {
+            log!("signal":"ipi">"pid({}) running on hart {}", self.pid, hart);
+            sbi_send_ipi(&(1 << hart));
+        }
+    }
+
+    pub fn discard(&self, signals: Signal) {
+        self.pending.discard(signals);
+    }
+
+    // 先取出只发送给这个线程的信号，再取出进程的信号
+    pub fn fetch(&self, shared: &SigPending) -> Option<(Signal, SigInfo)> {
+        let mask = self.mask();
+        self.pending.fetch(mask).or_else(|| shared.fetch(mask))
+    }
+}
+
+fn find_sigqueue(pid: Pid) -> Option<Arc<SigQueue>> {
+    SIGQUEUES.read().get(&pid).cloned()
+}
+
+// 内核发送的信号不受SIGQUEUE_MAX的限制
+pub fn sigqueue_send_info(pid: Pid, info: SigInfo) -> bool {
+    match find_sigqueue(pid) {
+        Some(queue) => queue.push(info, false).is_ok(),
+        None => false,
+    }
+}
+
+pub fn sigqueue_clear(pid: Pid) {
+    // 清除进程的sigqueue
+    log!("signal":"clear">"pid({})", pid);
+    SIGQUEUES.write().remove(&pid);
+}
+
+// 新线程继承创建者的信号掩码，没有pending的信号
+pub fn sigqueue_init(pid: Pid, mask: Signal) -> Arc<SigQueue> {
+    log!("signal":"init">"pid({})", pid);
+    let queue = Arc::new(SigQueue {
+        pid,
+        pending: SigPending::new(),
+        mask: AtomicUsize::new(mask.bits()),
+        waiter: Mutex::new(None),
+        hart: AtomicUsize::new(NO_HART),
+    });
+    if let Some(_) = SIGQUEUES.write().insert(pid, queue.clone()) {
+        panic!("dumplicated sigqueue for pid {}", pid)
+    }
+    queue
+}
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+// 信号的处理方式，Custom为用户设置的处理函数
+#[derive(Clone, Copy, Debug)]
 pub enum SigAction {
     Term,
     Ign,
     Core,
     Stop,
     Cont,
-    Custom(Arc<RefCell<CustomSigAction>>),
+    Custom(CustomSigAction),
 }
-unsafe impl Send for SigAction {}
-unsafe impl Sync for SigAction {}
 
-#[derive(Clone, Debug)]
+// sigaction设置的处理函数，sa_handler可以是SIG_DFL或SIG_IGN
+#[derive(Clone, Copy, Debug)]
 pub struct CustomSigAction {
     pub sa_handler: usize,
-    // pub sa_sigaction:usize,
     pub sa_mask: Signal,
     pub sa_flags: SaFlags,
-    pub trapframe: PageNum,
-    pub user_stack: PageNum,
 }
 
-impl Drop for CustomSigAction {
-    fn drop(&mut self) {
-        KALLOCATOR.lock().kfree(self.trapframe);
-        KALLOCATOR.lock().kfree(self.user_stack);
+impl Default for CustomSigAction {
+    fn default() -> Self {
+        Self {
+            sa_handler: SIG_DFL,
+            sa_mask: Signal::empty(),
+            sa_flags: SaFlags::empty(),
+        }
     }
 }
 
-pub type SigActionBinds = Vec<(Signal, SigAction)>;
+// 没有设置的信号使用默认处理
+pub type SigActionBinds = BTreeMap<Signal, CustomSigAction>;
 
 pub fn sigactionbinds_default(signal: Signal) -> SigAction {
     match signal {
@@ -181,6 +560,10 @@ pub fn sigactionbinds_default(signal: Signal) -> SigAction {
         Signal::SIGSTKFLT => SigAction::Term,
         Signal::SIGIO => SigAction::Term,
         Signal::SIGWINCH => SigAction::Ign,
+        Signal::SIGPWR => SigAction::Term,
+        Signal::SIGSYS => SigAction::Core,
+        // 实时信号的默认处理方式都是终止进程
+        _ if signal.is_realtime() => SigAction::Term,
         _ => {
             panic!("Error")
         }
@@ -1,6 +1,17 @@
+use super::cpu::current_hart;
+use super::Pid;
 use riscv::register::sstatus;
 use core::mem::size_of;
 
+// sstatus.FS记录浮点寄存器的状态，用户修改浮点寄存器后硬件将其置为Dirty
+const SSTATUS_FS: usize = 3 << 13;
+const FS_OFF: usize = 0;
+const FS_INITIAL: usize = 1 << 13;
+const FS_CLEAN: usize = 2 << 13;
+const FS_DIRTY: usize = 3 << 13;
+// 浮点寄存器没有加载到任何hart
+const NO_FP_HART: usize = usize::MAX;
+
 #[repr(C)]
 pub struct TrapFrame {
     pub general_reg: [usize; 32],
@@ -13,8 +24,16 @@ pub struct TrapFrame {
     pub kernel_satp: usize,
     pub kernel_sp: usize,
     pub trap_handler: usize,
+    // 用户态使用tp作为线程指针，内核的hartid在返回用户态时保存在这里，陷入时恢复
+    pub kernel_tp: usize,
 
     pub ra_backpu: usize,
+
+    // 浮点寄存器f0~f31，后面紧跟fcsr，由__save_fp和__restore_fp整体读写
+    pub fregs: [usize; 32],
+    pub fcsr: usize,
+    // 浮点寄存器最后一次加载到的hart，与hart的fp_owner一起判断寄存器中的值是否仍然有效
+    pub fp_hart: usize,
 }
 
 impl core::ops::Index<&str> for TrapFrame {
@@ -112,7 +131,11 @@ impl TrapFrame {
         let mut sstatus_reg = sstatus::read();
         sstatus_reg.set_spp(sstatus::SPP::User);
         sstatus_reg.set_spie(true);
-        self["sstatus"] = sstatus_reg.bits();
+        // 新的上下文从清零的浮点寄存器开始
+        self["sstatus"] = sstatus_reg.bits() & !SSTATUS_FS | FS_INITIAL;
+        self.fregs = [0; 32];
+        self.fcsr = 0;
+        self.invalidate_fp();
         self["sepc"] = sepc;
         self.trap_handler = crate::trap::trap_handler as usize;
         // 设置argv envp
@@ -124,4 +147,38 @@ impl TrapFrame {
         // envp
         self["a2"] = self["a1"] - size_of::<usize>();
     }
+
+    // 陷入内核时调用，只有用户修改过浮点寄存器时才需要保存
+    pub fn save_fp(&mut self) {
+        if self.sstatus & SSTATUS_FS == FS_DIRTY {
+            unsafe {
+                crate::trap::__save_fp(self.fregs.as_mut_ptr() as usize);
+            }
+            self.sstatus = self.sstatus & !SSTATUS_FS | FS_CLEAN;
+        }
+    }
+
+    // 返回用户态前调用，hart的浮点寄存器中仍然是这个线程的值时不需要重新加载
+    //     线程上次返回用户态时加载到这个hart，之后hart没有加载其他线程的浮点寄存器，线程也没有在其他hart上加载过
+    //     陷入时已经保存了Dirty的寄存器，这里的状态只能是Initial或Clean，寄存器与保存的值相同
+    pub fn restore_fp(&mut self, pid: Pid) {
+        if self.sstatus & SSTATUS_FS == FS_OFF {
+            return;
+        }
+        let hart = current_hart();
+        if hart.fp_owner != pid || self.fp_hart != hart.hartid {
+            unsafe {
+                crate::trap::__restore_fp(self.fregs.as_ptr() as usize);
+            }
+            hart.fp_owner = pid;
+            self.fp_hart = hart.hartid;
+        }
+        // 用户态写浮点寄存器后变为Dirty，下次陷入时保存
+        self.sstatus = self.sstatus & !SSTATUS_FS | FS_CLEAN;
+    }
+
+    // 内核修改了保存的浮点寄存器(execve、sigreturn)，下次返回用户态时重新加载
+    pub fn invalidate_fp(&mut self) {
+        self.fp_hart = NO_FP_HART;
+    }
 }
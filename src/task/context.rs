@@ -0,0 +1,39 @@
+/**
+ * 内核上下文切换
+ * 每个进程有自己的内核栈和TaskContext，系统调用可以在内核中阻塞，被唤醒后从阻塞处继续执行
+ * 每个hart的调度器运行在启动栈上，进程和调度器之间通过__switch互相切换
+ */
+use core::arch::global_asm;
+
+global_asm!(include_str!("switch.s"));
+
+extern "C" {
+    pub fn __switch(current: *mut TaskContext, next: *const TaskContext);
+}
+
+// 切换时保存的寄存器，布局与switch.s一致
+#[repr(C)]
+pub struct TaskContext {
+    ra: usize,
+    sp: usize,
+    s: [usize; 12],
+}
+
+impl TaskContext {
+    pub const fn zero() -> Self {
+        Self {
+            ra: 0,
+            sp: 0,
+            s: [0; 12],
+        }
+    }
+
+    // 第一次切换到该上下文时从entry开始运行，使用栈顶为sp的栈
+    pub fn new(entry: usize, sp: usize) -> Self {
+        Self {
+            ra: entry,
+            sp,
+            s: [0; 12],
+        }
+    }
+}
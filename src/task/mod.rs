@@ -1,83 +1,189 @@
+mod context;
+mod wait_queue;
+
+use crate::config::MAX_HARTS;
+use crate::errno::Errno;
 use crate::mm::MemorySpace;
 use crate::process::cpu::*;
 use crate::process::*;
+use crate::sbi::sbi_send_ipi;
+use crate::vfs::{parse_path, ROOT};
+use alloc::collections::VecDeque;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use spin::Mutex;
+use core::cmp::Reverse;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::{Mutex, MutexGuard};
+pub use context::*;
+pub use wait_queue::*;
 
+/**
+ * 调度器
+ * 每个hart有自己的就绪队列，被唤醒和让出的进程放入当前hart的队列，新进程放入最短的队列
+ * 自己的队列为空时从其他hart的队列尾部取走进程(work stealing)，都没有可以运行的进程时执行wfi休眠
+ * 休眠的hart在有进程加入就绪队列时由IPI唤醒，有进程等待超时时由时钟中断唤醒
+ * 进程的CPU亲和性(sched_setaffinity)在调度器取出进程时检查，不允许在当前hart上运行时转移到允许的hart
+ */
 lazy_static! {
-    // 保存所有可尝试调度的进程
-    static ref READYTASKS: Mutex<Vec<Arc<Mutex<Pcb>>>> = Mutex::new(Vec::new());
+    static ref RUNQUEUES: Vec<Mutex<VecDeque<Arc<Mutex<Pcb>>>>> =
+        (0..MAX_HARTS).map(|_| Mutex::new(VecDeque::new())).collect();
 }
 
-pub fn scheduler_load_pcb(memory_space: MemorySpace) {
-    let pcb = Arc::new(Mutex::new(Pcb::new(memory_space, 1, String::from("/"))));
+// 正在休眠或者即将休眠的hart
+static IDLE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
+// 从path加载init进程，init是第一个进程，接管孤儿进程，退出时关机
+pub fn scheduler_load_init(path: &str) -> Result<(), Errno> {
+    let inode = parse_path(&ROOT, path)?;
+    let memory_space = MemorySpace::from_elf_inode(inode)?;
+    let pcb = Pcb::new(memory_space, 0, String::from("/"))?;
+    assert_eq!(pcb.pid, pcb::INIT_PID);
+    pcb::set_init_proc(&pcb);
+    let pcb = Arc::new(Mutex::new(pcb));
     scheduler_insert_front(pcb);
+    Ok(())
 }
 
+// 新进程放入允许运行的hart中最短的队列
 pub fn scheduler_insert_front(pcb: Arc<Mutex<Pcb>>) {
-    log!("scheduler":"Ready">"pid({})", pcb.lock().pid);
-    READYTASKS.lock().insert(0, pcb);
+    let (pid, affinity) = {
+        let pcblock = pcb.lock();
+        (pcblock.pid, pcblock.affinity())
+    };
+    let hart = select_hart(affinity);
+    log!("scheduler":"Ready">"pid({}) hart({})", pid, hart);
+    enqueue(hart, pcb);
 }
 
+// 放回当前hart的就绪队列，调用者可能持有其他Pcb的锁，这里不能锁pcb
+fn scheduler_ready(pcb: Arc<Mutex<Pcb>>) {
+    enqueue(hartid(), pcb);
+}
 
+// 放在当前hart的队列头部，下一个运行
 #[allow(unused)]
 pub fn scheduler_push(pcb: Arc<Mutex<Pcb>>) {
-    READYTASKS.lock().push(pcb);
+    RUNQUEUES[hartid()].lock().push_front(pcb);
+}
+
+// 加入hart的就绪队列，有休眠的hart时唤醒一个，优先唤醒队列所在的hart
+fn enqueue(hart: usize, pcb: Arc<Mutex<Pcb>>) {
+    RUNQUEUES[hart].lock().push_back(pcb);
+    let idle = IDLE_HARTS.load(Ordering::SeqCst) & !(1 << hartid());
+    if idle != 0 {
+        let target = if idle & (1 << hart) != 0 {
+            hart
+        } else {
+            idle.trailing_zeros() as usize
+        };
+        log!("scheduler":"ipi">"wake hart {}", target);
+        sbi_send_ipi(&(1 << target));
+    }
+}
+
+// affinity中已经启动的hart里队列最短的一个
+fn select_hart(affinity: usize) -> usize {
+    let allowed = affinity & online_harts();
+    (0..MAX_HARTS)
+        .filter(|hart| allowed & (1 << hart) != 0)
+        .min_by_key(|&hart| RUNQUEUES[hart].lock().len())
+        .unwrap_or_else(hartid)
+}
+
+// 先从自己的队列头部取，为空时从其他hart的队列中取
+fn pick_next(me: usize) -> Option<Arc<Mutex<Pcb>>> {
+    let pcb = RUNQUEUES[me].lock().pop_front();
+    pcb.or_else(|| steal(me))
+}
+
+// 从最长的队列开始，取走最后加入的、允许在当前hart上运行的进程，队列头部的进程很快会被所在的hart运行
+//     不能等待进程锁(持有队列的锁)，被锁住的进程可能刚刚让出hart，跳过
+fn steal(me: usize) -> Option<Arc<Mutex<Pcb>>> {
+    let mut victims: Vec<usize> = (0..MAX_HARTS).filter(|&hart| hart != me).collect();
+    victims.sort_by_key(|&hart| Reverse(RUNQUEUES[hart].lock().len()));
+    for hart in victims {
+        let mut queue = RUNQUEUES[hart].lock();
+        let allowed = |pcb: &Arc<Mutex<Pcb>>| pcb.try_lock().map_or(false, |pcb| pcb.affinity() & (1 << me) != 0);
+        if let Some(index) = queue.iter().rposition(allowed) {
+            log!("scheduler":"steal">"hart {} from hart {}", me, hart);
+            return queue.remove(index);
+        }
+    }
+    None
 }
 
 pub fn schedule() -> ! {
-    // FCFS
     log!("scheduler":>"Enter");
+    let me = hartid();
     loop {
-        let pcb = READYTASKS.lock().pop();
-
-        if let Some(pcb) = pcb {
-            // assert!(!pcb.is_locked());
-            let state = pcb.lock().state();
-            match state {
-                PcbState::Running => match pcb.lock().try_handle_signal() {
-                    PcbState::Zombie(_) => {
-                        continue;
-                    }
-                    PcbState::Running => {}
-                    PcbState::SigHandling(_, _) => {}
-                    _ => {
-                        panic!("Invalid state");
-                    }
-                },
-                PcbState::Blocking => {
-                    let mut pcblock = pcb.lock();
-                    if pcblock.non_block() {
-                        log!("scheduler":"unblock">"");
-                        pcblock.block_fn = None;
-                        pcblock.set_state(PcbState::Running);
-                    } else {
-                        log!("scheduler":"block">"still blocking");
-                    }
-                    drop(pcblock);
-                    scheduler_insert_front(pcb);
-                    continue;
-                }
-                PcbState::SigHandling(_, _) => {}
-                _ => {
-                    panic!("invalid state pcb in tasks {:?}", state);
-                }
+        check_timers();
+        if let Some(pcb) = pick_next(me) {
+            IDLE_HARTS.fetch_and(!(1 << me), Ordering::SeqCst);
+            // 进程可能刚刚在其他hart上让出，获取锁时等待它的上下文保存完成
+            let mut pcblock = pcb.lock();
+            // 不允许在当前hart上运行，比如被其他hart唤醒或者刚刚修改了亲和性
+            if pcblock.affinity() & (1 << me) == 0 {
+                let hart = select_hart(pcblock.affinity());
+                drop(pcblock);
+                enqueue(hart, pcb);
+                continue;
             }
-            current_hart_run(pcb);
-        } else {
-            drop(pcb);
-            current_hart_leak();
-            #[cfg(feature = "batch")]
-            {
-                // 查看是否已经释放所有pcb
-                log!("scheduler":>"No ready Pcb");
-                log!("pcb":"remain">"{}", unsafe {crate::process::pcb::DROPPCBS.lock()});
-                loop {}
+            // 被唤醒的进程在task::block中继续运行
+            if let PcbState::Blocking = pcblock.state() {
+                pcblock.set_state(PcbState::Running);
             }
+            drop(pcblock);
+            current_hart_run(pcb);
+            continue;
+        }
+        current_hart_leak();
+        crate::vfs::console_poll();
+        // 先标记为休眠再检查一次就绪队列，之后加入的进程会通过IPI唤醒这个hart
+        if IDLE_HARTS.fetch_or(1 << me, Ordering::SeqCst) & (1 << me) == 0 {
+            continue;
         }
+        hart_idle(next_deadline());
+    }
+}
+
+// 切换到hart的调度器，返回时进程已经被某个hart重新调度
+//     切换时持有进程锁，调度器切换回来后才释放，其他hart在进程的上下文保存完成之前不能运行它
+fn sched(pcb: &mut MutexGuard<Pcb>) {
+    let context = &mut pcb.context as *mut TaskContext;
+    unsafe {
+        __switch(context, &current_hart().context);
+    }
+}
+
+// 当前进程让出hart，放回就绪队列
+pub fn yield_current(pcb: &mut MutexGuard<Pcb>) {
+    scheduler_ready(current_pcb().unwrap());
+    sched(pcb);
+}
+
+// 退出的进程最后一次切换到调度器，不再返回
+//     调用者不能持有Pcb的Arc，hart和调度器持有的Arc在切换之后释放，内核栈随Pcb释放
+pub fn exit_current() -> ! {
+    let context = {
+        let pcb = current_pcb().unwrap();
+        let mut pcblock = pcb.lock();
+        let context = &mut pcblock.context as *mut TaskContext;
+        core::mem::forget(pcblock);
+        context
+    };
+    unsafe {
+        __switch(context, &current_hart().context);
+    }
+    unreachable!()
+}
+
+// 新进程第一次被调度时从这里开始运行，调度器切换过来时持有进程锁
+pub extern "C" fn task_entry() -> ! {
+    unsafe {
+        current_pcb().unwrap().force_unlock();
     }
+    crate::trap::user_return()
 }
 
 pub fn current_pcb() -> Option<Arc<Mutex<Pcb>>> {
@@ -0,0 +1,28 @@
+.altmacro
+.macro SAVE_SN n
+    sd s\n, (\n+2)*8(a0)
+.endm
+.macro LOAD_SN n
+    ld s\n, (\n+2)*8(a1)
+.endm
+
+.section .text
+.globl __switch
+__switch:
+    # a0: 当前的*mut TaskContext，a1: 将要切换到的*const TaskContext
+    # 只需要保存被调用者保存的寄存器，tp是hartid，不随进程切换
+    sd ra, 0(a0)
+    sd sp, 8(a0)
+    .set n, 0
+    .rept 12
+        SAVE_SN %n
+        .set n, n+1
+    .endr
+    ld ra, 0(a1)
+    ld sp, 8(a1)
+    .set n, 0
+    .rept 12
+        LOAD_SN %n
+        .set n, n+1
+    .endr
+    ret
@@ -0,0 +1,217 @@
+/**
+ * 等待队列
+ * 进程阻塞时离开就绪队列，通过Waiter挂在所等待事件的WaitQueue上，事件发生时由唤醒者放回就绪队列
+ * 阻塞时可以设置超时，超时由调度器检查
+ * 等待的一般步骤:
+ *     1. block_current将当前进程设为阻塞并得到Waiter
+ *     2. 将Waiter加入等待队列
+ *     3. 再次检查等待的条件，已经满足时cancel_block，避免条件在1之前满足导致唤醒丢失
+ *     4. block切换到调度器，被唤醒后从这里继续执行
+ * 阻塞期间收到未屏蔽的信号也会唤醒进程，block返回BlockErr::Signal，block_killable不被信号唤醒
+ */
+use super::*;
+use alloc::collections::VecDeque;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockErr {
+    // 超过了block_current设置的时间
+    Timeout,
+    // 线程组退出，系统调用需要尽快返回
+    Interrupted,
+    // 收到未屏蔽的信号
+    Signal,
+}
+
+pub struct Waiter {
+    pid: Pid,
+    // 唤醒时取出并放回就绪队列，已唤醒的Waiter不再引用Pcb，留在其他等待队列中也不会造成循环引用
+    pcb: Mutex<Option<Arc<Mutex<Pcb>>>>,
+    // 超时的时间
+    deadline: Option<usize>,
+    timed_out: AtomicBool,
+}
+
+impl Waiter {
+    // 返回false表示已经被唤醒过
+    fn wake_up(&self, timed_out: bool) -> bool {
+        let pcb = self.pcb.lock().take();
+        match pcb {
+            Some(pcb) => {
+                log!("wait_queue":"wake">"pid({}) timed out({})", self.pid, timed_out);
+                self.timed_out.store(timed_out, Ordering::SeqCst);
+                scheduler_ready(pcb);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn wake(&self) -> bool {
+        self.wake_up(false)
+    }
+
+    pub fn is_woken(&self) -> bool {
+        self.pcb.lock().is_none()
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::SeqCst)
+    }
+}
+
+pub struct WaitQueue {
+    waiters: Mutex<VecDeque<Arc<Waiter>>>,
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WaitQueue {
+    pub fn new() -> Self {
+        Self {
+            waiters: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn add(&self, waiter: Arc<Waiter>) {
+        let mut waiters = self.waiters.lock();
+        // 清理被其他方式唤醒(超时、取消)的Waiter
+        waiters.retain(|waiter| !waiter.is_woken());
+        waiters.push_back(waiter);
+    }
+
+    // 按等待的顺序唤醒最多n个进程，返回唤醒的个数
+    pub fn wake(&self, n: usize) -> usize {
+        let mut waiters = self.waiters.lock();
+        let mut count = 0;
+        while count < n {
+            match waiters.pop_front() {
+                Some(waiter) => {
+                    if waiter.wake() {
+                        count += 1;
+                    }
+                }
+                None => break,
+            }
+        }
+        count
+    }
+
+    pub fn wake_one(&self) -> usize {
+        self.wake(1)
+    }
+
+    pub fn wake_all(&self) -> usize {
+        self.wake(usize::MAX)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.waiters.lock().iter().all(|waiter| waiter.is_woken())
+    }
+}
+
+lazy_static! {
+    // 设置了超时的Waiter
+    static ref TIMERS: Mutex<Vec<Arc<Waiter>>> = Mutex::new(Vec::new());
+}
+
+// 当前进程进入阻塞，在block中让出hart，直到返回的Waiter被唤醒或者超过deadline
+pub fn block_current(pcb: &mut Pcb, deadline: Option<usize>) -> Arc<Waiter> {
+    let waiter = Arc::new(Waiter {
+        pid: pcb.pid,
+        pcb: Mutex::new(current_pcb()),
+        deadline,
+        timed_out: AtomicBool::new(false),
+    });
+    log!("wait_queue":"block">"pid({})", pcb.pid);
+    if deadline.is_some() {
+        TIMERS.lock().push(waiter.clone());
+    }
+    // 线程组退出时唤醒所有阻塞的线程
+    pcb.thread_group.blocked.add(waiter.clone());
+    pcb.waiter = Some(waiter.clone());
+    pcb.set_state(PcbState::Blocking);
+    waiter
+}
+
+// 等待的条件已经满足，取消阻塞
+pub fn cancel_block(pcb: &mut MutexGuard<Pcb>) {
+    if let Some(waiter) = pcb.waiter.take() {
+        if waiter.pcb.lock().take().is_some() {
+            log!("wait_queue":"cancel">"pid({})", pcb.pid);
+            pcb.set_state(PcbState::Running);
+        } else {
+            // 已经被唤醒并放回就绪队列，等待调度器重新运行，不能让进程同时在两个hart上运行
+            sched(pcb);
+        }
+    }
+}
+
+// 让出hart直到block_current得到的Waiter被唤醒，已经cancel_block时直接返回
+pub fn block(pcb: &mut MutexGuard<Pcb>) -> Result<(), BlockErr> {
+    if let PcbState::Blocking = pcb.state() {
+        // 设置Waiter之前已经有未屏蔽的信号时不再阻塞
+        if pcb.sigqueue.set_waiter(pcb.waiter.clone(), &pcb.thread_group.signals) {
+            sched(pcb);
+        } else {
+            cancel_block(pcb);
+        }
+        pcb.sigqueue.set_waiter(None, &pcb.thread_group.signals);
+    }
+    let waiter = pcb.waiter.take();
+    log!("wait_queue":"resume">"pid({})", pcb.pid);
+    if pcb.thread_group.exit_code().is_some() {
+        return Err(BlockErr::Interrupted);
+    }
+    match waiter {
+        Some(waiter) if waiter.timed_out() => Err(BlockErr::Timeout),
+        _ if pcb.sigqueue.has_deliverable(&pcb.thread_group.signals) => Err(BlockErr::Signal),
+        _ => Ok(()),
+    }
+}
+
+// 与block相同，但是不被信号唤醒，只能由等待的事件或者线程组退出唤醒(线程组停止时使用)
+pub fn block_killable(pcb: &mut MutexGuard<Pcb>) -> Result<(), BlockErr> {
+    if let PcbState::Blocking = pcb.state() {
+        sched(pcb);
+    }
+    pcb.waiter.take();
+    log!("wait_queue":"resume">"pid({})", pcb.pid);
+    if pcb.thread_group.exit_code().is_some() {
+        return Err(BlockErr::Interrupted);
+    }
+    Ok(())
+}
+
+// 最早的超时时间，空闲的hart休眠到这个时间
+pub fn next_deadline() -> Option<usize> {
+    TIMERS
+        .lock()
+        .iter()
+        .filter(|waiter| !waiter.is_woken())
+        .filter_map(|waiter| waiter.deadline)
+        .min()
+}
+
+// 唤醒所有超时的Waiter，由调度器调用
+pub fn check_timers() {
+    let mut timers = TIMERS.lock();
+    if timers.is_empty() {
+        return;
+    }
+    let now = get_time();
+    timers.retain(|waiter| {
+        if waiter.is_woken() {
+            return false;
+        }
+        if waiter.deadline.unwrap() <= now {
+            waiter.wake_up(true);
+            return false;
+        }
+        true
+    });
+}
@@ -7,13 +7,20 @@ use riscv::register::{
     stval, stvec,
 };
 
+use crate::config::KERNEL_STACK_TOP;
 use crate::mm::*;
 use crate::process::cpu::*;
+use crate::process::signal::*;
+use crate::process::{Pcb, PcbState};
 use crate::task::*;
+use spin::MutexGuard;
 
 extern "C" {
     pub fn __alltraps();
     pub fn __restore(cx: usize);
+    pub fn __save_fp(fregs: usize);
+    pub fn __restore_fp(fregs: usize);
+    pub fn __sigreturn_trampoline();
 }
 
 global_asm!(include_str!("traps.s"));
@@ -24,12 +31,17 @@ pub fn init() {
     }
 }
 
-pub extern "C" fn trap_handler() {
+pub extern "C" fn trap_handler() -> ! {
+    // 其他hart可能在持有锁时等待当前hart离开用户态(见MemorySpace::shootdown)，在获取任何锁之前清除
+    hart_leave_user();
     // Fixme: Don't skip the reference lifetime checker;
-    current_pcb()
-        .unwrap()
-        .lock()
-        .utimes_add(get_time() - current_hart_set_trap_times(get_time()));
+    let pcb = current_pcb().unwrap();
+    let mut pcblock = pcb.lock();
+    pcblock.utimes_add(get_time() - current_hart_set_trap_times(get_time()));
+    // 在内核使用浮点寄存器之前保存用户的浮点上下文
+    pcblock.trapframe().save_fp();
+    drop(pcblock);
+    drop(pcb);
     let scause = scause::read();
     let stval = stval::read();
     // println!("scause {:?}, stval 0x{:x}, sepc 0x{:x}", scause.cause(), stval, riscv::register::sepc::read());
@@ -38,67 +50,104 @@ pub extern "C" fn trap_handler() {
             syscall::syscall_handler();
         }
         Trap::Exception(Exception::StoreFault) | Trap::Exception(Exception::StorePageFault) => {
-            // 判断是否是lazy
-            let va = VirtualAddr(stval::read());
+            let va = VirtualAddr(stval);
             let pcb = current_pcb().unwrap();
             let mut pcblock = pcb.lock();
-            if let Ok(_) = pcblock
-                .memory_space
-                .mmap_areas
-                .check_lazy(va, MapProt::READ)
-            {
+            let mut ms = pcblock.memory_space.lock();
+            // 判断是否是写时复制，或者其他线程已经更新了映射
+            if ms.copy_on_write(va).is_ok() || ms.is_mapped(va, PTEFlag::W) {
+                // 已经更新memory_space，由current_hart_run重新映射
+                log!("cow":"store">"copy on write va(0x{:x})", va.0);
+                drop(ms);
+            } else if let Ok(_) = ms.mmap_areas.check_lazy(va, MapProt::WRITE) {
+                // 判断是否是lazy
+                drop(ms);
                 // 已经分配物理页，由current_hart_run映射。
                 log!("mmap":"store">"Found mapped page va(0x{:x})", va.0);
-                drop(pcblock);
-                scheduler_insert_front(pcb);
-                schedule();
             } else {
                 log!("mmap":"store">"Not Found mapped page va(0x{:x})", va.0);
-                pcblock.exit(-1);
+                let code = segv_code(&ms, va);
+                drop(ms);
+                pcblock.force_signal(SigInfo::fault(Signal::SIGSEGV, code, va.0));
             }
         }
         Trap::Exception(Exception::LoadFault) | Trap::Exception(Exception::LoadPageFault) => {
             // 判断是否是lazy
-            let va = VirtualAddr(stval::read());
+            let va = VirtualAddr(stval);
             let pcb = current_pcb().unwrap();
             let mut pcblock = pcb.lock();
-            if let Ok(_) = pcblock
-                .memory_space
-                .mmap_areas
-                .check_lazy(va, MapProt::WRITE)
-            {
+            let mut ms = pcblock.memory_space.lock();
+            if ms.is_mapped(va, PTEFlag::R) || ms.mmap_areas.check_lazy(va, MapProt::READ).is_ok() {
+                drop(ms);
                 // 已经分配物理页，由current_hart_run映射。
                 log!("mmap":"load">"Found mapped page va(0x{:x})", va.0);
-                drop(pcblock);
-                scheduler_insert_front(pcb);
-                schedule();
             } else {
                 log!("mmap":"load">"Not Found mapped page va(0x{:x})", va.0);
-                pcblock.exit(-1);
+                let code = segv_code(&ms, va);
+                drop(ms);
+                pcblock.force_signal(SigInfo::fault(Signal::SIGSEGV, code, va.0));
+            }
+        }
+        Trap::Exception(Exception::InstructionFault) | Trap::Exception(Exception::InstructionPageFault) => {
+            // 可执行的mmap区域同样是lazy分配
+            let va = VirtualAddr(stval);
+            let pcb = current_pcb().unwrap();
+            let mut pcblock = pcb.lock();
+            let mut ms = pcblock.memory_space.lock();
+            if ms.is_mapped(va, PTEFlag::X) || ms.mmap_areas.check_lazy(va, MapProt::EXEC).is_ok() {
+                drop(ms);
+                log!("mmap":"exec">"Found mapped page va(0x{:x})", va.0);
+            } else {
+                log!("mmap":"exec">"Not Found mapped page va(0x{:x})", va.0);
+                let code = segv_code(&ms, va);
+                drop(ms);
+                pcblock.force_signal(SigInfo::fault(Signal::SIGSEGV, code, va.0));
             }
         }
         Trap::Exception(Exception::IllegalInstruction) => {
-            panic!(
-                "IllegalInstruction in application, core dumped. sepc:0x{:X}",
-                current_pcb().unwrap().lock().trapframe()["sepc"]
-            );
+            let pcb = current_pcb().unwrap();
+            let mut pcblock = pcb.lock();
+            let sepc = pcblock.trapframe()["sepc"];
+            pcblock.force_signal(SigInfo::fault(Signal::SIGILL, ILL_ILLOPC, sepc));
         }
-        Trap::Exception(Exception::InstructionPageFault) => {
-            use crate::mm::address::*;
-            let mut i = PhysAddr(0xdc);
-            let i: &mut usize = i.as_mut();
-            println!("is {}", i);
-            panic!(
-                "InstructionPageFault, core dumped, sepc: 0x{:x}, scause:{:?}",
-                current_pcb().unwrap().lock().trapframe()["sepc"],
-                scause.cause()
-            );
+        Trap::Exception(Exception::Breakpoint) => {
+            let pcb = current_pcb().unwrap();
+            let mut pcblock = pcb.lock();
+            let sepc = pcblock.trapframe()["sepc"];
+            pcblock.force_signal(SigInfo::fault(Signal::SIGTRAP, TRAP_BRKPT, sepc));
+        }
+        // 地址不对齐，riscv库没有单独的load地址不对齐(scause为4)
+        Trap::Exception(Exception::InstructionMisaligned) | Trap::Exception(Exception::StoreMisaligned) => {
+            current_pcb()
+                .unwrap()
+                .lock()
+                .force_signal(SigInfo::fault(Signal::SIGBUS, BUS_ADRALN, stval));
+        }
+        Trap::Exception(_) if scause.bits() == 4 => {
+            current_pcb()
+                .unwrap()
+                .lock()
+                .force_signal(SigInfo::fault(Signal::SIGBUS, BUS_ADRALN, stval));
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             log!("trap":"time_interrupt">"");
             hart_set_next_trigger();
-            scheduler_insert_front(current_hart().pcb.take().unwrap());
-            schedule();
+            // 检查是否有等待的输入
+            crate::vfs::console_poll();
+        }
+        // 其他hart向当前线程发送了信号，由trap_return和user_return处理
+        //     或者其他hart修改了映射，由trap_return经过current_hart_run重新映射
+        Trap::Interrupt(Interrupt::SupervisorSoft) => {
+            log!("trap":"ipi">"");
+            hart_clear_ipi();
+        }
+        // 其他用户态异常同样只终止进程
+        Trap::Exception(_) => {
+            log!("trap":"fault">"unsupported exception {:?}:0x{:x}, stval = {:#x}", scause.cause(), scause.bits(), stval);
+            let pcb = current_pcb().unwrap();
+            let mut pcblock = pcb.lock();
+            let sepc = pcblock.trapframe()["sepc"];
+            pcblock.force_signal(SigInfo::fault(Signal::SIGILL, ILL_ILLOPC, sepc));
         }
         _ => {
             panic!(
@@ -109,4 +158,104 @@ pub extern "C" fn trap_handler() {
             );
         }
     };
+    trap_return()
+}
+
+// 陷入处理完成，先回到调度器，由current_hart_run重新映射陷入处理中可能改变的MemorySpace，再返回用户态
+//     调用者不能持有Pcb的Arc或锁
+pub fn trap_return() -> ! {
+    let pcb = current_pcb().unwrap();
+    let mut pcblock = pcb.lock();
+    // 退出的进程不再放回就绪队列
+    if !matches!(pcblock.state(), PcbState::Zombie(_)) {
+        yield_current(&mut pcblock);
+    }
+    drop(pcblock);
+    drop(pcb);
+    user_return()
+}
+
+// 处理线程组退出、停止和信号后返回用户态，新进程第一次运行时从这里开始
+pub fn user_return() -> ! {
+    let pcb = current_pcb().unwrap();
+    let mut pcblock = pcb.lock();
+    loop {
+        // 线程组已经退出(exit_group或execve)
+        if let Some(wstatus) = pcblock.thread_group.exit_code() {
+            if !matches!(pcblock.state(), PcbState::Zombie(_)) {
+                log!("trap":"group_exit">"pid({}) exit(0x{:x})", pcblock.pid, wstatus);
+                pcblock.exit(wstatus);
+            }
+        }
+        if let PcbState::Running = pcblock.state() {
+            if pcblock.thread_group.take_continued_notify() {
+                pcblock.notify_parent(CLD_CONTINUED, Signal::SIGCONT);
+            }
+            pcblock.try_handle_signal();
+        }
+        // 线程组停止时等待SIGCONT，继续运行后重新检查线程组退出和信号
+        if let PcbState::Running = pcblock.state() {
+            if pcblock.thread_group.is_stopped() {
+                wait_continue(&mut pcblock);
+                continue;
+            }
+        }
+        break;
+    }
+    if let PcbState::Zombie(_) = pcblock.state() {
+        drop(pcblock);
+        drop(pcb);
+        exit_current();
+    }
+    // 其他hart在current_hart_run之后修改了映射时，回到调度器重新映射
+    //     持有MemorySpace的锁时检查并进入用户态，之后的修改会等待当前hart陷入
+    loop {
+        let memory_space = pcblock.memory_space.clone();
+        let ms = memory_space.lock();
+        if ms.generation() == current_hart().ms_generation {
+            hart_enter_user();
+            break;
+        }
+        drop(ms);
+        log!("trap":"return">"pid({}) remap stale memory space", pcblock.pid);
+        yield_current(&mut pcblock);
+    }
+    // 设置内核栈，下次陷入时使用
+    pcblock.trapframe().kernel_sp = KERNEL_STACK_TOP;
+    let pid = pcblock.pid;
+    pcblock.trapframe().restore_fp(pid);
+    log!("trap":"return">"pid({}) sepc: 0x{:x}", pcblock.pid, pcblock.trapframe()["sepc"]);
+    let tf = pcblock.trapframe() as *const _ as usize;
+    pcblock.stimes_add(get_time() - current_hart_set_trap_times(get_time()));
+    drop(pcblock);
+    drop(pcb);
+    unsafe {
+        __restore(tf);
+    }
+    unreachable!()
+}
+
+// 阻塞直到线程组收到SIGCONT或者退出
+fn wait_continue(pcb: &mut MutexGuard<Pcb>) {
+    let group = pcb.thread_group.clone();
+    while group.is_stopped() {
+        let waiter = block_current(pcb, None);
+        group.continued.add(waiter);
+        // SIGCONT可能在检查之后、加入等待队列之前到达
+        if !group.is_stopped() {
+            cancel_block(pcb);
+        }
+        if block_killable(pcb).is_err() {
+            break;
+        }
+    }
+}
+
+// 地址已经映射但是权限不够时为SEGV_ACCERR，否则为SEGV_MAPERR
+fn segv_code(ms: &MemorySpace, va: VirtualAddr) -> i32 {
+    if ms.is_mapped(va, PTEFlag::empty()) || ms.mmap_areas.pages().any(|page| page.vpage == va.floor()) {
+        SEGV_ACCERR
+    } else {
+        SEGV_MAPERR
+    }
 }
@@ -5,70 +5,49 @@ use core::mem::size_of;
 use core::ops::Add;
 
 use crate::config::*;
+use crate::errno::*;
 use crate::mm::*;
 use crate::process::*;
 use crate::sbi::sbi_legacy_call;
+use crate::task::*;
 use crate::user::INT;
 use crate::vfs::*;
-use spin::MutexGuard;
+use spin::{Mutex, MutexGuard};
 
 const AT_FDCWD: isize = -100;
-fn get_str(pa: &PhysAddr) -> &str {
-    let mut len = 0;
-    // Note: 将从用户空间传入的字符串大小作一个限制
-    while len < PATH_LIMITS {
-        if unsafe { *(pa.0 as *const u8).add(len) } != 0 {
-            len += 1;
-        } else {
-            break;
-        }
-    }
-    unsafe { core::str::from_utf8_unchecked(pa.as_slice(len)) }
-}
-
-fn get_cstr(pa: &PhysAddr) -> &str {
-    let mut len = 0;
-    // Note: 将从用户空间传入的字符串大小作一个限制
-    while len < PATH_LIMITS {
-        if unsafe { *(pa.0 as *const u8).add(len) } != 0 {
-            len += 1;
-        } else {
-            len += 1;
-            break;
-        }
+// unlinkat(2)中删除目录的flag
+const AT_REMOVEDIR: usize = 0x200;
+// 读取execve的argv或envp字符串数组
+fn get_str_array(pcb: &mut Pcb, va: VirtualAddr) -> Result<Vec<String>, UserAccessErr> {
+    let mut strs = Vec::new();
+    if va.0 == 0 {
+        return Ok(strs);
     }
-    unsafe { core::str::from_utf8_unchecked(pa.as_slice(len)) }
-}
-
-fn get_usize_array(pa: &PhysAddr) -> &[usize] {
-    // 可能数组过大或末尾未置0导致循环无法停止
-    let mut len = 0;
-    loop {
-        if unsafe { *(pa.0 as *const usize).add(len) } != 0 {
-            len += 1;
-        } else {
-            len += 1;
-            break;
-        }
+    for addr in pcb.memory_space.lock().read_user_usize_array(va)? {
+        strs.push(pcb.memory_space.lock().read_user_str(VirtualAddr(addr))?);
     }
-    unsafe { core::slice::from_raw_parts(pa.0 as *const usize, len) }
+    Ok(strs)
 }
 
 // 将fd和path的组合解析为(Inode, String)的元组，方便parse_path的调用
-fn make_path_tuple(pcb: &Pcb, fd: isize, path: &str) -> Option<(Inode, String)> {
+fn make_path_tuple(pcb: &Pcb, fd: isize, path: &str) -> Result<(Inode, String), Errno> {
+    if path.len() == 0 {
+        // 空路径
+        return Err(Errno::ENOENT);
+    }
     if is_absolute_path(path) {
-        Some((pcb.root.clone(), String::from(path)))
+        Ok((pcb.root.clone(), String::from(path)))
     } else if fd == AT_FDCWD {
         // 如果是相对于当前cwd的路径，构造一个绝对路径
         if let Some('/') = pcb.cwd.chars().last() {
-            Some((pcb.root.clone(), pcb.cwd.clone() + path))
+            Ok((pcb.root.clone(), pcb.cwd.clone() + path))
         } else {
-            Some((pcb.root.clone(), pcb.cwd.clone() + "/" + path))
+            Ok((pcb.root.clone(), pcb.cwd.clone() + "/" + path))
         }
     } else {
         match pcb.get_fd(fd) {
-            Some(fd) => Some((fd.read().inode.clone(), String::from(path))),
-            None => None,
+            Some(fd) => Ok((fd.read().inode.clone(), String::from(path))),
+            None => Err(Errno::EBADF),
         }
     }
 }
@@ -78,7 +57,7 @@ fn make_path_tuple(pcb: &Pcb, fd: isize, path: &str) -> Option<(Inode, String)>
 fn get_parent_inode<'a, 'b>(node: &'a Inode, path: &'b str) -> Result<(Inode, &'b str), FileErr> {
     let (rest, name) = rsplit_path(path);
     if name == "." || name == ".." || name.len() == 0 {
-        return Err(FileErr::NotDefine);
+        return Err(FileErr::InvalidArgs);
     }
     if let Some(rest) = rest {
         let parent = parse_path(&node, rest)?;
@@ -88,18 +67,19 @@ fn get_parent_inode<'a, 'b>(node: &'a Inode, path: &'b str) -> Result<(Inode, &'
     }
 }
 
-pub(super) fn sys_getcwd(pcb: &mut MutexGuard<Pcb>, buf: VirtualAddr, len: usize) -> VirtualAddr {
+pub(super) fn sys_getcwd(pcb: &mut MutexGuard<Pcb>, buf: VirtualAddr, len: usize) -> SysResult {
     if buf.0 == 0 {
         // 由系统分配缓存区，不支持
-        VirtualAddr(0)
-    } else {
-        let mut buf: PhysAddr = buf.into();
-        // Fixme: 考虑 len长度限制
-        buf.write(pcb.cwd.as_bytes());
-        // 最后一位写0
-        (buf + pcb.cwd.len()).write_bytes('\0' as u8, 1);
-        VirtualAddr(buf.0)
+        return Err(Errno::EFAULT);
+    }
+    let mut cwd = pcb.cwd.clone().into_bytes();
+    // 最后一位写0
+    cwd.push(0);
+    if cwd.len() > len {
+        return Err(Errno::ERANGE);
     }
+    pcb.memory_space.lock().copy_to_user(buf, cwd.as_slice())?;
+    Ok(buf.0)
 }
 
 pub(super) fn sys_mkdirat(
@@ -107,27 +87,19 @@ pub(super) fn sys_mkdirat(
     dirfd: isize,
     path: VirtualAddr,
     mode: usize,
-) -> isize {
-    let phys: PhysAddr = path.into();
-    let path = get_str(&phys);
-    let mode = FileMode::from_bits(mode).unwrap();
-    let path_tuple = make_path_tuple(&mut *pcb, dirfd, path);
-    if path_tuple.is_none() {
-        return -1;
-    }
-
-    let (node, path) = path_tuple.unwrap();
-    match get_parent_inode(&node, path.as_str()) {
-        Ok((parent, name)) => {
-            if let Ok(_) = parent.create(name, FileMode::empty(), InodeType::Directory) {
-                return 0;
-            }
-        }
+) -> SysResult {
+    let path = pcb.memory_space.lock().read_user_str(path)?;
+    let mode = FileMode::from_bits_truncate(mode);
+    let (node, path) = make_path_tuple(&mut *pcb, dirfd, path.as_str())?;
+    match get_parent_inode(&node, path.as_str())
+        .and_then(|(parent, name)| parent.create(name, FileMode::empty(), InodeType::Directory))
+    {
+        Ok(_) => Ok(0),
         Err(e) => {
             log!("syscall":"mkdirat">"{:?}", e);
+            Err(e.into())
         }
     }
-    return -1;
 }
 
 pub(super) fn sys_linkat(
@@ -137,25 +109,21 @@ pub(super) fn sys_linkat(
     newdirfd: isize,
     newpath: VirtualAddr,
     _: usize,
-) -> isize {
-    let oldpath: PhysAddr = oldpath.into();
-    let oldpath = get_str(&oldpath);
-    let old_path_tuple = make_path_tuple(&mut *pcb, olddirfd, oldpath);
-    if old_path_tuple.is_none() {
-        // fd和path的组合不正确
-        log!("syscall":"linkat">"invalid combinations old(fd:{}, path:\"{}\"", olddirfd, oldpath);
-        return -1;
-    }
-    let (oldnode, oldpath) = old_path_tuple.unwrap();
-    let newpath: PhysAddr = newpath.into();
-    let newpath = get_str(&newpath);
-    let new_path_tuple = make_path_tuple(&mut *pcb, newdirfd, newpath);
-    if new_path_tuple.is_none() {
-        // fd和path的组合不正确
-        log!("syscall":"linkat">"invalid combinations neew(fd:{}, path:\"{}\"", newdirfd, newpath);
-        return -1;
-    }
-    let (newnode, newpath) = new_path_tuple.unwrap();
+) -> SysResult {
+    let oldpath = pcb.memory_space.lock().read_user_str(oldpath)?;
+    let (oldnode, oldpath) = make_path_tuple(&mut *pcb, olddirfd, oldpath.as_str())
+        .map_err(|e| {
+            // fd和path的组合不正确
+            log!("syscall":"linkat">"invalid combinations old(fd:{}, path:\"{}\"", olddirfd, oldpath);
+            e
+        })?;
+    let newpath = pcb.memory_space.lock().read_user_str(newpath)?;
+    let (newnode, newpath) = make_path_tuple(&mut *pcb, newdirfd, newpath.as_str())
+        .map_err(|e| {
+            // fd和path的组合不正确
+            log!("syscall":"linkat">"invalid combinations neew(fd:{}, path:\"{}\"", newdirfd, newpath);
+            e
+        })?;
     match parse_path(&oldnode, oldpath.as_str()).and_then(|oldinode| {
         get_parent_inode(&newnode, newpath.as_str()).and_then(|(parent, name)| {
             parent.create(name, FileMode::empty(), InodeType::HardLink(oldinode))
@@ -163,11 +131,11 @@ pub(super) fn sys_linkat(
     }) {
         Ok(_) => {
             log!("syscall":"linkat""successed">"{}", newpath);
-            0
+            Ok(0)
         }
         Err(e) => {
             log!("syscall":"linkatl""failed">"{:?}", e);
-            -1
+            Err(e.into())
         }
     }
 }
@@ -177,90 +145,79 @@ pub(super) fn sys_unlinkat(
     dirfd: isize,
     path: VirtualAddr,
     flags: usize,
-) -> isize {
-    let path: PhysAddr = path.into();
-    let path = get_str(&path);
-    let path_tuple = make_path_tuple(&mut *pcb, dirfd, path);
-    if path_tuple.is_none() {
+) -> SysResult {
+    let path = pcb.memory_space.lock().read_user_str(path)?;
+    let (node, path) = make_path_tuple(&mut *pcb, dirfd, path.as_str()).map_err(|e| {
         // fd和path的组合不正确
         log!("syscall":"unlinkat">"invalid combinations (fd:{}, path:\"{}\"", dirfd, path);
-        return -1;
-    }
-    let (node, path) = path_tuple.unwrap();
-    match get_parent_inode(&node, path.as_str()).and_then(|(parent, name)| {
-        // todo: REMOVEDIR
-        parent.unlink_child(name, false)
-    }) {
+        e
+    })?;
+    match get_parent_inode(&node, path.as_str())
+        .and_then(|(parent, name)| parent.unlink_child(name, flags & AT_REMOVEDIR != 0))
+    {
         Ok(linknum) => {
             log!("syscall":"unlinkat""successed">"remain linknum {}", linknum);
-            0
+            Ok(0)
         }
         Err(e) => {
             log!("syscall":"unlinkat""failed">"{:?}", e);
-            -1
+            Err(e.into())
         }
     }
 }
 
-pub(super) fn sys_pipe(pcb: &mut MutexGuard<Pcb>, pipe: VirtualAddr) -> isize {
-    let mut phys: PhysAddr = pipe.into();
+pub(super) fn sys_pipe(pcb: &mut MutexGuard<Pcb>, pipe: VirtualAddr) -> SysResult {
+    let (reader, writer) = make_pipe()?;
+    let reader = pcb.fds_insert(reader).ok_or(Errno::EMFILE)?;
+    let writer = match pcb.fds_insert(writer) {
+        Some(writer) => writer,
+        None => {
+            pcb.fds_close(reader as isize);
+            return Err(Errno::EMFILE);
+        }
+    };
     // sizeof(int) == 4
-    let pipe: &mut [INT; 2] = phys.as_mut();
-    if let Ok((reader, writer)) = make_pipe().and_then(|(reader, writer)| {
-        pcb.fds_insert(reader)
-            .and_then(|rfd| pcb.fds_insert(writer).and_then(|wfd| Some((rfd, wfd))))
-            .ok_or(FileErr::NotDefine)
-    }) {
-        pipe[0] = reader as INT;
-        pipe[1] = writer as INT;
-        0
-    } else {
-        log!("syscall":"pipe">"fail");
-        -1
+    let fds: [INT; 2] = [reader as INT, writer as INT];
+    if let Err(e) = pcb.memory_space.lock().write_user(pipe, &fds) {
+        log!("syscall":"pipe">"{:?}", e);
+        pcb.fds_close(reader as isize);
+        pcb.fds_close(writer as isize);
+        return Err(e.into());
     }
+    Ok(0)
 }
 
-pub(super) fn sys_dup(pcb: &mut MutexGuard<Pcb>, fd: isize) -> isize {
-    match pcb.get_fd(fd).and_then(|fd| pcb.fds_insert(fd)) {
-        Some(fd) => fd as isize,
-        None => -1,
-    }
+pub(super) fn sys_dup(pcb: &mut MutexGuard<Pcb>, fd: isize) -> SysResult {
+    let fd = pcb.get_fd(fd).ok_or(Errno::EBADF)?;
+    pcb.fds_insert(fd).ok_or(Errno::EMFILE)
 }
 
-pub(super) fn sys_dup3(pcb: &mut MutexGuard<Pcb>, oldfd: isize, newfd: isize) -> isize {
+pub(super) fn sys_dup3(pcb: &mut MutexGuard<Pcb>, oldfd: isize, newfd: isize) -> SysResult {
     // Fixme: 2021初赛中没有指定flags选项
+    let fd = pcb.get_fd(oldfd).ok_or(Errno::EBADF)?;
     if oldfd == newfd {
-        return newfd as isize;
+        return Ok(newfd as usize);
     }
-    match pcb.get_fd(oldfd).and_then(|fd| {
-        pcb.fds_close(newfd);
-        if pcb.fds_add(newfd, fd) {
-            Some(())
-        } else {
-            None
-        }
-    }) {
-        Some(_) => newfd as isize,
-        None => -1,
+    pcb.fds_close(newfd);
+    if pcb.fds_add(newfd, fd) {
+        Ok(newfd as usize)
+    } else {
+        Err(Errno::EBADF)
     }
 }
 
-pub(super) fn sys_chdir(pcb: &mut MutexGuard<Pcb>, path: VirtualAddr) -> isize {
-    let path: PhysAddr = path.into();
-    let path = get_str(&path);
-    let path_tuple = make_path_tuple(&mut *pcb, AT_FDCWD, path);
-    if path_tuple.is_none() {
-        return -1;
-    }
-    let (node, path) = path_tuple.unwrap();
+pub(super) fn sys_chdir(pcb: &mut MutexGuard<Pcb>, path: VirtualAddr) -> SysResult {
+    let path = pcb.memory_space.lock().read_user_str(path)?;
+    let (node, path) = make_path_tuple(&mut *pcb, AT_FDCWD, path.as_str())?;
     match parse_path(&node, path.as_str()) {
+        Ok(inode) if !inode.is_dir() => Err(Errno::ENOTDIR),
         Ok(_) => {
             pcb.cwd = path;
-            0
+            Ok(0)
         }
         Err(e) => {
             log!("syscall":"chdir">"error {:?}", e);
-            -1
+            Err(e.into())
         }
     }
 }
@@ -271,67 +228,96 @@ pub(super) fn sys_openat(
     path: VirtualAddr,
     flags: usize,
     mode: usize,
-) -> isize {
-    let path: PhysAddr = path.into();
-    let path = get_str(&path);
-    let flags = OpenFlags::from_bits(flags).unwrap();
-    let mode = FileMode::from_bits(mode).unwrap();
+) -> SysResult {
+    let path = pcb.memory_space.lock().read_user_str(path)?;
+    // 忽略不支持的flags和mode
+    let flags = OpenFlags::from_bits_truncate(flags);
+    let mode = FileMode::from_bits_truncate(mode);
 
-    let path_tuple = make_path_tuple(&mut *pcb, dirfd, path);
-    if path_tuple.is_none() {
-        return -1;
-    }
-    let (node, path) = path_tuple.unwrap();
+    let (node, path) = make_path_tuple(&mut *pcb, dirfd, path.as_str())?;
     // 不能使用get_parent_inode，因为路径的最后为"."或".."是合理的
-    match parse_path(&node, path.as_str())
-        .and_then(|inode| File::open(inode, flags))
-        .and_then(|file| pcb.fds_insert(file).ok_or(FileErr::NotDefine))
-    {
-        Ok(fd) => return fd as isize,
+    let file = match parse_path(&node, path.as_str()).and_then(|inode| File::open(inode, flags)) {
+        Ok(file) => file,
         Err(FileErr::InodeNotChild) if flags.contains(OpenFlags::CREATE) => {
-            return get_parent_inode(&node, path.as_str())
+            get_parent_inode(&node, path.as_str())
                 .and_then(|(parent, name)| parent.create(name, FileMode::empty(), InodeType::File))
                 .and_then(|child| File::open(child, flags))
-                .and_then(|file| pcb.fds_insert(file).ok_or(FileErr::NotDefine))
-                .unwrap_or_else(|e| {
+                .map_err(|e| {
                     log!("syscall":"openat">"create error {:?}", e);
-                    -1 as isize as usize
-                }) as isize;
+                    e
+                })?
         }
-        Err(_) => return -1,
+        Err(e) => return Err(e.into()),
     };
+    pcb.fds_insert(file).ok_or(Errno::EMFILE)
 }
 
-pub(super) fn sys_close(pcb: &mut MutexGuard<Pcb>, fd: isize) -> isize {
+pub(super) fn sys_close(pcb: &mut MutexGuard<Pcb>, fd: isize) -> SysResult {
     if pcb.fds_close(fd) {
-        0
+        Ok(0)
     } else {
-        -1
+        Err(Errno::EBADF)
     }
 }
 
+pub(super) fn sys_mount(
+    pcb: &mut MutexGuard<Pcb>,
+    source: VirtualAddr,
+    target: VirtualAddr,
+    fstype: VirtualAddr,
+    _flags: usize,
+    _data: VirtualAddr,
+) -> SysResult {
+    let source = pcb.memory_space.lock().read_user_str(source)?;
+    let target = pcb.memory_space.lock().read_user_str(target)?;
+    let fstype = pcb.memory_space.lock().read_user_str(fstype)?;
+    let (node, path) = make_path_tuple(&mut *pcb, AT_FDCWD, target.as_str())?;
+    // 挂载点必须存在，如果已经是挂载点，新的文件系统覆盖在上面
+    let covered = parse_path(&node, path.as_str())?;
+    mount(source.as_str(), covered, path.as_str(), fstype.as_str())?;
+    Ok(0)
+}
+
+pub(super) fn sys_umount2(pcb: &mut MutexGuard<Pcb>, target: VirtualAddr, _flags: usize) -> SysResult {
+    let target = pcb.memory_space.lock().read_user_str(target)?;
+    let (node, path) = make_path_tuple(&mut *pcb, AT_FDCWD, target.as_str())?;
+    // 需要找到被覆盖的目录，不能经过parse_path进入挂载的文件系统
+    let (parent, name) = get_parent_inode(&node, path.as_str())?;
+    let covered = parent.get_child(name)?;
+    umount(&covered)?;
+    Ok(0)
+}
+
+pub(super) fn sys_fsync(pcb: &mut MutexGuard<Pcb>, fd: isize) -> SysResult {
+    let file = pcb.get_fd(fd).ok_or(Errno::EBADF)?;
+    let inode = file.read().get_inode();
+    inode.sync()?;
+    Ok(0)
+}
+
+pub(super) fn sys_sync() -> SysResult {
+    crate::driver::sync_all();
+    Ok(0)
+}
+
 pub(super) fn sys_getdents64(
     pcb: &mut MutexGuard<Pcb>,
     fd: isize,
     buf: VirtualAddr,
     len: usize,
-) -> isize {
-    let mut buf: PhysAddr = buf.into();
-    let mut buf = buf.as_slice_mut(len);
-    let file = pcb.get_fd(fd);
-    match file {
-        Some(file) => match file.write().get_dirents(&mut buf) {
-            Ok(size) => return size as isize,
-            Err(FileErr::InodeEndOfDir) => {
-                log!("syscall":"getdents64">"dirent eof");
-                return 0;
-            }
-            Err(_) => return -1,
-        },
-        None => {
-            log!("syscall":"getdents64">"invalid fd {}", fd);
-            -1
+) -> SysResult {
+    let file = pcb.get_fd(fd).ok_or_else(|| {
+        log!("syscall":"getdents64">"invalid fd {}", fd);
+        Errno::EBADF
+    })?;
+    let buf = pcb.memory_space.lock().user_buf_mut(buf, len)?;
+    match file.write().get_dirents(buf) {
+        Ok(size) => Ok(size),
+        Err(FileErr::InodeEndOfDir) => {
+            log!("syscall":"getdents64">"dirent eof");
+            Ok(0)
         }
+        Err(e) => Err(e.into()),
     }
 }
 
@@ -340,16 +326,28 @@ pub(super) fn sys_lseek(
     fd: isize,
     offset: isize,
     whence: usize,
-) -> isize {
-    if let Some(file) = pcb.get_fd(fd) {
-        if let Ok(pos) = file.write().lseek(whence, offset) {
-            pos as isize
-        } else {
-            -1
-        }
-    } else {
-        -1
+) -> SysResult {
+    let file = pcb.get_fd(fd).ok_or(Errno::EBADF)?;
+    let pos = file.write().lseek(whence, offset)?;
+    Ok(pos)
+}
+
+// 在inode的等待队列上阻塞，直到ready返回true
+fn wait_inode(
+    pcb: &mut MutexGuard<Pcb>,
+    inode: &Inode,
+    ready: impl Fn(&Inode) -> bool,
+) -> Result<(), BlockErr> {
+    let waiter = block_current(pcb, None);
+    match inode.wait_queue() {
+        Some(wait_queue) => wait_queue.add(waiter),
+        None => cancel_block(pcb),
+    }
+    // 加入等待队列之前可能已经可以读写
+    if ready(inode) {
+        cancel_block(pcb);
     }
+    block(pcb)
 }
 
 pub(super) fn sys_write(
@@ -357,43 +355,37 @@ pub(super) fn sys_write(
     fd: isize,
     buf: VirtualAddr,
     len: usize,
-) -> isize {
-    let mut buf: PhysAddr = buf.into();
-    let buf: &[u8] = buf.as_slice_mut(len);
-    if let Some(file) = pcb.get_fd(fd) {
-        match file.write().write(buf) {
-            Ok(size) => size as isize,
-            Err(FileErr::PipeWriteWait) => {
-                // 需要等待另一端，回退到ecall
+) -> SysResult {
+    let file = pcb.get_fd(fd).ok_or_else(|| {
+        log!("syscall":"sys_write">"fd invalid");
+        Errno::EBADF
+    })?;
+    // 阻塞写，直到写完所有数据或者另一端关闭
+    let mut written = 0;
+    loop {
+        // 阻塞期间用户的页面可能被写时复制，每次重新检查
+        let data = pcb.memory_space.lock().user_buf(buf + written, len - written)?;
+        let ret = file.write().write(data);
+        match ret {
+            Ok(size) => {
+                written += size;
+                if size == 0 || written == len {
+                    return Ok(written);
+                }
+            }
+            Err(FileErr::WriteWait) => {
+                // 需要等待另一端，被唤醒后继续写入剩下的数据
                 log!("vfs":"sys_write">"waiting fd({})", fd);
-                pcb.trapframe()["sepc"] -= 4;
-                pcb.block_fn = Some(Arc::new(move |pcb| {
-                    if let Some(_) = pcb.get_fd(fd).and_then(|file| {
-                        file.try_write().and_then(|file| {
-                            // 通过write_ready判断是否可以写
-                            if file.get_inode().write_ready() {
-                                Some(())
-                            } else {
-                                None
-                            }
-                        })
-                    }) {
-                        return true;
-                    }
-                    false
-                }));
-                pcb.set_state(PcbState::Blocking);
-                // 返回fd用于修改trapframe["a0"]，保证下次调用正确
-                fd as isize
+                let inode = file.read().get_inode();
+                if let Err(e) = wait_inode(pcb, &inode, |inode| inode.write_ready()) {
+                    return if written > 0 { Ok(written) } else { Err(e.into()) };
+                }
             }
             Err(e) => {
                 log!("syscall":"sys_write">"error {:?}", e);
-                -1
+                return if written > 0 { Ok(written) } else { Err(e.into()) };
             }
         }
-    } else {
-        log!("syscall":"sys_write">"fd invalid");
-        -1
     }
 }
 
@@ -402,113 +394,106 @@ pub(super) fn sys_read(
     fd: isize,
     buf: VirtualAddr,
     len: usize,
-) -> isize {
-    let mut buf: PhysAddr = buf.into();
-    let buf: &mut [u8] = buf.as_slice_mut(len);
-    if let Some(file) = pcb.get_fd(fd) {
-        match file.write().read(buf) {
-            Ok(size) => size as isize,
-            Err(FileErr::PipeReadWait) => {
-                // 需要等待另一端，回退到ecall
+) -> SysResult {
+    let file = pcb.get_fd(fd).ok_or_else(|| {
+        log!("syscall":"sys_read">"fd invalid");
+        Errno::EBADF
+    })?;
+    // 阻塞读，读到数据之后立即返回，可能少于len
+    loop {
+        // 阻塞期间用户的页面可能被写时复制，每次重新检查
+        let data = pcb.memory_space.lock().user_buf_mut(buf, len)?;
+        let ret = file.write().read(data);
+        match ret {
+            Ok(size) => return Ok(size),
+            // 读到文件末尾
+            Err(FileErr::FileEOF) => return Ok(0),
+            Err(FileErr::ReadWait) => {
+                // 需要等待另一端，被唤醒后重新读取
                 log!("vfs":"sys_read">"waiting fd({})", fd);
-                pcb.trapframe()["sepc"] -= 4;
-                pcb.block_fn = Some(Arc::new(move |pcb| {
-                    if let Some(_) = pcb.get_fd(fd).and_then(|file| {
-                        file.try_write().and_then(|file| {
-                            // 通过read_ready判断是否可以读
-                            if file.get_inode().read_ready() {
-                                Some(())
-                            } else {
-                                None
-                            }
-                        })
-                    }) {
-                        return true;
-                    }
-                    false
-                }));
-                pcb.set_state(PcbState::Blocking);
-                // 返回fd用于修改trapframe["a0"]，保证下次调用正确
-                fd as isize
+                let inode = file.read().get_inode();
+                wait_inode(pcb, &inode, |inode| inode.read_ready())?;
             }
             Err(e) => {
                 log!("syscall":"sys_read">"error {:?}", e);
-                -1
+                return Err(e.into());
             }
         }
-    } else {
-        log!("syscall":"sys_read">"fd invalid");
-        -1
     }
 }
 
+// 成功时不返回，a0由新的trapframe决定
 pub(super) fn sys_execve(
     pcb: &mut MutexGuard<Pcb>,
     path: VirtualAddr,
     argv: VirtualAddr,
     envp: VirtualAddr,
-) {
-    let path: PhysAddr = path.into();
-    let path = get_str(&path);
-    let argv: PhysAddr = argv.into();
-    let argv = get_usize_array(&argv);
-    let envp: PhysAddr = envp.into();
-    let envp = get_usize_array(&envp);
+) -> Result<(), Errno> {
+    // 在替换MemorySpace之前将path、argv和envp读入内核
+    let path = pcb.memory_space.lock().read_user_str(path)?;
+    let (argv, envp) = match get_str_array(pcb, argv)
+        .and_then(|argv| Ok((argv, get_str_array(pcb, envp)?)))
+    {
+        Ok(arrays) => arrays,
+        Err(UserAccessErr::TooLong) => return Err(Errno::E2BIG),
+        Err(e) => {
+            log!("syscall":"execve">"{:?}", e);
+            return Err(e.into());
+        }
+    };
 
     // 构造路径tuple
-    let path_tuple = make_path_tuple(&mut *pcb, AT_FDCWD, path);
-    if path_tuple.is_none() {
+    let (node, path) = make_path_tuple(&mut *pcb, AT_FDCWD, path.as_str()).map_err(|e| {
         log!("syscall":"execve">"invalid path {}", path);
-        return;
-    }
-    let (node, path) = path_tuple.unwrap();
+        e
+    })?;
     log!("execve":>"path {}", path);
-    if let Ok(_) = parse_path(&node, path.as_str()).and_then(|inode| {
-        let mut ms = MemorySpace::from_elf_inode(inode)?;
-        // 用户栈底的物理地址(栈由上往下增长)
-        let mut user_stack_high = ms.user_stack.offset_phys(USER_STACK_SIZE);
-        // 将argv和envp数组拷贝到用户栈上
-        match copy_execve_str_array(user_stack_high, argv, user_stack_high).and_then(
-            |(argv_pa, start_pa)| {
-                copy_execve_str_array(user_stack_high, envp, start_pa)
+    let inode = parse_path(&node, path.as_str())?;
+    let ms = MemorySpace::from_elf_inode(inode)?;
+    // 用户栈底的物理地址(栈由上往下增长)
+    let user_stack_high = ms.user_stack.page().offset_phys(USER_STACK_SIZE);
+    // 将argv和envp数组拷贝到用户栈上
+    let (argv_pa, envp_pa, stack_pa) =
+        copy_execve_str_array(user_stack_high, argv.as_slice(), user_stack_high)
+            .and_then(|(argv_pa, start_pa)| {
+                copy_execve_str_array(user_stack_high, envp.as_slice(), start_pa)
                     .and_then(|(envp_pa, stack_pa)| Ok((argv_pa, envp_pa, stack_pa)))
-            },
-        ) {
-            Ok((argv_pa, envp_pa, stack_pa)) => {
-                log!("execve":>"copying argv, envp");
-                let sp = MemorySpace::get_stack_sp().0 - (user_stack_high.0 - stack_pa.0);
-                // 更新栈
-                ms.trapframe()["sp"] = sp;
-                // 更新args
-                ms.trapframe()["a0"] = argv.len() - 1;
-                // 计算argv数组的虚拟地址
-                let a1 = MemorySpace::get_stack_sp().0 - (user_stack_high.0 - argv_pa.0);
-                ms.trapframe()["a1"] = a1;
-                // 计算envp数组的虚拟地址
-                let a2 = MemorySpace::get_stack_sp().0 - (user_stack_high.0 - envp_pa.0);
-                ms.trapframe()["a2"] = a2;
+            })
+            .map_err(|_| {
+                log!("syscall":"execve""fail">"argv and envp too large");
+                Errno::E2BIG
+            })?;
+    log!("execve":>"copying argv, envp");
+    let sp = MemorySpace::get_stack_sp().0 - (user_stack_high.0 - stack_pa.0);
+    let entry = ms.entry();
 
-                let sp = ms.trapframe()["sp"];
-                // 释放了原本的用户MemorySpace，不能再读写了
-                pcb.memory_space = ms;
-                Ok(())
-            }
-            Err(_) => Err(FileErr::NotDefine),
-        }
-    }) {
-        log!("syscall":"execve""success">"");
-    } else {
-        log!("syscall":"execve""fail">"");
-    }
+    // 进程的其他线程在下一次调度时退出，当前线程成为新的线程组
+    pcb.leave_thread_group();
+    pcb.reset_sigactions();
+    // 释放了原本的用户MemorySpace，不能再读写了
+    pcb.memory_space = Arc::new(Mutex::new(ms));
+    let trapframe = pcb.trapframe();
+    trapframe.general_reg = [0; 32];
+    trapframe.init(MemorySpace::get_stack_sp().0, entry);
+    // 更新栈
+    trapframe["sp"] = sp;
+    // 更新args
+    trapframe["a0"] = argv.len();
+    // 计算argv数组的虚拟地址
+    trapframe["a1"] = MemorySpace::get_stack_sp().0 - (user_stack_high.0 - argv_pa.0);
+    // 计算envp数组的虚拟地址
+    trapframe["a2"] = MemorySpace::get_stack_sp().0 - (user_stack_high.0 - envp_pa.0);
+    log!("syscall":"execve""success">"");
+    Ok(())
 }
 
 /**
  *       |--------------| <- stack_pa
  *       |       0      |
  *       |--------------|
- *       |  str_arr[-2] |
+ *       |  str_arr[-1] |
  *       |--------------|
- *       |  str_arr[-3] | -----\
+ *       |  str_arr[-2] | -----\
  *       |--------------|       \
  *       |      ...     |       |
  *       |--------------|       |
@@ -524,37 +509,38 @@ pub(super) fn sys_execve(
 // 将execve的argv和envp字符数组复制道新进程的栈中, 返回接下来的栈地址
 fn copy_execve_str_array(
     stack_high: PhysAddr,
-    str_array: &[usize],
+    str_array: &[String],
     stack_pa: PhysAddr,
 ) -> Result<(PhysAddr, PhysAddr), ()> {
-    // 计算字符串复制的地址
-    let mut str_pa = stack_pa - size_of::<usize>() * (str_array.len());
+    // 计算字符串复制的地址, 数组以0结尾
+    let mut str_pa = stack_pa - size_of::<usize>() * (str_array.len() + 1);
+    if stack_high.0 - str_pa.0 > USER_STACK_SIZE {
+        return Err(());
+    }
     // 计算数组复制的地址
     let mut arr_pa = str_pa;
     let arr_pa_ret = arr_pa;
-    for &va in str_array {
-        // va为0，数组结尾
-        if va == 0 {
-            let arr_i: &mut usize = arr_pa.as_mut();
-            *arr_i = 0;
-            break;
-        }
-        // 获得str_array指向的字符串
-        let str_pa_orignal: PhysAddr = VirtualAddr(va).into();
-        let str_original = get_cstr(&str_pa_orignal);
-        log!("execve":"copy_str_array">"str({}): \"{}\"", str_original.len(), str_original);
+    for string in str_array {
+        log!("execve":"copy_str_array">"str({}): \"{}\"", string.len(), string);
 
-        // 通过字符串长度计算要写入的地址
-        str_pa = str_pa - str_original.len();
-        // todo: 判断str_pa是否溢出栈
+        // 通过字符串长度计算要写入的地址，包括结尾的'\0'
+        if stack_high.0 - str_pa.0 + string.len() + 1 > USER_STACK_SIZE {
+            // 超出用户栈
+            return Err(());
+        }
+        str_pa = str_pa - (string.len() + 1);
 
         // 计算虚拟地址，写入栈中
         let arr_i: &mut usize = arr_pa.as_mut();
         *arr_i = MemorySpace::get_stack_sp().0 - (stack_high.0 - str_pa.0);
         // 复制字符串
-        str_pa.write(str_original.as_bytes());
+        str_pa.write(string.as_bytes());
+        (str_pa + string.len()).write_bytes(0, 1);
         // 指向下一个
         arr_pa = arr_pa + size_of::<usize>();
     }
+    // 数组结尾
+    let arr_i: &mut usize = arr_pa.as_mut();
+    *arr_i = 0;
     Ok((arr_pa_ret, str_pa))
 }
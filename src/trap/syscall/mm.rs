@@ -1,14 +1,16 @@
+use crate::errno::*;
 use crate::mm::*;
 use crate::process::*;
 use spin::MutexGuard;
 
 pub(super) fn sys_sbrk(pcb: &mut MutexGuard<Pcb>, inc: usize) -> usize {
-    let target = pcb.memory_space.prog_break + inc;
-    pcb.memory_space.prog_brk(target).0
+    let mut ms = pcb.memory_space.lock();
+    let target = ms.prog_break + inc;
+    ms.prog_brk(target).0
 }
 
 pub(super) fn sys_brk(pcb: &mut MutexGuard<Pcb>, va: VirtualAddr) -> usize {
-    pcb.memory_space.prog_brk(va).0
+    pcb.memory_space.lock().prog_brk(va).0
 }
 
 pub(super) fn sys_mmap(
@@ -19,30 +21,23 @@ pub(super) fn sys_mmap(
     flags: usize,
     fd: isize,
     offset: usize,
-) -> VirtualAddr {
-    let prot = MapProt::from_bits(prot).unwrap();
-    let flags = MapFlags::from_bits(flags).unwrap();
+) -> SysResult {
+    let prot = MapProt::from_bits(prot).ok_or(Errno::EINVAL)?;
+    let flags = MapFlags::from_bits(flags).ok_or(Errno::EINVAL)?;
     // todo: 支持匿名映射
-    if let Some(va) = pcb.get_fd(fd).and_then(|file| {
-        match pcb.memory_space.mmap(
-            start,
-            Some(file.read().inode.clone()),
-            offset,
-            length,
-            prot,
-            flags,
-        ) {
-            Ok(va) => Some(va),
-            Err(e) => None,
-        }
-    }) {
-        return va;
-    } else {
-        return VirtualAddr(-1 as isize as usize);
+    let file = pcb.get_fd(fd).ok_or(Errno::EBADF)?;
+    let inode = file.read().inode.clone();
+    match pcb
+        .memory_space
+        .lock()
+        .mmap(start, Some(inode), offset, length, prot, flags)
+    {
+        Ok(va) => Ok(va.0),
+        Err(_) => Err(Errno::EINVAL),
     }
 }
 
-pub(super) fn sys_munmap(pcb: &mut MutexGuard<Pcb>, start: VirtualAddr, length: usize) -> isize {
-    pcb.memory_space.munmap(start, length);
-    0
+pub(super) fn sys_munmap(pcb: &mut MutexGuard<Pcb>, start: VirtualAddr, length: usize) -> SysResult {
+    pcb.memory_space.lock().munmap(start, length);
+    Ok(0)
 }
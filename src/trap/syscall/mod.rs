@@ -4,9 +4,9 @@ mod mm;
 mod process;
 mod signal;
 mod sysinfo;
-use crate::config::RTCLK_FREQ;
+use crate::errno::*;
 use crate::mm::address::*;
-use crate::process::cpu::{current_hart, get_time};
+use crate::process::cpu::current_hart;
 use crate::task::*;
 use crate::{process::*, trap};
 use alloc::sync::Arc;
@@ -15,6 +15,7 @@ use mm::*;
 use sysinfo::*;
 use process::*;
 use signal::*;
+use crate::process::signal::{SigInfo, Signal, SI_KERNEL};
 
 const SYSCALL_GETCWD: usize = 17;
 const SYSCALL_DUP: usize = 23;
@@ -41,20 +42,33 @@ const SYSCALL_PSELECT6: usize = 72;
 const SYSCALL_READLINKAT: usize = 78;
 const SYSCALL_NEW_FSTATAT: usize = 79;
 const SYSCALL_FSTAT: usize = 80;
+const SYSCALL_SYNC: usize = 81;
 const SYSCALL_FSYNC: usize = 82;
 const SYSCALL_UTIMENSAT: usize = 88;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_EXIT_GRUOP: usize = 94;
 const SYSCALL_SET_TID_ADDRESS: usize = 96;
+const SYSCALL_FUTEX: usize = 98;
 const SYSCALL_NANOSLEEP: usize = 101;
 const SYSCALL_GETITIMER: usize = 102;
 const SYSCALL_SETITIMER: usize = 103;
 const SYSCALL_CLOCK_GETTIME: usize = 113;
+const SYSCALL_SCHED_SETAFFINITY: usize = 122;
+const SYSCALL_SCHED_GETAFFINITY: usize = 123;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_KILL: usize = 129;
+const SYSCALL_SIGALTSTACK: usize = 132;
+const SYSCALL_SIGSUSPEND: usize = 133;
 const SYSCALL_SIGACTION: usize = 134;
+const SYSCALL_SIGPROCMASK: usize = 135;
+const SYSCALL_SIGPENDING: usize = 136;
+const SYSCALL_SIGQUEUEINFO: usize = 138;
 const SYSCALL_SIGRETURN: usize = 139;
 const SYSCALL_TIMES: usize = 153;
+const SYSCALL_SETPGID: usize = 154;
+const SYSCALL_GETPGID: usize = 155;
+const SYSCALL_GETSID: usize = 156;
+const SYSCALL_SETSID: usize = 157;
 const SYSCALL_UNAME: usize = 160;
 const SYSCALL_GETRUSAGE: usize = 165;
 const SYSCALL_GET_TIME_OF_DAY: usize = 169;
@@ -64,7 +78,7 @@ const SYSCALL_GETUID: usize = 174;
 const SYSCALL_GETEUID: usize = 175;
 const SYSCALL_GETGID: usize = 176;
 const SYSCALL_GETEGID: usize = 177;
-const SYSCALL_GETTID: usize = 177;
+const SYSCALL_GETTID: usize = 178;
 const SYSCALL_SBRK: usize = 213;
 const SYSCALL_BRK: usize = 214;
 const SYSCALL_MUNMAP: usize = 215;
@@ -88,6 +102,8 @@ pub fn syscall_handler() {
     let mut pcblock = pcb.lock();
     let trapframe = pcblock.trapframe();
     let syscall_id = trapframe["a7"];
+    // 系统调用被信号中断后重新执行时需要原来的a0
+    let orig_a0 = trapframe["a0"];
 
     // 指向下一条指令
     trapframe["sepc"] += 4;
@@ -97,30 +113,30 @@ pub fn syscall_handler() {
             let buf = VirtualAddr(trapframe["a0"]);
             let size = trapframe["a1"];
             log!("syscall":"getcwd" > "pid({}) (0x{:x})", pcblock.pid, buf.0);
-            pcblock.trapframe()["a0"] = sys_getcwd(&mut pcblock, buf, size).0;
+            pcblock.trapframe()["a0"] = syscall_ret(sys_getcwd(&mut pcblock, buf, size));
         }
         SYSCALL_PIPE => {
             let pipe = VirtualAddr(trapframe["a0"]);
             log!("syscall":"pipe" > "pid({}) (0x{:x})", pcblock.pid, pipe.0);
-            pcblock.trapframe()["a0"] = sys_pipe(&mut pcblock, pipe) as usize;
+            pcblock.trapframe()["a0"] = syscall_ret(sys_pipe(&mut pcblock, pipe));
         }
         SYSCALL_DUP => {
             let fd = trapframe["a0"] as isize;
             log!("syscall":"dup" > "pid({}) ({})", pcblock.pid, fd);
-            pcblock.trapframe()["a0"] = sys_dup(&mut pcblock, fd) as usize;
+            pcblock.trapframe()["a0"] = syscall_ret(sys_dup(&mut pcblock, fd));
         }
         SYSCALL_DUP3 => {
             let oldfd = trapframe["a0"] as isize;
             let newfd = trapframe["a1"] as isize;
             log!("syscall":"dup3" > "pid({}) ({}, {})", pcblock.pid, oldfd, newfd);
-            pcblock.trapframe()["a0"] = sys_dup3(&mut pcblock, oldfd, newfd) as usize;
+            pcblock.trapframe()["a0"] = syscall_ret(sys_dup3(&mut pcblock, oldfd, newfd));
         }
         SYSCALL_MKDIRAT => {
             let fd = trapframe["a0"] as isize;
             let path = VirtualAddr(trapframe["a1"]);
             let mode = trapframe["a2"];
             log!("syscall":"mkdirat" > "pid({}) ({}, 0x{:x})", pcblock.pid, fd, mode);
-            pcblock.trapframe()["a0"] = sys_mkdirat(&mut pcblock, fd, path, mode) as usize;
+            pcblock.trapframe()["a0"] = syscall_ret(sys_mkdirat(&mut pcblock, fd, path, mode));
         }
         SYSCALL_LINKAT => {
             let olddirfd = trapframe["a0"] as isize;
@@ -129,18 +145,18 @@ pub fn syscall_handler() {
             let newpath = VirtualAddr(trapframe["a3"]);
             let flags = trapframe["a4"];
             pcblock.trapframe()["a0"] =
-                sys_linkat(&mut pcblock, olddirfd, oldpath, newdirfd, newpath, flags) as usize;
+                syscall_ret(sys_linkat(&mut pcblock, olddirfd, oldpath, newdirfd, newpath, flags));
         }
         SYSCALL_UNLINKAT => {
             let dirfd = trapframe["a0"] as isize;
             let path = VirtualAddr(trapframe["a1"]);
             let flags = trapframe["a2"];
-            pcblock.trapframe()["a0"] = sys_unlinkat(&mut pcblock, dirfd, path, flags) as usize;
+            pcblock.trapframe()["a0"] = syscall_ret(sys_unlinkat(&mut pcblock, dirfd, path, flags));
         }
         SYSCALL_CHDIR => {
             let path = VirtualAddr(trapframe["a0"]);
             log!("syscall":"chdir" > "pid({}) (0x{:x})", pcblock.pid, path.0);
-            pcblock.trapframe()["a0"] = sys_chdir(&mut pcblock, path) as usize;
+            pcblock.trapframe()["a0"] = syscall_ret(sys_chdir(&mut pcblock, path));
         }
         SYSCALL_OPENAT => {
             let fd = trapframe["a0"] as isize;
@@ -149,13 +165,42 @@ pub fn syscall_handler() {
             let mode = trapframe["a3"];
             log!("syscall":"openat" > "pid({}) ({}, 0x{:x})", pcblock.pid, fd, filename.0);
             pcblock.trapframe()["a0"] =
-                sys_openat(&mut pcblock, fd, filename, flags, mode) as usize;
+                syscall_ret(sys_openat(&mut pcblock, fd, filename, flags, mode));
         }
         SYSCALL_CLOSE => {
             let fd = trapframe["a0"] as isize;
             drop(trapframe);
             log!("syscall":"close" > "pid({}) ({})", pcblock.pid, fd);
-            pcblock.trapframe()["a0"] = sys_close(&mut pcblock, fd) as usize;
+            pcblock.trapframe()["a0"] = syscall_ret(sys_close(&mut pcblock, fd));
+        }
+        SYSCALL_MOUNT => {
+            let source = VirtualAddr(trapframe["a0"]);
+            let target = VirtualAddr(trapframe["a1"]);
+            let fstype = VirtualAddr(trapframe["a2"]);
+            let flags = trapframe["a3"];
+            let data = VirtualAddr(trapframe["a4"]);
+            drop(trapframe);
+            log!("syscall":"mount" > "pid({}) (0x{:x}, 0x{:x}, 0x{:x}, 0x{:x})", pcblock.pid, source.0, target.0, fstype.0, flags);
+            pcblock.trapframe()["a0"] =
+                syscall_ret(sys_mount(&mut pcblock, source, target, fstype, flags, data));
+        }
+        SYSCALL_UMOUNT2 => {
+            let target = VirtualAddr(trapframe["a0"]);
+            let flags = trapframe["a1"];
+            drop(trapframe);
+            log!("syscall":"umount2" > "pid({}) (0x{:x}, 0x{:x})", pcblock.pid, target.0, flags);
+            pcblock.trapframe()["a0"] = syscall_ret(sys_umount2(&mut pcblock, target, flags));
+        }
+        SYSCALL_SYNC => {
+            drop(trapframe);
+            log!("syscall":"sync" > "pid({})", pcblock.pid);
+            pcblock.trapframe()["a0"] = syscall_ret(sys_sync());
+        }
+        SYSCALL_FSYNC => {
+            let fd = trapframe["a0"] as isize;
+            drop(trapframe);
+            log!("syscall":"fsync" > "pid({}) ({})", pcblock.pid, fd);
+            pcblock.trapframe()["a0"] = syscall_ret(sys_fsync(&mut pcblock, fd));
         }
         SYSCALL_GETDENTS64 => {
             let fd = trapframe["a0"] as isize;
@@ -163,7 +208,7 @@ pub fn syscall_handler() {
             let len = trapframe["a2"];
             drop(trapframe);
             log!("syscall":"getdents64" > "pid({}) ({}, 0x{:x}, {})", pcblock.pid, fd, buf.0, len);
-            pcblock.trapframe()["a0"] = sys_getdents64(&mut pcblock, fd, buf, len) as usize;
+            pcblock.trapframe()["a0"] = syscall_ret(sys_getdents64(&mut pcblock, fd, buf, len));
         }
         SYSCALL_LSEEK => {
             let fd = trapframe["a0"] as isize;
@@ -171,7 +216,7 @@ pub fn syscall_handler() {
             let whence = trapframe["a2"];
             drop(trapframe);
             log!("syscall":"lseek" > "pid({}) ({}, {}, {})", pcblock.pid, fd, offset, whence);
-            pcblock.trapframe()["a0"] = sys_lseek(&mut pcblock, fd, offset, whence) as usize;
+            pcblock.trapframe()["a0"] = syscall_ret(sys_lseek(&mut pcblock, fd, offset, whence));
         }
         SYSCALL_WRITE => {
             let fd = trapframe["a0"] as isize;
@@ -179,7 +224,7 @@ pub fn syscall_handler() {
             let len = trapframe["a2"];
             drop(trapframe);
             log!("syscall":"write" > "pid({}) ({}, 0x{:x}, {})", pcblock.pid, fd, buf.0, len);
-            pcblock.trapframe()["a0"] = sys_write(&mut pcblock, fd, buf, len) as usize;
+            pcblock.trapframe()["a0"] = syscall_ret(sys_write(&mut pcblock, fd, buf, len));
         }
         SYSCALL_READ => {
             let fd = trapframe["a0"] as isize;
@@ -187,7 +232,7 @@ pub fn syscall_handler() {
             let len = trapframe["a2"];
             drop(trapframe);
             log!("syscall":"read" > "pid({}) ({}, 0x{:x}, {})", pcblock.pid, fd, buf.0, len);
-            pcblock.trapframe()["a0"] = sys_read(&mut pcblock, fd, buf, len) as usize;
+            pcblock.trapframe()["a0"] = syscall_ret(sys_read(&mut pcblock, fd, buf, len));
         }
         SYSCALL_EXIT => {
             let xcode = trapframe["a0"];
@@ -195,6 +240,47 @@ pub fn syscall_handler() {
             log!("syscall":"exit" > "pid({})->({})", pcblock.pid, xcode);
             sys_exit(&mut pcblock, xcode as isize);
         }
+        SYSCALL_EXIT_GRUOP => {
+            let xcode = trapframe["a0"];
+            drop(trapframe);
+            log!("syscall":"exit_group" > "pid({})->({})", pcblock.pid, xcode);
+            sys_exit_group(&mut pcblock, xcode as isize);
+        }
+        SYSCALL_SET_TID_ADDRESS => {
+            let tidptr = trapframe["a0"];
+            drop(trapframe);
+            log!("syscall":"set_tid_address" > "pid({}) (0x{:x})", pcblock.pid, tidptr);
+            pcblock.trapframe()["a0"] = sys_set_tid_address(&mut pcblock, tidptr);
+        }
+        SYSCALL_FUTEX => {
+            let uaddr = VirtualAddr(trapframe["a0"]);
+            let futex_op = trapframe["a1"];
+            let val = trapframe["a2"];
+            let timeout = VirtualAddr(trapframe["a3"]);
+            let uaddr2 = VirtualAddr(trapframe["a4"]);
+            let val3 = trapframe["a5"];
+            drop(trapframe);
+            log!("syscall":"futex" > "pid({}) (0x{:x}, {}, {})", pcblock.pid, uaddr.0, futex_op, val);
+            pcblock.trapframe()["a0"] =
+                syscall_ret(sys_futex(&mut pcblock, uaddr, futex_op, val, timeout, uaddr2, val3));
+        }
+        SYSCALL_SCHED_SETAFFINITY => {
+            let pid = trapframe["a0"];
+            let cpusetsize = trapframe["a1"];
+            let mask = VirtualAddr(trapframe["a2"]);
+            drop(trapframe);
+            log!("syscall":"sched_setaffinity">"pid({}) ({})", pcblock.pid, pid);
+            pcblock.trapframe()["a0"] =
+                syscall_ret(sys_sched_setaffinity(&mut pcblock, pid, cpusetsize, mask));
+        }
+        SYSCALL_SCHED_GETAFFINITY => {
+            let pid = trapframe["a0"];
+            let cpusetsize = trapframe["a1"];
+            let mask = VirtualAddr(trapframe["a2"]);
+            drop(trapframe);
+            pcblock.trapframe()["a0"] =
+                syscall_ret(sys_sched_getaffinity(&mut pcblock, pid, cpusetsize, mask));
+        }
         SYSCALL_YIELD => {
             trapframe["a0"] = sys_yield() as usize;
             log!("syscall": "yield" > "pid({})", pcblock.pid);
@@ -203,6 +289,32 @@ pub fn syscall_handler() {
             log!("syscall": "getpid"> "pid({})", pcblock.pid);
             pcblock.trapframe()["a0"] = sys_getpid(&pcblock) as usize;
         }
+        SYSCALL_GETTID => {
+            log!("syscall": "gettid"> "pid({})", pcblock.pid);
+            pcblock.trapframe()["a0"] = sys_gettid(&pcblock);
+        }
+        SYSCALL_SETPGID => {
+            let pid = trapframe["a0"];
+            let pgid = trapframe["a1"] as isize;
+            drop(trapframe);
+            log!("syscall":"setpgid">"pid({}) ({}, {})", pcblock.pid, pid, pgid);
+            pcblock.trapframe()["a0"] = syscall_ret(sys_setpgid(&mut pcblock, pid, pgid));
+        }
+        SYSCALL_GETPGID => {
+            let pid = trapframe["a0"];
+            drop(trapframe);
+            pcblock.trapframe()["a0"] = syscall_ret(sys_getpgid(&mut pcblock, pid));
+        }
+        SYSCALL_SETSID => {
+            drop(trapframe);
+            log!("syscall":"setsid">"pid({})", pcblock.pid);
+            pcblock.trapframe()["a0"] = syscall_ret(sys_setsid(&mut pcblock));
+        }
+        SYSCALL_GETSID => {
+            let pid = trapframe["a0"];
+            drop(trapframe);
+            pcblock.trapframe()["a0"] = syscall_ret(sys_getsid(&mut pcblock, pid));
+        }
         SYSCALL_GETPPID => {
             log!("syscall": "getppid"> "pid({})", pcblock.pid);
             pcblock.trapframe()["a0"] = sys_getppid(&pcblock);
@@ -214,7 +326,8 @@ pub fn syscall_handler() {
             let rusage = VirtualAddr(trapframe["a3"]);
             drop(trapframe);
             log!("syscall":"wait4" > "pid({}) ({}, 0x{:x}, 0x{:x})", pcblock.pid, waitpid, wstatus.0, options);
-            sys_wait4(&mut pcblock, waitpid, wstatus, options, rusage);
+            pcblock.trapframe()["a0"] =
+                syscall_ret(sys_wait4(&mut pcblock, waitpid, wstatus, options, rusage));
         }
         SYSCALL_SBRK => {
             let inc = trapframe["a0"];
@@ -229,21 +342,25 @@ pub fn syscall_handler() {
             pcblock.trapframe()["a0"] = sys_brk(&mut pcblock, va) as usize;
         }
         SYSCALL_CLONE => {
-            let flags = CloneFlags::from_bits(trapframe["a0"]).unwrap_or(CloneFlags::empty());
+            let flags = CloneFlags::from_bits_truncate(trapframe["a0"]);
             let stack_top = VirtualAddr(trapframe["a1"]);
             let ptid = trapframe["a2"];
-            let ctid = trapframe["a3"];
-            let newtls = trapframe["a4"];
+            let newtls = trapframe["a3"];
+            let ctid = trapframe["a4"];
             drop(trapframe);
             log!("syscall":"clone" > "pid({}) flags({:?}), stack(0x{:x})", pcblock.pid, flags, stack_top.0);
             pcblock.trapframe()["a0"] =
-                sys_clone(&mut pcblock, flags, stack_top, ptid, ctid, newtls) as usize;
+                syscall_ret(sys_clone(&mut pcblock, flags, stack_top, ptid, ctid, newtls));
         }
         SYSCALL_EXEC => {
             let path = VirtualAddr(trapframe["a0"]);
             let argv = VirtualAddr(trapframe["a1"]);
             let envp = VirtualAddr(trapframe["a2"]);
-            sys_execve(&mut pcblock, path, argv, envp);
+            drop(trapframe);
+            if let Err(errno) = sys_execve(&mut pcblock, path, argv, envp) {
+                log!("syscall":"execve""fail">"{:?}", errno);
+                pcblock.trapframe()["a0"] = errno.as_ret();
+            }
         }
         SYSCALL_MMAP => {
             let start = VirtualAddr(trapframe["a0"]);
@@ -253,90 +370,110 @@ pub fn syscall_handler() {
             let fd = trapframe["a4"] as isize;
             let offset = trapframe["a5"];
             pcblock.trapframe()["a0"] =
-                sys_mmap(&mut pcblock, start, length, prot, flags, fd, offset).0;
+                syscall_ret(sys_mmap(&mut pcblock, start, length, prot, flags, fd, offset));
         }
         SYSCALL_MUNMAP => {
             let start = VirtualAddr(trapframe["a0"]);
             let length = trapframe["a1"];
-            pcblock.trapframe()["a0"] = sys_munmap(&mut pcblock, start, length) as usize;
+            pcblock.trapframe()["a0"] = syscall_ret(sys_munmap(&mut pcblock, start, length));
         }
         SYSCALL_KILL => {
-            let pid = trapframe["a0"];
+            let pid = trapframe["a0"] as isize;
             let sig = trapframe["a1"];
             drop(trapframe);
-            log!("syscall":"times">"pid({})", pcblock.pid);
-            pcblock.trapframe()["a0"] = sys_kill(pid, sig) as usize;
+            log!("syscall":"kill">"pid({})", pcblock.pid);
+            pcblock.trapframe()["a0"] = syscall_ret(sys_kill(&mut pcblock, pid, sig));
         }
         SYSCALL_SIGACTION => {
             let signum = trapframe["a0"];
             let act = VirtualAddr(trapframe["a1"]);
             let oldact = VirtualAddr(trapframe["a2"]);
             drop(trapframe);
-            log!("syscall":"sigaction">"pid({}) signal({:?})", pcblock.pid, crate::process::signal::Signal::from_bits(signum));
+            log!("syscall":"sigaction">"pid({}) signal({})", pcblock.pid, signum);
+            pcblock.trapframe()["a0"] =
+                syscall_ret(sys_rt_sigaction(&mut pcblock, signum, act, oldact));
+        }
+        SYSCALL_SIGPROCMASK => {
+            let how = trapframe["a0"];
+            let set = VirtualAddr(trapframe["a1"]);
+            let oldset = VirtualAddr(trapframe["a2"]);
+            let sigsetsize = trapframe["a3"];
+            drop(trapframe);
+            log!("syscall":"sigprocmask">"pid({}) how({})", pcblock.pid, how);
             pcblock.trapframe()["a0"] =
-                sys_rt_sigaction(&mut pcblock, signum, act, oldact) as usize;
+                syscall_ret(sys_rt_sigprocmask(&mut pcblock, how, set, oldset, sigsetsize));
+        }
+        SYSCALL_SIGPENDING => {
+            let set = VirtualAddr(trapframe["a0"]);
+            let sigsetsize = trapframe["a1"];
+            drop(trapframe);
+            pcblock.trapframe()["a0"] = syscall_ret(sys_rt_sigpending(&mut pcblock, set, sigsetsize));
+        }
+        SYSCALL_SIGSUSPEND => {
+            let mask = VirtualAddr(trapframe["a0"]);
+            let sigsetsize = trapframe["a1"];
+            drop(trapframe);
+            log!("syscall":"sigsuspend">"pid({})", pcblock.pid);
+            pcblock.trapframe()["a0"] = syscall_ret(sys_rt_sigsuspend(&mut pcblock, mask, sigsetsize));
+        }
+        SYSCALL_SIGQUEUEINFO => {
+            let pid = trapframe["a0"];
+            let sig = trapframe["a1"];
+            let uinfo = VirtualAddr(trapframe["a2"]);
+            drop(trapframe);
+            pcblock.trapframe()["a0"] = syscall_ret(sys_rt_sigqueueinfo(&mut pcblock, pid, sig, uinfo));
         }
         SYSCALL_SIGRETURN => {
             drop(trapframe);
-            assert!(if let PcbState::SigHandling(_, _) = pcblock.state() {
-                true
-            } else {
-                false
-            });
-            pcblock.signal_return();
-            pcblock.set_state(PcbState::Running);
+            // 恢复的a0就是被中断时的a0，不需要设置返回值
+            //     栈上的ucontext不可读时，进程无法继续运行，与用户程序的错误一样强制以SIGSEGV处理
+            if let Err(e) = pcblock.signal_return() {
+                log!("syscall":"sigreturn">"pid({}) bad signal frame {:?}", pcblock.pid, e);
+                pcblock.force_signal(SigInfo::new(Signal::SIGSEGV, SI_KERNEL, 0));
+            }
+        }
+        SYSCALL_SIGALTSTACK => {
+            let ss = VirtualAddr(trapframe["a0"]);
+            let old_ss = VirtualAddr(trapframe["a1"]);
+            drop(trapframe);
+            log!("syscall":"sigaltstack">"pid({})", pcblock.pid);
+            pcblock.trapframe()["a0"] = syscall_ret(sys_sigaltstack(&mut pcblock, ss, old_ss));
         }
         SYSCALL_TIMES => {
             let tms = trapframe["a0"];
             drop(trapframe);
-            pcblock.trapframe()["a0"] = sys_times(&mut pcblock, VirtualAddr(tms));
+            pcblock.trapframe()["a0"] = syscall_ret(sys_times(&mut pcblock, VirtualAddr(tms)));
             log!("syscall":"times">"pid({})", pcblock.pid);
         }
         SYSCALL_UNAME => {
             let uts = VirtualAddr(trapframe["a0"]);
-            pcblock.trapframe()["a0"] = sys_uname(&mut pcblock, uts) as usize;
+            pcblock.trapframe()["a0"] = syscall_ret(sys_uname(&mut pcblock, uts));
         }
         SYSCALL_GET_TIME_OF_DAY => {
             let timespec = VirtualAddr(trapframe["a0"]);
             let timezone = VirtualAddr(trapframe["a1"]);
-            pcblock.trapframe()["a0"] = sys_gettimeofday(timespec, timezone) as usize;
+            pcblock.trapframe()["a0"] = syscall_ret(sys_gettimeofday(&mut pcblock, timespec, timezone));
         }
         SYSCALL_NANOSLEEP => {
-            let timespec = PhysAddr(trapframe["a0"]);
-            let timespec: &TimeSpec = timespec.as_ref();
-            let current_time = get_time();
-            trapframe["a0"] = 0;
-            let wakeup_time =
-                get_time() + timespec.tv_sec * RTCLK_FREQ + timespec.tv_nsec * RTCLK_FREQ / 1000;
-            pcblock.block_fn = Some(Arc::new(move |pcb| {
-                if wakeup_time <= get_time() {
-                    return true;
-                }
-                false
-            }));
-            pcblock.set_state(PcbState::Blocking);
+            let req = VirtualAddr(trapframe["a0"]);
+            drop(trapframe);
+            pcblock.trapframe()["a0"] = syscall_ret(sys_nanosleep(&mut pcblock, req));
         }
         SYSCALL_FORK => {
             drop(trapframe);
             log!("syscall":"fork" > "pid({}) ()", pcblock.pid);
-            pcblock.trapframe()["a0"] = sys_fork(&mut pcblock) as usize;
+            pcblock.trapframe()["a0"] = syscall_ret(sys_fork(&mut pcblock));
         }
         _ => {
             log!("syscall":>"unsupported syscall {}", trapframe["a7"]);
+            trapframe["a0"] = Errno::ENOSYS.as_ret();
         }
     }
-    let state = pcblock.state;
-    drop(pcblock);
-    if let PcbState::Zombie(_) = state {
-    }else if let PcbState::Blocking = state {
-        #[cfg(feature = "FCFS")]
-        scheduler_insert_front(pcb.clone());
-        #[cfg(not(feature = "FCFS"))]
-        scheduler_push(pcb.clone());
-    } else {
-        scheduler_insert_front(pcb.clone());
+    // 被信号中断的系统调用，返回用户态前由try_handle_signal决定重新执行还是返回EINTR
+    if syscall_id != SYSCALL_SIGRETURN && pcblock.trapframe()["a0"] == Errno::ERESTARTSYS.as_ret() {
+        pcblock.syscall_restart = Some(orig_a0);
     }
-    // Note: 这里必须显式调用drop释放进程锁
+    // Note: 这里必须显式调用drop释放进程锁，由trap_return返回用户态
+    drop(pcblock);
     drop(pcb);
-    schedule();
 }
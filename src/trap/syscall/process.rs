@@ -1,27 +1,24 @@
 use crate::config::*;
-use crate::mm::PhysAddr;
+use crate::errno::*;
 use crate::mm::VirtualAddr;
-use crate::process::pcb::alloc_pid;
-use crate::process::signal::*;
+use crate::process::cpu::online_harts;
+use crate::process::futex::*;
+use crate::process::pcb::{
+    alloc_pid, exit_wstatus, find_affinity, find_thread_group, thread_groups, JobReport, ThreadGroup,
+};
 use crate::process::*;
 use crate::task::*;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use spin::{Mutex, MutexGuard};
 
-pub(super) fn sys_fork(pcb: &mut MutexGuard<Pcb>) -> isize {
+pub(super) fn sys_fork(pcb: &mut MutexGuard<Pcb>) -> SysResult {
     return sys_clone(pcb, CloneFlags::empty(), VirtualAddr(0), 0, 0, 0);
 }
 
-bitflags! {
-    pub struct CloneFlags: usize{
-        const SIGCHLD = 17;
-        const CLONE_CHILD_CLEARTID = 0x00200000;
-        const CLONE_CHILD_SETTID = 0x01000000;
-    }
-}
-
+// Linux riscv的clone参数顺序为flags, stack, ptid, tls, ctid
 pub(super) fn sys_clone(
     pcb: &mut MutexGuard<Pcb>,
     flags: CloneFlags,
@@ -29,77 +26,372 @@ pub(super) fn sys_clone(
     ptid: usize,
     ctid: usize,
     newtls: usize,
-) -> isize {
-    // Note: 与Linux的clone不同，参考于UltraOs
-    let child = pcb.clone_child();
-    let childpid = child.lock().pid;
+) -> SysResult {
+    // 线程必须共享信号处理函数，共享信号处理函数必须共享内存空间
+    if flags.contains(CloneFlags::CLONE_THREAD) && !flags.contains(CloneFlags::CLONE_SIGHAND)
+        || flags.contains(CloneFlags::CLONE_SIGHAND) && !flags.contains(CloneFlags::CLONE_VM)
+    {
+        return Err(Errno::EINVAL);
+    }
+    let child = pcb.clone_child(flags).map_err(|e| {
+        log!("syscall":"clone">"{:?}", e);
+        e
+    })?;
+    let mut childlock = child.lock();
+    let tid = childlock.pid;
+    let sigqueue = childlock.sigqueue.clone();
+    let group = childlock.thread_group.clone();
     // 设置栈
     if stack_top.0 != 0 {
-        child.lock().trapframe()["sp"] = stack_top.0;
+        childlock.trapframe()["sp"] = stack_top.0;
+    }
+    if flags.contains(CloneFlags::CLONE_SETTLS) {
+        childlock.trapframe()["tp"] = newtls;
+    }
+    if flags.contains(CloneFlags::CLONE_CHILD_SETTID) {
+        childlock.set_child_tid = ctid;
+    }
+    if flags.contains(CloneFlags::CLONE_CHILD_CLEARTID) {
+        childlock.clear_child_tid = ctid;
+    }
+    drop(childlock);
+    if flags.contains(CloneFlags::CLONE_PARENT_SETTID) {
+        if let Err(e) = pcb.memory_space.lock().write_user(VirtualAddr(ptid), &(tid as u32)) {
+            // 子进程还没有运行，直接丢弃；线程没有加入父进程的children
+            if !flags.contains(CloneFlags::CLONE_THREAD) {
+                if let Some(parent_group) = group.parent().1.upgrade() {
+                    parent_group.children.lock().retain(|c| !Arc::ptr_eq(c, &group));
+                }
+            }
+            return Err(e.into());
+        }
+    }
+    if flags.contains(CloneFlags::CLONE_THREAD) {
+        pcb.thread_group.add_thread(sigqueue);
     }
     scheduler_insert_front(child);
-    childpid as isize
+    Ok(tid)
 }
 
 pub(super) fn sys_getpid(pcb: &MutexGuard<Pcb>) -> isize {
-    pcb.pid as isize
+    pcb.tgid as isize
+}
+
+pub(super) fn sys_gettid(pcb: &MutexGuard<Pcb>) -> usize {
+    pcb.pid
+}
+
+// 线程退出时将tidptr处的tid清零，返回tid
+pub(super) fn sys_set_tid_address(pcb: &mut MutexGuard<Pcb>, tidptr: usize) -> usize {
+    pcb.clear_child_tid = tidptr;
+    pcb.pid
+}
+
+// 同步原语futex，uaddr2和val3只用于requeue，timeout在requeue时表示移动的个数
+pub(super) fn sys_futex(
+    pcb: &mut MutexGuard<Pcb>,
+    uaddr: VirtualAddr,
+    futex_op: usize,
+    val: usize,
+    timeout: VirtualAddr,
+    uaddr2: VirtualAddr,
+    val3: usize,
+) -> SysResult {
+    // 私有futex以内存空间和用户地址为键，共享futex以物理地址为键
+    let cmd = futex_op & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME);
+    // 与Linux相同，FUTEX_CLOCK_REALTIME只能用于绝对时间的FUTEX_WAIT_BITSET等操作，这些操作都不支持
+    //     FUTEX_WAIT的超时是相对时间，不能按绝对时间处理
+    if futex_op & FUTEX_CLOCK_REALTIME != 0 {
+        log!("syscall":"futex">"unsupported clock for op {}", cmd);
+        return Err(Errno::ENOSYS);
+    }
+    if uaddr.0 % size_of::<u32>() != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let private = futex_op & FUTEX_PRIVATE_FLAG != 0;
+    // 查找物理地址、检查值和加入队列时持有内存空间的锁，写时复制不会在这期间换掉页面
+    let memory_space = pcb.memory_space.clone();
+    let mut ms = memory_space.lock();
+    let word = ms.user_paddr(uaddr)?;
+    let key = FutexKey::new(private, ms.id(), uaddr, word);
+    match cmd {
+        FUTEX_WAIT => {
+            // 相对时间，0表示一直等待
+            let deadline = if timeout.0 != 0 {
+                let ts = ms.read_user::<TimeSpec>(timeout)?;
+                Some(cpu::get_time() + ts.to_ticks())
+            } else {
+                None
+            };
+            let waiter = block_current(pcb, deadline);
+            let queued = futex_enqueue(key, word, ms.id(), val as u32, waiter);
+            drop(ms);
+            if !queued {
+                cancel_block(pcb);
+                return Err(Errno::EAGAIN);
+            }
+            block(pcb)?;
+            Ok(0)
+        }
+        FUTEX_WAKE => Ok(futex_wake(key, val)),
+        FUTEX_REQUEUE | FUTEX_CMP_REQUEUE => {
+            let word2 = ms.user_paddr(uaddr2)?;
+            let key2 = FutexKey::new(private, ms.id(), uaddr2, word2);
+            let cmp = if cmd == FUTEX_CMP_REQUEUE {
+                Some(val3 as u32)
+            } else {
+                None
+            };
+            futex_requeue(key, word, val, key2, timeout.0, cmp).map_err(|_| Errno::EAGAIN)
+        }
+        _ => {
+            log!("syscall":"futex">"unsupported op {}", futex_op);
+            Err(Errno::ENOSYS)
+        }
+    }
 }
 
 pub(super) fn sys_yield() -> isize {
     0
 }
 
+// 只退出当前线程
 pub(super) fn sys_exit(pcb: &mut MutexGuard<Pcb>, xstate: isize) {
-    pcb.exit(xstate);
-    sigqueue_send(pcb.parent, Signal::SIGCHLD);
+    pcb.exit(exit_wstatus(xstate));
+}
+
+// 退出线程组中的所有线程，其他线程在下一次调度时退出
+pub(super) fn sys_exit_group(pcb: &mut MutexGuard<Pcb>, xstate: isize) {
+    pcb.thread_group.exit(exit_wstatus(xstate));
+    sys_exit(pcb, xstate);
+}
+
+// wait4的options
+const WNOHANG: usize = 1;
+const WUNTRACED: usize = 2;
+const WCONTINUED: usize = 8;
+// __WNOTHREAD只等待当前线程创建的子进程，__WALL等待所有子进程，
+//     __WCLONE只等待退出时不发送SIGCHLD的子进程，子进程退出时总是发送SIGCHLD，没有这样的子进程
+const WNOTHREAD: usize = 0x20000000;
+const WALL: usize = 0x40000000;
+const WCLONE: usize = 0x80000000;
+
+#[repr(C)]
+struct TimeVal {
+    tv_sec: usize,
+    tv_usec: usize,
+}
+
+impl TimeVal {
+    fn from_ticks(ticks: usize) -> Self {
+        Self {
+            tv_sec: ticks / RTCLK_FREQ,
+            tv_usec: (ticks % RTCLK_FREQ) * 1000_000 / RTCLK_FREQ,
+        }
+    }
+}
+
+#[repr(C)]
+struct Rusage {
+    ru_utime: TimeVal,
+    ru_stime: TimeVal,
+    // maxrss、缺页次数等其他统计没有记录，填0
+    ru_others: [usize; 14],
+}
+
+// 子进程是否符合wait4的pid参数
+fn wait_match(pid: isize, pgid: Pid, child: &ThreadGroup) -> bool {
+    match pid {
+        -1 => true,
+        // 与当前进程同一个进程组
+        0 => child.pgid() == pgid,
+        pid if pid < -1 => child.pgid() == (-pid) as usize,
+        pid => child.tgid() == pid as usize,
+    }
+}
+
+// wait4可以报告的子进程状态变化
+#[derive(Clone, Copy)]
+enum WaitEvent {
+    // 退出或被信号终止，保存wstatus
+    Exited(isize),
+    Job(JobReport),
+}
+
+impl WaitEvent {
+    // wstatus的格式与Linux相同
+    fn wstatus(&self) -> i32 {
+        match *self {
+            WaitEvent::Exited(wstatus) => wstatus as i32,
+            WaitEvent::Job(JobReport::Stopped(signal)) => ((signal.signum() << 8) | 0x7f) as i32,
+            WaitEvent::Job(JobReport::Continued) => 0xffff,
+        }
+    }
+}
+
+// 找到pid指定的、状态发生变化的子进程，没有变化时返回None，没有符合的子进程时返回ECHILD
+//     停止和继续只在设置了WUNTRACED和WCONTINUED时报告
+//     reap为true时持有children的锁回收退出的子进程，停止和继续只报告一次
+fn find_child_event(
+    pcb: &Pcb,
+    pid: isize,
+    options: usize,
+    reap: bool,
+) -> Result<Option<(Arc<ThreadGroup>, WaitEvent)>, Errno> {
+    let pgid = pcb.thread_group.pgid();
+    let mut found = false;
+    // 子进程属于线程组，进程中的任意线程都可以wait，__WNOTHREAD时只检查当前线程创建的子进程
+    //     只检查子进程的线程组，不锁子进程的Pcb
+    let mut children = pcb.thread_group.children.lock();
+    for idx in 0..children.len() {
+        let child = &children[idx];
+        if !wait_match(pid, pgid, child) {
+            continue;
+        }
+        if options & WNOTHREAD != 0 && child.creator() != pcb.pid {
+            continue;
+        }
+        if options & (WCLONE | WALL) == WCLONE {
+            continue;
+        }
+        found = true;
+        // 所有线程都退出之后才能回收
+        let event = match (child.exit_status(), child.job_report()) {
+            (Some(wstatus), _) => WaitEvent::Exited(wstatus),
+            (None, Some(report @ JobReport::Stopped(_))) if options & WUNTRACED != 0 => WaitEvent::Job(report),
+            (None, Some(report @ JobReport::Continued)) if options & WCONTINUED != 0 => WaitEvent::Job(report),
+            _ => continue,
+        };
+        let child = child.clone();
+        if reap {
+            match event {
+                WaitEvent::Exited(_) => {
+                    children.remove(idx);
+                }
+                WaitEvent::Job(report) => child.clear_job_report(report),
+            }
+        }
+        return Ok(Some((child, event)));
+    }
+    if found {
+        Ok(None)
+    } else {
+        Err(Errno::ECHILD)
+    }
 }
 
-// 忽略rusage
 pub(super) fn sys_wait4(
     pcb: &mut MutexGuard<Pcb>,
     pid: isize,
     wstatus: VirtualAddr,
-    _: usize,
-    _: VirtualAddr,
-) {
-    // 阻塞直到某个子进程退出
-    // 找到pid指定的退出的子进程
-    let find_child_exit = move |_pcb: &mut Pcb| -> Option<usize> {
-        if let Some((idx, child)) = _pcb.children.iter().enumerate().find(|(_idx, child)| {
-            let child = child.lock();
-            if pid == -1 || child.pid == pid.abs() as usize {
-                if let PcbState::Zombie(_) = child.state() {
-                    return true;
-                }
+    options: usize,
+    rusage: VirtualAddr,
+) -> SysResult {
+    if options & !(WNOHANG | WUNTRACED | WCONTINUED | WNOTHREAD | WALL | WCLONE) != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let mut interrupted = None;
+    loop {
+        if let Some((child, event)) = find_child_event(pcb, pid, options, true)? {
+            // 子进程的时间包括它回收的子进程，还没有退出时为0
+            let (utimes, stimes) = child.times();
+            if let WaitEvent::Exited(_) = event {
+                pcb.cutimes_add(utimes);
+                pcb.cstimes_add(stimes);
             }
-            false
-        }) {
-            return Some(idx);
-        }
-        None
-    };
-    // 如果找到
-    if let Some(idx) = find_child_exit(pcb) {
-        let child = pcb.children.remove(idx);
-        let child = child.lock();
-        if let PcbState::Zombie(xcode) = child.state() {
-            pcb.trapframe()["a0"] = child.pid;
-            pcb.cutimes_add(child.utimes());
-            pcb.cstimes_add(child.stimes());
+            let mut ms = pcb.memory_space.lock();
             if wstatus.0 != 0 {
-                let mut wstatus: PhysAddr = wstatus.into();
-                let wstatus: &mut usize = wstatus.as_mut();
-                *wstatus = (xcode << 8) as usize;
+                ms.write_user(wstatus, &event.wstatus())?;
             }
+            if rusage.0 != 0 {
+                let usage = Rusage {
+                    ru_utime: TimeVal::from_ticks(utimes),
+                    ru_stime: TimeVal::from_ticks(stimes),
+                    ru_others: [0; 14],
+                };
+                ms.write_user(rusage, &usage)?;
+            }
+            return Ok(child.tgid());
+        }
+        if options & WNOHANG != 0 {
+            return Ok(0);
         }
+        // 被信号唤醒后先检查子进程，SIGCHLD和子进程的状态变化同时到达时报告子进程
+        if let Some(e) = interrupted {
+            return Err(Errno::from(e));
+        }
+        // 如果找不到，进程进入阻塞，直到有子进程退出、停止或继续
+        let waiter = block_current(pcb, None);
+        pcb.thread_group.child_exit.add(waiter);
+        // 子进程可能在检查之后、加入等待队列之前退出
+        if !matches!(find_child_event(pcb, pid, options, false), Ok(None)) {
+            cancel_block(pcb);
+        }
+        interrupted = block(pcb).err();
+    }
+}
+
+// setpgid的目标只能是当前进程或者子进程，pid为0表示当前进程
+//     从进程表中查找并检查父进程，不锁子进程的Pcb
+fn find_self_or_child(pcb: &Pcb, pid: Pid) -> Result<Arc<ThreadGroup>, Errno> {
+    if pid == 0 || pid == pcb.tgid {
+        return Ok(pcb.thread_group.clone());
+    }
+    find_thread_group(pid)
+        .filter(|group| group.parent().0 == pcb.tgid)
+        .ok_or(Errno::ESRCH)
+}
+
+// pid为0表示当前进程，否则查找进程表
+fn find_process(pcb: &Pcb, pid: Pid) -> Result<Arc<ThreadGroup>, Errno> {
+    if pid == 0 {
+        Ok(pcb.thread_group.clone())
     } else {
-        // 如果找不到，退回这条系统调用指令
-        pcb.trapframe()["sepc"] -= 4;
-        // 进程进入阻塞，知道指定子进程退出
-        pcb.block_fn = Some(Arc::new(move |pcb| find_child_exit(pcb).is_some()));
-        pcb.set_state(PcbState::Blocking);
+        find_thread_group(pid).ok_or(Errno::ESRCH)
+    }
+}
+
+// 将进程加入进程组，pgid为0时使用进程的pid创建新的进程组
+pub(super) fn sys_setpgid(pcb: &mut MutexGuard<Pcb>, pid: usize, pgid: isize) -> SysResult {
+    if pgid < 0 {
+        return Err(Errno::EINVAL);
+    }
+    let group = find_self_or_child(pcb, pid)?;
+    let pgid = if pgid == 0 { group.tgid() } else { pgid as Pid };
+    // 会话首进程不能改变进程组，子进程必须与当前进程在同一个会话
+    if group.sid() == group.tgid() || group.sid() != pcb.thread_group.sid() {
+        return Err(Errno::EPERM);
     }
+    // 只能加入同一个会话中已经存在的进程组
+    if pgid != group.tgid()
+        && !thread_groups()
+            .iter()
+            .any(|other| other.pgid() == pgid && other.sid() == group.sid())
+    {
+        return Err(Errno::EPERM);
+    }
+    group.set_pgid(pgid);
+    Ok(0)
 }
+
+pub(super) fn sys_getpgid(pcb: &mut MutexGuard<Pcb>, pid: usize) -> SysResult {
+    Ok(find_process(pcb, pid)?.pgid())
+}
+
+// 创建新的会话，进程组组长不能创建会话
+pub(super) fn sys_setsid(pcb: &mut MutexGuard<Pcb>) -> SysResult {
+    let tgid = pcb.tgid;
+    if thread_groups().iter().any(|group| group.pgid() == tgid) {
+        return Err(Errno::EPERM);
+    }
+    pcb.thread_group.set_sid();
+    Ok(tgid)
+}
+
+pub(super) fn sys_getsid(pcb: &mut MutexGuard<Pcb>, pid: usize) -> SysResult {
+    Ok(find_process(pcb, pid)?.sid())
+}
+
 #[repr(C)]
 struct Tms {
     utime: usize,
@@ -107,32 +399,101 @@ struct Tms {
     cutime: usize,
     cstime: usize,
 }
-pub(super) fn sys_times(pcb: &mut MutexGuard<Pcb>, tms: VirtualAddr) -> usize {
-    let mut tms: PhysAddr = tms.into();
-    let tms: &mut Tms = tms.as_mut();
-    tms.utime = pcb.utimes();
-    tms.stime = pcb.stimes();
-    tms.cutime = pcb.cutimes();
-    tms.cstime = pcb.cstimes();
+pub(super) fn sys_times(pcb: &mut MutexGuard<Pcb>, tms: VirtualAddr) -> SysResult {
+    let times = Tms {
+        utime: pcb.utimes(),
+        stime: pcb.stimes(),
+        cutime: pcb.cutimes(),
+        cstime: pcb.cstimes(),
+    };
+    pcb.memory_space.lock().write_user(tms, &times)?;
     // Fix: 只是简单返回times
-    cpu::get_time()
+    Ok(cpu::get_time())
 }
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub(super) struct TimeSpec {
     pub tv_sec: usize,
     pub tv_nsec: usize,
 }
 
-pub(super) fn sys_gettimeofday(timespec: VirtualAddr, _: VirtualAddr) -> isize {
-    let mut timespec: PhysAddr = timespec.into();
-    let timespec: &mut TimeSpec = timespec.as_mut();
+impl TimeSpec {
+    // 转换为时钟周期数，先除以1000防止溢出
+    fn to_ticks(&self) -> usize {
+        self.tv_sec * RTCLK_FREQ + self.tv_nsec * (RTCLK_FREQ / 1000) / 1000_000
+    }
+}
+
+// 不在任何等待队列上，只能超时唤醒，被信号中断时返回EINTR，不重新执行
+pub(super) fn sys_nanosleep(pcb: &mut MutexGuard<Pcb>, req: VirtualAddr) -> SysResult {
+    let ts = pcb.memory_space.lock().read_user::<TimeSpec>(req)?;
+    block_current(pcb, Some(cpu::get_time() + ts.to_ticks()));
+    match block(pcb) {
+        Err(BlockErr::Interrupted) | Err(BlockErr::Signal) => Err(Errno::EINTR),
+        _ => Ok(0),
+    }
+}
+
+pub(super) fn sys_gettimeofday(
+    pcb: &mut MutexGuard<Pcb>,
+    timespec: VirtualAddr,
+    _: VirtualAddr,
+) -> SysResult {
     let time = cpu::get_time();
-    timespec.tv_sec = time / RTCLK_FREQ;
-    timespec.tv_nsec = (time % RTCLK_FREQ) * 1000_000 / RTCLK_FREQ;
-    0
+    let tv = TimeSpec {
+        tv_sec: time / RTCLK_FREQ,
+        tv_nsec: (time % RTCLK_FREQ) * 1000_000 / RTCLK_FREQ,
+    };
+    pcb.memory_space.lock().write_user(timespec, &tv)?;
+    Ok(0)
 }
 
 pub(super) fn sys_getppid(pcb: &MutexGuard<Pcb>) -> usize {
-    pcb.parent
+    pcb.thread_group.parent().0
+}
+
+// sched_setaffinity和sched_getaffinity的目标线程，pid为0时为当前线程
+//     从线程表中查找，不锁目标线程的Pcb，目标线程可能正在阻塞并持有自己的锁
+fn find_sched_target(pcb: &Pcb, pid: Pid) -> Result<Arc<AtomicUsize>, Errno> {
+    if pid == 0 {
+        return Ok(pcb.affinity.clone());
+    }
+    find_affinity(pid).ok_or(Errno::ESRCH)
+}
+
+// 设置线程允许运行的hart，cpu_set_t的第n位对应hartid为n的hart
+//     正在其他hart上运行的线程在下次被调度时转移
+pub(super) fn sys_sched_setaffinity(
+    pcb: &mut MutexGuard<Pcb>,
+    pid: Pid,
+    cpusetsize: usize,
+    mask: VirtualAddr,
+) -> SysResult {
+    if cpusetsize < size_of::<usize>() {
+        return Err(Errno::EINVAL);
+    }
+    let mask: usize = pcb.memory_space.lock().read_user(mask)?;
+    // 只能在已经启动的hart上运行
+    let affinity = mask & online_harts();
+    if affinity == 0 {
+        return Err(Errno::EINVAL);
+    }
+    find_sched_target(pcb, pid)?.store(affinity, Ordering::SeqCst);
+    Ok(0)
+}
+
+// 返回写入的字节数
+pub(super) fn sys_sched_getaffinity(
+    pcb: &mut MutexGuard<Pcb>,
+    pid: Pid,
+    cpusetsize: usize,
+    mask: VirtualAddr,
+) -> SysResult {
+    if cpusetsize < size_of::<usize>() || cpusetsize % size_of::<usize>() != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let affinity = find_sched_target(pcb, pid)?.load(Ordering::SeqCst) & online_harts();
+    pcb.memory_space.lock().write_user(mask, &affinity)?;
+    Ok(size_of::<usize>())
 }
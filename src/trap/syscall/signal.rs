@@ -1,12 +1,14 @@
+use crate::errno::*;
 use crate::mm::address::*;
-use crate::mm::kalloc::KALLOCATOR;
 use crate::process::signal::*;
+use crate::process::pcb::{kill_all, kill_pgrp, kill_process};
 use crate::process::*;
-use alloc::sync::Arc;
-use core::cell::RefCell;
+use crate::task::*;
+use core::mem::size_of;
 use spin::mutex::MutexGuard;
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub(super) struct rt_sigaction {
     pub sa_handler: usize,
     pub sa_flags: usize,
@@ -25,36 +27,181 @@ pub(super) struct rt_sigaction {
  * };
  */
 
+// rt_sigprocmask的how
+const SIG_BLOCK: usize = 0;
+const SIG_UNBLOCK: usize = 1;
+const SIG_SETMASK: usize = 2;
+
+// 内核的sigset_t为64位
+const SIGSET_SIZE: usize = size_of::<usize>();
+
 pub(super) fn sys_rt_sigaction(
     pcb: &mut MutexGuard<Pcb>,
     signum: usize,
     act: VirtualAddr,
     oldact: VirtualAddr,
-) -> isize {
-    let mut sa = PhysAddr::from(act);
-    let sa: &mut rt_sigaction = sa.as_mut();
-    if let Some(signal) = Signal::from_bits(signum) {
-        let tf = KALLOCATOR.lock().kalloc();
-        let stack = KALLOCATOR.lock().kalloc();
+) -> SysResult {
+    let signal = Signal::from_signum(signum).ok_or(Errno::EINVAL)?;
+    let old = if act.0 != 0 {
+        // SIGKILL和SIGSTOP的处理方式不能修改
+        if SIG_UNBLOCKABLE.contains(signal) {
+            return Err(Errno::EINVAL);
+        }
+        let sa: rt_sigaction = pcb.memory_space.lock().read_user(act)?;
+        let sa_flags = SaFlags::from_bits(sa.sa_flags).ok_or(Errno::EINVAL)?;
+        let sa_mask = Signal::from_bits_truncate(sa.sa_mask);
         pcb.sigaction_bind(
             signal,
-            SigAction::Custom(Arc::new(RefCell::new(CustomSigAction {
+            CustomSigAction {
                 sa_handler: sa.sa_handler,
-                sa_flags: SaFlags::from_bits(sa.sa_flags).unwrap(),
-                sa_mask: Signal::from_bits(sa.sa_mask).unwrap(),
-                trapframe: tf,
-                user_stack: stack,
-            }))),
-        );
-        0
+                sa_flags,
+                sa_mask,
+            },
+        )
     } else {
-        -1
+        pcb.sigaction_get(signal)
+    };
+    if oldact.0 != 0 {
+        let sa = rt_sigaction {
+            sa_handler: old.sa_handler,
+            sa_flags: old.sa_flags.bits(),
+            sa_mask: old.sa_mask.bits(),
+        };
+        pcb.memory_space.lock().write_user(oldact, &sa)?;
+    }
+    Ok(0)
+}
+
+pub(super) fn sys_rt_sigprocmask(
+    pcb: &mut MutexGuard<Pcb>,
+    how: usize,
+    set: VirtualAddr,
+    oldset: VirtualAddr,
+    sigsetsize: usize,
+) -> SysResult {
+    if sigsetsize != SIGSET_SIZE {
+        return Err(Errno::EINVAL);
+    }
+    let old = pcb.sigqueue.mask();
+    if set.0 != 0 {
+        let set = Signal::from_bits_truncate(pcb.memory_space.lock().read_user::<usize>(set)?);
+        let mask = match how {
+            SIG_BLOCK => old | set,
+            SIG_UNBLOCK => old - set,
+            SIG_SETMASK => set,
+            _ => return Err(Errno::EINVAL),
+        };
+        pcb.sigqueue.set_mask(mask);
     }
+    if oldset.0 != 0 {
+        pcb.memory_space.lock().write_user(oldset, &old.bits())?;
+    }
+    Ok(0)
 }
 
-pub(super) fn sys_kill(pid: usize, sig: usize) -> isize {
-    let signal = Signal::from_bits(sig).unwrap();
-    log!("syscall":"kill">"-> (pid({}), sig({:?}))", pid, signal);
-    sigqueue_send(pid, signal);
-    0
+// 被屏蔽且等待处理的信号
+pub(super) fn sys_rt_sigpending(
+    pcb: &mut MutexGuard<Pcb>,
+    set: VirtualAddr,
+    sigsetsize: usize,
+) -> SysResult {
+    if sigsetsize != SIGSET_SIZE {
+        return Err(Errno::EINVAL);
+    }
+    // 包括只发送给这个线程的和发送给进程的信号
+    let pending = (pcb.sigqueue.pending() | pcb.thread_group.signals.pending()) & pcb.sigqueue.mask();
+    pcb.memory_space.lock().write_user(set, &pending.bits())?;
+    Ok(0)
+}
+
+// 临时替换信号掩码并等待信号，总是返回EINTR，原来的掩码在信号处理之后恢复
+pub(super) fn sys_rt_sigsuspend(
+    pcb: &mut MutexGuard<Pcb>,
+    mask: VirtualAddr,
+    sigsetsize: usize,
+) -> SysResult {
+    if sigsetsize != SIGSET_SIZE {
+        return Err(Errno::EINVAL);
+    }
+    let mask = Signal::from_bits_truncate(pcb.memory_space.lock().read_user::<usize>(mask)?);
+    let old = pcb.sigqueue.set_mask(mask);
+    pcb.saved_sigmask = Some(old);
+    loop {
+        // 不在任何等待队列上，只有信号能唤醒
+        block_current(pcb, None);
+        if let Err(BlockErr::Signal) | Err(BlockErr::Interrupted) = block(pcb) {
+            return Err(Errno::EINTR);
+        }
+    }
+}
+
+// 设置备用信号栈，正在备用信号栈上执行时不能修改
+pub(super) fn sys_sigaltstack(
+    pcb: &mut MutexGuard<Pcb>,
+    ss: VirtualAddr,
+    old_ss: VirtualAddr,
+) -> SysResult {
+    let sp = pcb.trapframe()["sp"];
+    let old = pcb.sigaltstack.status(sp);
+    if ss.0 != 0 {
+        let new: SignalStack = pcb.memory_space.lock().read_user(ss)?;
+        if old.ss_flags == SS_ONSTACK {
+            return Err(Errno::EPERM);
+        }
+        pcb.sigaltstack = match new.ss_flags {
+            SS_DISABLE => SignalStack::disabled(),
+            0 if new.ss_size < MINSIGSTKSZ => return Err(Errno::ENOMEM),
+            0 => new,
+            _ => return Err(Errno::EINVAL),
+        };
+    }
+    if old_ss.0 != 0 {
+        pcb.memory_space.lock().write_user(old_ss, &old)?;
+    }
+    Ok(0)
+}
+
+// pid大于0时发送给进程，0为当前进程组，-1为除init外的所有进程，小于-1为进程组-pid
+pub(super) fn sys_kill(pcb: &mut MutexGuard<Pcb>, pid: isize, signum: usize) -> SysResult {
+    log!("syscall":"kill">"-> (pid({}), sig({}))", pid, signum);
+    // 信号0只检查进程是否存在
+    let info = match signum {
+        0 => None,
+        signum => {
+            let signal = Signal::from_signum(signum).ok_or(Errno::EINVAL)?;
+            Some(SigInfo::new(signal, SI_USER, pcb.tgid))
+        }
+    };
+    match pid {
+        0 => kill_pgrp(pcb.thread_group.pgid(), info),
+        -1 => kill_all(pcb.tgid, info),
+        pid if pid < -1 => kill_pgrp((-pid) as Pid, info),
+        pid => kill_process(pid as Pid, info),
+    }?;
+    Ok(0)
+}
+
+// sigqueue使用，发送由用户填写的siginfo，实时信号可以通过si_value携带数据
+//     发送给其他进程时si_code必须为负数，不能伪装成kill或者内核发送的信号
+pub(super) fn sys_rt_sigqueueinfo(
+    pcb: &mut MutexGuard<Pcb>,
+    pid: Pid,
+    signum: usize,
+    uinfo: VirtualAddr,
+) -> SysResult {
+    log!("syscall":"sigqueueinfo">"-> (pid({}), sig({}))", pid, signum);
+    let mut info: SigInfo = pcb.memory_space.lock().read_user(uinfo)?;
+    if (info.si_code >= 0 || info.si_code == SI_TKILL) && pid != pcb.tgid {
+        return Err(Errno::EPERM);
+    }
+    let info = match signum {
+        0 => None,
+        signum => {
+            Signal::from_signum(signum).ok_or(Errno::EINVAL)?;
+            info.si_signo = signum as i32;
+            Some(info)
+        }
+    };
+    kill_process(pid, info)?;
+    Ok(0)
 }
@@ -1,4 +1,5 @@
 use spin::MutexGuard;
+use crate::errno::*;
 use crate::mm::*;
 use crate::process::Pcb;
 
@@ -19,14 +20,21 @@ const VERSION: &'static str = "v0.1\0";
 const MACHINE: &'static str = "Hifive Unmatched\0";
 const DOMAINNAME: &'static str = "\0";
 
-pub(super) fn sys_uname(pcb: &mut MutexGuard<Pcb>, utsname: VirtualAddr) -> isize {
-    let mut phys: PhysAddr = utsname.into();
-    let utsname: &mut UtsName = phys.as_mut();
-    utsname.sysname[..SYSNAME.len()].copy_from_slice(SYSNAME.as_bytes());
-    utsname.nodename[..NODENAME.len()].copy_from_slice(NODENAME.as_bytes());
-    utsname.release[..RELEASE.len()].copy_from_slice(RELEASE.as_bytes());
-    utsname.version[..VERSION.len()].copy_from_slice(VERSION.as_bytes());
-    utsname.machine[..MACHINE.len()].copy_from_slice(MACHINE.as_bytes());
-    utsname.domainname[..DOMAINNAME.len()].copy_from_slice(DOMAINNAME.as_bytes());
-    0
+pub(super) fn sys_uname(pcb: &mut MutexGuard<Pcb>, utsname: VirtualAddr) -> SysResult {
+    let mut uts = UtsName {
+        sysname: [0; 65],
+        nodename: [0; 65],
+        release: [0; 65],
+        version: [0; 65],
+        machine: [0; 65],
+        domainname: [0; 65],
+    };
+    uts.sysname[..SYSNAME.len()].copy_from_slice(SYSNAME.as_bytes());
+    uts.nodename[..NODENAME.len()].copy_from_slice(NODENAME.as_bytes());
+    uts.release[..RELEASE.len()].copy_from_slice(RELEASE.as_bytes());
+    uts.version[..VERSION.len()].copy_from_slice(VERSION.as_bytes());
+    uts.machine[..MACHINE.len()].copy_from_slice(MACHINE.as_bytes());
+    uts.domainname[..DOMAINNAME.len()].copy_from_slice(DOMAINNAME.as_bytes());
+    pcb.memory_space.lock().write_user(utsname, &uts)?;
+    Ok(0)
 }
\ No newline at end of file
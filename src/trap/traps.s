@@ -10,11 +10,9 @@ __alltraps:
     csrrw sp, sscratch, sp
     sd x1, 1*8(sp)
     # skip sp(x2), we will save it later
-    sd x3, 3*8(sp)
-    # skip tp(x4), application does not use it
-    # save x5~x31
-    .set n, 5
-    .rept 27
+    # save x3~x31
+    .set n, 3
+    .rept 29
         SAVE_GP %n
         .set n, n+1
     .endr
@@ -27,6 +25,8 @@ __alltraps:
     csrr t2, sscratch
     sd t2, 2*8(sp)
     csrw sscratch, sp
+    # tp保存的是用户的线程指针，恢复内核使用的hartid
+    ld tp, 38*8(sp)
     # load kernel_satp into t0
     ld t0, 35*8(sp)
     # load trap_handler into t1
@@ -58,21 +58,61 @@ __restore:
     # switch to user space
     csrw sscratch, a0
     mv sp, a0
+    # 保存hartid，下次陷入时恢复到tp
+    sd tp, 38*8(sp)
     # now sp points to TrapContext in user space, start restoring based on it
     # restore sstatus/sepc
     ld t0, 32*8(sp)
     ld t1, 33*8(sp)
     csrw sstatus, t0
     csrw sepc, t1
-    # restore general purpose registers except x0/sp/tp
+    # restore general purpose registers except x0/sp
     ld x1, 1*8(sp)
-    ld x3, 3*8(sp)
-    .set n, 5
-    .rept 27
+    .set n, 3
+    .rept 29
         LOAD_GP %n
         .set n, n+1
     .endr
     # back to user stack
     ld sp, 2*8(sp)
     sret
+
+.macro SAVE_FP reg
+    fsd f\reg, \reg*8(a0)
+.endm
+.macro LOAD_FP reg
+    fld f\reg, \reg*8(a0)
+.endm
+.globl __save_fp
+__save_fp:
+    # a0: f0~f31和fcsr的保存位置
+    .set n, 0
+    .rept 32
+        SAVE_FP %n
+        .set n, n+1
+    .endr
+    frcsr t0
+    sd t0, 32*8(a0)
+    ret
+
+.globl __restore_fp
+__restore_fp:
+    .set n, 0
+    .rept 32
+        LOAD_FP %n
+        .set n, n+1
+    .endr
+    ld t0, 32*8(a0)
+    fscsr t0
+    ret
 trampoline:
+
+# 信号处理函数的返回地址，调用sigreturn恢复被中断的上下文
+#     单独占用一页，映射到每个进程的SIGRETURN_TRAMPOLINE(见config.rs)
+    .section .text.trampoline
+    .align 12
+    .globl __sigreturn_trampoline
+__sigreturn_trampoline:
+    li a7, 139
+    ecall
+    .align 12
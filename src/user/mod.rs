@@ -24,7 +24,21 @@ pub static GET_DIRENTS: &'static [u8] = include_bytes!("bin/get_dirents");
 pub static SYS_CLONE: &'static [u8] = include_bytes!("bin/sys_clone");
 pub static EXECVE: &'static [u8] = include_bytes!("bin/execve");
 pub static SHELL: &'static [u8] = include_bytes!("bin/shell");
+pub static INIT: &'static [u8] = include_bytes!("bin/init");
+pub static SIGACTION: &'static [u8] = include_bytes!("bin/sigaction");
+pub static SIGFRAME: &'static [u8] = include_bytes!("bin/sigframe");
+pub static JOBCTL: &'static [u8] = include_bytes!("bin/jobctl");
+pub static FAULT: &'static [u8] = include_bytes!("bin/fault");
+pub static RTSIG: &'static [u8] = include_bytes!("bin/rtsig");
+pub static SIGSTRESS: &'static [u8] = include_bytes!("bin/sigstress");
+pub static AFFINITY: &'static [u8] = include_bytes!("bin/affinity");
 pub static FILELINK: &'static [u8] = include_bytes!("bin/filelink");
+pub static MOUNT: &'static [u8] = include_bytes!("bin/mount");
+pub static THREAD: &'static [u8] = include_bytes!("bin/thread");
+pub static FLOAT: &'static [u8] = include_bytes!("bin/float");
+pub static FUTEX: &'static [u8] = include_bytes!("bin/futex");
+pub static PIPE_BLOCK: &'static [u8] = include_bytes!("bin/pipe_block");
+pub static WAIT_OPTS: &'static [u8] = include_bytes!("bin/wait_opts");
 
 #[cfg(feature = "gitee_test")]
 pub static GITEE_TEST_ECHO: &'static [u8] = include_bytes!("bin/test_echo");
@@ -93,77 +107,11 @@ pub static GITEE_WRITE: &'static [u8] = include_bytes!("bin/gitee_write");
 #[cfg(feature = "gitee_test")]
 pub static GITEE_YIELD: &'static [u8] = include_bytes!("bin/gitee_yield");
 
-#[cfg(feature = "batch")]
-lazy_static! {
-    pub static ref BATCH: Box<[&'static [u8]]> = Box::new([
-        HELLO_WORLD,
-        #[cfg(feature = "gitee_test")]
-        GITEE_BRK,
-        #[cfg(feature = "gitee_test")]
-        GITEE_CHDIR,
-        #[cfg(feature = "gitee_test")]
-        GITEE_CLONE,
-        #[cfg(feature = "gitee_test")]
-        GITEE_CLONE,
-        #[cfg(feature = "gitee_test")]
-        GITEE_DUP,
-        #[cfg(feature = "gitee_test")]
-        GITEE_DUP2,
-        #[cfg(feature = "gitee_test")]
-        GITEE_EXECVE,
-        #[cfg(feature = "gitee_test")]
-        GITEE_EXIT,
-        #[cfg(feature = "gitee_test")]
-        GITEE_FORK,
-        // #[cfg(feature = "gitee_test")]
-        // GITEE_FSTAT
-        #[cfg(feature = "gitee_test")]
-        GITEE_GETCWD,
-        #[cfg(feature = "gitee_test")]
-        GITEE_GETDENTS,
-        #[cfg(feature = "gitee_test")]
-        GITEE_GETPID,
-        #[cfg(feature = "gitee_test")]
-        GITEE_GETPPID,
-        #[cfg(feature = "gitee_test")]
-        GITEE_GETTIMEOFDAY,
-        #[cfg(feature = "gitee_test")]
-        GITEE_MKDIR_,
-        #[cfg(feature = "gitee_test")]
-        GITEE_MMAP,
-        // #[cfg(feature = "gitee_test")]
-        // GITEE_MOUNT,
-        #[cfg(feature = "gitee_test")]
-        GITEE_MUNMAP,
-        // #[cfg(feature = "gitee_test")]
-        // GITEE_OPEN,
-        // #[cfg(feature = "gitee_test")]
-        // GITEE_OPENAT,
-        #[cfg(feature = "gitee_test")]
-        GITEE_PIPE,
-        #[cfg(feature = "gitee_test")]
-        GITEE_READ,
-        #[cfg(feature = "gitee_test")]
-        GITEE_SLEEP,
-        #[cfg(feature = "gitee_test")]
-        GITEE_TIMES,
-        // #[cfg(feature = "gitee_test")]
-        // GITEE_UMOUNT,
-        #[cfg(feature = "gitee_test")]
-        GITEE_UNAME,
-        #[cfg(feature = "gitee_test")]
-        GITEE_UNLINK,
-        #[cfg(feature = "gitee_test")]
-        GITEE_WAIT,
-        #[cfg(feature = "gitee_test")]
-        GITEE_WAITPID,
-        #[cfg(feature = "gitee_test")]
-        GITEE_WRITE,
-        #[cfg(feature = "gitee_test")]
-        GITEE_YIELD
-
-    ]);
-}
+// init逐行执行的启动脚本，开启gitee_test时依次运行测试程序
+#[cfg(not(feature = "gitee_test"))]
+pub static RC: &'static [u8] = include_bytes!("rc");
+#[cfg(feature = "gitee_test")]
+pub static RC: &'static [u8] = include_bytes!("rc_gitee");
 
 lazy_static! {
     pub static ref APP: BTreeMap<&'static str, Box<&'static [u8]>> = {
@@ -188,6 +136,22 @@ lazy_static! {
         map.insert("sys_clone", Box::new(SYS_CLONE));
         map.insert("execve", Box::new(EXECVE));
         map.insert("filelink", Box::new(FILELINK));
+        map.insert("mount", Box::new(MOUNT));
+        map.insert("thread", Box::new(THREAD));
+        map.insert("float", Box::new(FLOAT));
+        map.insert("futex", Box::new(FUTEX));
+        map.insert("pipe_block", Box::new(PIPE_BLOCK));
+        map.insert("wait_opts", Box::new(WAIT_OPTS));
+        map.insert("init", Box::new(INIT));
+        map.insert("shell", Box::new(SHELL));
+        map.insert("rc", Box::new(RC));
+        map.insert("sigaction", Box::new(SIGACTION));
+        map.insert("sigframe", Box::new(SIGFRAME));
+        map.insert("jobctl", Box::new(JOBCTL));
+        map.insert("fault", Box::new(FAULT));
+        map.insert("rtsig", Box::new(RTSIG));
+        map.insert("sigstress", Box::new(SIGSTRESS));
+        map.insert("affinity", Box::new(AFFINITY));
 
         #[cfg(feature = "gitee_test")]
         map.insert("gitee_brk", Box::new(GITEE_BRK));
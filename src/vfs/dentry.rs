@@ -1,34 +1,69 @@
+/**
+ * 目录项
+ * Inode使用固定长度的LinuxDirent返回目录项，File写入用户缓冲区时转换为linux_dirent64格式的变长记录:
+ *     d_ino(8) d_off(8) d_reclen(2) d_type(1) d_name(以0结尾)，整个记录按8字节对齐
+ */
+use super::FileErr;
 use crate::config::PATH_LIMITS;
 
-#[repr(C)]
 pub struct LinuxDirent {
     pub d_ino: usize,
+    // 下一个目录项的偏移，lseek到d_off后可以继续读取
     pub d_off: isize,
-    pub d_reclen: u16,
-    pub d_type: u8,                // linux manual中d_type应该在d_name后面?
-    pub d_name: [u8; PATH_LIMITS], // 使用固定的name长度
+    pub d_type: u8,
+    name_len: usize,
+    d_name: [u8; PATH_LIMITS],
 }
 
 pub const DT_UNKNOWN: u8 = 0;
 pub const DT_DIR: u8 = 4;
-pub const DT_REG: u8 = 4; //常规文件
+pub const DT_REG: u8 = 8; //常规文件
+
+// d_name在记录中的偏移
+const DIRENT_NAME_OFFSET: usize = 19;
+const DIRENT_ALIGN: usize = 8;
 
 impl LinuxDirent {
     pub fn new() -> Self {
         Self {
             d_ino: 0,
             d_off: 0,
-            d_reclen: 0,
-            d_type: 0,
+            d_type: DT_UNKNOWN,
+            name_len: 0,
             d_name: [0; PATH_LIMITS],
         }
     }
 
-    pub fn fill(&mut self, other: &Self) {
-        self.d_ino = other.d_ino;
-        self.d_off = other.d_off;
-        self.d_reclen = other.d_reclen;
-        self.d_type = other.d_type;
-        self.d_name.copy_from_slice(&other.d_name);
+    pub fn set_name(&mut self, name: &str) -> Result<(), FileErr> {
+        // 需要留出结尾的0
+        if name.len() >= PATH_LIMITS {
+            return Err(FileErr::NameTooLong);
+        }
+        self.d_name[..name.len()].copy_from_slice(name.as_bytes());
+        self.name_len = name.len();
+        Ok(())
+    }
+
+    pub fn name(&self) -> &[u8] {
+        &self.d_name[..self.name_len]
+    }
+
+    // 记录的长度，包括名字结尾的0和对齐的填充
+    pub fn reclen(&self) -> usize {
+        (DIRENT_NAME_OFFSET + self.name_len + 1 + DIRENT_ALIGN - 1) & !(DIRENT_ALIGN - 1)
+    }
+
+    // 将记录写入buf的开头，返回写入的长度，buf的长度不能小于reclen
+    pub fn write_to(&self, buf: &mut [u8]) -> usize {
+        let reclen = self.reclen();
+        let record = &mut buf[..reclen];
+        record[0..8].copy_from_slice(&(self.d_ino as u64).to_ne_bytes());
+        record[8..16].copy_from_slice(&(self.d_off as i64).to_ne_bytes());
+        record[16..18].copy_from_slice(&(reclen as u16).to_ne_bytes());
+        record[18] = self.d_type;
+        let name_end = DIRENT_NAME_OFFSET + self.name_len;
+        record[DIRENT_NAME_OFFSET..name_end].copy_from_slice(self.name());
+        record[name_end..].fill(0);
+        reclen
     }
 }
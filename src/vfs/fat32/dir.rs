@@ -0,0 +1,410 @@
+/**
+ * FAT32目录项
+ * 每个目录项32字节，长文件名(LFN)保存在短目录项前面的若干个LFN目录项中
+ */
+use super::*;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub(super) const DIRENT_SIZE: usize = 32;
+
+pub(super) const ATTR_READ_ONLY: u8 = 0x01;
+pub(super) const ATTR_HIDDEN: u8 = 0x02;
+pub(super) const ATTR_SYSTEM: u8 = 0x04;
+pub(super) const ATTR_VOLUME_ID: u8 = 0x08;
+pub(super) const ATTR_DIRECTORY: u8 = 0x10;
+pub(super) const ATTR_ARCHIVE: u8 = 0x20;
+pub(super) const ATTR_LFN: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+// 目录项第一个字节的特殊值
+const ENTRY_END: u8 = 0x00;
+const ENTRY_DELETED: u8 = 0xE5;
+// 短文件名第一个字符为0xE5时保存为0x05
+const ENTRY_KANJI: u8 = 0x05;
+
+// 短文件名中基本名和扩展名为小写(Windows NT)
+const NT_LOWER_BASE: u8 = 0x08;
+const NT_LOWER_EXT: u8 = 0x10;
+
+// LFN最后一个目录项的序号标志
+const LFN_LAST: u8 = 0x40;
+// 每个LFN目录项保存的UCS-2字符数
+const LFN_CHARS: usize = 13;
+const LFN_MAX_LEN: usize = 255;
+// LFN目录项中字符的偏移
+const LFN_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+// 没有时钟，时间戳统一为1980-01-01
+const FAT_DATE: u16 = (1 << 5) | 1;
+
+// 目录中的一个文件
+pub(super) struct DirEntry {
+    pub name: String,
+    // 短目录项
+    pub raw: [u8; DIRENT_SIZE],
+    // 短目录项在目录中的序号
+    pub slot: usize,
+    // 第一个LFN目录项的序号，没有LFN时等于slot
+    pub first_slot: usize,
+}
+
+impl DirEntry {
+    pub fn attr(&self) -> u8 {
+        self.raw[11]
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.attr() & ATTR_DIRECTORY != 0
+    }
+
+    pub fn first_cluster(&self) -> u32 {
+        ((le16(&self.raw, 20) as u32) << 16) | le16(&self.raw, 26) as u32
+    }
+
+    pub fn size(&self) -> usize {
+        le32(&self.raw, 28) as usize
+    }
+}
+
+// 短文件名的校验和，保存在LFN目录项中
+fn short_name_checksum(name: &[u8]) -> u8 {
+    name[..11]
+        .iter()
+        .fold(0u8, |sum, &ch| (sum >> 1).wrapping_add(sum << 7).wrapping_add(ch))
+}
+
+fn short_name_string(raw: &[u8; DIRENT_SIZE]) -> String {
+    let lower = |ch: u8, flag: u8| {
+        if raw[12] & flag != 0 {
+            ch.to_ascii_lowercase()
+        } else {
+            ch
+        }
+    };
+    let mut name = String::new();
+    for (i, &ch) in raw[0..8].iter().enumerate() {
+        if ch == b' ' {
+            break;
+        }
+        let ch = if i == 0 && ch == ENTRY_KANJI { ENTRY_DELETED } else { ch };
+        name.push(lower(ch, NT_LOWER_BASE) as char);
+    }
+    if raw[8] != b' ' {
+        name.push('.');
+        for &ch in raw[8..11].iter().take_while(|&&ch| ch != b' ') {
+            name.push(lower(ch, NT_LOWER_EXT) as char);
+        }
+    }
+    name
+}
+
+fn is_short_char(ch: u8) -> bool {
+    ch.is_ascii_uppercase() || ch.is_ascii_digit() || b"$%'-_@~`!(){}^#&".contains(&ch)
+}
+
+// 名字本身就是合法的大写8.3短文件名时，不需要LFN
+fn as_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, ext) = match name.find('.') {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, ""),
+    };
+    if base.len() == 0 || base.len() > 8 || ext.len() > 3 {
+        return None;
+    }
+    if !base.bytes().chain(ext.bytes()).all(is_short_char) {
+        return None;
+    }
+    let mut short = [b' '; 11];
+    short[..base.len()].copy_from_slice(base.as_bytes());
+    short[8..8 + ext.len()].copy_from_slice(ext.as_bytes());
+    Some(short)
+}
+
+// 从长文件名生成短文件名的基本名和扩展名，不包括"~N"
+fn short_name_parts(name: &str) -> (Vec<u8>, Vec<u8>) {
+    let convert = |s: &str, limit: usize| -> Vec<u8> {
+        s.bytes()
+            .filter(|&ch| ch != b' ' && ch != b'.')
+            .map(|ch| {
+                let ch = ch.to_ascii_uppercase();
+                if is_short_char(ch) {
+                    ch
+                } else {
+                    b'_'
+                }
+            })
+            .take(limit)
+            .collect()
+    };
+    match name.rfind('.') {
+        Some(i) if i > 0 => (convert(&name[..i], 8), convert(&name[i + 1..], 3)),
+        _ => (convert(name, 8), Vec::new()),
+    }
+}
+
+// 检查文件名能否保存为长文件名
+pub(super) fn check_name(name: &str) -> Result<(), FileErr> {
+    if name.encode_utf16().count() > LFN_MAX_LEN {
+        return Err(FileErr::NameTooLong);
+    }
+    if name.len() == 0
+        || name == "."
+        || name == ".."
+        || name
+            .chars()
+            .any(|ch| (ch as u32) < 0x20 || "\"*/:<>?\\|".contains(ch))
+    {
+        return Err(FileErr::InvalidArgs);
+    }
+    Ok(())
+}
+
+// 构造短目录项
+pub(super) fn make_short_entry(short: &[u8; 11], attr: u8, cluster: u32) -> [u8; DIRENT_SIZE] {
+    let mut raw = [0u8; DIRENT_SIZE];
+    raw[0..11].copy_from_slice(short);
+    raw[11] = attr;
+    raw[16..18].copy_from_slice(&FAT_DATE.to_le_bytes());
+    raw[18..20].copy_from_slice(&FAT_DATE.to_le_bytes());
+    raw[24..26].copy_from_slice(&FAT_DATE.to_le_bytes());
+    set_entry_cluster(&mut raw, cluster);
+    raw
+}
+
+pub(super) fn set_entry_cluster(raw: &mut [u8; DIRENT_SIZE], cluster: u32) {
+    raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+}
+
+pub(super) fn set_entry_size(raw: &mut [u8; DIRENT_SIZE], size: u32) {
+    raw[28..32].copy_from_slice(&size.to_le_bytes());
+}
+
+impl Fat32 {
+    // 读写目录中的第slot个目录项，目录簇链不够长时返回None
+    pub(super) fn read_slot(&self, dir: u32, slot: usize) -> Option<[u8; DIRENT_SIZE]> {
+        let offset = slot * DIRENT_SIZE;
+        let cluster = self.chain_cluster(dir, offset / self.cluster_size())?;
+        let mut raw = [0u8; DIRENT_SIZE];
+        self.read_cluster(cluster, offset % self.cluster_size(), &mut raw);
+        Some(raw)
+    }
+
+    // 修改目录项，持有entry_lock保证修改不会丢失
+    pub(super) fn modify_slot(&self, dir: u32, slot: usize, f: impl FnOnce(&mut [u8; DIRENT_SIZE])) {
+        let offset = slot * DIRENT_SIZE;
+        let cluster = self
+            .chain_cluster(dir, offset / self.cluster_size())
+            .expect("fat32: modify slot out of directory");
+        let offset = offset % self.cluster_size();
+        let _lock = self.entry_lock.lock();
+        let mut raw = [0u8; DIRENT_SIZE];
+        self.read_cluster(cluster, offset, &mut raw);
+        f(&mut raw);
+        self.write_cluster(cluster, offset, &raw);
+    }
+
+    // 从第slot个目录项开始查找下一个文件，返回文件和之后的目录项序号
+    // 读到目录结尾时返回None
+    pub(super) fn dir_next(&self, dir: u32, mut slot: usize) -> Option<(DirEntry, usize)> {
+        // 正在解析的LFN: (UCS-2字符，校验和，下一个期望的序号，第一个LFN目录项)
+        let mut lfn: Option<(Vec<u16>, u8, u8, usize)> = None;
+        while let Some(raw) = self.read_slot(dir, slot) {
+            slot += 1;
+            if raw[0] == ENTRY_END {
+                return None;
+            }
+            if raw[0] == ENTRY_DELETED {
+                lfn = None;
+                continue;
+            }
+            if raw[11] & ATTR_LFN == ATTR_LFN {
+                let ord = raw[0] & !LFN_LAST;
+                if raw[0] & LFN_LAST != 0 && ord > 0 && ord as usize * LFN_CHARS <= LFN_MAX_LEN + LFN_CHARS {
+                    lfn = Some((vec![0xFFFF; ord as usize * LFN_CHARS], raw[13], ord, slot - 1));
+                }
+                if let Some((chars, checksum, next, _)) = lfn.as_mut() {
+                    if ord == 0 || ord != *next || raw[13] != *checksum {
+                        // 损坏的LFN, 忽略
+                        lfn = None;
+                        continue;
+                    }
+                    let base = (ord as usize - 1) * LFN_CHARS;
+                    for (i, &off) in LFN_OFFSETS.iter().enumerate() {
+                        chars[base + i] = le16(&raw, off);
+                    }
+                    *next -= 1;
+                }
+                continue;
+            }
+            if raw[11] & ATTR_VOLUME_ID != 0 {
+                lfn = None;
+                continue;
+            }
+            // 短目录项
+            let (name, first_slot) = match lfn.take() {
+                Some((chars, checksum, 0, first_slot)) if checksum == short_name_checksum(&raw) => {
+                    let len = chars
+                        .iter()
+                        .position(|&ch| ch == 0 || ch == 0xFFFF)
+                        .unwrap_or(chars.len());
+                    let name = core::char::decode_utf16(chars[..len].iter().cloned())
+                        .map(|ch| ch.unwrap_or(core::char::REPLACEMENT_CHARACTER))
+                        .collect();
+                    (name, first_slot)
+                }
+                _ => (short_name_string(&raw), slot - 1),
+            };
+            return Some((
+                DirEntry {
+                    name,
+                    raw,
+                    slot: slot - 1,
+                    first_slot,
+                },
+                slot,
+            ));
+        }
+        None
+    }
+
+    // 按名字查找文件，不区分大小写
+    pub(super) fn dir_find(&self, dir: u32, name: &str) -> Option<DirEntry> {
+        let mut slot = 0;
+        while let Some((entry, next)) = self.dir_next(dir, slot) {
+            if entry.name.eq_ignore_ascii_case(name) {
+                return Some(entry);
+            }
+            slot = next;
+        }
+        None
+    }
+
+    // 目录中除了"."和".."没有其他文件
+    pub(super) fn dir_is_empty(&self, dir: u32) -> bool {
+        let mut slot = 0;
+        while let Some((entry, next)) = self.dir_next(dir, slot) {
+            if entry.name != "." && entry.name != ".." {
+                return false;
+            }
+            slot = next;
+        }
+        true
+    }
+
+    fn short_name_exists(&self, dir: u32, short: &[u8; 11]) -> bool {
+        let mut slot = 0;
+        while let Some((entry, next)) = self.dir_next(dir, slot) {
+            if &entry.raw[0..11] == short {
+                return true;
+            }
+            slot = next;
+        }
+        false
+    }
+
+    // 为长文件名生成目录中不重复的短文件名"BASE~N.EXT"
+    fn gen_short_name(&self, dir: u32, name: &str) -> Result<[u8; 11], FileErr> {
+        let (base, ext) = short_name_parts(name);
+        for n in 1..1000000usize {
+            let mut tail = String::from("~");
+            tail.push_str(n.to_string().as_str());
+            let keep = min(base.len(), 8 - tail.len());
+            let mut short = [b' '; 11];
+            short[..keep].copy_from_slice(&base[..keep]);
+            short[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+            short[8..8 + ext.len()].copy_from_slice(&ext);
+            if !self.short_name_exists(dir, &short) {
+                return Ok(short);
+            }
+        }
+        Err(FileErr::InodeChildExist)
+    }
+
+    // 找到连续n个空闲的目录项，不够时扩展目录的簇链，返回第一个目录项的序号
+    fn dir_alloc_slots(&self, dir: u32, n: usize) -> Result<usize, FileErr> {
+        let mut slot = 0;
+        let mut start = 0;
+        loop {
+            match self.read_slot(dir, slot) {
+                Some(raw) if raw[0] == ENTRY_END || raw[0] == ENTRY_DELETED => {
+                    if slot + 1 - start == n {
+                        return Ok(start);
+                    }
+                }
+                Some(_) => start = slot + 1,
+                None => {
+                    // 目录的最后一个簇已经用完
+                    let cluster = self.alloc_cluster()?;
+                    let slots_per_cluster = self.cluster_size() / DIRENT_SIZE;
+                    let last = self
+                        .chain_cluster(dir, slot / slots_per_cluster - 1)
+                        .unwrap();
+                    self.set_next(last, cluster);
+                    continue;
+                }
+            }
+            slot += 1;
+        }
+    }
+
+    // 在目录中添加文件，需要时写入LFN目录项
+    pub(super) fn dir_add(
+        &self,
+        dir: u32,
+        name: &str,
+        attr: u8,
+        cluster: u32,
+    ) -> Result<DirEntry, FileErr> {
+        let (short, lfn) = match as_short_name(name) {
+            Some(short) => (short, None),
+            None => {
+                let chars: Vec<u16> = name.encode_utf16().collect();
+                (self.gen_short_name(dir, name)?, Some(chars))
+            }
+        };
+        let nlfn = lfn
+            .as_ref()
+            .map_or(0, |chars| (chars.len() + LFN_CHARS - 1) / LFN_CHARS);
+        let first_slot = self.dir_alloc_slots(dir, nlfn + 1)?;
+        if let Some(chars) = lfn {
+            let checksum = short_name_checksum(&short);
+            // LFN目录项倒序保存，序号最大的在最前面
+            for i in 0..nlfn {
+                let ord = (nlfn - i) as u8;
+                let mut raw = [0u8; DIRENT_SIZE];
+                raw[0] = if i == 0 { ord | LFN_LAST } else { ord };
+                raw[11] = ATTR_LFN;
+                raw[13] = checksum;
+                let base = (ord as usize - 1) * LFN_CHARS;
+                for (j, &off) in LFN_OFFSETS.iter().enumerate() {
+                    // 名字以0结尾，之后填充0xFFFF
+                    let ch = match chars.get(base + j) {
+                        Some(&ch) => ch,
+                        None if base + j == chars.len() => 0,
+                        None => 0xFFFF,
+                    };
+                    raw[off..off + 2].copy_from_slice(&ch.to_le_bytes());
+                }
+                self.modify_slot(dir, first_slot + i, |slot| *slot = raw);
+            }
+        }
+        let raw = make_short_entry(&short, attr, cluster);
+        self.modify_slot(dir, first_slot + nlfn, |slot| *slot = raw);
+        log!("fat32":"dir_add">"{} ({:?}) slot {}", name, core::str::from_utf8(&short), first_slot + nlfn);
+        Ok(DirEntry {
+            name: String::from(name),
+            raw,
+            slot: first_slot + nlfn,
+            first_slot,
+        })
+    }
+
+    // 删除文件的所有目录项，不释放文件的簇
+    pub(super) fn dir_remove(&self, dir: u32, entry: &DirEntry) {
+        for slot in entry.first_slot..=entry.slot {
+            self.modify_slot(dir, slot, |raw| raw[0] = ENTRY_DELETED);
+        }
+    }
+}
@@ -0,0 +1,403 @@
+/**
+ * FAT32文件和目录的Inode
+ * 文件的元数据保存在父目录的短目录项中，修改文件长度或第一个簇时写回目录项
+ */
+use super::dir::*;
+use super::*;
+
+pub(super) struct Fat32Inode {
+    fs: Arc<Fat32>,
+    // 短目录项的位置: (父目录的第一个簇，目录项序号)，根目录没有目录项
+    entry: Option<(u32, usize)>,
+    is_dir: bool,
+    inner: Mutex<Fat32InodeInner>,
+}
+
+struct Fat32InodeInner {
+    // 空文件没有分配簇，为0
+    first_cluster: u32,
+    // 目录的长度总是0
+    size: usize,
+    // 上一次访问的簇: (簇链中的序号，簇号)，顺序读写时不用从头遍历簇链
+    hint: Option<(usize, u32)>,
+    // 目录项已经删除，Inode释放时回收簇
+    deleted: bool,
+}
+
+impl Fat32Inode {
+    pub fn root(fs: Arc<Fat32>) -> Self {
+        let root_cluster = fs.root_cluster;
+        Self {
+            fs,
+            entry: None,
+            is_dir: true,
+            inner: Mutex::new(Fat32InodeInner {
+                first_cluster: root_cluster,
+                size: 0,
+                hint: None,
+                deleted: false,
+            }),
+        }
+    }
+
+    // 获取目录项对应的Inode，文件已经打开时返回同一个Inode
+    pub fn from_entry(fs: &Arc<Fat32>, dir: u32, entry: &DirEntry) -> Arc<Self> {
+        let key = (dir, entry.slot);
+        let mut inodes = fs.inodes.lock();
+        if let Some(inode) = inodes.get(&key).and_then(|inode| inode.upgrade()) {
+            return inode;
+        }
+        let inode = Arc::new(Self {
+            fs: fs.clone(),
+            entry: Some(key),
+            is_dir: entry.is_dir(),
+            inner: Mutex::new(Fat32InodeInner {
+                first_cluster: entry.first_cluster(),
+                size: if entry.is_dir() { 0 } else { entry.size() },
+                hint: None,
+                deleted: false,
+            }),
+        });
+        inodes.insert(key, Arc::downgrade(&inode));
+        inode
+    }
+
+    // 文件的第index个簇，alloc为true时簇链不够长则分配新的簇
+    fn cluster_at(
+        &self,
+        inner: &mut Fat32InodeInner,
+        index: usize,
+        alloc: bool,
+    ) -> Result<Option<u32>, FileErr> {
+        if inner.first_cluster == 0 {
+            if !alloc {
+                return Ok(None);
+            }
+            inner.first_cluster = self.fs.alloc_cluster()?;
+            inner.hint = None;
+        }
+        let (mut i, mut cluster) = match inner.hint {
+            Some((i, cluster)) if i <= index => (i, cluster),
+            _ => (0, inner.first_cluster),
+        };
+        while i < index {
+            cluster = match self.fs.next_cluster(cluster) {
+                Some(next) => next,
+                None if alloc => {
+                    let next = self.fs.alloc_cluster()?;
+                    self.fs.set_next(cluster, next);
+                    next
+                }
+                None => return Ok(None),
+            };
+            i += 1;
+        }
+        inner.hint = Some((index, cluster));
+        Ok(Some(cluster))
+    }
+
+    fn read_data(
+        &self,
+        inner: &mut Fat32InodeInner,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result<usize, FileErr> {
+        let end = min(offset.saturating_add(buf.len()), inner.size);
+        let csize = self.fs.cluster_size();
+        let mut pos = offset;
+        while pos < end {
+            let cluster = match self.cluster_at(inner, pos / csize, false)? {
+                Some(cluster) => cluster,
+                None => {
+                    log!("fat32":"read">"cluster chain shorter than size {}", inner.size);
+                    break;
+                }
+            };
+            let start = pos % csize;
+            let len = min(csize - start, end - pos);
+            self.fs
+                .read_cluster(cluster, start, &mut buf[pos - offset..pos - offset + len]);
+            pos += len;
+        }
+        Ok(pos - offset)
+    }
+
+    // 写入数据并更新文件长度，空间不足时返回已经写入的字节数
+    fn write_data(
+        &self,
+        inner: &mut Fat32InodeInner,
+        offset: usize,
+        buf: &[u8],
+    ) -> Result<usize, FileErr> {
+        // FAT32的文件长度不能超过4G-1
+        let limit = u32::MAX as usize;
+        if offset >= limit && buf.len() > 0 {
+            return Err(FileErr::NoSpace);
+        }
+        let end = min(offset + buf.len(), limit);
+        let csize = self.fs.cluster_size();
+        let mut pos = offset;
+        while pos < end {
+            let cluster = match self.cluster_at(inner, pos / csize, true) {
+                Ok(cluster) => cluster.unwrap(),
+                Err(e) if pos == offset => return Err(e),
+                Err(_) => break,
+            };
+            let start = pos % csize;
+            let len = min(csize - start, end - pos);
+            self.fs
+                .write_cluster(cluster, start, &buf[pos - offset..pos - offset + len]);
+            pos += len;
+        }
+        if pos > inner.size {
+            inner.size = pos;
+        }
+        Ok(pos - offset)
+    }
+
+    // 将[from, to)清零，用于文件变长时填充空洞
+    fn fill_zero(&self, inner: &mut Fat32InodeInner, from: usize, to: usize) -> Result<(), FileErr> {
+        let zero = [0u8; BLOCK_SIZE];
+        let mut pos = from;
+        while pos < to {
+            let len = min(BLOCK_SIZE, to - pos);
+            pos += self.write_data(inner, pos, &zero[..len])?;
+        }
+        Ok(())
+    }
+
+    // 将第一个簇和文件长度写回目录项
+    fn update_entry(&self, inner: &Fat32InodeInner) {
+        if inner.deleted {
+            return;
+        }
+        if let Some((dir, slot)) = self.entry {
+            let is_dir = self.is_dir;
+            self.fs.modify_slot(dir, slot, |raw| {
+                set_entry_cluster(raw, inner.first_cluster);
+                if !is_dir {
+                    set_entry_size(raw, inner.size as u32);
+                }
+            });
+        }
+    }
+}
+
+impl Drop for Fat32Inode {
+    fn drop(&mut self) {
+        if let Some(key) = self.entry {
+            let mut inodes = self.fs.inodes.lock();
+            // 目录项可能已经被删除并分配给了新的文件
+            if let Some(inode) = inodes.get(&key) {
+                if core::ptr::eq(inode.as_ptr(), self) {
+                    inodes.remove(&key);
+                }
+            }
+        }
+        let inner = self.inner.get_mut();
+        if inner.deleted && inner.first_cluster != 0 {
+            self.fs.free_chain(inner.first_cluster);
+        }
+    }
+}
+
+impl _Inode for Fat32Inode {
+    fn get_child(&self, name: &str) -> Result<Inode, FileErr> {
+        if !self.is_dir {
+            return Err(FileErr::InodeNotDir);
+        }
+        // "."和".."由路径解析处理
+        if name == "." || name == ".." {
+            return Err(FileErr::InodeNotChild);
+        }
+        let inner = self.inner.lock();
+        match self.fs.dir_find(inner.first_cluster, name) {
+            Some(entry) => {
+                log!("fat32":"get_child">"got child name ({})", name);
+                Ok(Fat32Inode::from_entry(&self.fs, inner.first_cluster, &entry))
+            }
+            None => Err(FileErr::InodeNotChild),
+        }
+    }
+
+    fn get_dirent(&self, offset: usize, dirent: &mut LinuxDirent) -> Result<usize, FileErr> {
+        if !self.is_dir {
+            return Err(FileErr::InodeNotDir);
+        }
+        let inner = self.inner.lock();
+        // offset为目录项的序号
+        let (entry, next) = match self.fs.dir_next(inner.first_cluster, offset) {
+            Some(entry) => entry,
+            None => return Err(FileErr::InodeEndOfDir),
+        };
+        dirent.set_name(&entry.name)?;
+        dirent.d_ino = ((inner.first_cluster as usize) << 32) | entry.slot;
+        dirent.d_off = next as isize;
+        dirent.d_type = if entry.is_dir() { DT_DIR } else { DT_REG };
+        Ok(next - offset)
+    }
+
+    fn unlink_child(&self, name: &str, rm_dir: bool) -> Result<usize, FileErr> {
+        if !self.is_dir {
+            return Err(FileErr::InodeNotDir);
+        }
+        if name == "." || name == ".." {
+            return Err(FileErr::InvalidArgs);
+        }
+        let inner = self.inner.lock();
+        let dir = inner.first_cluster;
+        let entry = self
+            .fs
+            .dir_find(dir, name)
+            .ok_or(FileErr::InodeNotChild)?;
+        if entry.is_dir() {
+            if !rm_dir {
+                return Err(FileErr::InodeIsDir);
+            }
+            if !self.fs.dir_is_empty(entry.first_cluster()) {
+                return Err(FileErr::DirNotEmpty);
+            }
+        } else if rm_dir {
+            return Err(FileErr::InodeNotDir);
+        }
+        self.fs.dir_remove(dir, &entry);
+
+        // 文件仍然被打开时，等Inode释放后再回收簇
+        let key = (dir, entry.slot);
+        let child = {
+            let mut inodes = self.fs.inodes.lock();
+            let child = inodes.get(&key).and_then(|inode| inode.upgrade());
+            inodes.remove(&key);
+            child
+        };
+        match child {
+            Some(child) => child.inner.lock().deleted = true,
+            None if entry.first_cluster() != 0 => self.fs.free_chain(entry.first_cluster()),
+            None => {}
+        }
+        log!("fat32":"unlink">"{}", name);
+        // FAT32不支持硬链接
+        Ok(0)
+    }
+
+    fn create(&self, name: &str, _: FileMode, itype: InodeType) -> Result<Inode, FileErr> {
+        if !self.is_dir {
+            return Err(FileErr::InodeNotDir);
+        }
+        check_name(name)?;
+        let inner = self.inner.lock();
+        let dir = inner.first_cluster;
+        if self.fs.dir_find(dir, name).is_some() {
+            return Err(FileErr::InodeChildExist);
+        }
+        let entry = match itype {
+            InodeType::File => self.fs.dir_add(dir, name, ATTR_ARCHIVE, 0)?,
+            InodeType::Directory => {
+                let cluster = self.fs.alloc_cluster()?;
+                // 父目录为根目录时".."的簇号为0
+                let parent = if dir == self.fs.root_cluster { 0 } else { dir };
+                let dot = make_short_entry(b".          ", ATTR_DIRECTORY, cluster);
+                let dotdot = make_short_entry(b"..         ", ATTR_DIRECTORY, parent);
+                self.fs.modify_slot(cluster, 0, |raw| *raw = dot);
+                self.fs.modify_slot(cluster, 1, |raw| *raw = dotdot);
+                match self.fs.dir_add(dir, name, ATTR_DIRECTORY, cluster) {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        self.fs.free_chain(cluster);
+                        return Err(e);
+                    }
+                }
+            }
+            _ => {
+                log!("fat32":"create">"unsupported inode type ({})", name);
+                return Err(FileErr::InvalidArgs);
+            }
+        };
+        Ok(Fat32Inode::from_entry(&self.fs, dir, &entry))
+    }
+
+    fn read_offset(&self, offset: usize, buf: &mut [u8]) -> Result<usize, FileErr> {
+        if self.is_dir {
+            return Err(FileErr::InodeIsDir);
+        }
+        let mut inner = self.inner.lock();
+        self.read_data(&mut inner, offset, buf)
+    }
+
+    fn write_offset(&self, offset: usize, buf: &[u8]) -> Result<usize, FileErr> {
+        if self.is_dir {
+            return Err(FileErr::InodeIsDir);
+        }
+        let mut inner = self.inner.lock();
+        let size = inner.size;
+        if offset > size {
+            if let Err(e) = self.fill_zero(&mut inner, size, offset) {
+                self.update_entry(&inner);
+                return Err(e);
+            }
+        }
+        let ret = self.write_data(&mut inner, offset, buf);
+        self.update_entry(&inner);
+        ret
+    }
+
+    fn truncate(&self, len: usize) -> Result<(), FileErr> {
+        if self.is_dir {
+            return Err(FileErr::InodeIsDir);
+        }
+        let mut inner = self.inner.lock();
+        let size = inner.size;
+        if len > size {
+            let ret = self.fill_zero(&mut inner, size, len);
+            self.update_entry(&inner);
+            return ret;
+        }
+        if len < size {
+            let keep = (len + self.fs.cluster_size() - 1) / self.fs.cluster_size();
+            if keep == 0 {
+                if inner.first_cluster != 0 {
+                    self.fs.free_chain(inner.first_cluster);
+                    inner.first_cluster = 0;
+                }
+            } else if let Some(last) = self.cluster_at(&mut inner, keep - 1, false)? {
+                if let Some(next) = self.fs.next_cluster(last) {
+                    self.fs.set_next(last, FAT_EOC);
+                    self.fs.free_chain(next);
+                }
+            }
+            inner.hint = None;
+            inner.size = len;
+            self.update_entry(&inner);
+        }
+        Ok(())
+    }
+
+    fn sync(&self) -> Result<(), FileErr> {
+        self.fs.sync();
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.inner.lock().size
+    }
+
+    fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    // 与get_dirent中的d_ino一致，根目录没有目录项，使用1
+    fn ino(&self) -> usize {
+        match self.entry {
+            Some((dir, slot)) => ((dir as usize) << 32) | slot,
+            None => 1,
+        }
+    }
+
+    fn read_ready(&self) -> bool {
+        true
+    }
+
+    fn write_ready(&self) -> bool {
+        true
+    }
+}
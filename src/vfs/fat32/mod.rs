@@ -0,0 +1,320 @@
+/**
+ * FAT32文件系统
+ * 通过BlockDevice读写磁盘，Fat32Inode实现了_Inode接口，可以挂载到vfs中
+ * 只支持扇区大小与BLOCK_SIZE相同的磁盘
+ */
+mod dir;
+mod inode;
+
+use super::*;
+use crate::driver::{BlockDevice, BLOCK_SIZE};
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use core::cmp::min;
+use core::convert::TryInto;
+use core::sync::atomic::{AtomicBool, Ordering};
+use inode::Fat32Inode;
+use spin::Mutex;
+
+// FAT表项只有低28位有效
+const FAT_ENTRY_MASK: u32 = 0x0FFF_FFFF;
+const FAT_FREE: u32 = 0;
+// 簇链结尾
+const FAT_EOC: u32 = 0x0FFF_FFFF;
+
+// FSInfo扇区的签名
+const FSINFO_LEAD_SIG: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIG: u32 = 0x6141_7272;
+const FSINFO_FREE_COUNT: usize = 488;
+const FSINFO_NEXT_FREE: usize = 492;
+
+fn le16(buf: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes(buf[off..off + 2].try_into().unwrap())
+}
+
+fn le32(buf: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(buf[off..off + 4].try_into().unwrap())
+}
+
+pub struct Fat32 {
+    dev: Arc<dyn BlockDevice>,
+    sectors_per_cluster: usize,
+    reserved_sectors: usize,
+    num_fats: usize,
+    sectors_per_fat: usize,
+    root_cluster: u32,
+    // 数据区的第一个扇区
+    data_start: usize,
+    // 最大的有效簇号
+    max_cluster: u32,
+    fsinfo_sector: usize,
+    // FSInfo中的空闲簇数是否已经标记为未知
+    fsinfo_invalid: AtomicBool,
+    // 修改FAT表时持有，记录下一次分配簇时开始查找的位置
+    fat: Mutex<u32>,
+    // 修改目录项时持有，保证扇区的读-改-写不会丢失更新
+    entry_lock: Mutex<()>,
+    // 已经打开的Inode，以目录项的位置为key，保证同一个文件只有一个Inode
+    inodes: Mutex<BTreeMap<(u32, usize), Weak<Fat32Inode>>>,
+}
+
+impl Fat32 {
+    // 读取引导扇区，检查是否为FAT32
+    pub fn open(dev: Arc<dyn BlockDevice>) -> Result<Arc<Self>, FileErr> {
+        let mut sector = [0u8; BLOCK_SIZE];
+        dev.read_block(0, &mut sector);
+        if sector[510] != 0x55 || sector[511] != 0xAA {
+            log!("fat32":"open">"invalid boot sector signature");
+            return Err(FileErr::InvalidArgs);
+        }
+        let bytes_per_sector = le16(&sector, 11) as usize;
+        let sectors_per_cluster = sector[13] as usize;
+        let reserved_sectors = le16(&sector, 14) as usize;
+        let num_fats = sector[16] as usize;
+        let root_entries = le16(&sector, 17);
+        let total_sectors = match le16(&sector, 19) {
+            0 => le32(&sector, 32) as usize,
+            n => n as usize,
+        };
+        let fat_size16 = le16(&sector, 22);
+        let sectors_per_fat = le32(&sector, 36) as usize;
+        let root_cluster = le32(&sector, 44);
+        let fsinfo_sector = le16(&sector, 48) as usize;
+        // FAT12/16的根目录项数和FAT大小不为0
+        if bytes_per_sector != BLOCK_SIZE
+            || sectors_per_cluster == 0
+            || num_fats == 0
+            || root_entries != 0
+            || fat_size16 != 0
+            || sectors_per_fat == 0
+        {
+            log!("fat32":"open">"not a fat32 filesystem");
+            return Err(FileErr::InvalidArgs);
+        }
+        let data_start = reserved_sectors + num_fats * sectors_per_fat;
+        if total_sectors <= data_start || total_sectors > dev.num_blocks() {
+            log!("fat32":"open">"invalid total sectors {}", total_sectors);
+            return Err(FileErr::InvalidArgs);
+        }
+        let clusters = (total_sectors - data_start) / sectors_per_cluster;
+        // FAT表能记录的簇数
+        let clusters = min(clusters, sectors_per_fat * BLOCK_SIZE / 4 - 2);
+        let max_cluster = (clusters + 1) as u32;
+        if root_cluster < 2 || root_cluster > max_cluster {
+            return Err(FileErr::InvalidArgs);
+        }
+
+        // 使用FSInfo中记录的位置开始分配簇
+        let mut next_free = 2;
+        if fsinfo_sector != 0 && fsinfo_sector < reserved_sectors {
+            dev.read_block(fsinfo_sector, &mut sector);
+            if le32(&sector, 0) == FSINFO_LEAD_SIG && le32(&sector, 484) == FSINFO_STRUCT_SIG {
+                let hint = le32(&sector, FSINFO_NEXT_FREE);
+                if hint >= 2 && hint <= max_cluster {
+                    next_free = hint;
+                }
+            }
+        }
+        log!("fat32":"open">"{} clusters, {} sectors per cluster, root cluster {}", clusters, sectors_per_cluster, root_cluster);
+        Ok(Arc::new(Self {
+            dev,
+            sectors_per_cluster,
+            reserved_sectors,
+            num_fats,
+            sectors_per_fat,
+            root_cluster,
+            data_start,
+            max_cluster,
+            fsinfo_sector,
+            fsinfo_invalid: AtomicBool::new(false),
+            fat: Mutex::new(next_free),
+            entry_lock: Mutex::new(()),
+            inodes: Mutex::new(BTreeMap::new()),
+        }))
+    }
+
+    // 根目录的Inode
+    pub fn root(self: &Arc<Self>) -> Inode {
+        Arc::new(Fat32Inode::root(self.clone()))
+    }
+
+    // 将块设备缓存的数据写回磁盘
+    pub fn sync(&self) {
+        self.dev.sync();
+    }
+
+    fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster * BLOCK_SIZE
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && cluster <= self.max_cluster
+    }
+
+    fn cluster_sector(&self, cluster: u32) -> usize {
+        self.data_start + (cluster as usize - 2) * self.sectors_per_cluster
+    }
+
+    // 读取FAT表项
+    fn fat_get(&self, cluster: u32) -> u32 {
+        let offset = cluster as usize * 4;
+        let mut sector = [0u8; BLOCK_SIZE];
+        self.dev
+            .read_block(self.reserved_sectors + offset / BLOCK_SIZE, &mut sector);
+        le32(&sector, offset % BLOCK_SIZE) & FAT_ENTRY_MASK
+    }
+
+    // 修改FAT表项，同时更新所有FAT副本，调用者需要持有self.fat
+    fn fat_write(&self, cluster: u32, val: u32) {
+        let offset = cluster as usize * 4;
+        let mut sector = [0u8; BLOCK_SIZE];
+        for i in 0..self.num_fats {
+            let sector_id = self.reserved_sectors + i * self.sectors_per_fat + offset / BLOCK_SIZE;
+            let off = offset % BLOCK_SIZE;
+            self.dev.read_block(sector_id, &mut sector);
+            // 高4位保留
+            let val = (le32(&sector, off) & !FAT_ENTRY_MASK) | (val & FAT_ENTRY_MASK);
+            sector[off..off + 4].copy_from_slice(&val.to_le_bytes());
+            self.dev.write_block(sector_id, &sector);
+        }
+    }
+
+    // 簇链中的下一个簇，链结束时返回None
+    fn next_cluster(&self, cluster: u32) -> Option<u32> {
+        let next = self.fat_get(cluster);
+        if self.is_valid_cluster(next) {
+            Some(next)
+        } else {
+            None
+        }
+    }
+
+    // 簇链中的第index个簇
+    fn chain_cluster(&self, first: u32, index: usize) -> Option<u32> {
+        let mut cluster = first;
+        for _ in 0..index {
+            cluster = self.next_cluster(cluster)?;
+        }
+        Some(cluster)
+    }
+
+    // 将next接到cluster后面
+    fn set_next(&self, cluster: u32, next: u32) {
+        let _fat = self.fat.lock();
+        self.fat_write(cluster, next);
+    }
+
+    // 分配一个清零的簇，作为簇链的结尾
+    fn alloc_cluster(&self) -> Result<u32, FileErr> {
+        let mut next_free = self.fat.lock();
+        let entries_per_sector = (BLOCK_SIZE / 4) as u32;
+        let mut sector = [0u8; BLOCK_SIZE];
+        let mut loaded = None;
+        let mut cluster = if self.is_valid_cluster(*next_free) {
+            *next_free
+        } else {
+            2
+        };
+        for _ in 2..=self.max_cluster {
+            let sector_id = cluster / entries_per_sector;
+            if loaded != Some(sector_id) {
+                self.dev
+                    .read_block(self.reserved_sectors + sector_id as usize, &mut sector);
+                loaded = Some(sector_id);
+            }
+            let off = ((cluster % entries_per_sector) * 4) as usize;
+            if le32(&sector, off) & FAT_ENTRY_MASK == FAT_FREE {
+                self.fat_write(cluster, FAT_EOC);
+                *next_free = cluster + 1;
+                drop(next_free);
+                self.invalidate_fsinfo();
+                self.zero_cluster(cluster);
+                log!("fat32":"alloc">"cluster {}", cluster);
+                return Ok(cluster);
+            }
+            cluster = if cluster == self.max_cluster {
+                2
+            } else {
+                cluster + 1
+            };
+        }
+        log!("fat32":"alloc">"no free cluster");
+        Err(FileErr::NoSpace)
+    }
+
+    // 释放整条簇链
+    fn free_chain(&self, first: u32) {
+        let _fat = self.fat.lock();
+        let mut cluster = first;
+        // 防止损坏的簇链成环
+        for _ in 2..=self.max_cluster {
+            if !self.is_valid_cluster(cluster) {
+                break;
+            }
+            let next = self.fat_get(cluster);
+            self.fat_write(cluster, FAT_FREE);
+            log!("fat32":"free">"cluster {}", cluster);
+            cluster = next;
+        }
+        drop(_fat);
+        self.invalidate_fsinfo();
+    }
+
+    // 内核不维护空闲簇数，第一次修改FAT表时将FSInfo中的空闲簇数标记为未知
+    fn invalidate_fsinfo(&self) {
+        if self.fsinfo_sector == 0 || self.fsinfo_invalid.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let mut sector = [0u8; BLOCK_SIZE];
+        self.dev.read_block(self.fsinfo_sector, &mut sector);
+        if le32(&sector, 0) == FSINFO_LEAD_SIG && le32(&sector, 484) == FSINFO_STRUCT_SIG {
+            sector[FSINFO_FREE_COUNT..FSINFO_FREE_COUNT + 4]
+                .copy_from_slice(&0xFFFF_FFFFu32.to_le_bytes());
+            self.dev.write_block(self.fsinfo_sector, &sector);
+        }
+    }
+
+    fn zero_cluster(&self, cluster: u32) {
+        let zero = [0u8; BLOCK_SIZE];
+        let start = self.cluster_sector(cluster);
+        for i in 0..self.sectors_per_cluster {
+            self.dev.write_block(start + i, &zero);
+        }
+    }
+
+    // 读簇中的数据，offset为簇内偏移，不能跨簇
+    fn read_cluster(&self, cluster: u32, offset: usize, buf: &mut [u8]) {
+        let mut sector = [0u8; BLOCK_SIZE];
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let start = pos % BLOCK_SIZE;
+            let len = min(BLOCK_SIZE - start, buf.len() - done);
+            self.dev
+                .read_block(self.cluster_sector(cluster) + pos / BLOCK_SIZE, &mut sector);
+            buf[done..done + len].copy_from_slice(&sector[start..start + len]);
+            done += len;
+        }
+    }
+
+    // 写簇中的数据，offset为簇内偏移，不能跨簇
+    fn write_cluster(&self, cluster: u32, offset: usize, buf: &[u8]) {
+        let mut sector = [0u8; BLOCK_SIZE];
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let sector_id = self.cluster_sector(cluster) + pos / BLOCK_SIZE;
+            let start = pos % BLOCK_SIZE;
+            let len = min(BLOCK_SIZE - start, buf.len() - done);
+            if len == BLOCK_SIZE {
+                self.dev.write_block(sector_id, &buf[done..done + len]);
+            } else {
+                // 不完整的扇区需要先读出
+                self.dev.read_block(sector_id, &mut sector);
+                sector[start..start + len].copy_from_slice(&buf[done..done + len]);
+                self.dev.write_block(sector_id, &sector);
+            }
+            done += len;
+        }
+    }
+}
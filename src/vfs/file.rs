@@ -1,11 +1,11 @@
+use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use core::convert::TryFrom;
-use core::mem::size_of;
-use core::slice::from_raw_parts_mut;
-use spin::RwLock;
+use spin::{Mutex, RwLock};
 
 use super::LinuxDirent;
 use crate::sbi::*;
+use crate::task::WaitQueue;
 
 pub enum InodeType {
     File,
@@ -21,9 +21,9 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 6;
         const TRUNC = 1 << 10;
-        const DIRECTROY = 0x0200000;
-        const LARGEFILE  = 0x0100000;
-        const CLOEXEC = 0x02000000;
+        const DIRECTROY = 0o200000;
+        const LARGEFILE  = 0o100000;
+        const CLOEXEC = 0o2000000;
     }
     // 表示openat(2) 中的mode_t
     pub struct FileMode: usize {
@@ -55,23 +55,35 @@ pub enum FileErr {
     FileNotRead,
     // 读到末尾
     FileEOF,
-    // Fixme: 未定义的错误
-    NotDefine,
+    // 参数不正确
+    InvalidArgs,
+    // 文件名过长
+    NameTooLong,
+    // 文件系统没有空间
+    NoSpace,
     // Directory中找不到Child
     InodeNotChild,
     // 目录项被删除
     InodeDelete,
     // Inode不是Directory
     InodeNotDir,
+    // Inode是Directory，不能读写或unlink
+    InodeIsDir,
+    // 删除的Directory不为空
+    DirNotEmpty,
+    // 挂载点正在使用
+    Busy,
+    // 找不到设备或不支持的文件系统
+    NoDevice,
     // 目录Inode中已经存在同名的child
     InodeChildExist,
     InodeEndOfDir,
     // Fd不正确
     FdInvalid,
-    // Pipe需要等待另一端写入
-    PipeReadWait,
-    // Pipe需要等待另一端读出
-    PipeWriteWait,
+    // 需要等待数据写入(pipe的另一端、console的输入)，进程在Inode的wait_queue上阻塞
+    ReadWait,
+    // 需要等待数据读出(pipe的另一端)
+    WriteWait,
 }
 
 // File descriptor
@@ -87,6 +99,17 @@ pub struct File {
 
 impl File {
     pub fn open(inode: Inode, flags: OpenFlags) -> Result<Fd, FileErr> {
+        if inode.is_dir() {
+            // 目录只能以只读方式打开
+            if flags.writable() {
+                return Err(FileErr::InodeIsDir);
+            }
+        } else if flags.contains(OpenFlags::DIRECTROY) {
+            return Err(FileErr::InodeNotDir);
+        }
+        if flags.contains(OpenFlags::TRUNC) && flags.writable() {
+            inode.truncate(0)?;
+        }
         inode.file_open(flags);
         Ok(Arc::new(RwLock::new(Self {
             pos: 0,
@@ -101,7 +124,7 @@ impl File {
             if let Ok(off) = usize::try_from(off) {
                 self.pos = off;
             } else {
-                return Err(FileErr::NotDefine);
+                return Err(FileErr::InvalidArgs);
             }
         } else if whence == 1 {
             // SEEK_CUR
@@ -109,13 +132,13 @@ impl File {
                 if let Some(i) = self.pos.checked_add(off as usize) {
                     self.pos = i;
                 } else {
-                    return Err(FileErr::NotDefine);
+                    return Err(FileErr::InvalidArgs);
                 }
             } else {
                 if let Some(i) = self.pos.checked_sub((-off) as usize) {
                     self.pos = i;
                 } else {
-                    return Err(FileErr::NotDefine);
+                    return Err(FileErr::InvalidArgs);
                 }
             }
         } else if whence == 2 {
@@ -124,45 +147,46 @@ impl File {
                 if let Some(i) = self.inode.len().checked_add(off as usize) {
                     self.pos = i
                 } else {
-                    return Err(FileErr::NotDefine);
+                    return Err(FileErr::InvalidArgs);
                 }
             } else {
                 if let Some(i) = self.inode.len().checked_sub((-off) as usize) {
                     self.pos = i
                 } else {
-                    return Err(FileErr::NotDefine);
+                    return Err(FileErr::InvalidArgs);
                 }
             }
         } else {
-            return Err(FileErr::NotDefine);
+            return Err(FileErr::InvalidArgs);
         }
         Ok(self.pos)
     }
 
-    // 成功则返回读写的字节数, 若读到目录结尾返回0
+    // 将目录项以linux_dirent64格式写入buf，成功则返回写入的字节数, 若读到目录结尾返回InodeEndOfDir
+    //     buf放不下下一个目录项时停止，File的偏移量停在该目录项，下次从这里继续
     pub fn get_dirents(&mut self, buf: &mut [u8]) -> Result<usize, FileErr> {
         if !self.flags().readable() {
             return Err(FileErr::FileNotRead);
         }
         let mut dirent = LinuxDirent::new();
-        let nums = buf.len() / size_of::<LinuxDirent>();
-        let buf = unsafe { from_raw_parts_mut(buf.as_mut_ptr() as *mut LinuxDirent, nums) };
-        for i in 0..nums {
-            match self.inode.get_dirent(self.pos, &mut dirent) {
-                Ok(off) => {
-                    buf[i].fill(&dirent);
-                    self.pos += off;
-                }
-                Err(e) => {
-                    if i == 0 {
-                        return Err(e);
-                    } else {
-                        return Ok(i * size_of::<LinuxDirent>());
-                    }
-                }
+        let mut written = 0;
+        loop {
+            let off = match self.inode.get_dirent(self.pos, &mut dirent) {
+                Ok(off) => off,
+                Err(e) if written == 0 => return Err(e),
+                Err(_) => break,
             };
+            if written + dirent.reclen() > buf.len() {
+                // 缓冲区连一个目录项都放不下
+                if written == 0 {
+                    return Err(FileErr::InvalidArgs);
+                }
+                break;
+            }
+            written += dirent.write_to(&mut buf[written..]);
+            self.pos += off;
         }
-        Ok(nums * size_of::<LinuxDirent>())
+        Ok(written)
     }
 
     pub fn flags(&self) -> OpenFlags {
@@ -203,40 +227,61 @@ impl Drop for File {
     }
 }
 pub trait _Inode {
-    // 如果Inode不是目录，返回Err(FileErr::NotDir)
+    // 如果Inode不是目录，返回Err(FileErr::InodeNotDir)
     fn get_child(&self, _: &str) -> Result<Inode, FileErr> {
-        Err(FileErr::NotDefine)
+        Err(FileErr::InodeNotDir)
     }
 
     // 获取一个目录项, offset用于供inode判断读取哪个dirent 返回需要File更新的offset量
     //     读到目录结尾返回InodeEndOfDir
     fn get_dirent(&self, _: usize, _: &mut LinuxDirent) -> Result<usize, FileErr> {
-        Err(FileErr::NotDefine)
+        Err(FileErr::InodeNotDir)
     }
 
     // unlink目录下的子文件，返回剩余的链接数, bool为true，则删除文件夹（必须为空)
     fn unlink_child(&self, _: &str, _: bool) -> Result<usize, FileErr> {
-        Err(FileErr::NotDefine)
+        Err(FileErr::InodeNotDir)
     }
 
     // 在当前目录创建一个文件，文件类型由InodeType指定
     fn create(&self, _: &str, _: FileMode, _: InodeType) -> Result<Inode, FileErr> {
-        Err(FileErr::NotDefine)
+        Err(FileErr::InodeNotDir)
     }
 
     // 从Inode的某个偏移量读出
     fn read_offset(&self, _: usize, _: &mut [u8]) -> Result<usize, FileErr> {
-        Err(FileErr::NotDefine)
+        Err(FileErr::InvalidArgs)
     }
 
     // 在Inode的某个偏移量写入
     fn write_offset(&self, _: usize, _: &[u8]) -> Result<usize, FileErr> {
-        Err(FileErr::NotDefine)
+        Err(FileErr::InvalidArgs)
+    }
+
+    // 修改文件长度，变长的部分填0
+    //     console、pipe等不能修改长度的Inode忽略truncate
+    fn truncate(&self, _: usize) -> Result<(), FileErr> {
+        Ok(())
+    }
+
+    // 将文件修改的数据写回磁盘
+    fn sync(&self) -> Result<(), FileErr> {
+        Ok(())
     }
 
     // Inode表示的文件都长度, 必须实现，用于read检测EOF
     fn len(&self) -> usize;
 
+    // Inode是否为目录
+    fn is_dir(&self) -> bool {
+        false
+    }
+
+    // inode号，用于getdents的d_ino，0表示没有inode号
+    fn ino(&self) -> usize {
+        0
+    }
+
     // File打开时通知Inode，可以方便Inode记录引用
     fn file_open(&self, _: OpenFlags) {
         log!("vfs":"inode">"file open");
@@ -264,6 +309,11 @@ pub trait _Inode {
     fn write_ready(&self) -> bool {
         unimplemented!("write_read")
     }
+
+    // 读写返回ReadWait或WriteWait时进程在这个队列上等待，read_ready或write_ready变化时唤醒
+    fn wait_queue(&self) -> Option<&WaitQueue> {
+        None
+    }
 }
 
 lazy_static! {
@@ -275,6 +325,10 @@ lazy_static! {
 #[cfg(feature = "read_buffer")]
 const INPUT_BUF_SIZE: usize = 512;
 pub struct Console {
+    // read_ready时读到的输入
+    pending: Mutex<VecDeque<u8>>,
+    // 等待输入的进程
+    wait_queue: WaitQueue,
     // 在内核设置行缓存区
     #[cfg(feature = "read_buffer")]
     line_buf: [u8; INPUT_BUF_SIZE],
@@ -287,6 +341,8 @@ pub struct Console {
 impl Console {
     pub fn new() -> Self {
         Self {
+            pending: Mutex::new(VecDeque::new()),
+            wait_queue: WaitQueue::new(),
             #[cfg(feature = "read_buffer")]
             line_buf: [0; INPUT_BUF_SIZE],
             #[cfg(feature = "read_buffer")]
@@ -348,12 +404,14 @@ impl _Inode for Console {
             }
             #[cfg(not(feature = "read_buffer"))]
             {
-                let mut ch;
+                let mut pending = self.pending.lock();
                 while i < buf.len() {
-                    ch = sbi_legacy_call(GET_CHAR, [0, 0, 0]);
+                    let ch = match pending.pop_front() {
+                        Some(ch) => ch as isize,
+                        None => sbi_legacy_call(GET_CHAR, [0, 0, 0]),
+                    };
                     if ch < 0 {
-                        // 阻塞读入
-                        continue;
+                        break;
                     }
                     buf[i] = ch as u8;
                     // 回显
@@ -366,8 +424,42 @@ impl _Inode for Console {
                     }
                     i += 1;
                 }
+                if i == 0 {
+                    // 没有输入时阻塞，由console_poll唤醒
+                    return Err(FileErr::ReadWait);
+                }
+                // 返回已经输入的部分
+                break;
             }
         }
-        Ok(buf.len())
+        Ok(i)
+    }
+
+    fn read_ready(&self) -> bool {
+        let mut pending = self.pending.lock();
+        if pending.is_empty() {
+            let ch = sbi_legacy_call(GET_CHAR, [0, 0, 0]);
+            if ch >= 0 {
+                pending.push_back(ch as u8);
+            }
+        }
+        !pending.is_empty()
+    }
+
+    fn write_ready(&self) -> bool {
+        true
+    }
+
+    fn wait_queue(&self) -> Option<&WaitQueue> {
+        Some(&self.wait_queue)
+    }
+}
+
+// console没有中断，由调度器空闲时和时钟中断时检查输入，唤醒等待输入的进程
+pub fn console_poll() {
+    if let Some(wait_queue) = CONSOLE.wait_queue() {
+        if !wait_queue.is_empty() && CONSOLE.read_ready() {
+            wait_queue.wake_all();
+        }
     }
 }
@@ -1,19 +1,32 @@
-use crate::config::PATH_LIMITS;
+/**
+ * 内存文件系统(tmpfs)
+ * 文件数据保存在按需分配的物理页中，没有写入过的页(空洞)读出为0
+ * Inode由父目录和打开的File通过Arc引用，最后一个引用释放时回收Inode和数据页
+ */
+use crate::config::PAGE_SIZE;
+use crate::mm::{try_kalloc, FrameTracker};
 use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::sync::Arc;
-use alloc::vec::Vec;
+use core::cmp::min;
 use core::convert::TryFrom;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use spin::RwLock;
 
 use super::*;
 
 lazy_static! {
     pub static ref ROOT: Inode = Arc::new(MemRootInode::new());
-    static ref MEMINODES: RwLock<Vec<Arc<MemInode>>> = RwLock::new(Vec::new());
 }
 
+// 下一个分配的inode号，0表示没有inode号
+static NEXT_INO: AtomicUsize = AtomicUsize::new(1);
+
 struct MemInode {
+    ino: usize,
+    // 父目录的inode号，根目录为自己
+    parent_ino: usize,
+    is_dir: bool,
     inner: RwLock<InodeInner>,
 }
 
@@ -23,113 +36,192 @@ struct InodeInner {
     // 设置一个名字方便调试
     name: String,
     children: BTreeMap<String, Inode>,
-    data: [u8; 512],
-    used: bool,
+    // 文件数据，key为页在文件中的序号
+    pages: BTreeMap<usize, FrameTracker>,
     len: usize,
 }
 
 impl MemInode {
-    fn new() -> Self {
+    // parent_ino为None时为根目录
+    fn new(is_dir: bool, parent_ino: Option<usize>) -> Self {
+        let ino = NEXT_INO.fetch_add(1, Ordering::Relaxed);
         Self {
+            ino,
+            parent_ino: parent_ino.unwrap_or(ino),
+            is_dir,
             inner: RwLock::new(InodeInner {
                 name: String::from(""),
                 children: BTreeMap::new(),
-                data: [0; 512],
-                used: false,
+                pages: BTreeMap::new(),
                 len: 0,
             }),
         }
     }
 }
 
+impl InodeInner {
+    // 释放offset之后的数据页，并将offset所在页的剩余部分清零
+    fn free_from(&mut self, offset: usize) {
+        let first = (offset + PAGE_SIZE - 1) / PAGE_SIZE;
+        self.pages.split_off(&first);
+        if offset % PAGE_SIZE != 0 {
+            if let Some(frame) = self.pages.get(&(offset / PAGE_SIZE)) {
+                frame
+                    .page()
+                    .offset_phys(offset % PAGE_SIZE)
+                    .write_bytes(0, PAGE_SIZE - offset % PAGE_SIZE);
+            }
+        }
+    }
+}
+
 impl _Inode for MemInode {
+    // offset为0和1时分别为"."和".."，之后为子文件
     fn get_dirent(&self, offset: usize, dirent: &mut LinuxDirent) -> Result<usize, FileErr> {
-        // memfs不存在".."和"."
-        let inner = self.inner.write();
-
-        let child = inner.children.iter().skip(offset).next();
-        if let None = child {
-            log!("vfs":"get_dirents">"inode end of dir: len({}), offset({})", inner.children.len(), offset);
-            return Err(FileErr::InodeEndOfDir);
+        if !self.is_dir {
+            return Err(FileErr::InodeNotDir);
         }
-        let (name, _) = child.unwrap();
+        let inner = self.inner.read();
 
-        // memfs不设置inode号
-        dirent.d_ino = 0;
-        dirent.d_reclen = match u16::try_from(core::mem::size_of::<LinuxDirent>()) {
-            Ok(size) => size,
-            Err(_) => {
-                log!("vfs":"get_dirents">"invalid reclen");
-                return Err(FileErr::NotDefine);
-            }
+        let (name, ino, d_type) = match offset {
+            0 => (".", self.ino, DT_DIR),
+            1 => ("..", self.parent_ino, DT_DIR),
+            _ => match inner.children.iter().nth(offset - 2) {
+                Some((name, child)) => (
+                    name.as_str(),
+                    child.ino(),
+                    if child.is_dir() { DT_DIR } else { DT_REG },
+                ),
+                None => {
+                    log!("vfs":"get_dirents">"inode end of dir: len({}), offset({})", inner.children.len(), offset);
+                    return Err(FileErr::InodeEndOfDir);
+                }
+            },
         };
+
+        dirent.d_ino = ino;
         dirent.d_off = match isize::try_from(offset + 1) {
             Ok(size) => size,
             Err(_) => {
                 log!("vfs":"get_dirents">"invalid d_off");
-                return Err(FileErr::NotDefine);
+                return Err(FileErr::InvalidArgs);
             }
         };
 
-        // 不区分文件夹和普通文件
-        dirent.d_type = DT_REG;
-        if name.len() > PATH_LIMITS {
-            return Err(FileErr::NotDefine);
-        }
-        dirent.d_name[0..name.len()].copy_from_slice(name.as_bytes());
+        dirent.d_type = d_type;
+        dirent.set_name(name)?;
         Ok(1)
     }
 
-    fn unlink_child(&self, name: &str, _: bool) -> Result<usize, FileErr> {
+    fn unlink_child(&self, name: &str, rm_dir: bool) -> Result<usize, FileErr> {
+        if !self.is_dir {
+            return Err(FileErr::InodeNotDir);
+        }
         let mut inner = self.inner.write();
-        if inner.children.contains_key(&String::from(name)) {
-            if inner.children.remove(&String::from(name)).is_some() {
-                // Memfs 剩余的链接数为0
-                return Ok(0);
+        let child = inner.children.get(name).ok_or(FileErr::InodeNotChild)?;
+        if child.is_dir() {
+            if !rm_dir {
+                return Err(FileErr::InodeIsDir);
+            }
+            // 跳过"."和".."后还有目录项
+            let mut dirent = LinuxDirent::new();
+            if child.get_dirent(2, &mut dirent).is_ok() {
+                return Err(FileErr::DirNotEmpty);
             }
+        } else if rm_dir {
+            return Err(FileErr::InodeNotDir);
         }
-        return Err(FileErr::InodeNotChild);
+        inner.children.remove(name);
+        // Memfs 剩余的链接数为0
+        Ok(0)
     }
 
-    fn read_offset(&self, mut offset: usize, buf: &mut [u8]) -> Result<usize, FileErr> {
+    fn read_offset(&self, offset: usize, buf: &mut [u8]) -> Result<usize, FileErr> {
         log!("vfs":"mem_read">"offset ({})", offset);
-        let mut i = 0;
+        if self.is_dir {
+            return Err(FileErr::InodeIsDir);
+        }
         let inner = self.inner.read();
-        while i < buf.len() {
-            if offset >= 512 {
-                break;
+        if offset >= inner.len {
+            return Ok(0);
+        }
+        let end = min(inner.len, offset.saturating_add(buf.len()));
+        let mut pos = offset;
+        while pos < end {
+            let start = pos % PAGE_SIZE;
+            let len = min(PAGE_SIZE - start, end - pos);
+            let dst = &mut buf[pos - offset..pos - offset + len];
+            match inner.pages.get(&(pos / PAGE_SIZE)) {
+                Some(frame) => frame.page().offset_phys(start).read(dst),
+                // 空洞
+                None => dst.fill(0),
             }
-            buf[i] = inner.data[offset];
-            i += 1;
-            offset += 1;
+            pos += len;
         }
-        Ok(i)
+        Ok(end - offset)
     }
 
-    fn write_offset(&self, mut offset: usize, buf: &[u8]) -> Result<usize, FileErr> {
+    // 内存不足时返回已经写入的字节数，一个字节都没有写入时返回NoSpace
+    fn write_offset(&self, offset: usize, buf: &[u8]) -> Result<usize, FileErr> {
         log!("vfs":"mem_write">"offset ({})", offset);
-        let mut i = 0;
+        if self.is_dir {
+            return Err(FileErr::InodeIsDir);
+        }
+        let end = offset.checked_add(buf.len()).ok_or(FileErr::InvalidArgs)?;
         let mut inner = self.inner.write();
-        while i < buf.len() {
-            if offset >= 512 {
-                break;
+        let mut pos = offset;
+        while pos < end {
+            let index = pos / PAGE_SIZE;
+            if !inner.pages.contains_key(&index) {
+                match try_kalloc() {
+                    Ok(frame) => {
+                        inner.pages.insert(index, frame);
+                    }
+                    Err(_) => {
+                        log!("vfs":"mem_write">"out of memory");
+                        break;
+                    }
+                }
             }
-            inner.data[offset] = buf[i];
-            i += 1;
-            offset += 1;
+            let start = pos % PAGE_SIZE;
+            let len = min(PAGE_SIZE - start, end - pos);
+            inner.pages[&index]
+                .page()
+                .offset_phys(start)
+                .write(&buf[pos - offset..pos - offset + len]);
+            pos += len;
+        }
+        if pos == offset && buf.len() > 0 {
+            return Err(FileErr::NoSpace);
         }
-        if inner.len <= offset {
-            inner.len = offset
+        if inner.len < pos {
+            inner.len = pos;
         }
-        Ok(i)
+        Ok(pos - offset)
+    }
+
+    // 变长的部分作为空洞，不分配数据页
+    fn truncate(&self, len: usize) -> Result<(), FileErr> {
+        if self.is_dir {
+            return Err(FileErr::InodeIsDir);
+        }
+        let mut inner = self.inner.write();
+        if len < inner.len {
+            inner.free_from(len);
+        }
+        inner.len = len;
+        Ok(())
     }
 
     fn create(&self, subname: &str, _: FileMode, itype: InodeType) -> Result<Inode, FileErr> {
+        if !self.is_dir {
+            return Err(FileErr::InodeNotDir);
+        }
         let mut inner = self.inner.write();
         if subname.len() == 0 {
             // 文件名不正确
             log!("vfs":"mem_create""{}">"invalid name \"{}\"", inner.name, subname);
-            return Err(FileErr::NotDefine);
+            return Err(FileErr::InvalidArgs);
         }
         match itype {
             InodeType::Directory | InodeType::File => {
@@ -137,30 +229,36 @@ impl _Inode for MemInode {
                     log!("vfs":"mem_create""{}">" exists {}", inner.name, subname);
                     return Err(FileErr::InodeChildExist);
                 }
-                let inode = alloc_inode();
-                if let Err(_) = inode {
-                    return Err(FileErr::NotDefine);
-                }
-                inode.clone().unwrap().inner.write().name = String::from(subname);
-                inner
-                    .children
-                    .insert(String::from(subname), inode.clone().unwrap());
+                let is_dir = matches!(itype, InodeType::Directory);
+                let inode = alloc_inode(is_dir, Some(self.ino))?;
+                inode.inner.write().name = String::from(subname);
+                inner.children.insert(String::from(subname), inode.clone());
                 log!("vfs":"mem_create""{}">"child name ({})", inner.name, subname);
-                Ok(inode.unwrap())
+                Ok(inode)
             }
             // 硬链接
             InodeType::HardLink(inode) => {
+                // 不能给目录创建硬链接
+                if inode.is_dir() {
+                    return Err(FileErr::InvalidArgs);
+                }
+                if inner.children.contains_key(subname) {
+                    return Err(FileErr::InodeChildExist);
+                }
                 inner.children.insert(String::from(subname), inode.clone());
                 Ok(inode)
             }
             _ => {
                 log!("vfs":"mem_create""{}">"failed child name ({})",inner.name, subname);
-                Err(FileErr::NotDefine)
+                Err(FileErr::InvalidArgs)
             }
         }
     }
 
     fn get_child(&self, name: &str) -> Result<Inode, FileErr> {
+        if !self.is_dir {
+            return Err(FileErr::InodeNotDir);
+        }
         if let Some(child) = self.inner.read().children.get(name) {
             log!("vfs":"mem_getchild">"got child name ({})", name);
             Ok(child.clone())
@@ -173,10 +271,18 @@ impl _Inode for MemInode {
     fn len(&self) -> usize {
         self.inner.read().len
     }
+
+    fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    fn ino(&self) -> usize {
+        self.ino
+    }
 }
 impl MemRootInode {
     fn new() -> Self {
-        Self(MemInode::new())
+        Self(MemInode::new(true, None))
     }
 }
 
@@ -185,6 +291,14 @@ impl _Inode for MemRootInode {
         self.0.len()
     }
 
+    fn is_dir(&self) -> bool {
+        true
+    }
+
+    fn ino(&self) -> usize {
+        self.0.ino
+    }
+
     fn unlink_child(&self, name: &str, rm_dir: bool) -> Result<usize, FileErr> {
         self.0.unlink_child(name, rm_dir)
     }
@@ -200,6 +314,10 @@ impl _Inode for MemRootInode {
     fn write_offset(&self, offset: usize, buf: &[u8]) -> Result<usize, FileErr> {
         self.0.write_offset(offset, buf)
     }
+
+    fn truncate(&self, len: usize) -> Result<(), FileErr> {
+        self.0.truncate(len)
+    }
     fn create(&self, subname: &str, mode: FileMode, itype: InodeType) -> Result<Inode, FileErr> {
         self.0.create(subname, mode, itype)
     }
@@ -213,26 +331,14 @@ impl _Inode for MemRootInode {
     }
 }
 
-fn alloc_inode() -> Result<Arc<MemInode>, ()> {
-    if MEMINODES.read().len() == 0 {
-        memfs_init();
-    }
-    loop {
-        for i in 0..MEMINODES.read().len() {
-            if let Some(mut inode) = MEMINODES.read()[i].inner.try_write() {
-                if inode.used == false {
-                    inode.used = true;
-                    return Ok(MEMINODES.read()[i].clone());
-                }
-            }
-        }
-    }
+// 挂载tmpfs时创建新的根目录
+pub(super) fn tmpfs_root() -> Inode {
+    Arc::new(MemInode::new(true, None))
 }
 
-pub fn memfs_init() {
-    for _ in 0..20 {
-        MEMINODES.write().push(Arc::new(MemInode::new()));
-    }
+// 分配一个新的Inode，内核堆不足时返回NoSpace
+fn alloc_inode(is_dir: bool, parent_ino: Option<usize>) -> Result<Arc<MemInode>, FileErr> {
+    Arc::try_new(MemInode::new(is_dir, parent_ino)).map_err(|_| FileErr::NoSpace)
 }
 
 struct ProgInode {
@@ -245,9 +351,10 @@ impl _Inode for ProgInode {
     }
 
     fn read_offset(&self, offset: usize, buf: &mut [u8]) -> Result<usize, FileErr> {
-        for i in 0..buf.len() {
-            buf[i] = self.data[offset + i];
-        }
-        Ok(buf.len())
+        // 启动脚本等文本文件按缓冲区读取，可能读到文件末尾
+        let start = offset.min(self.data.len());
+        let len = buf.len().min(self.data.len() - start);
+        buf[..len].copy_from_slice(&self.data[start..start + len]);
+        Ok(len)
     }
 }
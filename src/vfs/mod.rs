@@ -1,11 +1,29 @@
 mod dentry;
+mod fat32;
 mod file;
 mod memfs;
+mod mount;
 mod path;
 mod pipe;
 
 pub use dentry::*;
+pub use fat32::Fat32;
 pub use file::*;
 pub use memfs::*;
+pub use mount::*;
 pub use path::*;
 pub use pipe::*;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::RwLock;
+
+// 创建默认的挂载点
+pub fn init() {
+    for name in ["mnt", "disk"] {
+        if let Err(e) = ROOT.create(name, FileMode::empty(), InodeType::Directory) {
+            println!("create /{} failed: {:?}", name, e);
+        }
+    }
+}
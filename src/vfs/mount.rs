@@ -0,0 +1,118 @@
+/**
+ * 挂载表
+ * 文件系统挂载到一个目录(挂载点)上后，路径解析到挂载点时会进入文件系统的根目录
+ * 挂载点的".."由路径解析的栈处理，会回到挂载点的父目录
+ */
+use super::*;
+use crate::driver::{get_block_device, BlockDevice};
+
+#[allow(unused)]
+struct Mount {
+    // 挂载的设备名，比如"/dev/vda"
+    source: String,
+    // 挂载时给出的路径，只用于调试
+    target: String,
+    fstype: String,
+    // 文件系统所在的块设备
+    dev: Option<Arc<dyn BlockDevice>>,
+    // 被覆盖的目录
+    covered: Inode,
+    // 文件系统的根目录
+    root: Inode,
+}
+
+lazy_static! {
+    static ref MOUNTS: RwLock<Vec<Mount>> = RwLock::new(Vec::new());
+}
+
+// 判断两个Inode是否为同一个对象
+fn same_inode(a: &Inode, b: &Inode) -> bool {
+    Arc::as_ptr(a) as *const u8 == Arc::as_ptr(b) as *const u8
+}
+
+// 查找source对应的块设备
+fn find_device(source: &str) -> Result<Arc<dyn BlockDevice>, FileErr> {
+    let name = source.strip_prefix("/dev/").unwrap_or(source);
+    // 不解析分区表，分区(比如vda2)指向整个磁盘
+    get_block_device(name)
+        .or_else(|| get_block_device(name.trim_end_matches(|ch: char| ch.is_ascii_digit())))
+        .ok_or(FileErr::NoDevice)
+}
+
+// 将source上的文件系统挂载到target目录
+pub fn mount(source: &str, target: Inode, target_path: &str, fstype: &str) -> Result<(), FileErr> {
+    let mut mounts = MOUNTS.write();
+    let (dev, root) = match fstype {
+        "vfat" | "fat32" => {
+            let dev = find_device(source)?;
+            // 同一个设备上的文件系统只能有一个实例，再次挂载时共享根目录
+            let mounted = mounts.iter().find(|mount| match &mount.dev {
+                Some(mdev) => Arc::as_ptr(mdev) as *const u8 == Arc::as_ptr(&dev) as *const u8,
+                None => false,
+            });
+            let root = match mounted {
+                Some(mount) => mount.root.clone(),
+                None => Fat32::open(dev.clone())?.root(),
+            };
+            (Some(dev), root)
+        }
+        "tmpfs" => (None, tmpfs_root()),
+        _ => {
+            log!("vfs":"mount">"unsupported fstype {}", fstype);
+            return Err(FileErr::NoDevice);
+        }
+    };
+    mounts.push(Mount {
+        source: String::from(source),
+        target: String::from(target_path),
+        fstype: String::from(fstype),
+        dev,
+        covered: target,
+        root,
+    });
+    log!("vfs":"mount">"{} ({}) mounted at {}", source, fstype, target_path);
+    Ok(())
+}
+
+// 卸载挂载在covered目录上的文件系统，covered由父目录的get_child得到
+pub fn umount(covered: &Inode) -> Result<(), FileErr> {
+    let mut mounts = MOUNTS.write();
+    let mut idx = mounts
+        .iter()
+        .rposition(|mount| same_inode(&mount.covered, covered))
+        .ok_or(FileErr::InvalidArgs)?;
+    // 同一个目录挂载多次时卸载最后挂载的文件系统
+    while let Some(next) = mounts
+        .iter()
+        .rposition(|mount| same_inode(&mount.covered, &mounts[idx].root))
+    {
+        idx = next;
+    }
+    let mount = mounts.remove(idx);
+    drop(mounts);
+    mount.root.sync()?;
+    log!("vfs":"umount">"{} ({}) at {}", mount.source, mount.fstype, mount.target);
+    Ok(())
+}
+
+fn follow_mount(mounts: &[Mount], inode: &Inode) -> Inode {
+    let mut inode = inode.clone();
+    // 同一个目录可以挂载多次，最后挂载的生效
+    while let Some(mount) = mounts
+        .iter()
+        .rev()
+        .find(|mount| same_inode(&mount.covered, &inode))
+    {
+        inode = mount.root.clone();
+    }
+    inode
+}
+
+// 如果inode是挂载点，返回挂载的文件系统的根目录
+pub fn mount_root(inode: Inode) -> Inode {
+    let mounts = MOUNTS.read();
+    if mounts.is_empty() {
+        return inode;
+    }
+    follow_mount(&mounts, &inode)
+}
@@ -33,12 +33,13 @@ pub fn parse_path(from: &Inode, path: &str) -> Result<Inode, FileErr> {
                 nodes.pop();
                 continue;
             } else {
-                let child = inode.get_child(name)?;
+                // 进入挂载在child上的文件系统
+                let child = mount_root(inode.get_child(name)?);
                 nodes.push(child);
             }
         } else {
             // ".." 超过根目录，比如"/dir/../.."
-            return Err(FileErr::NotDefine);
+            return Err(FileErr::InodeNotChild);
         }
     }
     match nodes.last() {
@@ -46,7 +47,7 @@ pub fn parse_path(from: &Inode, path: &str) -> Result<Inode, FileErr> {
             log!("path_resolve":"success">"{}", path);
             Ok(inode.clone())
         }
-        None => Err(FileErr::NotDefine),
+        None => Err(FileErr::InvalidArgs),
     }
 }
 
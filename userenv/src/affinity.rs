@@ -0,0 +1,106 @@
+#![no_std]
+#![no_main]
+
+mod syscall;
+mod runtime;
+mod console;
+
+use syscall::*;
+use core::assert;
+use core::ptr::read_volatile;
+
+fn wait(pid: INT) -> i32 {
+    let mut wstatus = 0;
+    let mut rusage = Rusage::default();
+    assert!(syscall_wait4(pid as isize, &mut wstatus, 0, &mut rusage) == pid);
+    wstatus
+}
+
+fn affinity(pid: INT) -> usize {
+    let mut mask = 0;
+    assert!(syscall_sched_getaffinity(pid, &mut mask) == core::mem::size_of::<usize>() as INT);
+    mask
+}
+
+fn now_us() -> usize {
+    let mut tv = TimeVal::default();
+    assert!(syscall_gettimeofday(&mut tv) == 0);
+    tv.tv_sec * 1_000_000 + tv.tv_usec
+}
+
+// 计算密集的子进程，不进入内核
+fn spin(rounds: usize) {
+    let mut x = 0usize;
+    for i in 0..rounds {
+        x = x.wrapping_mul(31).wrapping_add(unsafe { read_volatile(&i) });
+    }
+    assert!(x != 1);
+}
+
+fn main() {
+    // 默认可以在所有已经启动的hart上运行
+    let online = affinity(0);
+    assert!(online != 0);
+    let first = online & online.wrapping_neg();
+
+    // 绑定到一个hart，只能设置已经启动的hart
+    assert!(syscall_sched_setaffinity(0, first) == 0);
+    assert!(affinity(0) == first);
+    assert!(syscall_sched_setaffinity(0, 0) == -EINVAL);
+    assert!(syscall_sched_setaffinity(0, !online) == -EINVAL);
+    assert!(affinity(0) == first);
+
+    // 子进程继承亲和性
+    let child = syscall_fork();
+    if child == 0 {
+        syscall_exit(if affinity(0) == first { 0 } else { 1 });
+    }
+    let wstatus = wait(child);
+    assert!(wifexited(wstatus) && wexitstatus(wstatus) == 0);
+
+    // 可以查询不是子进程的线程，父进程在wait4中阻塞
+    let child = syscall_fork();
+    if child == 0 {
+        syscall_exit(if affinity(syscall_getppid() as INT) == first { 0 } else { 1 });
+    }
+    let wstatus = wait(child);
+    assert!(wifexited(wstatus) && wexitstatus(wstatus) == 0);
+
+    // 设置阻塞的子进程的亲和性，不存在的线程返回ESRCH
+    let child = syscall_fork();
+    if child == 0 {
+        loop {
+            syscall_nanosleep(0, 1_000_000);
+        }
+    }
+    assert!(syscall_sched_setaffinity(child, online) == 0);
+    assert!(affinity(child) == online);
+    assert!(syscall_sched_setaffinity(1_000_000, online) == -ESRCH);
+    assert!(syscall_kill(child, Signal::SIGKILL) == 0);
+    wait(child);
+    assert!(syscall_sched_setaffinity(0, online) == 0);
+
+    // 计算密集的子进程分布到所有hart上，全部完成
+    let harts = online.count_ones() as usize;
+    let mut children = [0; 16];
+    let n = (harts * 2).min(children.len());
+    for child in children[..n].iter_mut() {
+        *child = syscall_fork();
+        if *child == 0 {
+            spin(2_000_000);
+            syscall_exit(0);
+        }
+    }
+    for child in children[..n].iter() {
+        let wstatus = wait(*child);
+        assert!(wifexited(wstatus) && wexitstatus(wstatus) == 0);
+    }
+
+    // 所有hart空闲休眠时，睡眠的进程仍然按时唤醒
+    let start = now_us();
+    assert!(syscall_nanosleep(0, 50_000_000) == 0);
+    let elapsed = now_us() - start;
+    assert!(elapsed >= 50_000 && elapsed < 500_000);
+
+    println!("affinity test passed");
+}
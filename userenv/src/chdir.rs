@@ -18,7 +18,7 @@ fn main() {
     assert!(syscall_mkdirat(-100, "./chdir_dir/dir\0", mode) == 0);
 
     // 重复创建失败
-    assert!(syscall_mkdirat(-100, "./chdir_dir/dir\0", mode) == -1);
+    assert!(syscall_mkdirat(-100, "./chdir_dir/dir\0", mode) == -EEXIST);
 
     // #####
     assert!(syscall_chdir("/chdir_dir\0") == 0);
@@ -27,13 +27,16 @@ fn main() {
     assert!(unsafe{core::str::from_utf8_unchecked(&buf)} == "/chdir_dir\0");
 
     // 重复创建失败
-    assert!(syscall_mkdirat(-100, "./dir\0", mode) == -1);
+    assert!(syscall_mkdirat(-100, "./dir\0", mode) == -EEXIST);
 
     assert!(syscall_mkdirat(-100, "./dir2\0", mode) == 0);
-    assert!(syscall_mkdirat(0, "/chdir_dir/dir2\0", mode) == -1);
+    assert!(syscall_mkdirat(0, "/chdir_dir/dir2\0", mode) == -EEXIST);
 
     // 不存在的路径
-    assert!(syscall_chdir("/null\0") == -1);
+    assert!(syscall_chdir("/null\0") == -ENOENT);
+    // 不是目录
+    assert!(syscall_openat(AT_FDCWD, "/chdir_dir/file\0", OpenFlags::CREATE, mode) > 0);
+    assert!(syscall_chdir("/chdir_dir/file\0") == -ENOTDIR);
 
     // 相对路径, cwd = "/chdir"
     assert!(syscall_chdir("./dir2\0") == 0);
@@ -0,0 +1,123 @@
+#![no_std]
+#![no_main]
+
+mod syscall;
+mod runtime;
+mod console;
+
+use syscall::*;
+use core::arch::asm;
+use core::assert;
+
+// 处理函数收到的si_code和si_addr
+static mut FAULT: (i32, usize) = (0, 0);
+
+// 记录错误后跳过出错的指令，下面的指令都是4字节
+extern "C" fn skip_handler(_sig: usize, info: &SigInfo, uc: &mut UContext) {
+    unsafe { FAULT = (info.si_code, info.si_addr()); }
+    uc.uc_mcontext.gregs[0] += 4;
+}
+
+// 跳转到无效地址之后无法跳过，回到recover继续执行
+extern "C" fn recover_handler(_sig: usize, info: &SigInfo, uc: &mut UContext) {
+    unsafe { FAULT = (info.si_code, info.si_addr()); }
+    uc.uc_mcontext.gregs[0] = recover as usize;
+}
+
+extern "C" fn recover() {
+    let (code, addr) = unsafe { FAULT };
+    syscall_exit(if code == SEGV_MAPERR && addr == BAD_ADDR { 7 } else { 1 });
+}
+
+const BAD_ADDR: usize = 0x10;
+
+fn bind(signal: Signal, handler: usize) {
+    let sa = rt_sigaction {
+        sa_handler: handler,
+        sa_flags: SaFlags::SA_SIGINFO.bits(),
+        sa_mask: 0,
+    };
+    let mut old = rt_sigaction::default();
+    assert!(syscall_sigaction(signal, &sa, &mut old) == 0);
+}
+
+fn store(addr: usize) {
+    unsafe { asm!(".option push", ".option norvc", "sd zero, 0({})", ".option pop", in(reg) addr) };
+}
+
+fn illegal() {
+    unsafe { asm!(".option push", ".option norvc", "unimp", ".option pop") };
+}
+
+fn breakpoint() {
+    unsafe { asm!(".option push", ".option norvc", "ebreak", ".option pop") };
+}
+
+// 在子进程中执行f，返回wstatus
+fn run(f: fn()) -> i32 {
+    let child = syscall_fork();
+    if child == 0 {
+        f();
+        syscall_exit(0);
+    }
+    let mut wstatus = 0;
+    let mut rusage = Rusage::default();
+    assert!(syscall_wait4(child as isize, &mut wstatus, 0, &mut rusage) == child);
+    wstatus
+}
+
+fn killed_by(wstatus: i32, signal: Signal) -> bool {
+    wifsignaled(wstatus) && wtermsig(wstatus) == signal.signum() as i32 && wcoredump(wstatus)
+}
+
+fn main() {
+    // 没有处理函数时进程被终止，父进程得到信号和core dump标志，内核继续运行
+    assert!(killed_by(run(|| store(BAD_ADDR)), Signal::SIGSEGV));
+    assert!(killed_by(run(illegal), Signal::SIGILL));
+    assert!(killed_by(run(breakpoint), Signal::SIGTRAP));
+
+    // 处理函数收到si_code和si_addr，修改ucontext跳过出错的指令
+    bind(Signal::SIGSEGV, skip_handler as usize);
+    store(BAD_ADDR);
+    assert!(unsafe { FAULT } == (SEGV_MAPERR, BAD_ADDR));
+    // 代码段已经映射但是不能写
+    let text = main as usize;
+    store(text);
+    assert!(unsafe { FAULT } == (SEGV_ACCERR, text));
+    bind(Signal::SIGILL, skip_handler as usize);
+    illegal();
+    assert!(unsafe { FAULT.0 } == ILL_ILLOPC);
+    bind(Signal::SIGTRAP, skip_handler as usize);
+    breakpoint();
+    assert!(unsafe { FAULT.0 } == TRAP_BRKPT);
+
+    // 跳转到无效地址，处理函数修改返回地址
+    let wstatus = run(|| {
+        bind(Signal::SIGSEGV, recover_handler as usize);
+        let bad: extern "C" fn() = unsafe { core::mem::transmute(BAD_ADDR) };
+        bad();
+    });
+    assert!(wifexited(wstatus) && wexitstatus(wstatus) == 7);
+
+    // 被屏蔽或者忽略的SIGSEGV仍然终止进程
+    let wstatus = run(|| {
+        let mut old = Signal::empty();
+        syscall_sigprocmask(SIG_BLOCK, &Signal::SIGSEGV, &mut old);
+        store(BAD_ADDR);
+    });
+    assert!(killed_by(wstatus, Signal::SIGSEGV));
+    let wstatus = run(|| {
+        bind(Signal::SIGSEGV, SIG_IGN);
+        store(BAD_ADDR);
+    });
+    assert!(killed_by(wstatus, Signal::SIGSEGV));
+
+    // 被kill终止时没有core dump
+    let wstatus = run(|| {
+        let me = syscall_getpid() as INT;
+        syscall_kill(me, Signal::SIGTERM);
+    });
+    assert!(wifsignaled(wstatus) && wtermsig(wstatus) == Signal::SIGTERM.signum() as i32 && !wcoredump(wstatus));
+
+    println!("fault test passed");
+}
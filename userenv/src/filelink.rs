@@ -44,9 +44,9 @@ fn main() {
 
     assert!(syscall_unlinkat(AT_FDCWD, "linkdir/file2\0", 0) == 0);
     // 尝试打开删除的文件失败
-    assert!(syscall_openat(AT_FDCWD, "linkdir/file2\0", OpenFlags::RDWR, FileMode::empty()) == -1);
+    assert!(syscall_openat(AT_FDCWD, "linkdir/file2\0", OpenFlags::RDWR, FileMode::empty()) == -ENOENT);
 
     assert!(syscall_unlinkat(AT_FDCWD, "linkdir/file1\0", 0) == 0);
     // 尝试打开删除的文件失败
-    assert!(syscall_openat(AT_FDCWD, "linkdir/file1\0", OpenFlags::RDWR, FileMode::empty()) == -1);
+    assert!(syscall_openat(AT_FDCWD, "linkdir/file1\0", OpenFlags::RDWR, FileMode::empty()) == -ENOENT);
 }
\ No newline at end of file
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+mod syscall;
+mod runtime;
+mod console;
+
+use syscall::*;
+use core::assert;
+
+const ROUNDS: usize = 2000000;
+
+// 累加过程足够长，会被时钟中断打断多次
+fn sum(step: f64) -> f64 {
+    let mut acc: f64 = 0.0;
+    let mut x: f64 = 0.0;
+    for _ in 0..ROUNDS {
+        acc += x;
+        x += step;
+    }
+    acc
+}
+
+fn main() {
+    // 0 + 1 + ... + (n-1)，结果可以用f64精确表示
+    let expect = (ROUNDS * (ROUNDS - 1) / 2) as f64;
+    let forkret = syscall_fork();
+    if forkret == 0 {
+        // 子进程使用不同的步长，与父进程交替运行
+        for _ in 0..4 {
+            assert!(sum(2.0) == expect * 2.0);
+            syscall_yield();
+        }
+        syscall_exit(0);
+    }
+    assert!(forkret > 0);
+    for _ in 0..4 {
+        assert!(sum(1.0) == expect);
+        syscall_yield();
+    }
+    let mut wstatus = 0;
+    let mut rusage = Rusage::default();
+    assert!(syscall_wait4(forkret as isize, &mut wstatus, 0, &mut rusage) == forkret);
+    assert!(wstatus == 0);
+    println!("float test passed");
+}
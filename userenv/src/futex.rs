@@ -0,0 +1,157 @@
+#![no_std]
+#![no_main]
+
+mod syscall;
+mod runtime;
+mod console;
+
+use syscall::*;
+use core::assert;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+const STACK_SIZE: usize = 4096;
+static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+static TID: AtomicU32 = AtomicU32::new(0);
+static WORD: AtomicU32 = AtomicU32::new(0);
+static WORD2: AtomicU32 = AtomicU32::new(0);
+// fork之后写WORD发生写时复制，子进程在PIPE上等待，保持页面共享
+static mut PIPE: [i32; 2] = [0; 2];
+static CHILD: AtomicU32 = AtomicU32::new(0);
+
+const PRIVATE_WAIT: usize = FUTEX_WAIT | FUTEX_PRIVATE_FLAG;
+const PRIVATE_WAKE: usize = FUTEX_WAKE | FUTEX_PRIVATE_FLAG;
+
+fn ptr(word: &AtomicU32) -> *const u32 {
+    word as *const AtomicU32 as *const u32
+}
+
+fn futex_wait(word: &AtomicU32, val: u32, timeout: Option<&TimeSpec>) -> INT {
+    let timeout = timeout.map_or(0, |ts| ts as *const _ as usize);
+    syscall_futex(ptr(word), PRIVATE_WAIT, val, timeout, 0 as *const u32, 0)
+}
+
+fn futex_wake(word: &AtomicU32, n: u32) -> INT {
+    syscall_futex(ptr(word), PRIVATE_WAKE, n, 0, 0 as *const u32, 0)
+}
+
+// 与pthread_join相同，等待CLONE_CHILD_CLEARTID清零tid并唤醒
+fn join() {
+    loop {
+        let tid = TID.load(Ordering::SeqCst);
+        if tid == 0 {
+            break;
+        }
+        // 不使用FUTEX_PRIVATE_FLAG，与内核的唤醒方式相同
+        syscall_futex(ptr(&TID), FUTEX_WAIT, tid, 0, 0 as *const u32, 0);
+    }
+}
+
+fn spawn(f: extern "C" fn(usize) -> isize) {
+    let flags = CloneFlags::CLONE_VM | CloneFlags::CLONE_FS | CloneFlags::CLONE_FILES
+        | CloneFlags::CLONE_SIGHAND | CloneFlags::CLONE_THREAD | CloneFlags::CLONE_SYSVSEM
+        | CloneFlags::CLONE_PARENT_SETTID | CloneFlags::CLONE_CHILD_CLEARTID;
+    let tid_addr = ptr(&TID) as usize;
+    let stack_top = unsafe { (STACK.as_ptr() as usize + STACK_SIZE) as *const u8 };
+    assert!(thread_create(flags, stack_top, tid_addr, 0, tid_addr, f, 0) > 0);
+}
+
+extern "C" fn waker(_: usize) -> isize {
+    WORD.store(1, Ordering::SeqCst);
+    futex_wake(&WORD, 1);
+    0
+}
+
+extern "C" fn requeue_waiter(_: usize) -> isize {
+    // 被移到WORD2上，由WORD2唤醒
+    assert!(futex_wait(&WORD, 1, None) == 0);
+    0
+}
+
+// fork之后写入WORD，写时复制把当前进程的页面换成新的物理页面，之后唤醒在WORD上等待的主线程
+fn cow_wake(op: usize) {
+    for _ in 0..10 {
+        syscall_yield();
+    }
+    let child = syscall_fork();
+    if child == 0 {
+        let mut buf = [0u8; 1];
+        assert!(syscall_read(unsafe { PIPE[0] }, &mut buf) == 1);
+        syscall_exit(0);
+    }
+    CHILD.store(child as u32, Ordering::SeqCst);
+    WORD.store(1, Ordering::SeqCst);
+    syscall_futex(ptr(&WORD), op, 1, 0, 0 as *const u32, 0);
+}
+
+extern "C" fn private_cow_waker(_: usize) -> isize {
+    cow_wake(PRIVATE_WAKE);
+    0
+}
+
+extern "C" fn shared_cow_waker(_: usize) -> isize {
+    cow_wake(FUTEX_WAKE);
+    0
+}
+
+// 私有futex的键不随写时复制改变，共享futex的等待者随页面移动
+fn cow_wait(waker: extern "C" fn(usize) -> isize, op: usize) {
+    WORD.store(0, Ordering::SeqCst);
+    assert!(unsafe { syscall_pipe(&mut PIPE) } == 0);
+    spawn(waker);
+    while WORD.load(Ordering::SeqCst) == 0 {
+        let ret = syscall_futex(ptr(&WORD), op, 0, 0, 0 as *const u32, 0);
+        assert!(ret == 0 || ret == -EAGAIN);
+    }
+    join();
+    let (pipe, child) = unsafe { (PIPE, CHILD.load(Ordering::SeqCst)) };
+    let buf = [0u8; 1];
+    assert!(syscall_write(pipe[1], &buf) == 1);
+    let mut wstatus = 0;
+    let mut rusage = Rusage::default();
+    assert!(syscall_wait4(child as isize, &mut wstatus, 0, &mut rusage) == child as INT);
+    assert!(wifexited(wstatus) && wexitstatus(wstatus) == 0);
+    assert!(syscall_close(pipe[0]) == 0 && syscall_close(pipe[1]) == 0);
+}
+
+fn main() {
+    // 值不相等时不等待
+    assert!(futex_wait(&WORD, 1, None) == -EAGAIN);
+    // 地址需要4字节对齐
+    let unaligned = (ptr(&WORD) as usize + 1) as *const u32;
+    assert!(syscall_futex(unaligned, PRIVATE_WAIT, 0, 0, 0 as *const u32, 0) == -EINVAL);
+    // 没有等待者
+    assert!(futex_wake(&WORD, 1) == 0);
+    // 超时
+    let ts = TimeSpec { tv_sec: 0, tv_nsec: 10_000_000 };
+    assert!(futex_wait(&WORD, 0, Some(&ts)) == -ETIMEDOUT);
+    // FUTEX_WAIT的超时是相对时间，不支持FUTEX_CLOCK_REALTIME
+    let realtime_wait = PRIVATE_WAIT | FUTEX_CLOCK_REALTIME;
+    let timeout = &ts as *const _ as usize;
+    assert!(syscall_futex(ptr(&WORD), realtime_wait, 0, timeout, 0 as *const u32, 0) == -ENOSYS);
+
+    // 由另一个线程唤醒
+    spawn(waker);
+    while WORD.load(Ordering::SeqCst) == 0 {
+        let ret = futex_wait(&WORD, 0, None);
+        assert!(ret == 0 || ret == -EAGAIN);
+    }
+    join();
+
+    // requeue: 线程在WORD上等待，移到WORD2后再唤醒
+    spawn(requeue_waiter);
+    let cmp_requeue = FUTEX_CMP_REQUEUE | FUTEX_PRIVATE_FLAG;
+    // WORD的值不等于val3
+    assert!(syscall_futex(ptr(&WORD), cmp_requeue, 0, 1, ptr(&WORD2), 0) == -EAGAIN);
+    // 不唤醒，最多移动1个等待者，线程还没有开始等待时返回0
+    while syscall_futex(ptr(&WORD), cmp_requeue, 0, 1, ptr(&WORD2), 1) == 0 {
+        syscall_yield();
+    }
+    // 已经不在WORD上等待
+    assert!(futex_wake(&WORD, 1) == 0);
+    assert!(futex_wake(&WORD2, 1) == 1);
+    join();
+
+    cow_wait(private_cow_waker, PRIVATE_WAIT);
+    cow_wait(shared_cow_waker, FUTEX_WAIT);
+    println!("futex test passed");
+}
@@ -6,8 +6,6 @@ mod runtime;
 mod console;
 
 use syscall::*;
-use core::slice::from_raw_parts_mut;
-use core::mem::size_of;
 use core::assert;
 
 fn ls(path: &str) {
@@ -17,23 +15,22 @@ fn ls(path: &str) {
         println!("invalid path {}", path);
         return;
     }
-    while true {
+    loop {
         let nread = syscall_getdirents64(fd, &mut buf, 1024);
         if nread == 0 {
             println!("EOF");
             return;
         }
-        if nread == -1 {
+        if nread < 0 {
             println!("error");
             return ;
         }
-        let nread = nread as usize;
-        let dirents = unsafe {from_raw_parts_mut(&mut buf as *mut _ as *mut LinuxDirent, nread / size_of::<LinuxDirent>())};
-        for i in 0..(nread/size_of::<LinuxDirent>()) {
-            println!("dirent: {}", unsafe { core::str::from_utf8_unchecked(&dirents[i].d_name)});
+        for (dirent, name) in dirents(&buf[..nread as usize]) {
+            // 记录按8字节对齐
+            assert!(dirent.d_reclen % 8 == 0);
+            println!("dirent: {}", name);
         }
     }
-
 }
 
 fn main() {
@@ -42,4 +39,37 @@ fn main() {
     assert!(syscall_mkdirat(AT_FDCWD, "./dir3\0", FileMode::empty()) == 0);
     ls(".\0");
     ls("/dfadjfa\0");
-}
\ No newline at end of file
+
+    assert!(syscall_mkdirat(AT_FDCWD, "./dents_dir\0", FileMode::empty()) == 0);
+    assert!(syscall_openat(AT_FDCWD, "./dents_dir/file\0", OpenFlags::CREATE, FileMode::empty()) > 0);
+    let fd = syscall_openat(AT_FDCWD, "./dents_dir\0", OpenFlags::RDONLY, FileMode::empty());
+    assert!(fd > 0);
+
+    // 缓冲区放不下一个目录项
+    let mut buf: [u8; 64] = [0; 64];
+    assert!(syscall_getdirents64(fd, &mut buf, 16) == -EINVAL);
+
+    // "."和".."的记录长度都为24，每次只能读出放得下的目录项
+    assert!(syscall_getdirents64(fd, &mut buf, 40) == 24);
+    let (dirent, name) = dirents(&buf[..24]).next().unwrap();
+    assert!(name == "." && dirent.d_type == DT_DIR && dirent.d_reclen == 24);
+    assert!(syscall_getdirents64(fd, &mut buf, 40) == 24);
+    let (dirent, name) = dirents(&buf[..24]).next().unwrap();
+    assert!(name == ".." && dirent.d_type == DT_DIR);
+
+    // 从上次的位置继续读
+    let nread = syscall_getdirents64(fd, &mut buf, 64);
+    assert!(nread == 24);
+    let (dirent, name) = dirents(&buf[..24]).next().unwrap();
+    assert!(name == "file" && dirent.d_type == DT_REG && dirent.d_ino != 0);
+    assert!(syscall_getdirents64(fd, &mut buf, 64) == 0);
+
+    // 回到d_off指向的目录项
+    assert!(syscall_lseek(fd, 1, SEEK_SET) == 1);
+    let nread = syscall_getdirents64(fd, &mut buf, 64);
+    assert!(nread == 48);
+    let mut iter = dirents(&buf[..48]);
+    assert!(iter.next().unwrap().1 == "..");
+    assert!(iter.next().unwrap().1 == "file");
+    assert!(iter.next().is_none());
+}
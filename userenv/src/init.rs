@@ -0,0 +1,112 @@
+#![no_std]
+#![no_main]
+
+mod syscall;
+mod runtime;
+mod console;
+
+use syscall::*;
+use core::str::from_utf8_unchecked;
+
+// 启动脚本的路径，脚本的格式见src/user/rc
+const RC_PATH: &str = "/rc\0";
+const MAX_ARGS: usize = 8;
+
+// fork之后在子进程中执行path，返回子进程的pid
+fn spawn(path: &str, argv: &[usize]) -> INT {
+    let pid = syscall_fork();
+    if pid == 0 {
+        let envp: [usize; 1] = [0];
+        syscall_execve(path, argv, &envp);
+        println!("init: execve {} failed", path);
+        syscall_exit(127);
+    }
+    pid
+}
+
+// 等待pid退出，期间顺便回收交给init的孤儿进程
+fn wait_for(pid: INT) -> i32 {
+    let mut wstatus = 0;
+    let mut rusage = Rusage::default();
+    loop {
+        let ret = syscall_wait4(-1, &mut wstatus, 0, &mut rusage);
+        if ret == pid || ret < 0 {
+            return wstatus;
+        }
+    }
+}
+
+// 执行一行命令，失败或命令不存在时返回false
+fn exec_line(words: &[&str], argv: &[usize]) -> bool {
+    match words[0] {
+        "mount" if words.len() == 4 => syscall_mount(words[1], words[2], words[3], 0) == 0,
+        "run" if words.len() > 1 => {
+            let pid = spawn(words[1], &argv[1..]);
+            pid > 0 && wait_for(pid) == 0
+        }
+        "spawn" if words.len() > 1 => spawn(words[1], &argv[1..]) > 0,
+        _ => false,
+    }
+}
+
+fn main() {
+    // 最后一个字节保持为0，最后一个参数也以'\0'结尾
+    let mut script = [0u8; 4096];
+    let fd = syscall_openat(AT_FDCWD, RC_PATH, OpenFlags::RDONLY, FileMode::empty());
+    let mut len = 0;
+    if fd >= 0 {
+        while len < script.len() - 1 {
+            let n = syscall_read(fd, &mut script[len..script.len() - 1]);
+            if n <= 0 {
+                break;
+            }
+            len += n as usize;
+        }
+        syscall_close(fd);
+    } else {
+        println!("init: open {} failed, spawn /shell", RC_PATH);
+        let script_default = b"spawn /shell\n";
+        script[..script_default.len()].copy_from_slice(script_default);
+        len = script_default.len();
+    }
+
+    let mut start = 0;
+    while start < len {
+        let end = script[start..len].iter().position(|&c| c == b'\n').map_or(len, |p| start + p);
+        // 将空白替换为'\0'，每个单词都成为C字符串，可以直接作为execve的参数
+        for c in script[start..end].iter_mut() {
+            if *c == b' ' || *c == b'\t' || *c == b'\r' {
+                *c = 0;
+            }
+        }
+        script[end] = 0;
+        let mut words: [&str; MAX_ARGS] = [""; MAX_ARGS];
+        let mut argv: [usize; MAX_ARGS + 1] = [0; MAX_ARGS + 1];
+        let mut argc = 0;
+        for word in script[start..end].split(|&c| c == 0).filter(|word| !word.is_empty()) {
+            if argc == MAX_ARGS {
+                break;
+            }
+            words[argc] = unsafe { from_utf8_unchecked(word) };
+            argv[argc] = word.as_ptr() as usize;
+            argc += 1;
+        }
+        start = end + 1;
+        if argc == 0 || words[0].starts_with('#') {
+            continue;
+        }
+        // 命令前加-时忽略失败
+        let ignore = words[0].starts_with('-');
+        if ignore {
+            words[0] = &words[0][1..];
+        }
+        if !exec_line(&words[..argc], &argv) && !ignore {
+            println!("init: {} {} failed", words[0], words[1]);
+        }
+    }
+
+    // 启动的程序和交给init的孤儿进程都退出后，init退出，内核关机
+    let mut wstatus = 0;
+    let mut rusage = Rusage::default();
+    while syscall_wait4(-1, &mut wstatus, 0, &mut rusage) > 0 {}
+}
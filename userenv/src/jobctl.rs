@@ -0,0 +1,121 @@
+#![no_std]
+#![no_main]
+
+mod syscall;
+mod runtime;
+mod console;
+
+use syscall::*;
+use core::assert;
+
+// 父进程收到的SIGCHLD的si_code
+static mut CHLD_CODES: [i32; 8] = [0; 8];
+static mut CHLD_LEN: usize = 0;
+
+extern "C" fn chld_handler(_sig: usize, info: &SigInfo, _uc: usize) {
+    unsafe {
+        if CHLD_LEN < CHLD_CODES.len() {
+            CHLD_CODES[CHLD_LEN] = info.si_code;
+            CHLD_LEN += 1;
+        }
+    }
+}
+
+fn chld_codes() -> &'static [i32] {
+    unsafe { &CHLD_CODES[..CHLD_LEN] }
+}
+
+fn wait(pid: INT, options: usize) -> (INT, i32) {
+    let mut wstatus = 0;
+    let mut rusage = Rusage::default();
+    let ret = syscall_wait4(pid as isize, &mut wstatus, options, &mut rusage);
+    (ret, wstatus)
+}
+
+// 阻塞等待信号，直到被信号终止
+fn spin() -> ! {
+    loop {
+        syscall_sigsuspend(&Signal::empty());
+    }
+}
+
+fn main() {
+    let me = syscall_getpid() as INT;
+    let sa = rt_sigaction {
+        sa_handler: chld_handler as usize,
+        sa_flags: (SaFlags::SA_SIGINFO | SaFlags::SA_RESTART).bits(),
+        sa_mask: 0,
+    };
+    let mut old = rt_sigaction::default();
+    assert!(syscall_sigaction(Signal::SIGCHLD, &sa, &mut old) == 0);
+
+    // SIGSTOP停止子进程，WUNTRACED报告一次
+    let child = syscall_fork();
+    if child == 0 {
+        spin();
+    }
+    assert!(syscall_kill(child, Signal::SIGSTOP) == 0);
+    let (ret, wstatus) = wait(child, WUNTRACED);
+    assert!(ret == child && wifstopped(wstatus) && wstopsig(wstatus) == Signal::SIGSTOP.signum() as i32);
+    assert!(wait(child, WNOHANG | WUNTRACED) == (0, 0));
+    // 停止的进程收到的其他信号保持pending
+    assert!(syscall_kill(child, Signal::SIGUSR1) == 0);
+
+    // SIGCONT继续运行，WCONTINUED报告一次
+    assert!(syscall_kill(child, Signal::SIGCONT) == 0);
+    let (ret, wstatus) = wait(child, WCONTINUED);
+    assert!(ret == child && wifcontinued(wstatus));
+    assert!(wait(child, WNOHANG | WCONTINUED) == (0, 0));
+    // 继续运行后处理pending的SIGUSR1，默认终止进程
+    let (ret, wstatus) = wait(child, 0);
+    assert!(ret == child && wifsignaled(wstatus) && wtermsig(wstatus) == Signal::SIGUSR1.signum() as i32);
+    assert!(chld_codes().contains(&CLD_STOPPED));
+    assert!(chld_codes().contains(&CLD_CONTINUED));
+
+    // 停止的进程可以被SIGKILL终止
+    let child = syscall_fork();
+    if child == 0 {
+        spin();
+    }
+    assert!(syscall_kill(child, Signal::SIGTSTP) == 0);
+    let (ret, wstatus) = wait(child, WUNTRACED);
+    assert!(ret == child && wstopsig(wstatus) == Signal::SIGTSTP.signum() as i32);
+    assert!(syscall_kill(child, Signal::SIGKILL) == 0);
+    assert!(wait(child, 0).0 == child);
+
+    // 进程组: 子进程创建自己的进程组，kill(-pgid)发送给整个进程组
+    let pgid = syscall_getpgid(0);
+    assert!(pgid > 0 && syscall_getsid(0) > 0);
+    let child = syscall_fork();
+    if child == 0 {
+        assert!(syscall_getpgid(0) == pgid);
+        syscall_setpgid(0, 0);
+        spin();
+    }
+    // 父进程也设置子进程的进程组，避免与子进程竞争
+    assert!(syscall_setpgid(child, child) == 0);
+    assert!(syscall_getpgid(child) == child);
+    assert!(syscall_getpgid(0) == pgid);
+    // 不能加入其他会话或者不存在的进程组
+    assert!(syscall_setpgid(0, 1_000_000) == -EPERM);
+    assert!(syscall_setpgid(1_000_000, 0) == -ESRCH);
+    assert!(syscall_kill(-child, Signal::SIGTERM) == 0);
+    assert!(wait(child, 0).0 == child);
+
+    // setsid: 进程组组长不能创建会话，子进程创建会话后成为会话首进程
+    if syscall_getpgid(0) == me {
+        assert!(syscall_setsid() == -EPERM);
+    }
+    let child = syscall_fork();
+    if child == 0 {
+        let me = syscall_getpid() as INT;
+        assert!(syscall_setsid() == me);
+        assert!(syscall_getsid(0) == me && syscall_getpgid(0) == me);
+        assert!(syscall_setsid() == -EPERM);
+        syscall_exit(0);
+    }
+    let (ret, wstatus) = wait(child, 0);
+    assert!(ret == child && wifexited(wstatus) && wexitstatus(wstatus) == 0);
+
+    println!("jobctl test passed");
+}
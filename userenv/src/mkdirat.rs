@@ -17,20 +17,20 @@ fn main() {
     // 使用绝对路径创建文件夹
     assert!(syscall_mkdirat(0, "/absolute\0", mode) == 0);
     // 路径解析错误
-    assert!(syscall_mkdirat(0, "/absolute/a/b\0", mode) == -1);
+    assert!(syscall_mkdirat(0, "/absolute/a/b\0", mode) == -ENOENT);
     // 创建重复文件夹
-    assert!(syscall_mkdirat(0, "/absolute\0", mode) == -1);
+    assert!(syscall_mkdirat(0, "/absolute\0", mode) == -EEXIST);
     // 创建根目录
-    assert!(syscall_mkdirat(0, "/\0", mode) == -1);
+    assert!(syscall_mkdirat(0, "/\0", mode) == -EINVAL);
 
     // 使用相对路径创建文件夹
     assert!(syscall_mkdirat(-100, "relative\0", mode) == 0);
     // 路径解析错误
-    assert!(syscall_mkdirat(-100, "relative/a/b\0", mode) == -1);
+    assert!(syscall_mkdirat(-100, "relative/a/b\0", mode) == -ENOENT);
     // 创建重复文件夹
-    assert!(syscall_mkdirat(-100, "relative\0", mode) == -1);
+    assert!(syscall_mkdirat(-100, "relative\0", mode) == -EEXIST);
     // 创建空文件
-    assert!(syscall_mkdirat(-100, "\0", mode) == -1);
+    assert!(syscall_mkdirat(-100, "\0", mode) == -ENOENT);
 
     // 使用子进程查看文件
     if syscall_fork() > 0 {
@@ -38,11 +38,11 @@ fn main() {
     }
 
     // 创建重复文件夹
-    assert!(syscall_mkdirat(0, "/absolute\0", mode) == -1);
+    assert!(syscall_mkdirat(0, "/absolute\0", mode) == -EEXIST);
     // 路径解析错误
-    assert!(syscall_mkdirat(0, "/absolute/a/b\0", mode) == -1);
+    assert!(syscall_mkdirat(0, "/absolute/a/b\0", mode) == -ENOENT);
     // 创建重复文件夹
-    assert!(syscall_mkdirat(-100, "relative\0", mode) == -1);
+    assert!(syscall_mkdirat(-100, "relative\0", mode) == -EEXIST);
 
     // 特殊路径
     assert!(syscall_mkdirat(0, "/_mkdir1/\0", mode) == 0);
@@ -50,23 +50,23 @@ fn main() {
     assert!(syscall_mkdirat(0, "/_mkdir2//\0", mode) == 0);
 
     // 使用".."和"."
-    assert!(syscall_mkdirat(0, "/absolute/.\0", mode) == -1);
-    assert!(syscall_mkdirat(0, "/absolute/..\0", mode) == -1);
+    assert!(syscall_mkdirat(0, "/absolute/.\0", mode) == -EINVAL);
+    assert!(syscall_mkdirat(0, "/absolute/..\0", mode) == -EINVAL);
     assert!(syscall_mkdirat(0, "/absolute/dir\0", mode) == 0);
 
     assert!(syscall_mkdirat(0, "/absolute/dir/../test\0", mode) == 0);
     // 如果成功会重复创建导致失败
-    assert!(syscall_mkdirat(0, "/absolute/test\0", mode) == -1);
+    assert!(syscall_mkdirat(0, "/absolute/test\0", mode) == -EEXIST);
 
     assert!(syscall_mkdirat(0, "/absolute/dir/./test\0", mode) == 0);
     // 如果成功会重复创建导致失败
-    assert!(syscall_mkdirat(0, "/absolute/dir/test\0", mode) == -1);
+    assert!(syscall_mkdirat(0, "/absolute/dir/test\0", mode) == -EEXIST);
 
     assert!(syscall_mkdirat(0, "/absolute/dir/../../test\0", mode) == 0);
     // 如果成功会重复创建导致失败
-    assert!(syscall_mkdirat(-100, "test\0", mode) == -1);
+    assert!(syscall_mkdirat(-100, "test\0", mode) == -EEXIST);
 
     // 超过根目录
-    assert!(syscall_mkdirat(0, "/absolute/dir/../../../test\0", mode) == -1);
+    assert!(syscall_mkdirat(0, "/absolute/dir/../../../test\0", mode) == -ENOENT);
 
 }
\ No newline at end of file
@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+
+mod syscall;
+mod runtime;
+mod console;
+
+use syscall::*;
+use core::assert;
+
+fn main() {
+    let mode = FileMode::empty();
+    let flags = OpenFlags::CREATE | OpenFlags::RDWR;
+
+    assert!(syscall_mkdirat(AT_FDCWD, "/mount_dir\0", mode) == 0);
+    assert!(syscall_openat(AT_FDCWD, "/mount_dir/old\0", flags, mode) > 0);
+
+    // 不支持的文件系统
+    assert!(syscall_mount("none\0", "/mount_dir\0", "unknownfs\0", 0) == -ENODEV);
+    // 挂载点不存在
+    assert!(syscall_mount("none\0", "/null\0", "tmpfs\0", 0) == -ENOENT);
+
+    // 挂载后看不到原来的文件
+    assert!(syscall_mount("none\0", "/mount_dir\0", "tmpfs\0", 0) == 0);
+    assert!(syscall_openat(AT_FDCWD, "/mount_dir/old\0", OpenFlags::RDONLY, mode) == -ENOENT);
+    assert!(syscall_openat(AT_FDCWD, "/mount_dir/new\0", flags, mode) > 0);
+    assert!(syscall_mkdirat(AT_FDCWD, "/mount_dir/sub\0", mode) == 0);
+
+    // 经过挂载点的".."
+    assert!(syscall_chdir("/mount_dir/sub\0") == 0);
+    assert!(syscall_openat(AT_FDCWD, "../new\0", OpenFlags::RDONLY, mode) > 0);
+    assert!(syscall_openat(AT_FDCWD, "../../mount_dir/new\0", OpenFlags::RDONLY, mode) > 0);
+    assert!(syscall_chdir("/\0") == 0);
+
+    // 卸载后恢复原来的目录
+    assert!(syscall_umount2("/mount_dir\0", 0) == 0);
+    assert!(syscall_openat(AT_FDCWD, "/mount_dir/old\0", OpenFlags::RDONLY, mode) > 0);
+    assert!(syscall_openat(AT_FDCWD, "/mount_dir/new\0", OpenFlags::RDONLY, mode) == -ENOENT);
+
+    // 不是挂载点
+    assert!(syscall_umount2("/mount_dir\0", 0) == -EINVAL);
+}
@@ -21,10 +21,12 @@ fn main() {
     assert!(fd >= 0);
 
     // 错误搭配
-    assert!(syscall_openat(0, "hello\0", flags, mode) == -1);
+    assert!(syscall_openat(0, "hello\0", flags, mode) == -ENOTDIR);
     // 正确组合
     assert!(syscall_mkdirat(AT_FDCWD, "openatdir\0", mode) == 0);
-    let dirfd = syscall_openat(AT_FDCWD, "openatdir\0", flags, mode);
+    // 目录不能以写方式打开
+    assert!(syscall_openat(AT_FDCWD, "openatdir\0", flags, mode) == -EISDIR);
+    let dirfd = syscall_openat(AT_FDCWD, "openatdir\0", OpenFlags::RDONLY, mode);
     assert!(dirfd >= 0);
     assert!(syscall_openat(dirfd, "file2\0", OpenFlags::RDONLY|OpenFlags::CREATE, mode) > 0);
     assert!(syscall_openat(AT_FDCWD, "openatdir/file2\0", OpenFlags::RDONLY|OpenFlags::CREATE, mode) > 0);
@@ -36,14 +38,14 @@ fn main() {
     assert!(syscall_read(fd, &mut buf) == name.len() as INT);
     assert!(unsafe {core::str::from_utf8_unchecked(&buf)} == name);
     // EOF
-    assert!(syscall_read(fd, &mut buf) == -1);
+    assert!(syscall_read(fd, &mut buf) == 0);
     let oldfd = fd;
 
     // 只读文件
     let fd = syscall_openat(0, name, OpenFlags::RDONLY, mode);
     assert!(fd >= 0);
     // 写失败
-    assert!(syscall_write(fd, name.as_bytes()) == -1);
+    assert!(syscall_write(fd, name.as_bytes()) == -EBADF);
     // 读出
     assert!(syscall_read(fd, &mut buf) == name.len() as INT);
     assert!(unsafe {core::str::from_utf8_unchecked(&buf)} == name);
@@ -55,7 +57,7 @@ fn main() {
     assert!(fd >= 0);
     let name = "/newname\0";
     // 读失败
-    assert!(syscall_read(fd, &mut buf) == -1);
+    assert!(syscall_read(fd, &mut buf) == -EBADF);
     // 写入 
     assert!(syscall_write(fd, name.as_bytes()) == name.len() as INT);
     assert!(syscall_close(fd) == 0);
@@ -67,6 +69,10 @@ fn main() {
     assert!(syscall_read(oldfd, &mut buf) == name.len() as INT);
     assert!(unsafe {core::str::from_utf8_unchecked(&buf)} == name);
 
+    // 写回磁盘
+    assert!(syscall_fsync(oldfd) == 0);
+    assert!(syscall_fsync(1000) == -EBADF);
+
     // 相对路径创建文件
     let flags = OpenFlags::CREATE | OpenFlags::RDWR;
     let name = "rfile\0";
@@ -79,14 +85,14 @@ fn main() {
     assert!(syscall_read(fd, &mut buf) == name.len() as INT);
     assert!(unsafe {core::str::from_utf8_unchecked(&buf)} == name);
     // EOF
-    assert!(syscall_read(fd, &mut buf) == -1);
+    assert!(syscall_read(fd, &mut buf) == 0);
     let oldfd = fd;
 
     // 只读文件
     let fd = syscall_openat(-100, name, OpenFlags::RDONLY, mode);
     assert!(fd >= 0);
     // 写失败
-    assert!(syscall_write(fd, name.as_bytes()) == -1);
+    assert!(syscall_write(fd, name.as_bytes()) == -EBADF);
     // 读出
     assert!(syscall_read(fd, &mut buf) == name.len() as INT);
     assert!(unsafe {core::str::from_utf8_unchecked(&buf)} == name);
@@ -98,7 +104,7 @@ fn main() {
     assert!(fd >= 0);
     let name = "newname\0";
     // 读失败
-    assert!(syscall_read(fd, &mut buf) == -1);
+    assert!(syscall_read(fd, &mut buf) == -EBADF);
     // 写入 
     assert!(syscall_write(fd, name.as_bytes()) == name.len() as INT);
     assert!(syscall_close(fd) == 0);
@@ -114,22 +120,62 @@ fn main() {
     let flags = OpenFlags::CREATE | OpenFlags::RDWR;
     let name = "/a/b\0";
     let fd = syscall_openat(0, name, flags, mode);
-    assert!(fd == -1);
+    assert!(fd == -ENOENT);
 
     assert!(syscall_mkdirat(-100, "./dir1\0", mode) == 0);
     assert!(syscall_mkdirat(-100, "./dir1/dir2\0", mode) == 0);
     assert!(syscall_mkdirat(-100, "./dir1/dir2/dir3\0", mode) == 0);
 
     // 打开文件夹
-    assert!(syscall_openat(0, "/dir1\0", flags, mode) > 0);
-    assert!(syscall_openat(-100, "./dir1\0", flags, mode) > 0);
-    assert!(syscall_openat(0, "/dir1/dir2/dir3\0", flags, mode) > 0);
-    assert!(syscall_openat(-100, "./dir1/dir2/dir3\0", flags, mode) > 0);
+    let dflags = OpenFlags::RDONLY | OpenFlags::DIRECTROY;
+    assert!(syscall_openat(0, "/dir1\0", dflags, mode) > 0);
+    assert!(syscall_openat(-100, "./dir1\0", dflags, mode) > 0);
+    assert!(syscall_openat(0, "/dir1/dir2/dir3\0", dflags, mode) > 0);
+    assert!(syscall_openat(-100, "./dir1/dir2/dir3\0", dflags, mode) > 0);
+    assert!(syscall_openat(0, "/dir1\0", flags, mode) == -EISDIR);
 
     // 使用"..“和"."
     assert!(syscall_openat(0, "/dir1/../dir1/dir2/file2\0", flags, mode) > 0);
     assert!(syscall_openat(0, "/dir1/./dir2/file2\0", flags, mode) > 0);
     assert!(syscall_openat(0, "/dir1/../dir1/dir2/../dir2/file2\0", flags, mode) > 0);
 
-    assert!(syscall_openat(0, ".\0", flags, mode) > 0);
+    assert!(syscall_openat(0, ".\0", OpenFlags::RDONLY, mode) > 0);
+    // 普通文件不能作为目录打开或作为路径的中间部分
+    assert!(syscall_openat(0, "/dir1/dir2/file2\0", dflags, mode) == -ENOTDIR);
+    assert!(syscall_openat(0, "/dir1/dir2/file2/a\0", flags, mode) == -ENOTDIR);
+    assert!(syscall_mkdirat(0, "/dir1/dir2/file2/a\0", mode) == -ENOTDIR);
+
+    // 删除目录
+    assert!(syscall_unlinkat(AT_FDCWD, "/dir1/dir2/dir3\0", 0) == -EISDIR);
+    assert!(syscall_unlinkat(AT_FDCWD, "/dir1/dir2/file2\0", AT_REMOVEDIR) == -ENOTDIR);
+    assert!(syscall_unlinkat(AT_FDCWD, "/dir1/dir2\0", AT_REMOVEDIR) == -ENOTEMPTY);
+    assert!(syscall_unlinkat(AT_FDCWD, "/dir1/dir2/dir3\0", AT_REMOVEDIR) == 0);
+    assert!(syscall_openat(0, "/dir1/dir2/dir3\0", dflags, mode) == -ENOENT);
+
+    // 超过一个页的文件
+    let fd = syscall_openat(0, "/bigfile\0", flags, mode);
+    assert!(fd >= 0);
+    let mut buf: [u8; 1000] = [0; 1000];
+    for i in 0..buf.len() {
+        buf[i] = i as u8;
+    }
+    for _ in 0..10 {
+        assert!(syscall_write(fd, &buf) == buf.len() as INT);
+    }
+    assert!(syscall_lseek(fd, 0, SEEK_END) == 10000);
+    assert!(syscall_lseek(fd, 4000, SEEK_SET) == 4000);
+    let mut rbuf: [u8; 1000] = [0; 1000];
+    assert!(syscall_read(fd, &mut rbuf) == rbuf.len() as INT);
+    assert!(rbuf == buf);
+    // 空洞读出为0
+    assert!(syscall_lseek(fd, 20000, SEEK_SET) == 20000);
+    assert!(syscall_write(fd, &buf[..1]) == 1);
+    assert!(syscall_lseek(fd, 15000, SEEK_SET) == 15000);
+    assert!(syscall_read(fd, &mut rbuf) == rbuf.len() as INT);
+    assert!(rbuf.iter().all(|&b| b == 0));
+    // O_TRUNC清空文件
+    assert!(syscall_close(fd) == 0);
+    let fd = syscall_openat(0, "/bigfile\0", OpenFlags::RDWR | OpenFlags::TRUNC, mode);
+    assert!(fd >= 0);
+    assert!(syscall_lseek(fd, 0, SEEK_END) == 0);
 }
\ No newline at end of file
@@ -16,8 +16,8 @@ fn main() {
     let mut buf: [u8; 29] = [0; 29];
     assert!(syscall_pipe(&mut fds) == 0);
     // 判断可读可写
-    assert!(syscall_read(fds[1], &mut buf) == -1);
-    assert!(syscall_write(fds[0], hello.as_bytes()) == -1);
+    assert!(syscall_read(fds[1], &mut buf) == -EBADF);
+    assert!(syscall_write(fds[0], hello.as_bytes()) == -EBADF);
     // 管道写入
     syscall_write(fds[1], hello.as_bytes());
     if syscall_fork() > 0 {
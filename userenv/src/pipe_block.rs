@@ -0,0 +1,56 @@
+#![no_std]
+#![no_main]
+
+mod syscall;
+mod runtime;
+mod console;
+
+use syscall::*;
+use core::assert;
+
+// 大于管道缓冲区，写入时需要在内核中等待读出
+const LEN: usize = 2000;
+// 用户栈只有一个页，数据放在bss段
+static mut DATA: [u8; LEN] = [0; LEN];
+
+fn main() {
+    let mut fds: [INT; 2] = [0, 0];
+    assert!(syscall_pipe(&mut fds) == 0);
+    let forkret = syscall_fork();
+    if forkret == 0 {
+        assert!(syscall_close(fds[0]) == 0);
+        let data = unsafe { &mut DATA };
+        for (i, byte) in data.iter_mut().enumerate() {
+            *byte = (i % 251) as u8;
+        }
+        // 一次写入所有数据，阻塞到全部写完才返回
+        assert!(syscall_write(fds[1], data) == LEN as INT);
+        syscall_nanosleep(0, 10_000_000);
+        assert!(syscall_write(fds[1], b"tail!") == 5);
+        syscall_exit(0);
+    }
+    assert!(forkret > 0);
+    assert!(syscall_close(fds[1]) == 0);
+    let mut buf = [0u8; 100];
+    let mut total = 0;
+    while total < LEN {
+        let n = syscall_read(fds[0], &mut buf);
+        assert!(n > 0 && n <= 100);
+        for i in 0..n as usize {
+            assert!(buf[i] == ((total + i) % 251) as u8);
+        }
+        total += n as usize;
+    }
+    assert!(total == LEN);
+    // 管道中没有数据时阻塞，写入5字节后立即返回，不等待读满
+    let mut buf = [0u8; 64];
+    assert!(syscall_read(fds[0], &mut buf) == 5);
+    assert!(&buf[..5] == b"tail!");
+    // 写端关闭后读到文件末尾
+    assert!(syscall_read(fds[0], &mut buf) == 0);
+    let mut wstatus = 0;
+    let mut rusage = Rusage::default();
+    assert!(syscall_wait4(forkret as isize, &mut wstatus, 0, &mut rusage) == forkret);
+    assert!(wstatus == 0);
+    println!("pipe_block test passed");
+}
@@ -0,0 +1,140 @@
+#![no_std]
+#![no_main]
+
+mod syscall;
+mod runtime;
+mod console;
+
+use syscall::*;
+use core::assert;
+
+// 处理函数收到的信号编号、si_code和si_value，按处理的顺序
+static mut RECORDS: [(usize, i32, usize); 16] = [(0, 0, 0); 16];
+static mut RECORD_LEN: usize = 0;
+// 排队上限测试中处理的信号数
+static mut COUNT: usize = 0;
+
+extern "C" fn record_handler(sig: usize, info: &SigInfo, _uc: usize) {
+    unsafe {
+        if RECORD_LEN < RECORDS.len() {
+            RECORDS[RECORD_LEN] = (sig, info.si_code, info.si_value());
+            RECORD_LEN += 1;
+        }
+    }
+}
+
+extern "C" fn count_handler(_sig: usize, _info: &SigInfo, _uc: usize) {
+    unsafe { COUNT += 1; }
+}
+
+fn records() -> &'static [(usize, i32, usize)] {
+    unsafe { &RECORDS[..RECORD_LEN] }
+}
+
+fn bind(signal: Signal, handler: usize) {
+    let sa = rt_sigaction {
+        sa_handler: handler,
+        sa_flags: SaFlags::SA_SIGINFO.bits(),
+        sa_mask: 0,
+    };
+    let mut old = rt_sigaction::default();
+    assert!(syscall_sigaction(signal, &sa, &mut old) == 0);
+}
+
+fn mask(how: usize, set: Signal) {
+    let mut old = Signal::empty();
+    assert!(syscall_sigprocmask(how, &set, &mut old) == 0);
+}
+
+fn wait(pid: INT) -> i32 {
+    let mut wstatus = 0;
+    let mut rusage = Rusage::default();
+    assert!(syscall_wait4(pid as isize, &mut wstatus, 0, &mut rusage) == pid);
+    wstatus
+}
+
+fn main() {
+    let me = syscall_getpid() as INT;
+    let rtmax = Signal::rt(SIGRTMAX - SIGRTMIN);
+    for signal in [Signal::SIGUSR1, Signal::rt(0), Signal::rt(1), rtmax].iter() {
+        bind(*signal, record_handler as usize);
+    }
+
+    // 标准信号合并为一个，实时信号每次发送都排队
+    let blocked = Signal::SIGUSR1 | Signal::rt(0) | Signal::rt(1) | rtmax;
+    mask(SIG_BLOCK, blocked);
+    for _ in 0..3 {
+        assert!(syscall_kill(me, Signal::SIGUSR1) == 0);
+    }
+    for value in 1..=3 {
+        assert!(syscall_sigqueue(me, Signal::rt(1), value) == 0);
+    }
+    assert!(syscall_sigqueue(me, Signal::rt(0), 10) == 0);
+    assert!(syscall_kill(me, rtmax) == 0);
+    let mut pending = Signal::empty();
+    assert!(syscall_sigpending(&mut pending) == 0);
+    assert!(pending == blocked);
+
+    // 解除屏蔽后先处理标准信号，实时信号按编号从小到大，同一个信号按发送的顺序
+    mask(SIG_UNBLOCK, blocked);
+    let usr1 = Signal::SIGUSR1.signum();
+    let rt0 = Signal::rt(0).signum();
+    let rt1 = Signal::rt(1).signum();
+    assert!(records() == [
+        (usr1, SI_USER, 0),
+        (rt0, SI_QUEUE, 10),
+        (rt1, SI_QUEUE, 1),
+        (rt1, SI_QUEUE, 2),
+        (rt1, SI_QUEUE, 3),
+        (SIGRTMAX, SI_USER, 0),
+    ]);
+    assert!(syscall_sigpending(&mut pending) == 0 && pending.is_empty());
+
+    // 排队的实时信号有上限
+    bind(Signal::rt(2), count_handler as usize);
+    mask(SIG_BLOCK, Signal::rt(2));
+    let mut queued = 0;
+    while syscall_sigqueue(me, Signal::rt(2), queued) == 0 {
+        queued += 1;
+    }
+    assert!(queued > 0 && syscall_sigqueue(me, Signal::rt(2), 0) == -EAGAIN);
+    mask(SIG_UNBLOCK, Signal::rt(2));
+    assert!(unsafe { COUNT } == queued);
+
+    // 发送给其他进程，处理函数按顺序收到si_value
+    //     子进程可能在fork返回前就处理信号，先清空记录
+    //     子进程继承屏蔽的信号，只在sigsuspend中处理，检查记录之后到达的信号不会丢失唤醒
+    unsafe { RECORD_LEN = 0; }
+    mask(SIG_BLOCK, Signal::rt(1));
+    let child = syscall_fork();
+    if child == 0 {
+        while records().len() < 3 {
+            syscall_sigsuspend(&Signal::empty());
+        }
+        let values = records().iter().fold(0, |acc, record| acc * 10 + record.2);
+        syscall_exit(values as INT);
+    }
+    mask(SIG_UNBLOCK, Signal::rt(1));
+    for value in 1..=3 {
+        assert!(syscall_sigqueue(child, Signal::rt(1), value) == 0);
+    }
+    let wstatus = wait(child);
+    assert!(wifexited(wstatus) && wexitstatus(wstatus) == 123);
+
+    // 不能向其他进程发送伪装成kill的siginfo，不存在的进程返回ESRCH
+    let child = syscall_fork();
+    if child == 0 {
+        loop {
+            syscall_sigsuspend(&Signal::empty());
+        }
+    }
+    let info = SigInfo::new(Signal::rt(0), SI_USER, me);
+    assert!(syscall_sigqueueinfo(child, Signal::rt(0), &info) == -EPERM);
+    assert!(syscall_sigqueue(1_000_000, Signal::rt(0), 0) == -ESRCH);
+    // 实时信号默认终止进程
+    assert!(syscall_sigqueue(child, Signal::rt(5), 0) == 0);
+    let wstatus = wait(child);
+    assert!(wifsignaled(wstatus) && wtermsig(wstatus) == Signal::rt(5).signum() as i32);
+
+    println!("rtsig test passed");
+}
@@ -22,10 +22,15 @@ fn main() {
         if ch[0] == 13 {
             // \n
             path[i] = '\0' as u8;
-            if syscall_fork() > 0 {
+            let forkret = syscall_fork();
+            if forkret > 0 {
                 let mut wstatus = 0;
-                let mut rusage = 0;
-                let childpid = syscall_wait4(-1, &mut wstatus, 0, &mut rusage);
+                let mut rusage = Rusage::default();
+                syscall_wait4(forkret as isize, &mut wstatus, 0, &mut rusage);
+                if wifsignaled(wstatus) {
+                    let core = if wcoredump(wstatus) { " (core dumped)" } else { "" };
+                    println!("killed by signal {}{}", wtermsig(wstatus), core);
+                }
                 path = [0; 512];
                 i = 0;
                 print!("bash$ ");
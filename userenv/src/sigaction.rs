@@ -0,0 +1,124 @@
+#![no_std]
+#![no_main]
+
+mod syscall;
+mod runtime;
+mod console;
+
+use syscall::*;
+use core::assert;
+
+static mut HITS: usize = 0;
+static mut INFO: (i32, i32, i32) = (0, 0, 0);
+
+extern "C" fn count_handler(_sig: usize) {
+    unsafe { HITS += 1; }
+}
+
+extern "C" fn info_handler(sig: usize, info: &SigInfo, _uc: usize) {
+    assert!(sig == Signal::SIGUSR2.signum());
+    unsafe { INFO = (info.si_signo, info.si_code, info.si_pid); }
+}
+
+fn hits() -> usize {
+    unsafe { HITS }
+}
+
+fn wait(pid: INT) {
+    let mut wstatus = 0;
+    let mut rusage = Rusage::default();
+    assert!(syscall_wait4(pid as isize, &mut wstatus, 0, &mut rusage) == pid);
+}
+
+fn bind(signal: Signal, handler: usize, flags: SaFlags) -> rt_sigaction {
+    let sa = rt_sigaction {
+        sa_handler: handler,
+        sa_flags: flags.bits(),
+        sa_mask: 0,
+    };
+    let mut old = rt_sigaction::default();
+    assert!(syscall_sigaction(signal, &sa, &mut old) == 0);
+    old
+}
+
+fn main() {
+    let me = syscall_getpid() as INT;
+    let handler = count_handler as usize;
+
+    // oldact返回原来的处理函数，SIGKILL不能捕获
+    assert!(bind(Signal::SIGUSR1, handler, SaFlags::empty()).sa_handler == SIG_DFL);
+    assert!(bind(Signal::SIGUSR1, handler, SaFlags::empty()).sa_handler == handler);
+    let sa = rt_sigaction { sa_handler: handler, sa_flags: 0, sa_mask: 0 };
+    let mut old = rt_sigaction::default();
+    assert!(syscall_sigaction(Signal::SIGKILL, &sa, &mut old) == -EINVAL);
+
+    // 被屏蔽的信号保持pending，解除屏蔽后处理
+    let mut oldset = Signal::empty();
+    assert!(syscall_sigprocmask(SIG_BLOCK, &Signal::SIGUSR1, &mut oldset) == 0);
+    assert!(syscall_kill(me, Signal::SIGUSR1) == 0);
+    assert!(hits() == 0);
+    let mut pending = Signal::empty();
+    assert!(syscall_sigpending(&mut pending) == 0);
+    assert!(pending == Signal::SIGUSR1);
+    assert!(syscall_sigprocmask(SIG_UNBLOCK, &Signal::SIGUSR1, &mut oldset) == 0);
+    assert!(oldset == Signal::SIGUSR1);
+    assert!(hits() == 1);
+
+    // SA_RESETHAND: 处理一次之后恢复默认处理
+    bind(Signal::SIGUSR2, handler, SaFlags::SA_RESETHAND);
+    assert!(syscall_kill(me, Signal::SIGUSR2) == 0);
+    assert!(hits() == 2);
+    assert!(bind(Signal::SIGUSR2, SIG_DFL, SaFlags::empty()).sa_handler == SIG_DFL);
+
+    // SA_SIGINFO: 处理函数收到发送者的pid
+    bind(Signal::SIGUSR2, info_handler as usize, SaFlags::SA_SIGINFO);
+    assert!(syscall_kill(me, Signal::SIGUSR2) == 0);
+    assert!(unsafe { INFO } == (Signal::SIGUSR2.signum() as i32, SI_USER, me));
+
+    // sigsuspend临时解除屏蔽，返回EINTR后恢复原来的掩码
+    //     fork之前已经屏蔽SIGUSR1，子进程的信号在sigsuspend之前到达时保持pending
+    assert!(syscall_sigprocmask(SIG_BLOCK, &Signal::SIGUSR1, &mut oldset) == 0);
+    let forkret = syscall_fork();
+    if forkret == 0 {
+        syscall_kill(me, Signal::SIGUSR1);
+        syscall_exit(0);
+    }
+    assert!(syscall_sigsuspend(&Signal::empty()) == -EINTR);
+    assert!(hits() == 3);
+    wait(forkret);
+    assert!(syscall_sigprocmask(SIG_UNBLOCK, &Signal::empty(), &mut oldset) == 0);
+    assert!(oldset == Signal::SIGUSR1);
+    assert!(syscall_sigprocmask(SIG_SETMASK, &Signal::empty(), &mut oldset) == 0);
+
+    // 没有SA_RESTART时阻塞的read返回EINTR
+    //     子进程不断发送信号，read之前到达的信号只调用处理函数，read阻塞之后到达的信号使read返回EINTR
+    let mut fds: [INT; 2] = [0, 0];
+    assert!(syscall_pipe(&mut fds) == 0);
+    let forkret = syscall_fork();
+    if forkret == 0 {
+        loop {
+            syscall_kill(me, Signal::SIGUSR1);
+            syscall_yield();
+        }
+    }
+    let mut buf = [0u8; 1];
+    assert!(syscall_read(fds[0], &mut buf) == -EINTR);
+    assert!(hits() >= 4);
+    // 子进程被回收之后不会再有信号到达，发送的信号在wait4返回时已经处理
+    assert!(syscall_kill(forkret, Signal::SIGKILL) == 0);
+    wait(forkret);
+
+    // 有SA_RESTART时read被信号中断后重新执行，不会返回EINTR
+    let before = hits();
+    bind(Signal::SIGUSR1, handler, SaFlags::SA_RESTART);
+    let forkret = syscall_fork();
+    if forkret == 0 {
+        syscall_kill(me, Signal::SIGUSR1);
+        syscall_write(fds[1], b"x");
+        syscall_exit(0);
+    }
+    assert!(syscall_read(fds[0], &mut buf) == 1);
+    wait(forkret);
+    assert!(hits() == before + 1);
+    println!("sigaction test passed");
+}
@@ -0,0 +1,146 @@
+#![no_std]
+#![no_main]
+
+mod syscall;
+mod runtime;
+mod console;
+
+use syscall::*;
+use core::arch::asm;
+use core::assert;
+
+// 用户栈只有一页，备用信号栈放在bss里
+const ALTSTACK_SIZE: usize = 8192;
+static mut ALTSTACK: [u8; ALTSTACK_SIZE] = [0; ALTSTACK_SIZE];
+
+// 处理函数的执行顺序，用来检查嵌套
+static mut TRACE: [usize; 4] = [0; 4];
+static mut TRACE_LEN: usize = 0;
+// 处理函数中局部变量的地址、看到的备用信号栈状态
+static mut HANDLER_SP: usize = 0;
+static mut ALT_FLAGS: i32 = 0;
+static mut ALT_SET_RET: INT = 0;
+// ucontext中保存的信号掩码、备用信号栈状态和pc
+static mut UC_MASK: Signal = Signal::empty();
+static mut UC_STACK_FLAGS: i32 = 0;
+static mut UC_PC: usize = 0;
+
+fn trace(sig: usize) {
+    unsafe {
+        TRACE[TRACE_LEN] = sig;
+        TRACE_LEN += 1;
+    }
+}
+
+fn altstack_range() -> (usize, usize) {
+    let start = unsafe { ALTSTACK.as_ptr() as usize };
+    (start, start + ALTSTACK_SIZE)
+}
+
+// 在SIGUSR1的处理函数中收到SIGUSR2，处理函数嵌套执行
+extern "C" fn outer_handler(sig: usize) {
+    trace(sig);
+    let me = syscall_getpid() as INT;
+    syscall_kill(me, Signal::SIGUSR2);
+    trace(sig);
+}
+
+extern "C" fn inner_handler(sig: usize) {
+    let local = 0usize;
+    unsafe {
+        HANDLER_SP = &local as *const _ as usize;
+        let mut old = SignalStack::default();
+        syscall_sigaltstack(None, Some(&mut old));
+        ALT_FLAGS = old.ss_flags;
+        // 正在备用信号栈上执行时不能修改
+        let ss = SignalStack { ss_sp: 0, ss_flags: SS_DISABLE, ss_size: 0 };
+        ALT_SET_RET = syscall_sigaltstack(Some(&ss), None);
+    }
+    trace(sig);
+}
+
+extern "C" fn context_handler(_sig: usize, _info: &SigInfo, uc: &UContext) {
+    unsafe {
+        UC_MASK = uc.uc_sigmask;
+        UC_STACK_FLAGS = uc.uc_stack.ss_flags;
+        UC_PC = uc.uc_mcontext.gregs[0];
+    }
+}
+
+fn bind(signal: Signal, handler: usize, flags: SaFlags) {
+    let sa = rt_sigaction {
+        sa_handler: handler,
+        sa_flags: flags.bits(),
+        sa_mask: 0,
+    };
+    let mut old = rt_sigaction::default();
+    assert!(syscall_sigaction(signal, &sa, &mut old) == 0);
+}
+
+fn main() {
+    let me = syscall_getpid() as INT;
+
+    // 处理函数直接返回，通过内核的跳板调用sigreturn，ucontext保存了被中断时的掩码
+    bind(Signal::SIGUSR1, context_handler as usize, SaFlags::SA_SIGINFO);
+    let mut oldset = Signal::empty();
+    assert!(syscall_sigprocmask(SIG_BLOCK, &Signal::SIGALRM, &mut oldset) == 0);
+    let x = 6.0f64;
+    assert!(syscall_kill(me, Signal::SIGUSR1) == 0);
+    assert!(unsafe { UC_MASK } == Signal::SIGALRM);
+    assert!(unsafe { UC_STACK_FLAGS } == SS_DISABLE);
+    assert!(unsafe { UC_PC } != 0);
+    assert!(x * 7.0 == 42.0);
+    // 处理函数返回后恢复原来的掩码
+    assert!(syscall_sigprocmask(SIG_SETMASK, &Signal::empty(), &mut oldset) == 0);
+    assert!(oldset == Signal::SIGALRM);
+
+    // 备用信号栈太小或者ss_flags无效
+    let (start, end) = altstack_range();
+    let mut ss = SignalStack { ss_sp: start, ss_flags: 0, ss_size: MINSIGSTKSZ - 1 };
+    assert!(syscall_sigaltstack(Some(&ss), None) == -ENOMEM);
+    ss.ss_flags = SS_ONSTACK;
+    ss.ss_size = ALTSTACK_SIZE;
+    assert!(syscall_sigaltstack(Some(&ss), None) == -EINVAL);
+    ss.ss_flags = 0;
+    assert!(syscall_sigaltstack(Some(&ss), None) == 0);
+
+    // 嵌套的处理函数，SA_ONSTACK在备用信号栈上执行
+    bind(Signal::SIGUSR1, outer_handler as usize, SaFlags::SA_ONSTACK);
+    bind(Signal::SIGUSR2, inner_handler as usize, SaFlags::SA_ONSTACK);
+    assert!(syscall_kill(me, Signal::SIGUSR1) == 0);
+    let u1 = Signal::SIGUSR1.signum();
+    let u2 = Signal::SIGUSR2.signum();
+    assert!(unsafe { TRACE_LEN } == 3);
+    assert!(unsafe { TRACE[..3] == [u1, u2, u1] });
+    let sp = unsafe { HANDLER_SP };
+    assert!(sp > start && sp < end);
+    assert!(unsafe { ALT_FLAGS } == SS_ONSTACK);
+    assert!(unsafe { ALT_SET_RET } == -EPERM);
+
+    // 回到原来的栈之后可以修改备用信号栈
+    let mut old = SignalStack::default();
+    assert!(syscall_sigaltstack(None, Some(&mut old)) == 0);
+    assert!(old.ss_flags == 0 && old.ss_sp == start && old.ss_size == ALTSTACK_SIZE);
+    ss.ss_flags = SS_DISABLE;
+    assert!(syscall_sigaltstack(Some(&ss), None) == 0);
+    assert!(syscall_sigaltstack(None, Some(&mut old)) == 0);
+    assert!(old.ss_flags == SS_DISABLE);
+
+    // sp不指向信号帧时sigreturn以SIGSEGV终止进程，屏蔽或者捕获SIGSEGV也一样
+    let child = syscall_fork();
+    if child == 0 {
+        bind(Signal::SIGSEGV, outer_handler as usize, SaFlags::empty());
+        let mut oldset = Signal::empty();
+        assert!(syscall_sigprocmask(SIG_BLOCK, &Signal::SIGSEGV, &mut oldset) == 0);
+        unsafe {
+            // 139为rt_sigreturn
+            asm!("mv sp, zero", "ecall", in("x17") 139, options(noreturn));
+        }
+    }
+    let mut wstatus = 0;
+    let mut rusage = Rusage::default();
+    assert!(syscall_wait4(child as isize, &mut wstatus, 0, &mut rusage) == child);
+    assert!(wifsignaled(wstatus) && wtermsig(wstatus) == Signal::SIGSEGV.signum() as i32);
+
+    println!("sigframe test passed");
+}
@@ -19,7 +19,8 @@ fn main() {
             sa_flags: SaFlags::empty().bits(),
             sa_mask: Signal::empty().bits(),
         };
-        syscall_sigaction(Signal::SIGCHLD, &sa,  &sa);
+        let mut old = rt_sigaction::default();
+        syscall_sigaction(Signal::SIGCHLD, &sa, &mut old);
         for i in 0..10 {
             syscall_yield();
         }
@@ -30,7 +31,7 @@ fn main() {
     }
 }
 
-extern "C" fn sig_handler(sig: Signal) {
-    println!("child dead {:?}",sig);
+extern "C" fn sig_handler(sig: usize) {
+    println!("child dead {}", sig);
     syscall_exit(0);
 }
\ No newline at end of file
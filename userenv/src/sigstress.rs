@@ -0,0 +1,124 @@
+#![no_std]
+#![no_main]
+
+mod syscall;
+mod runtime;
+mod console;
+
+use syscall::*;
+use core::assert;
+use core::ptr::read_volatile;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// 每个子进程收到的信号数
+const ROUNDS: usize = 200;
+const CHILDREN: usize = 4;
+
+// 子进程已经处理的信号数，si_value不连续时记录错误
+static mut COUNT: usize = 0;
+static mut DISORDER: bool = false;
+
+const STACK_SIZE: usize = 4096;
+static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+// 处理发送给进程的信号的线程
+static HANDLER_TID: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn count_handler(_sig: usize, info: &SigInfo, _uc: usize) {
+    unsafe {
+        if info.si_value() != COUNT {
+            DISORDER = true;
+        }
+        COUNT += 1;
+    }
+}
+
+extern "C" fn tid_handler(_sig: usize, _info: &SigInfo, _uc: usize) {
+    HANDLER_TID.store(syscall_gettid(), Ordering::SeqCst);
+}
+
+// 只有这个线程没有屏蔽SIGUSR1，在sigsuspend中等待信号
+extern "C" fn unblocked_main(_arg: usize) -> isize {
+    let mut oldset = Signal::empty();
+    assert!(syscall_sigprocmask(SIG_UNBLOCK, &Signal::SIGUSR1, &mut oldset) == 0);
+    while HANDLER_TID.load(Ordering::SeqCst) == 0 {
+        syscall_sigsuspend(&Signal::empty());
+    }
+    0
+}
+
+// 发送给进程的信号由没有屏蔽它的线程处理，主线程一直屏蔽SIGUSR1
+fn thread_target() {
+    let sa = rt_sigaction {
+        sa_handler: tid_handler as usize,
+        sa_flags: SaFlags::SA_SIGINFO.bits(),
+        sa_mask: 0,
+    };
+    let mut old = rt_sigaction::default();
+    assert!(syscall_sigaction(Signal::SIGUSR1, &sa, &mut old) == 0);
+    // fork之前屏蔽，信号在线程解除屏蔽之前到达时保持pending
+    let mut oldset = Signal::empty();
+    assert!(syscall_sigprocmask(SIG_BLOCK, &Signal::SIGUSR1, &mut oldset) == 0);
+    let child = syscall_fork();
+    if child == 0 {
+        let flags = CloneFlags::CLONE_VM | CloneFlags::CLONE_FS | CloneFlags::CLONE_FILES
+            | CloneFlags::CLONE_SIGHAND | CloneFlags::CLONE_THREAD;
+        let stack_top = unsafe { (STACK.as_ptr() as usize + STACK_SIZE) as *const u8 };
+        let tid = thread_create(flags, stack_top, 0, 0, 0, unblocked_main, 0);
+        assert!(tid > 0);
+        while HANDLER_TID.load(Ordering::SeqCst) == 0 {
+            syscall_yield();
+        }
+        syscall_exit(if HANDLER_TID.load(Ordering::SeqCst) == tid as usize { 0 } else { 1 });
+    }
+    assert!(syscall_kill(child, Signal::SIGUSR1) == 0);
+    let mut wstatus = 0;
+    let mut rusage = Rusage::default();
+    assert!(syscall_wait4(child as isize, &mut wstatus, 0, &mut rusage) == child);
+    assert!(wifexited(wstatus) && wexitstatus(wstatus) == 0);
+    assert!(syscall_sigprocmask(SIG_SETMASK, &Signal::empty(), &mut oldset) == 0);
+}
+
+fn main() {
+    let sa = rt_sigaction {
+        sa_handler: count_handler as usize,
+        sa_flags: SaFlags::SA_SIGINFO.bits(),
+        sa_mask: 0,
+    };
+    let mut old = rt_sigaction::default();
+    assert!(syscall_sigaction(Signal::rt(0), &sa, &mut old) == 0);
+
+    // 子进程在用户态忙等，不进入内核，多核时可能运行在其他hart上
+    let mut children = [0; CHILDREN];
+    for child in children.iter_mut() {
+        *child = syscall_fork();
+        if *child == 0 {
+            while unsafe { read_volatile(&COUNT) } < ROUNDS {}
+            let ordered = unsafe { !read_volatile(&DISORDER) };
+            syscall_exit(if ordered { ROUNDS as INT } else { 1 });
+        }
+    }
+
+    // 轮流向每个子进程发送实时信号，队列满时让出hart等待子进程处理
+    for value in 0..ROUNDS {
+        for child in children.iter() {
+            loop {
+                match syscall_sigqueue(*child, Signal::rt(0), value) {
+                    0 => break,
+                    ret => assert!(ret == -EAGAIN),
+                }
+                syscall_yield();
+            }
+        }
+    }
+
+    for child in children.iter() {
+        let mut wstatus = 0;
+        let mut rusage = Rusage::default();
+        assert!(syscall_wait4(*child as isize, &mut wstatus, 0, &mut rusage) == *child);
+        assert!(wifexited(wstatus) && wexitstatus(wstatus) == ROUNDS as i32);
+    }
+
+    thread_target();
+
+    println!("sigstress test passed");
+}
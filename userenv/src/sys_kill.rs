@@ -27,14 +27,14 @@ fn main() {
             sa_flags: SaFlags::empty().bits(),
             sa_mask: Signal::empty().bits(),
         };
-        syscall_sigaction(Signal::SIGUSR2, &sa,  &sa);
+        let mut old = rt_sigaction::default();
+        syscall_sigaction(Signal::SIGUSR2, &sa, &mut old);
         loop {}
     }
 }
 
-extern "C" fn sig_handler(sig: Signal) {
+extern "C" fn sig_handler(sig: usize) {
     for i in 0..5 {
         println!("Hello world")
     }
-    syscall_sigreturn();
 }
\ No newline at end of file
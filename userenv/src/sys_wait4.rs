@@ -13,7 +13,7 @@ fn main() {
     if forkret > 0 {
         println!("sys_wait4 waiting all childrent");
         let mut wstatus = 0;
-        let mut rusage = 0;
+        let mut rusage = Rusage::default();
         let childpid = syscall_wait4(-1, &mut wstatus, 0, &mut rusage);
         if childpid == forkret {
             println!("sys_wait4 waited child pid is right");
@@ -1,8 +1,6 @@
 #![allow(dead_code)]
 use core::arch::asm;
 use crate::println;
-use core::mem::size_of;
-use core::slice::from_raw_parts_mut;
 
 pub type INT = i32;
 
@@ -36,15 +34,27 @@ const SYSCALL_UTIMENSAT:usize = 88;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_EXIT_GRUOP: usize = 94;
 const SYSCALL_SET_TID_ADDRESS: usize = 96;
+const SYSCALL_FUTEX: usize = 98;
 const SYSCALL_NANOSLEEP: usize = 101;
 const SYSCALL_GETITIMER: usize = 102;
 const SYSCALL_SETITIMER: usize = 103;
 const SYSCALL_CLOCK_GETTIME: usize = 113;
+const SYSCALL_SCHED_SETAFFINITY: usize = 122;
+const SYSCALL_SCHED_GETAFFINITY: usize = 123;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_KILL: usize = 129;
+const SYSCALL_SIGALTSTACK: usize = 132;
+const SYSCALL_SIGSUSPEND: usize = 133;
 const SYSCALL_SIGACTION: usize = 134;
+const SYSCALL_SIGPROCMASK: usize = 135;
+const SYSCALL_SIGPENDING: usize = 136;
+const SYSCALL_SIGQUEUEINFO: usize = 138;
 const SYSCALL_SIGRETURN: usize = 139;
 const SYSCALL_TIMES: usize = 153;
+const SYSCALL_SETPGID: usize = 154;
+const SYSCALL_GETPGID: usize = 155;
+const SYSCALL_GETSID: usize = 156;
+const SYSCALL_SETSID: usize = 157;
 const SYSCALL_UNAME: usize = 160;
 const SYSCALL_GETRUSAGE: usize = 165;
 const SYSCALL_GET_TIME_OF_DAY: usize = 169;
@@ -54,7 +64,7 @@ const SYSCALL_GETUID: usize = 174;
 const SYSCALL_GETEUID: usize = 175;
 const SYSCALL_GETGID: usize = 176;
 const SYSCALL_GETEGID: usize = 177;
-const SYSCALL_GETTID: usize = 177;
+const SYSCALL_GETTID: usize = 178;
 const SYSCALL_SBRK: usize = 213;
 const SYSCALL_BRK: usize = 214;
 const SYSCALL_MUNMAP: usize = 215;
@@ -73,6 +83,25 @@ const SYSCALL_SHUTDOWN: usize = 501;
 const SYSCALL_CLEAR: usize = 502;
 
 pub const AT_FDCWD: INT = -100;
+pub const AT_REMOVEDIR: usize = 0x200;
+
+// 系统调用失败时返回-errno
+pub const ENOENT: INT = 2;
+pub const EBADF: INT = 9;
+pub const EEXIST: INT = 17;
+pub const ENOTDIR: INT = 20;
+pub const EISDIR: INT = 21;
+pub const ENODEV: INT = 19;
+pub const EAGAIN: INT = 11;
+pub const EINVAL: INT = 22;
+pub const ECHILD: INT = 10;
+pub const EINTR: INT = 4;
+pub const EPERM: INT = 1;
+pub const ESRCH: INT = 3;
+pub const ENOMEM: INT = 12;
+pub const ETIMEDOUT: INT = 110;
+pub const ENOTEMPTY: INT = 39;
+pub const ENOSYS: INT = 38;
 bitflags! {
     // 表示openat(2) 中的flags
     pub struct OpenFlags: usize {
@@ -81,9 +110,9 @@ bitflags! {
         const RDWR = 1 << 1;
         const CREATE = 1 << 6;
         const TRUNC = 1 << 10;
-        const DIRECTROY = 0200000;
-        const LARGEFILE  = 0100000;
-        const CLOEXEC = 02000000;
+        const DIRECTROY = 0o200000;
+        const LARGEFILE  = 0o100000;
+        const CLOEXEC = 0o2000000;
     }
     // 表示openat(2) 中的mode_t
     pub struct FileMode: usize {
@@ -114,14 +143,12 @@ pub fn ls(path: &str) {
             println!("EOF");
             return;
         }
-        if nread == -1 {
+        if nread < 0 {
             println!("error");
             return ;
         }
-        let nread = nread as usize;
-        let dirents = unsafe {from_raw_parts_mut(&mut buf as *mut _ as *mut LinuxDirent, nread / size_of::<LinuxDirent>())};
-        for i in 0..(nread/size_of::<LinuxDirent>()) {
-            println!("dirent: {}", unsafe { core::str::from_utf8_unchecked(&dirents[i].d_name)});
+        for (_, name) in dirents(&buf[..nread as usize]) {
+            println!("dirent: {}", name);
         }
     }
 
@@ -138,37 +165,46 @@ pub fn syscall_getcwd(buf: &mut [u8]) -> INT {
     a0 as INT
 }
 
-const PATH_LIMITS: usize = 512;
+// linux_dirent64的头部，d_name紧跟在d_type之后，以0结尾，整个记录按8字节对齐
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct LinuxDirent {
-    pub d_ino: usize,
-    pub d_off: isize,
+    pub d_ino: u64,
+    pub d_off: i64,
     pub d_reclen: u16,
-    pub d_type: u8,                // linux manual中d_type应该在d_name后面?
-    pub d_name: [u8; PATH_LIMITS]  // 使用固定的name长度
+    pub d_type: u8,
 }
 
 pub const DT_UNKNOWN:u8 = 0;
 pub const DT_DIR:u8 = 4;
-pub const DT_REG:u8 = 4; //常规文件
+pub const DT_REG:u8 = 8; //常规文件
 
-impl LinuxDirent {
-    pub fn new() -> Self {
-        Self {
-            d_ino: 0,
-            d_off: 0,
-            d_reclen: 0,
-            d_type: 0,
-            d_name: [0; PATH_LIMITS]
-        }
-    }
+// d_name在记录中的偏移
+const DIRENT_NAME_OFFSET: usize = 19;
+
+// 遍历getdents64返回的变长目录项
+pub struct Dirents<'a> {
+    buf: &'a [u8],
+}
+
+pub fn dirents(buf: &[u8]) -> Dirents {
+    Dirents { buf }
+}
 
-    pub fn fill(&mut self, other: &Self) {
-        self.d_ino = other.d_ino;
-        self.d_off= other.d_off;
-        self.d_reclen= other.d_reclen;
-        self.d_type = other.d_type;
-        self.d_name.copy_from_slice(&other.d_name);
+impl<'a> Iterator for Dirents<'a> {
+    type Item = (LinuxDirent, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.len() < DIRENT_NAME_OFFSET {
+            return None;
+        }
+        // 缓冲区不一定按8字节对齐
+        let dirent = unsafe { (self.buf.as_ptr() as *const LinuxDirent).read_unaligned() };
+        let record = &self.buf[..dirent.d_reclen as usize];
+        let name = &record[DIRENT_NAME_OFFSET..];
+        let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        self.buf = &self.buf[dirent.d_reclen as usize..];
+        Some((dirent, unsafe { core::str::from_utf8_unchecked(&name[..len]) }))
     }
 }
 
@@ -194,6 +230,31 @@ pub fn syscall_chdir(buf: &str) -> INT {
     a0 as INT
 }
 
+pub fn syscall_mount(source: &str, target: &str, fstype: &str, flags: usize) -> INT {
+    let mut a0 = source.as_ptr() as usize;
+    unsafe {
+        asm!("ecall", inout("x10") a0,
+            in("x11") target.as_ptr() as usize,
+            in("x12") fstype.as_ptr() as usize,
+            in("x13") flags,
+            in("x14") 0,
+            in("x17") SYSCALL_MOUNT
+        )
+    }
+    a0 as INT
+}
+
+pub fn syscall_umount2(target: &str, flags: usize) -> INT {
+    let mut a0 = target.as_ptr() as usize;
+    unsafe {
+        asm!("ecall", inout("x10") a0,
+            in("x11") flags,
+            in("x17") SYSCALL_UMOUNT2
+        )
+    }
+    a0 as INT
+}
+
 pub fn syscall_linkat(olddirfd: INT, oldpath: &str, newdirfd: INT, newpath: &str, flags: usize) -> INT {
     let mut a0 = olddirfd as usize;
     unsafe {
@@ -289,6 +350,16 @@ pub fn syscall_close(fd: INT) -> INT {
 
 }
 
+pub fn syscall_fsync(fd: INT) -> INT {
+    let mut a0 = fd as usize;
+    unsafe {
+        asm!("ecall", inout("x10") a0,
+            in("x17") SYSCALL_FSYNC
+        )
+    }
+    a0 as INT
+}
+
 pub const SEEK_SET: usize = 0;
 pub const SEEK_CUR : usize = 1;
 pub const SEEK_END : usize = 2;
@@ -336,6 +407,32 @@ pub fn syscall_exit(xcode: isize) -> !{
     loop {}
 }
 
+// mask的第n位对应hartid为n的hart
+pub fn syscall_sched_setaffinity(pid: INT, mask: usize) -> INT {
+    let mut a0 = pid as isize;
+    unsafe {
+        asm!("ecall", inout("x10") a0,
+            in("x11") core::mem::size_of::<usize>(),
+            in("x12") &mask as *const _ as usize,
+            in("x17") SYSCALL_SCHED_SETAFFINITY
+        )
+    }
+    a0 as INT
+}
+
+// 成功时返回写入的字节数
+pub fn syscall_sched_getaffinity(pid: INT, mask: &mut usize) -> INT {
+    let mut a0 = pid as isize;
+    unsafe {
+        asm!("ecall", inout("x10") a0,
+            in("x11") core::mem::size_of::<usize>(),
+            in("x12") mask as *mut _ as usize,
+            in("x17") SYSCALL_SCHED_GETAFFINITY
+        )
+    }
+    a0 as INT
+}
+
 pub fn syscall_yield() {
     unsafe {
         asm!("ecall",in("x17") SYSCALL_YIELD);
@@ -353,19 +450,83 @@ pub fn syscall_fork() -> INT {
 bitflags! {
     pub struct CloneFlags: usize{
         const SIGCHLD = 17;
+        const CLONE_VM = 0x00000100;
+        const CLONE_FS = 0x00000200;
+        const CLONE_FILES = 0x00000400;
+        const CLONE_SIGHAND = 0x00000800;
+        const CLONE_THREAD = 0x00010000;
+        const CLONE_SYSVSEM = 0x00040000;
+        const CLONE_SETTLS = 0x00080000;
+        const CLONE_PARENT_SETTID = 0x00100000;
         const CLONE_CHILD_CLEARTID = 0x00200000;
         const CLONE_CHILD_SETTID = 0x01000000;
     }
 }
 
+// 创建线程，子线程在stack_top上运行f(arg)，f返回后子线程退出
+// 子线程不能返回到调用者的栈帧，所以在汇编中完成clone之后的跳转
+pub fn thread_create(flags: CloneFlags, stack_top: *const u8, ptid: usize, tls: usize, ctid: usize,
+                     f: extern "C" fn(usize) -> isize, arg: usize) -> INT {
+    let mut a0 = flags.bits() as usize;
+    unsafe {
+        asm!("ecall",
+             "bnez a0, 2f",
+             "mv a0, t1",
+             "jalr t0",
+             "li a7, 93",
+             "ecall",
+             "2:",
+             inout("x10") a0,
+             in("x11") stack_top as usize,
+             in("x12") ptid,
+             in("x13") tls,
+             in("x14") ctid,
+             in("x17") SYSCALL_CLONE,
+             in("x5") f as usize,
+             in("x6") arg);
+    }
+    a0 as INT
+}
+
+pub fn get_tp() -> usize {
+    let tp;
+    unsafe {
+        asm!("mv {}, tp", out(reg) tp);
+    }
+    tp
+}
+
+pub fn set_tp(tp: usize) {
+    unsafe {
+        asm!("mv tp, {}", in(reg) tp);
+    }
+}
+
+pub fn syscall_gettid() -> usize {
+    let mut ret = 0;
+    unsafe {
+        asm!("ecall",inout("x10") ret, in("x17") SYSCALL_GETTID);
+    }
+    ret
+}
+
+// 线程退出时将tidptr处的tid清零，返回当前线程的tid
+pub fn syscall_set_tid_address(tidptr: *const u32) -> usize {
+    let mut a0 = tidptr as usize;
+    unsafe {
+        asm!("ecall", inout("x10") a0, in("x17") SYSCALL_SET_TID_ADDRESS);
+    }
+    a0
+}
+
 pub fn syscall_clone(flags: CloneFlags, stack_top: *const u8, ptid: usize, ctid: usize, newtls: usize) -> INT {
     let mut a0 = flags.bits() as usize;
     unsafe {
         asm!("ecall", inout("x10") a0,
          in("x11") stack_top as usize,
          in("x12") ptid,
-         in("x13") ctid,
-         in("x14") newtls,
+         in("x13") newtls,
+         in("x14") ctid,
          in("x17") SYSCALL_CLONE);
     }
     a0 as INT
@@ -392,7 +553,117 @@ pub fn syscall_getpid() -> usize {
     ret
 }
 
-pub fn syscall_wait4(pid: isize, wstatus: &mut isize, options: usize, rusage: &mut usize) -> INT {
+pub fn syscall_getppid() -> usize {
+    let mut ret = 0;
+    unsafe {
+        asm!("ecall",inout("x10") ret, in("x17") SYSCALL_GETPPID);
+    }
+    ret
+}
+
+pub const WNOHANG: usize = 1;
+pub const WUNTRACED: usize = 2;
+pub const WCONTINUED: usize = 8;
+pub const WNOTHREAD: usize = 0x20000000;
+pub const WALL: usize = 0x40000000;
+pub const WCLONE: usize = 0x80000000;
+
+// 解析wait4的wstatus
+pub fn wifexited(wstatus: i32) -> bool {
+    wstatus & 0x7f == 0
+}
+
+pub fn wexitstatus(wstatus: i32) -> i32 {
+    (wstatus >> 8) & 0xff
+}
+
+pub fn wifstopped(wstatus: i32) -> bool {
+    wstatus & 0xff == 0x7f
+}
+
+pub fn wstopsig(wstatus: i32) -> i32 {
+    (wstatus >> 8) & 0xff
+}
+
+pub fn wifcontinued(wstatus: i32) -> bool {
+    wstatus == 0xffff
+}
+
+pub fn wifsignaled(wstatus: i32) -> bool {
+    !wifexited(wstatus) && !wifstopped(wstatus) && !wifcontinued(wstatus)
+}
+
+pub fn wtermsig(wstatus: i32) -> i32 {
+    wstatus & 0x7f
+}
+
+pub fn wcoredump(wstatus: i32) -> bool {
+    wstatus & 0x80 != 0
+}
+
+// pid为0表示当前进程，pgid为0表示使用pid
+pub fn syscall_setpgid(pid: INT, pgid: INT) -> INT {
+    let mut a0 = pid as isize;
+    unsafe {
+        asm!("ecall", inout("x10") a0,
+            in("x11") pgid as isize,
+            in("x17") SYSCALL_SETPGID
+        )
+    }
+    a0 as INT
+}
+
+pub fn syscall_getpgid(pid: INT) -> INT {
+    let mut a0 = pid as isize;
+    unsafe {
+        asm!("ecall", inout("x10") a0, in("x17") SYSCALL_GETPGID);
+    }
+    a0 as INT
+}
+
+pub fn syscall_setsid() -> INT {
+    let a0: isize;
+    unsafe {
+        asm!("ecall", out("x10") a0, in("x17") SYSCALL_SETSID);
+    }
+    a0 as INT
+}
+
+pub fn syscall_getsid(pid: INT) -> INT {
+    let mut a0 = pid as isize;
+    unsafe {
+        asm!("ecall", inout("x10") a0, in("x17") SYSCALL_GETSID);
+    }
+    a0 as INT
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct TimeVal {
+    pub tv_sec: usize,
+    pub tv_usec: usize
+}
+
+pub fn syscall_gettimeofday(tv: &mut TimeVal) -> INT {
+    let mut a0 = tv as *mut _ as usize;
+    unsafe {
+        asm!("ecall", inout("x10") a0,
+            in("x11") 0,
+            in("x17") SYSCALL_GET_TIME_OF_DAY
+        )
+    }
+    a0 as INT
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct Rusage {
+    pub ru_utime: TimeVal,
+    pub ru_stime: TimeVal,
+    pub ru_others: [usize; 14]
+}
+
+pub fn syscall_wait4(pid: isize, wstatus: &mut i32, options: usize, rusage: &mut Rusage) -> INT {
     let mut pid = pid as usize;
     unsafe {
         asm!("ecall", inout("x10") pid, 
@@ -424,7 +695,9 @@ pub fn syscall_brk(addr: *const u8) -> *mut u8{
     addr as *mut u8
 }
 
-pub const SIGTMIN: usize = 32;
+// 实时信号的编号范围
+pub const SIGRTMIN: usize = 32;
+pub const SIGRTMAX: usize = 64;
 use bitflags::bitflags;
 bitflags!{
     pub struct Signal: usize{
@@ -458,6 +731,10 @@ bitflags!{
         const	SIGPROF		= 1 << (27-1);	
         const	SIGWINCH	= 1 << (28-1);	
         const	SIGIO		= 1 << (29-1);	
+        const	SIGPWR		= 1 << (30-1);
+        const	SIGSYS		= 1 << (31-1);
+        // 所有实时信号
+        const	SIGRT		= !0 << (SIGRTMIN-1);
     }
     pub struct SaFlags: usize{
         const SA_NOCLDSTOP = 1		   ;     /* Don't send SIGCHLD when children stop.  */
@@ -470,34 +747,206 @@ bitflags!{
         const SA_INTERRUPT = 0x20000000;    /* Historical no-op.  */
     }
 }
-pub fn syscall_kill(mut pid: INT, sig: Signal) -> INT {
+impl Signal {
+    // 系统调用使用信号的编号
+    pub fn signum(&self) -> usize {
+        self.bits().trailing_zeros() as usize + 1
+    }
+
+    // 实时信号SIGRTMIN+n
+    pub fn rt(n: usize) -> Signal {
+        Signal::from_bits_truncate(1 << (SIGRTMIN + n - 1))
+    }
+}
+
+// pid为0或负数时发送给进程组
+pub fn syscall_kill(pid: INT, sig: Signal) -> INT {
+    let mut pid = pid as isize;
     unsafe {
         asm!("ecall", inout("x10") pid,
-            in("x11") sig.bits(),
+            in("x11") sig.signum(),
             in("x17") SYSCALL_KILL
         )
     }
     pid as INT
 }
 
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
 #[repr(C)]
+#[derive(Default, Clone, Copy)]
 pub struct rt_sigaction {
     pub sa_handler: usize,
     pub sa_flags: usize,
     pub sa_mask: usize
 }
 
-pub fn syscall_sigaction(signal: Signal, act: &rt_sigaction, old: &rt_sigaction) -> INT {
-    let mut a0 = signal.bits();
+pub fn syscall_sigaction(signal: Signal, act: &rt_sigaction, old: &mut rt_sigaction) -> INT {
+    let mut a0 = signal.signum();
     unsafe {
         asm!("ecall", inout("x10") a0,
             in("x11") act as *const _ as usize,
-            in("x12") old as *const _ as usize,
+            in("x12") old as *mut _ as usize,
             in("x17") SYSCALL_SIGACTION
         )
     }
     a0 as INT
 }
+
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+pub fn syscall_sigprocmask(how: usize, set: &Signal, oldset: &mut Signal) -> INT {
+    let mut a0 = how;
+    unsafe {
+        asm!("ecall", inout("x10") a0,
+            in("x11") set as *const _ as usize,
+            in("x12") oldset as *mut _ as usize,
+            in("x13") core::mem::size_of::<Signal>(),
+            in("x17") SYSCALL_SIGPROCMASK
+        )
+    }
+    a0 as INT
+}
+
+pub fn syscall_sigpending(set: &mut Signal) -> INT {
+    let mut a0 = set as *mut _ as usize;
+    unsafe {
+        asm!("ecall", inout("x10") a0,
+            in("x11") core::mem::size_of::<Signal>(),
+            in("x17") SYSCALL_SIGPENDING
+        )
+    }
+    a0 as INT
+}
+
+pub fn syscall_sigsuspend(mask: &Signal) -> INT {
+    let mut a0 = mask as *const _ as usize;
+    unsafe {
+        asm!("ecall", inout("x10") a0,
+            in("x11") core::mem::size_of::<Signal>(),
+            in("x17") SYSCALL_SIGSUSPEND
+        )
+    }
+    a0 as INT
+}
+
+pub const SI_USER: i32 = 0;
+pub const SI_QUEUE: i32 = -1;
+pub const CLD_EXITED: i32 = 1;
+pub const CLD_KILLED: i32 = 2;
+pub const CLD_DUMPED: i32 = 3;
+pub const CLD_STOPPED: i32 = 5;
+pub const CLD_CONTINUED: i32 = 6;
+pub const ILL_ILLOPC: i32 = 1;
+pub const SEGV_MAPERR: i32 = 1;
+pub const SEGV_ACCERR: i32 = 2;
+pub const TRAP_BRKPT: i32 = 1;
+
+// siginfo_t，SA_SIGINFO的处理函数的第二个参数
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct SigInfo {
+    pub si_signo: i32,
+    pub si_errno: i32,
+    pub si_code: i32,
+    _pad: i32,
+    pub si_pid: i32,
+    pub si_uid: u32,
+    pub si_status: i32,
+    _reserved: [i32; 25],
+}
+
+impl SigInfo {
+    pub fn new(sig: Signal, code: i32, pid: INT) -> Self {
+        Self {
+            si_signo: sig.signum() as i32,
+            si_code: code,
+            si_pid: pid,
+            ..Default::default()
+        }
+    }
+
+    // SIGSEGV等信号出错的地址，与si_pid、si_uid位于相同的位置
+    pub fn si_addr(&self) -> usize {
+        self.si_pid as u32 as usize | (self.si_uid as usize) << 32
+    }
+
+    // sigqueue携带的数据，与si_status位于相同的位置
+    pub fn si_value(&self) -> usize {
+        self.si_status as u32 as usize | (self._reserved[0] as u32 as usize) << 32
+    }
+
+    pub fn set_si_value(&mut self, value: usize) {
+        self.si_status = value as u32 as i32;
+        self._reserved[0] = (value >> 32) as u32 as i32;
+    }
+}
+
+// 发送用户填写的siginfo，si_code必须为负数，除非发送给自己
+pub fn syscall_sigqueueinfo(pid: INT, sig: Signal, info: &SigInfo) -> INT {
+    let mut a0 = pid as isize;
+    unsafe {
+        asm!("ecall", inout("x10") a0,
+            in("x11") sig.signum(),
+            in("x12") info as *const _ as usize,
+            in("x17") SYSCALL_SIGQUEUEINFO
+        )
+    }
+    a0 as INT
+}
+
+// sigqueue(3)，实时信号排队，处理函数通过si_value得到value
+pub fn syscall_sigqueue(pid: INT, sig: Signal, value: usize) -> INT {
+    let mut info = SigInfo::new(sig, SI_QUEUE, syscall_getpid() as INT);
+    info.set_si_value(value);
+    syscall_sigqueueinfo(pid, sig, &info)
+}
+
+// stack_t
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct SignalStack {
+    pub ss_sp: usize,
+    pub ss_flags: i32,
+    pub ss_size: usize,
+}
+
+pub const SS_ONSTACK: i32 = 1;
+pub const SS_DISABLE: i32 = 2;
+pub const MINSIGSTKSZ: usize = 2048;
+
+// ucontext_t，SA_SIGINFO的处理函数的第三个参数，这里只列出用到的部分
+#[repr(C)]
+pub struct UContext {
+    pub uc_flags: usize,
+    pub uc_link: usize,
+    pub uc_stack: SignalStack,
+    pub uc_sigmask: Signal,
+    _unused: [u8; 120],
+    pub uc_mcontext: MContext,
+}
+
+// gregs[0]为被中断时的pc
+#[repr(C, align(16))]
+pub struct MContext {
+    pub gregs: [usize; 32],
+}
+
+pub fn syscall_sigaltstack(ss: Option<&SignalStack>, old_ss: Option<&mut SignalStack>) -> INT {
+    let mut a0 = ss.map_or(0, |ss| ss as *const _ as usize);
+    unsafe {
+        asm!("ecall", inout("x10") a0,
+            in("x11") old_ss.map_or(0, |old_ss| old_ss as *mut _ as usize),
+            in("x17") SYSCALL_SIGALTSTACK
+        )
+    }
+    a0 as INT
+}
+
+// 信号处理函数返回时由内核的跳板调用，处理函数不需要直接调用
 pub fn syscall_sigreturn() {
     unsafe {
         asm!("ecall", in("x17") SYSCALL_SIGRETURN
@@ -541,4 +990,28 @@ pub fn syscall_nanosleep(sec: usize, nsec: usize) -> INT {
         )
     }
     a0 as INT
-}
\ No newline at end of file
+}
+
+pub const FUTEX_WAIT: usize = 0;
+pub const FUTEX_WAKE: usize = 1;
+pub const FUTEX_REQUEUE: usize = 3;
+pub const FUTEX_CMP_REQUEUE: usize = 4;
+pub const FUTEX_PRIVATE_FLAG: usize = 128;
+pub const FUTEX_CLOCK_REALTIME: usize = 256;
+
+// timeout是TimeSpec的地址，requeue时表示移动的等待者个数
+pub fn syscall_futex(uaddr: *const u32, op: usize, val: u32, timeout: usize,
+                     uaddr2: *const u32, val3: u32) -> INT {
+    let mut a0 = uaddr as usize;
+    unsafe {
+        asm!("ecall", inout("x10") a0,
+            in("x11") op,
+            in("x12") val as usize,
+            in("x13") timeout,
+            in("x14") uaddr2 as usize,
+            in("x15") val3 as usize,
+            in("x17") SYSCALL_FUTEX
+        )
+    }
+    a0 as INT
+}
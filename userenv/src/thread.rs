@@ -0,0 +1,267 @@
+#![no_std]
+#![no_main]
+
+mod syscall;
+mod runtime;
+mod console;
+
+use syscall::*;
+use core::assert;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+const STACK_SIZE: usize = 4096;
+static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+// 线程的tid，由CLONE_PARENT_SETTID写入，线程退出时由CLONE_CHILD_CLEARTID清零
+static TID: AtomicU32 = AtomicU32::new(0);
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+static THREAD_FD: AtomicUsize = AtomicUsize::new(0);
+// 线程的tp由CLONE_SETTLS设置
+static THREAD_TLS: usize = 0x1234;
+static MAIN_TLS: usize = 0x5678;
+// 子进程的主线程退出时由内核清零
+static LEADER_TID: AtomicU32 = AtomicU32::new(0);
+static mut PIPES: [[i32; 2]; 2] = [[0; 2]; 2];
+// 线程创建的子进程，线程在RELEASE之后退出
+static FORKED: AtomicUsize = AtomicUsize::new(0);
+static RELEASE: AtomicUsize = AtomicUsize::new(0);
+// fork时一直写入的线程，STOP之后退出
+static WRITES: AtomicUsize = AtomicUsize::new(0);
+static STOP: AtomicUsize = AtomicUsize::new(0);
+
+// 写时复制之后两个线程在同一个页面上通信
+#[repr(C, align(4096))]
+struct CowPage {
+    ping: AtomicUsize,
+    pong: AtomicUsize,
+}
+static COW_PAGE: CowPage = CowPage { ping: AtomicUsize::new(0), pong: AtomicUsize::new(0) };
+
+extern "C" fn thread_main(arg: usize) -> isize {
+    // 与主线程在同一个进程中，但tid不同
+    assert!(syscall_gettid() != syscall_getpid());
+    assert!(syscall_gettid() == TID.load(Ordering::SeqCst) as usize);
+    assert!(get_tp() == &THREAD_TLS as *const _ as usize);
+    COUNTER.fetch_add(arg, Ordering::SeqCst);
+    // 共享文件描述符表
+    let fd = syscall_openat(AT_FDCWD, "/thread_file\0", OpenFlags::CREATE | OpenFlags::RDWR, FileMode::empty());
+    assert!(fd > 0);
+    // 系统调用不会改变tp
+    assert!(get_tp() == &THREAD_TLS as *const _ as usize);
+    THREAD_FD.store(fd as usize, Ordering::SeqCst);
+    0
+}
+
+// 主线程退出后继续运行，通知父进程之后等待父进程的回复
+extern "C" fn orphan_main(_arg: usize) -> isize {
+    while LEADER_TID.load(Ordering::SeqCst) != 0 {
+        syscall_yield();
+    }
+    let (ping, pong) = unsafe { (PIPES[0], PIPES[1]) };
+    let mut buf = [0u8; 1];
+    assert!(syscall_write(ping[1], &buf) == 1);
+    assert!(syscall_read(pong[0], &mut buf) == 1);
+    0
+}
+
+// 创建子进程之后等待主线程检查__WNOTHREAD
+extern "C" fn fork_main(_arg: usize) -> isize {
+    let ping = unsafe { PIPES[0] };
+    let child = syscall_fork();
+    if child == 0 {
+        let mut buf = [0u8; 1];
+        assert!(syscall_read(ping[0], &mut buf) == 1);
+        syscall_exit(5);
+    }
+    FORKED.store(child as usize, Ordering::SeqCst);
+    while RELEASE.load(Ordering::SeqCst) == 0 {
+        syscall_yield();
+    }
+    0
+}
+
+// 子进程属于整个进程，__WNOTHREAD只等待当前线程创建的子进程，线程退出后子进程交给其他线程
+fn wait_thread_child(flags: CloneFlags, stack_top: *const u8, tid_addr: usize, tls: usize) {
+    let ping = unsafe {
+        assert!(syscall_pipe(&mut PIPES[0]) == 0);
+        PIPES[0]
+    };
+    assert!(thread_create(flags, stack_top, tid_addr, tls, tid_addr, fork_main, 0) > 0);
+    while FORKED.load(Ordering::SeqCst) == 0 {
+        syscall_yield();
+    }
+    let child = FORKED.load(Ordering::SeqCst) as isize;
+    let mut wstatus = 0;
+    let mut rusage = Rusage::default();
+    assert!(syscall_wait4(child, &mut wstatus, WNOHANG, &mut rusage) == 0);
+    assert!(syscall_wait4(child, &mut wstatus, WNOHANG | WALL, &mut rusage) == 0);
+    assert!(syscall_wait4(child, &mut wstatus, WNOHANG | WNOTHREAD, &mut rusage) == -ECHILD);
+    // 子进程退出时发送SIGCHLD，不是clone子进程
+    assert!(syscall_wait4(child, &mut wstatus, WNOHANG | WCLONE, &mut rusage) == -ECHILD);
+    RELEASE.store(1, Ordering::SeqCst);
+    while TID.load(Ordering::SeqCst) != 0 {
+        syscall_yield();
+    }
+    assert!(syscall_wait4(child, &mut wstatus, WNOHANG | WNOTHREAD, &mut rusage) == 0);
+    let buf = [0u8; 1];
+    assert!(syscall_write(ping[1], &buf) == 1);
+    assert!(syscall_wait4(child, &mut wstatus, WNOTHREAD, &mut rusage) == child);
+    assert!(wifexited(wstatus) && wexitstatus(wstatus) == 5);
+    assert!(syscall_close(ping[0]) == 0 && syscall_close(ping[1]) == 0);
+}
+
+fn now_ms() -> usize {
+    let mut tv = TimeVal::default();
+    assert!(syscall_gettimeofday(&mut tv) == 0);
+    tv.tv_sec * 1000 + tv.tv_usec / 1000
+}
+
+fn join_thread() {
+    while TID.load(Ordering::SeqCst) != 0 {
+        syscall_yield();
+    }
+}
+
+// 不进入内核，一直使用当前hart上的映射
+extern "C" fn writer_main(_arg: usize) -> isize {
+    while STOP.load(Ordering::SeqCst) == 0 {
+        WRITES.fetch_add(1, Ordering::SeqCst);
+    }
+    0
+}
+
+// 其他hart上的线程一直在写，fork之后不能再写入子进程看到的页面
+fn fork_while_writing(flags: CloneFlags, stack_top: *const u8, tid_addr: usize, tls: usize) {
+    assert!(thread_create(flags, stack_top, tid_addr, tls, tid_addr, writer_main, 0) > 0);
+    while WRITES.load(Ordering::SeqCst) == 0 {
+        syscall_yield();
+    }
+    let child = syscall_fork();
+    if child == 0 {
+        let before = WRITES.load(Ordering::SeqCst);
+        for _ in 0..10 {
+            syscall_yield();
+        }
+        let mut x: usize = 0;
+        for i in 0..1000000 {
+            x = x.wrapping_add(i);
+            unsafe { core::ptr::write_volatile(&mut x, x) };
+        }
+        syscall_exit(if WRITES.load(Ordering::SeqCst) == before { 0 } else { 1 });
+    }
+    let mut wstatus = 0;
+    let mut rusage = Rusage::default();
+    assert!(syscall_wait4(child as isize, &mut wstatus, 0, &mut rusage) == child);
+    assert!(wifexited(wstatus) && wexitstatus(wstatus) == 0);
+    // 写时复制之后线程继续写入自己的页面
+    let writes = WRITES.load(Ordering::SeqCst);
+    while WRITES.load(Ordering::SeqCst) == writes {
+        syscall_yield();
+    }
+    STOP.store(1, Ordering::SeqCst);
+    join_thread();
+}
+
+// 不进入内核，主线程的写入复制了页面之后要能看到新的页面
+extern "C" fn echo_main(_arg: usize) -> isize {
+    while COW_PAGE.ping.load(Ordering::SeqCst) == 0 {}
+    COW_PAGE.pong.store(1, Ordering::SeqCst);
+    0
+}
+
+// 子进程共享页面时主线程写入，页面被复制，另一个hart上的线程不能继续读旧的页面
+//     时钟中断每秒一次，线程在中断时才重新映射会超时
+fn write_after_cow(flags: CloneFlags, stack_top: *const u8, tid_addr: usize, tls: usize) {
+    let mut pipe = [0i32; 2];
+    assert!(syscall_pipe(&mut pipe) == 0);
+    assert!(thread_create(flags, stack_top, tid_addr, tls, tid_addr, echo_main, 0) > 0);
+    let child = syscall_fork();
+    if child == 0 {
+        let mut buf = [0u8; 1];
+        assert!(syscall_read(pipe[0], &mut buf) == 1);
+        // 子进程的页面不受父进程写入的影响
+        let untouched = COW_PAGE.ping.load(Ordering::SeqCst) == 0 && COW_PAGE.pong.load(Ordering::SeqCst) == 0;
+        syscall_exit(if untouched { 0 } else { 1 });
+    }
+    COW_PAGE.ping.store(1, Ordering::SeqCst);
+    let start = now_ms();
+    while COW_PAGE.pong.load(Ordering::SeqCst) == 0 {
+        assert!(now_ms() - start < 500);
+        syscall_yield();
+    }
+    join_thread();
+    let buf = [0u8; 1];
+    assert!(syscall_write(pipe[1], &buf) == 1);
+    let mut wstatus = 0;
+    let mut rusage = Rusage::default();
+    assert!(syscall_wait4(child as isize, &mut wstatus, 0, &mut rusage) == child);
+    assert!(wifexited(wstatus) && wexitstatus(wstatus) == 0);
+    assert!(syscall_close(pipe[0]) == 0 && syscall_close(pipe[1]) == 0);
+}
+
+// 主线程exit之后进程还没有退出，最后一个线程退出后才能被wait4回收，退出码为主线程的退出码
+fn leader_exit(flags: CloneFlags, stack_top: *const u8) {
+    let (ping, pong) = unsafe {
+        assert!(syscall_pipe(&mut PIPES[0]) == 0 && syscall_pipe(&mut PIPES[1]) == 0);
+        (PIPES[0], PIPES[1])
+    };
+    let child = syscall_fork();
+    if child == 0 {
+        let tid = syscall_set_tid_address(&LEADER_TID as *const _ as *const u32);
+        LEADER_TID.store(tid as u32, Ordering::SeqCst);
+        let flags = flags - CloneFlags::CLONE_SETTLS - CloneFlags::CLONE_PARENT_SETTID - CloneFlags::CLONE_CHILD_CLEARTID;
+        assert!(thread_create(flags, stack_top, 0, 0, 0, orphan_main, 0) > 0);
+        // 只退出主线程
+        syscall_exit(3);
+    }
+    let mut buf = [0u8; 1];
+    assert!(syscall_read(ping[0], &mut buf) == 1);
+    let mut wstatus = 0;
+    let mut rusage = Rusage::default();
+    assert!(syscall_wait4(child as isize, &mut wstatus, WNOHANG, &mut rusage) == 0);
+    assert!(syscall_write(pong[1], &buf) == 1);
+    assert!(syscall_wait4(child as isize, &mut wstatus, 0, &mut rusage) == child);
+    assert!(wifexited(wstatus) && wexitstatus(wstatus) == 3);
+    for fd in [ping[0], ping[1], pong[0], pong[1]].iter() {
+        assert!(syscall_close(*fd) == 0);
+    }
+}
+
+fn main() {
+    let pid = syscall_getpid();
+    assert!(syscall_gettid() == pid);
+    let main_tls = &MAIN_TLS as *const _ as usize;
+    set_tp(main_tls);
+
+    let flags = CloneFlags::CLONE_VM | CloneFlags::CLONE_FS | CloneFlags::CLONE_FILES
+        | CloneFlags::CLONE_SIGHAND | CloneFlags::CLONE_THREAD | CloneFlags::CLONE_SYSVSEM
+        | CloneFlags::CLONE_SETTLS | CloneFlags::CLONE_PARENT_SETTID
+        | CloneFlags::CLONE_CHILD_CLEARTID;
+    let tls = &THREAD_TLS as *const _ as usize;
+    let tid_addr = &TID as *const _ as usize;
+    let stack_top = unsafe { (STACK.as_ptr() as usize + STACK_SIZE) as *const u8 };
+
+    // CLONE_THREAD需要CLONE_SIGHAND和CLONE_VM
+    assert!(thread_create(CloneFlags::CLONE_THREAD, stack_top, 0, 0, 0, thread_main, 1) == -EINVAL);
+
+    let tid = thread_create(flags, stack_top, tid_addr, tls, tid_addr, thread_main, 10);
+    assert!(tid > 0 && tid as usize != pid);
+    // 等待线程退出
+    while TID.load(Ordering::SeqCst) != 0 {
+        syscall_yield();
+    }
+    // 线程切换和系统调用不会改变主线程的tp
+    assert!(get_tp() == main_tls);
+    // 共享内存空间
+    assert!(COUNTER.load(Ordering::SeqCst) == 10);
+    let fd = THREAD_FD.load(Ordering::SeqCst) as INT;
+    let buf = "thread";
+    assert!(syscall_write(fd, buf.as_bytes()) == buf.len() as INT);
+    assert!(syscall_close(fd) == 0);
+    assert!(syscall_getpid() == pid);
+
+    wait_thread_child(flags, stack_top, tid_addr, tls);
+    fork_while_writing(flags, stack_top, tid_addr, tls);
+    write_after_cow(flags, stack_top, tid_addr, tls);
+    leader_exit(flags, stack_top);
+    println!("thread test passed");
+}
@@ -20,7 +20,7 @@ fn main() {
         let times = syscall_times(&mut tms);
         println!("(times, utime, stime, cutime, cstime): ({}, {}, {}, {}, {})", times, tms.utime, tms.stime, tms.cutime, tms.cstime);
         let mut xcode = 0;
-        let mut rusage = 0;
+        let mut rusage = Rusage::default();
         syscall_wait4(-1, &mut xcode, 0, &mut rusage);
         for i in 0..5 {
             let times = syscall_times(&mut tms);
@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+mod syscall;
+mod runtime;
+mod console;
+
+use syscall::*;
+use core::assert;
+
+fn busy() {
+    let mut x: usize = 0;
+    for i in 0..1000000 {
+        x = x.wrapping_add(i);
+        unsafe { core::ptr::write_volatile(&mut x, x) };
+    }
+}
+
+fn main() {
+    let mut wstatus = 0;
+    let mut rusage = Rusage::default();
+    // 没有子进程
+    assert!(syscall_wait4(-1, &mut wstatus, 0, &mut rusage) == -ECHILD);
+    assert!(syscall_wait4(-1, &mut wstatus, 0x100, &mut rusage) == -EINVAL);
+
+    let child = syscall_fork();
+    if child == 0 {
+        busy();
+        syscall_nanosleep(0, 10_000_000);
+        syscall_exit(3);
+    }
+    // 子进程还在运行
+    assert!(syscall_wait4(child as isize, &mut wstatus, WNOHANG, &mut rusage) == 0);
+    // 不是当前进程的子进程
+    assert!(syscall_wait4(child as isize + 1000, &mut wstatus, 0, &mut rusage) == -ECHILD);
+    // 同一进程组
+    assert!(syscall_wait4(0, &mut wstatus, 0, &mut rusage) == child);
+    assert!((wstatus >> 8) & 0xff == 3);
+    assert!(rusage.ru_utime.tv_sec > 0 || rusage.ru_utime.tv_usec > 0
+        || rusage.ru_stime.tv_sec > 0 || rusage.ru_stime.tv_usec > 0);
+    assert!(syscall_wait4(child as isize, &mut wstatus, WNOHANG, &mut rusage) == -ECHILD);
+
+    // 子进程退出后，孙进程交给init
+    let child = syscall_fork();
+    if child == 0 {
+        let parent = syscall_getpid();
+        if syscall_fork() == 0 {
+            syscall_nanosleep(0, 20_000_000);
+            assert!(syscall_getppid() != parent);
+            println!("orphan reparented to {}", syscall_getppid());
+            syscall_exit(0);
+        }
+        syscall_exit(0);
+    }
+    assert!(syscall_wait4(child as isize, &mut wstatus, 0, &mut rusage) == child);
+    // 孙进程不是当前进程的子进程
+    assert!(syscall_wait4(-1, &mut wstatus, WNOHANG, &mut rusage) == -ECHILD);
+    syscall_nanosleep(0, 40_000_000);
+    println!("wait_opts test passed");
+}